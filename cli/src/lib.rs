@@ -0,0 +1,5 @@
+//! The REPL of the goscript command.
+
+mod repl;
+
+pub use repl::{complete, Repl};
@@ -0,0 +1,328 @@
+use goscript_cli::{complete, Repl};
+use goscript_engine::{Config, Engine, RunError};
+use goscript_parser::printer::format_source;
+use std::env;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+use std::process;
+
+const USAGE: &str = "\
+Usage: goscript [flags] [command]
+
+Commands:
+  run FILE [ARGS...]    run a program, ARGS are in os.Args after FILE
+  check FILE            parse and type check a program
+  build [-o OUT] FILE   compile a program to bytecode, to FILE.gosc by default
+  disasm FILE           print the bytecode of a program
+  fmt [-l] [-w] FILE... format programs the way gofmt does and print them,
+                        -l lists the files that aren't formatted and -w
+                        writes the result to the files
+Without a command it starts a REPL. FILE can be a .gosc file for run and
+disasm. run exits with 1 if the program doesn't compile and with 2 if it
+panics.
+
+Flags:
+  --work-dir DIR    the directory of the local imports, ./ by default
+  --base-path DIR   the directory of the std packages, $GOSCRIPT_PATH or
+                    ./std/ by default
+  --trace           print the debug info of the parser and the checker
+  --stats           print the statistics of the opcodes run";
+
+const HELP: &str = "\
+Enter Go statements, expressions, functions, types and imports, the value of
+an expression is printed. Every input runs once, the variables it declares
+with := or var are kept for the later inputs and the functions.
+  :source   print the program of the session
+  :reset    start over
+  :quit     quit, or Ctrl-D";
+
+/// the extension of the bytecode files
+const BYTECODE_EXT: &str = "gosc";
+
+fn main() {
+    let mut config = Config {
+        work_dir: Some("./".to_string()),
+        base_path: Some(env::var("GOSCRIPT_PATH").unwrap_or("./std/".to_string())),
+        trace_parser: false,
+        trace_checker: false,
+        // it's what prints the errors of the program
+        trace_vm: true,
+        opcode_stats: false,
+    };
+    let mut args = env::args().skip(1);
+    let mut rest = vec![];
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--work-dir" => config.work_dir = Some(flag_value(&arg, args.next())),
+            "--base-path" => config.base_path = Some(flag_value(&arg, args.next())),
+            "--trace" => {
+                config.trace_parser = true;
+                config.trace_checker = true;
+            }
+            "--stats" => config.opcode_stats = true,
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return;
+            }
+            _ if arg.starts_with("--") => usage_error(&format!("unknown flag {}", arg)),
+            _ => {
+                // the rest are the command and its arguments
+                rest.push(arg);
+                rest.extend(args);
+                break;
+            }
+        }
+    }
+    if rest.is_empty() {
+        return repl(config);
+    }
+    let cmd = rest.remove(0);
+    let code = match cmd.as_str() {
+        "run" => {
+            if rest.is_empty() {
+                usage_error("run needs a file");
+            }
+            run(config, rest)
+        }
+        "check" => check(config, &file_arg(&cmd, rest)),
+        "build" => {
+            let (out, file) = match rest.first().map(|s| s.as_str()) {
+                Some("-o") if rest.len() > 1 => (Some(rest[1].clone()), rest[2..].to_vec()),
+                _ => (None, rest),
+            };
+            let file = file_arg(&cmd, file);
+            let out = out.unwrap_or_else(|| {
+                Path::new(&file)
+                    .with_extension(BYTECODE_EXT)
+                    .to_string_lossy()
+                    .to_string()
+            });
+            build(config, &file, &out)
+        }
+        "disasm" => disasm(config, &file_arg(&cmd, rest)),
+        "fmt" => {
+            let (mut list, mut write) = (false, false);
+            while let Some(flag) = rest.first() {
+                match flag.as_str() {
+                    "-l" => list = true,
+                    "-w" => write = true,
+                    _ => break,
+                }
+                rest.remove(0);
+            }
+            if rest.is_empty() {
+                usage_error("fmt needs a file");
+            }
+            fmt(&rest, list, write)
+        }
+        _ => usage_error(&format!("unknown command {}", cmd)),
+    };
+    process::exit(code);
+}
+
+fn flag_value(flag: &str, value: Option<String>) -> String {
+    value.unwrap_or_else(|| usage_error(&format!("{} needs a value", flag)))
+}
+
+/// file_arg returns the only argument of a command, the file
+fn file_arg(cmd: &str, args: Vec<String>) -> String {
+    match args.len() {
+        1 => args.into_iter().next().unwrap(),
+        0 => usage_error(&format!("{} needs a file", cmd)),
+        _ => usage_error(&format!("{} takes only one file", cmd)),
+    }
+}
+
+fn usage_error(msg: &str) -> ! {
+    eprintln!("goscript: {}\n\n{}", msg, USAGE);
+    process::exit(2)
+}
+
+/// local_path makes the path of a program a local import path, one that is
+/// not looked up in the base path
+fn local_path(file: &str) -> String {
+    if Path::new(file).is_absolute() || file.starts_with("./") || file.starts_with("../") {
+        file.to_string()
+    } else {
+        format!("./{}", file)
+    }
+}
+
+fn is_bytecode(file: &str) -> bool {
+    Path::new(file)
+        .extension()
+        .is_some_and(|e| e == BYTECODE_EXT)
+}
+
+fn open(file: &str) -> BufReader<File> {
+    match File::open(file) {
+        Ok(f) => BufReader::new(f),
+        Err(e) => {
+            eprintln!("goscript: {}: {}", file, e);
+            process::exit(1)
+        }
+    }
+}
+
+/// run runs the program in args[0], with all the args in os.Args, it returns
+/// 2 if the program panics like a Go program does
+fn run(config: Config, args: Vec<String>) -> i32 {
+    let file = args[0].clone();
+    let mut engine = Engine::new(config);
+    engine.set_args(args);
+    let result = if is_bytecode(&file) {
+        engine.run_bytecode(&mut open(&file))
+    } else {
+        engine.run(&local_path(&file))
+    };
+    match result {
+        Ok(()) => 0,
+        // the errors are printed already
+        Err(RunError::Compile(_)) => 1,
+        Err(e @ RunError::Io(_)) => {
+            eprintln!("goscript: {}: {}", file, e);
+            1
+        }
+        Err(e @ RunError::Panic) => {
+            eprintln!("goscript: {}: {}", file, e);
+            2
+        }
+    }
+}
+
+fn check(config: Config, file: &str) -> i32 {
+    match Engine::new(config).check(&local_path(file)) {
+        Ok(()) => 0,
+        Err(el) => {
+            print!("{}", el);
+            1
+        }
+    }
+}
+
+fn build(config: Config, file: &str, out: &str) -> i32 {
+    let engine = Engine::new(config);
+    let file = &local_path(file);
+    // the errors are checked first, not to leave an empty file behind
+    if let Err(el) = engine.check(file) {
+        print!("{}", el);
+        return 1;
+    }
+    let result = File::create(out).and_then(|f| {
+        let mut w = BufWriter::new(f);
+        engine.compile(file, &mut w)?;
+        w.flush()
+    });
+    match result {
+        Ok(()) => 0,
+        Err(e) => {
+            eprintln!("goscript: {}: {}", out, e);
+            1
+        }
+    }
+}
+
+fn disasm(config: Config, file: &str) -> i32 {
+    if is_bytecode(file) {
+        match goscript_vm::vm::ByteCode::read_from(&mut open(file)) {
+            Ok((bc, fs)) => {
+                print!(
+                    "{}",
+                    goscript_vm::disasm::Disassembler::new(&bc, fs.as_ref())
+                );
+                0
+            }
+            Err(e) => {
+                eprintln!("goscript: {}: {}", file, e);
+                1
+            }
+        }
+    } else {
+        match Engine::new(config).disassemble(&local_path(file)) {
+            Ok(listing) => {
+                print!("{}", listing);
+                0
+            }
+            Err(_) => 1,
+        }
+    }
+}
+
+/// fmt formats the files, it goes on with the others if one has errors
+fn fmt(files: &[String], list: bool, write: bool) -> i32 {
+    let mut code = 0;
+    for file in files.iter() {
+        let src = match std::fs::read_to_string(file) {
+            Ok(s) => s,
+            Err(e) => {
+                eprintln!("goscript: {}: {}", file, e);
+                code = 1;
+                continue;
+            }
+        };
+        let formatted = match format_source(file, &src) {
+            Ok(s) => s,
+            Err(el) => {
+                eprint!("{}", el);
+                code = 1;
+                continue;
+            }
+        };
+        if list && formatted != src {
+            println!("{}", file);
+        }
+        if write {
+            if formatted != src {
+                if let Err(e) = std::fs::write(file, &formatted) {
+                    eprintln!("goscript: {}: {}", file, e);
+                    code = 1;
+                }
+            }
+        } else if !list {
+            print!("{}", formatted);
+        }
+    }
+    code
+}
+
+fn repl(mut config: Config) {
+    config.trace_vm = false;
+    let mut repl = Repl::new(config, Box::new(io::stdout()));
+    let stdin = io::stdin();
+    let mut lines = stdin.lock().lines();
+    let mut input = String::new();
+    loop {
+        print!("{}", if input.is_empty() { "> " } else { "... " });
+        io::stdout().flush().unwrap();
+        let line = match lines.next() {
+            Some(Ok(line)) => line,
+            _ => break,
+        };
+        if input.is_empty() {
+            match line.trim() {
+                ":help" => {
+                    println!("{}", HELP);
+                    continue;
+                }
+                ":source" => {
+                    print!("{}", repl.source());
+                    continue;
+                }
+                ":reset" => {
+                    repl.reset();
+                    continue;
+                }
+                ":quit" | ":q" => break,
+                _ => {}
+            }
+        }
+        input.push_str(&line);
+        input.push('\n');
+        if complete(&input) {
+            repl.eval(&input);
+            input.clear();
+        }
+    }
+    println!();
+}
@@ -0,0 +1,610 @@
+//! An interactive session. Every input is type checked with what the earlier
+//! ones declared and runs once, on top of them: the imports, functions and
+//! types go to the package, and so do the variables, an `x := e` or `var x = e`
+//! input declares a variable of package main, initialized only then, that the
+//! functions can use too. The other statements go to main. An input is kept
+//! only if it compiles and runs without a panic, so an error never loses the
+//! session. The variables declared by an input of several statements are
+//! local to it.
+
+use goscript_engine::{Config, Engine, Session};
+use goscript_parser::ast::{Decl, Expr, Node, Spec, Stmt};
+use goscript_parser::errors::{Error, ErrorList};
+use goscript_parser::objects::Objects;
+use goscript_parser::{parse_file, FileSet, Pos, Token};
+use goscript_vm::ffi::Ffi;
+use goscript_vm::value::GosValue;
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::io::Write;
+use std::panic::{self, AssertUnwindSafe};
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+
+const FILE_NAME: &str = "repl.gos";
+
+/// the helpers of every program
+const PRELUDE: &str = "
+type __replFfi interface {
+    print(a ...interface{})
+}
+
+func __replPrint(a ...interface{}) {
+    var f = ffi(__replFfi, \"repl\")
+    f.print(a...)
+}
+";
+
+/// an expression input is printed with __replPrint
+const PRINT: &str = "__replPrint(";
+
+/// Output takes the place of the fmt package, and prints for __replPrint
+struct Output {
+    out: Rc<RefCell<Box<dyn Write>>>,
+}
+
+impl Ffi for Output {
+    // fmt has no printf yet, every func prints like println
+    fn call(&self, _: &str, params: Vec<GosValue>) -> Vec<GosValue> {
+        let vec = params[0].as_slice().0.get_vec();
+        let strs: Vec<String> = vec
+            .iter()
+            .map(|x| {
+                if x.is_nil() {
+                    "<nil>".to_string()
+                } else {
+                    x.iface_underlying().unwrap().to_string()
+                }
+            })
+            .collect();
+        let _ = writeln!(self.out.borrow_mut(), "{}", strs.join(", "));
+        vec![]
+    }
+}
+
+/// the part of the program a line of it comes from
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+enum Part {
+    Import(usize),
+    Var(usize),
+    Decl(usize),
+    New,
+}
+
+/// what an input adds to the program
+enum Input {
+    Imports(Vec<String>),
+    // a function or a type, with the name that replaces an earlier one
+    Decl(Option<String>, String),
+    // package variables, with the shift of the columns of the first line
+    Vars(Vec<Var>, isize),
+    Stmts(String),
+}
+
+impl Input {
+    fn shift(&self) -> isize {
+        match self {
+            Input::Vars(_, shift) => *shift,
+            _ => 0,
+        }
+    }
+
+    /// fresh returns the names the input declares again
+    fn fresh(&self) -> Vec<String> {
+        match self {
+            Input::Vars(vars, _) => vars
+                .iter()
+                .flat_map(|v| v.names.iter())
+                .filter(|n| *n != "_")
+                .cloned()
+                .collect(),
+            Input::Decl(Some(name), _) => vec![name.clone()],
+            _ => vec![],
+        }
+    }
+}
+
+/// a variable declaration of the package, the names declared again by a
+/// later input are blanked
+struct Var {
+    names: Vec<String>,
+    // what follows the names, the type and the values
+    rest: String,
+}
+
+impl Var {
+    /// source returns the declaration with the names in fresh blanked, none
+    /// if they all are
+    fn source(&self, fresh: &[String]) -> Option<String> {
+        let names: Vec<&str> = self
+            .names
+            .iter()
+            .map(|n| if fresh.contains(n) { "_" } else { n.as_str() })
+            .collect();
+        if names.iter().all(|n| *n == "_") {
+            return None;
+        }
+        Some(format!("var {}{}", names.join(", "), self.rest))
+    }
+}
+
+/// the program of the session with the input
+struct Program {
+    source: String,
+    // the part of every line
+    lines: Vec<Option<Part>>,
+}
+
+impl Program {
+    fn push(&mut self, text: &str, part: Option<Part>) {
+        for line in text.lines() {
+            self.source.push_str(line);
+            self.source.push('\n');
+            self.lines.push(part);
+        }
+    }
+
+    fn part(&self, e: &Error) -> Option<Part> {
+        if e.pos.filename.as_str() != FILE_NAME || e.pos.line == 0 {
+            return None;
+        }
+        self.lines.get(e.pos.line - 1).copied().flatten()
+    }
+
+    fn first_line(&self, part: Part) -> usize {
+        self.lines
+            .iter()
+            .position(|p| *p == Some(part))
+            .unwrap_or(0)
+            + 1
+    }
+}
+
+/// the result of compiling and running a program
+enum Run {
+    Done,
+    Failed,
+    Errors(Vec<Error>, Program),
+}
+
+pub struct Repl {
+    engine: Engine,
+    session: Session,
+    out: Rc<RefCell<Box<dyn Write>>>,
+    imports: Vec<String>,
+    vars: Vec<Var>,
+    decls: Vec<(Option<String>, String)>,
+}
+
+impl Repl {
+    /// new starts a session, the output of the program and the errors go to out
+    pub fn new(config: Config, out: Box<dyn Write>) -> Repl {
+        let out = Rc::new(RefCell::new(out));
+        let mut engine = Engine::new(config);
+        for name in ["fmt", "repl"].iter() {
+            let out = out.clone();
+            engine.register_extension(
+                name,
+                Box::new(move |_| Ok(Rc::new(RefCell::new(Output { out: out.clone() })))),
+            );
+        }
+        Repl {
+            engine,
+            session: Session::new(),
+            out,
+            imports: vec![],
+            vars: vec![],
+            decls: vec![],
+        }
+    }
+
+    /// eval adds the input to the session and runs it, an expression has its
+    /// value printed. It returns false if the input is not kept, because of
+    /// errors or a panic.
+    pub fn eval(&mut self, input: &str) -> bool {
+        let input = input.trim();
+        if input.is_empty() {
+            return true;
+        }
+        let mut input = classify(input);
+        if let Input::Imports(specs) = &mut input {
+            specs.retain(|s| !self.imports.contains(s));
+        }
+        let run = if let Input::Stmts(src) = &input {
+            // try it as an expression first
+            let print = Input::Stmts(format!("{}{})", PRINT, src));
+            match self.run(&print) {
+                Run::Errors(print_errs, print_prog) => {
+                    if let Some(vars) = hoist(src) {
+                        input = vars;
+                    }
+                    match self.run(&input) {
+                        Run::Errors(errs, prog) => {
+                            if by_parser(&errs) && !by_parser(&print_errs) {
+                                self.report(&print_errs, &print_prog, PRINT.len() as isize);
+                            } else {
+                                self.report(&errs, &prog, input.shift());
+                            }
+                            return false;
+                        }
+                        run => run,
+                    }
+                }
+                run => run,
+            }
+        } else {
+            self.run(&input)
+        };
+        match run {
+            Run::Done => {
+                self.commit(input);
+                true
+            }
+            Run::Failed => false,
+            Run::Errors(errs, prog) => {
+                self.report(&errs, &prog, 0);
+                false
+            }
+        }
+    }
+
+    /// source returns the program of the session
+    pub fn source(&self) -> String {
+        self.program(&Input::Imports(vec![]), &HashSet::new(), &[])
+            .source
+    }
+
+    pub fn reset(&mut self) {
+        self.session = Session::new();
+        self.imports.clear();
+        self.vars.clear();
+        self.decls.clear();
+    }
+
+    fn commit(&mut self, input: Input) {
+        let fresh = input.fresh();
+        match input {
+            Input::Imports(specs) => self.imports.extend(specs),
+            Input::Decl(name, src) => {
+                if name.is_some() {
+                    self.decls.retain(|(n, _)| *n != name);
+                }
+                self.decls.push((name, src));
+            }
+            Input::Vars(vars, _) => {
+                for var in self.vars.iter_mut() {
+                    for name in var.names.iter_mut() {
+                        if fresh.contains(name) {
+                            *name = "_".to_string();
+                        }
+                    }
+                }
+                self.vars.retain(|v| v.names.iter().any(|n| n != "_"));
+                self.vars.extend(vars);
+            }
+            // it has run, there's nothing left of it
+            Input::Stmts(_) => {}
+        }
+    }
+
+    /// run compiles and runs the program with the input, the imports that
+    /// are not used are left out, and the variables not used get used
+    fn run(&mut self, input: &Input) -> Run {
+        let fresh = input.fresh();
+        let mut skip = HashSet::new();
+        let mut uses = vec![];
+        loop {
+            let prog = self.program(input, &skip, &uses);
+            let engine = &self.engine;
+            let session = &mut self.session;
+            // a bug of the vm shouldn't end the session, nor flood it with a
+            // backtrace, the executor of the fibers catches some of them
+            let bug = Arc::new(Mutex::new(None));
+            let hook = panic::take_hook();
+            let bug_in_hook = bug.clone();
+            panic::set_hook(Box::new(move |info| {
+                *bug_in_hook.lock().unwrap() = Some(info.to_string());
+            }));
+            let result = panic::catch_unwind(AssertUnwindSafe(|| {
+                engine.run_session(session, FILE_NAME, &prog.source, &fresh)
+            }));
+            panic::set_hook(hook);
+            if let Some(msg) = bug.lock().unwrap().take() {
+                let _ = writeln!(self.out.borrow_mut(), "internal error of the vm: {}", msg);
+                return Run::Failed;
+            }
+            let errs: Vec<Error> = match result {
+                Ok(Ok(true)) => return Run::Done,
+                // the panic has been printed
+                Ok(Ok(false)) | Err(_) => return Run::Failed,
+                Ok(Err(el)) => el.borrow().clone(),
+            };
+            let mut fixed = false;
+            let mut hard = false;
+            for e in errs.iter() {
+                match (prog.part(e), e.soft) {
+                    (Some(Part::Import(i)), true) if e.msg.contains("imported but not used") => {
+                        fixed |= skip.insert(i);
+                    }
+                    (Some(Part::New), true)
+                        if e.msg.ends_with(" declared but not used")
+                            && !e.msg.starts_with("label ") =>
+                    {
+                        let name = e.msg.trim_end_matches(" declared but not used");
+                        uses.push(name.to_string());
+                        fixed = true;
+                    }
+                    _ => hard = true,
+                }
+            }
+            if hard || !fixed {
+                return Run::Errors(errs, prog);
+            }
+        }
+    }
+
+    fn program(&self, input: &Input, skip: &HashSet<usize>, uses: &[String]) -> Program {
+        let mut prog = Program {
+            source: String::new(),
+            lines: vec![],
+        };
+        prog.push("package main\n", None);
+        let new_imports = match input {
+            Input::Imports(specs) => &specs[..],
+            _ => &[],
+        };
+        for (i, spec) in self.imports.iter().chain(new_imports.iter()).enumerate() {
+            if !skip.contains(&i) {
+                prog.push(&format!("import {}", spec), Some(Part::Import(i)));
+            }
+        }
+        prog.push(PRELUDE, None);
+        let fresh = input.fresh();
+        for (i, var) in self.vars.iter().enumerate() {
+            if let Some(src) = var.source(&fresh) {
+                prog.push(&src, Some(Part::Var(i)));
+            }
+        }
+        if let Input::Vars(vars, _) = input {
+            for var in vars.iter() {
+                prog.push(&var.source(&[]).unwrap(), Some(Part::New));
+            }
+        }
+        let replaced = match input {
+            Input::Decl(Some(name), _) => Some(name),
+            _ => None,
+        };
+        for (i, (name, src)) in self.decls.iter().enumerate() {
+            if name.is_none() || name.as_ref() != replaced {
+                prog.push("\n", None);
+                prog.push(src, Some(Part::Decl(i)));
+            }
+        }
+        if let Input::Decl(_, src) = input {
+            prog.push("\n", None);
+            prog.push(src, Some(Part::New));
+        }
+        prog.push("\nfunc main() {", None);
+        if let Input::Stmts(src) = input {
+            prog.push(src, Some(Part::New));
+            for name in uses.iter() {
+                prog.push(&format!("_ = {}", name), None);
+            }
+        }
+        prog.push("}", None);
+        prog
+    }
+
+    /// report prints the errors, with the positions in the input, shift is
+    /// how much further the first line of it is in the program
+    fn report(&self, errs: &[Error], prog: &Program, shift: isize) {
+        let first = prog.first_line(Part::New);
+        let mut out = self.out.borrow_mut();
+        let mut seen = HashSet::new();
+        for e in errs.iter() {
+            let text = match prog.part(e) {
+                // the imports are for the later inputs as well
+                Some(Part::Import(_)) if e.soft && e.msg.contains("imported but not used") => {
+                    continue;
+                }
+                Some(Part::New) => {
+                    let line = e.pos.line + 1 - first;
+                    let col = if line == 1 {
+                        (e.pos.column as isize - shift).max(1) as usize
+                    } else {
+                        e.pos.column
+                    };
+                    format!("{}:{}: {}", line, col, e.msg)
+                }
+                _ => e.msg.clone(),
+            };
+            if seen.insert(text.clone()) {
+                let _ = writeln!(out, "{}", text);
+            }
+        }
+    }
+}
+
+/// hoist returns the package variables of an input of a single `:=` or `var`
+/// statement
+fn hoist(input: &str) -> Option<Input> {
+    const HEAD: &str = "package main\nfunc main() {\n";
+    let src = format!("{}{}\n}}\n", HEAD, input);
+    let fset = &mut FileSet::new();
+    let objs = &mut Objects::new();
+    let el = ErrorList::new();
+    let (_, file) = parse_file(objs, fset, &el, FILE_NAME, &src, false);
+    let file = file.filter(|_| el.len() == 0)?;
+    let body = match file.decls.last()? {
+        Decl::Func(f) => objs.fdecls[*f].body.clone()?,
+        _ => return None,
+    };
+    let chars: Vec<char> = src.chars().collect();
+    // the offsets are of chars, in the input
+    let offset = |p: Pos| fset.position(p).offset - HEAD.len();
+    let text = |from: Pos, to: Pos| -> String {
+        chars[fset.position(from).offset..fset.position(to).offset]
+            .iter()
+            .collect()
+    };
+    let mut vars = vec![];
+    // where the rest of the first one starts in the input
+    let mut rest_at = 0;
+    let stmt = match body.list.as_slice() {
+        [stmt] => stmt,
+        _ => return None,
+    };
+    match stmt {
+        Stmt::Assign(key) if objs.a_stmts[*key].token == Token::DEFINE => {
+            let assign = &objs.a_stmts[*key];
+            let mut names = vec![];
+            for e in assign.lhs.iter() {
+                match e {
+                    Expr::Ident(i) => names.push(objs.idents[*i].name.clone()),
+                    _ => return None,
+                }
+            }
+            let between = text(assign.lhs.last()?.end(objs), assign.token_pos);
+            let after = assign.token_pos + 2;
+            // the values stay after the `=` that takes the place of `:=`
+            rest_at = offset(after) as isize - between.chars().count() as isize - 1;
+            vars.push(Var {
+                names,
+                rest: format!("{}={}", between, text(after, stmt.end(objs))),
+            });
+        }
+        Stmt::Decl(decl) => match &**decl {
+            Decl::Gen(gen) if gen.token == Token::VAR => {
+                for (i, key) in gen.specs.iter().enumerate() {
+                    let spec = match &objs.specs[*key] {
+                        Spec::Value(v) => v,
+                        _ => return None,
+                    };
+                    let names: Vec<String> = spec
+                        .names
+                        .iter()
+                        .map(|n| objs.idents[*n].name.clone())
+                        .collect();
+                    let last = &objs.idents[*spec.names.last()?];
+                    let last_end = last.pos + last.name.chars().count();
+                    if i == 0 {
+                        rest_at = offset(last_end) as isize;
+                    }
+                    vars.push(Var {
+                        names,
+                        rest: text(last_end, objs.specs[*key].end(objs)),
+                    });
+                }
+            }
+            _ => return None,
+        },
+        _ => return None,
+    }
+    // a blank one would be initialized again by every input
+    if vars.is_empty() || vars.iter().any(|v| v.names.iter().all(|n| n == "_")) {
+        return None;
+    }
+    // where the rest of the first one starts in the program
+    let hoisted = format!("var {}", vars[0].names.join(", ")).chars().count() as isize;
+    Some(Input::Vars(vars, hoisted - rest_at))
+}
+
+fn by_parser(errs: &[Error]) -> bool {
+    errs.iter().any(|e| e.by_parser)
+}
+
+fn classify(input: &str) -> Input {
+    let word: String = input
+        .chars()
+        .take_while(|c| c.is_alphanumeric() || *c == '_')
+        .collect();
+    let rest = input[word.len()..].trim_start();
+    match word.as_str() {
+        "import" => {
+            let specs = match rest.strip_prefix('(') {
+                Some(group) => group
+                    .trim_end()
+                    .trim_end_matches(')')
+                    .split(['\n', ';'])
+                    .map(|s| s.trim().to_string())
+                    .filter(|s| !s.is_empty())
+                    .collect(),
+                None => vec![rest.to_string()],
+            };
+            Input::Imports(specs)
+        }
+        "type" => Input::Decl(
+            Some(ident(rest)).filter(|n| !n.is_empty()),
+            input.to_string(),
+        ),
+        "func" => match rest.strip_prefix('(') {
+            // a method, named by its receiver type
+            Some(recv) => {
+                let end = recv.find(')').unwrap_or(recv.len());
+                let typ = recv[..end].split_whitespace().last().unwrap_or("");
+                let after = recv[end..].trim_start_matches(')').trim_start();
+                let name = ident(after);
+                if name.is_empty() || !after[name.len()..].trim_start().starts_with('(') {
+                    // a function literal
+                    return Input::Stmts(input.to_string());
+                }
+                Input::Decl(
+                    Some(format!("{}.{}", typ.trim_start_matches('*'), name)),
+                    input.to_string(),
+                )
+            }
+            None => Input::Decl(Some(ident(rest)), input.to_string()),
+        },
+        _ => Input::Stmts(input.to_string()),
+    }
+}
+
+fn ident(s: &str) -> String {
+    s.chars()
+        .take_while(|c| c.is_alphanumeric() || *c == '_')
+        .collect()
+}
+
+/// complete returns false if the input needs more lines, as it has brackets,
+/// a raw string or a comment not closed
+pub fn complete(input: &str) -> bool {
+    let mut depth = 0;
+    let mut chars = input.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '(' | '[' | '{' => depth += 1,
+            ')' | ']' | '}' => depth -= 1,
+            '"' | '\'' => {
+                while let Some(s) = chars.next() {
+                    match s {
+                        '\\' => {
+                            chars.next();
+                        }
+                        '\n' => break,
+                        _ if s == c => break,
+                        _ => {}
+                    }
+                }
+            }
+            '`' if !chars.any(|s| s == '`') => return false,
+            '/' if chars.peek() == Some(&'/') => {
+                while chars.peek().is_some_and(|s| *s != '\n') {
+                    chars.next();
+                }
+            }
+            '/' if chars.peek() == Some(&'*') => {
+                chars.next();
+                let mut last = ' ';
+                loop {
+                    match chars.next() {
+                        Some('/') if last == '*' => break,
+                        Some(s) => last = s,
+                        None => return false,
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    depth <= 0
+}
@@ -0,0 +1,129 @@
+use std::env;
+use std::process::{Command, Output};
+
+/// goscript runs the binary in the engine directory, with its tests and std
+fn goscript(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_goscript"))
+        .current_dir("../engine")
+        .env("GOSCRIPT_PATH", "./std/")
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+fn stdout(out: &Output) -> String {
+    String::from_utf8_lossy(&out.stdout).to_string()
+}
+
+#[test]
+fn test_run() {
+    let out = goscript(&["run", "tests/group1/func1.gos"]);
+    assert!(out.status.success());
+    let out = goscript(&["run", "tests/group1/check.gos"]);
+    assert_eq!(out.status.code(), Some(1));
+    assert!(stdout(&out).contains("undeclared name: undefined"));
+}
+
+#[test]
+fn test_run_panic() {
+    let file = env::temp_dir().join("goscript_cli_panic.gos");
+    let file = file.to_str().unwrap();
+    for body in [
+        "panic(\"boom\")",
+        "assert(len(os.Args) == 0)",
+        "s := []int{1}\n\t_ = s[len(os.Args)+1]",
+    ]
+    .iter()
+    {
+        let src = format!(
+            "package main\n\nimport \"os\"\n\nfunc main() {{\n\t_ = os.Args\n\t{}\n}}\n",
+            body
+        );
+        std::fs::write(file, src).unwrap();
+        let out = goscript(&["run", file]);
+        assert_eq!(out.status.code(), Some(2), "{}", body);
+        assert!(String::from_utf8_lossy(&out.stderr).contains("panicked"));
+    }
+    std::fs::remove_file(file).unwrap();
+}
+
+#[test]
+fn test_check() {
+    let out = goscript(&["check", "tests/group1/func1.gos"]);
+    assert!(out.status.success());
+    assert_eq!(stdout(&out), "");
+    let out = goscript(&["check", "tests/group1/check.gos"]);
+    assert_eq!(out.status.code(), Some(1));
+    let errs = stdout(&out);
+    assert!(errs.starts_with("Result: 3 errors\n"), "{}", errs);
+    assert!(errs.contains("tests/group1/check.gos:4:20  cannot convert 1"));
+    let out = goscript(&["check", "tests/group1/nothing.gos"]);
+    assert_eq!(out.status.code(), Some(1));
+    assert!(stdout(&out).contains("failed to locate path"));
+}
+
+#[test]
+fn test_build() {
+    let file = env::temp_dir().join("goscript_cli_test.gosc");
+    let file = file.to_str().unwrap();
+    let out = goscript(&["build", "-o", file, "tests/group1/func1.gos"]);
+    assert!(out.status.success());
+    let out = goscript(&["run", file]);
+    assert!(out.status.success());
+    let out = goscript(&["disasm", file]);
+    assert!(out.status.success());
+    let listing = stdout(&out);
+    assert!(listing.contains("TEXT main.main"));
+    // the positions are in the bytecode too
+    assert_eq!(
+        listing,
+        stdout(&goscript(&["disasm", "tests/group1/func1.gos"]))
+    );
+    std::fs::remove_file(file).unwrap();
+    let out = goscript(&["build", "-o", file, "tests/group1/check.gos"]);
+    assert_eq!(out.status.code(), Some(1));
+    assert!(!std::path::Path::new(file).exists());
+}
+
+#[test]
+fn test_fmt() {
+    let file = env::temp_dir().join("goscript_cli_fmt.gos");
+    let file = file.to_str().unwrap();
+    std::fs::write(file, "package main\nfunc main() {\nx:=1\n_ = x}\n").unwrap();
+    let formatted = "package main\n\nfunc main() {\n\tx := 1\n\t_ = x\n}\n";
+    let out = goscript(&["fmt", file]);
+    assert!(out.status.success());
+    assert_eq!(stdout(&out), formatted);
+    let out = goscript(&["fmt", "-l", file]);
+    assert_eq!(stdout(&out), format!("{}\n", file));
+    let out = goscript(&["fmt", "-w", file]);
+    assert_eq!(stdout(&out), "");
+    assert_eq!(std::fs::read_to_string(file).unwrap(), formatted);
+    let out = goscript(&["fmt", "-l", file]);
+    assert_eq!(stdout(&out), "");
+    std::fs::write(file, "package main\nfunc main() {\n").unwrap();
+    let out = goscript(&["fmt", file]);
+    assert_eq!(out.status.code(), Some(1));
+    assert!(!String::from_utf8_lossy(&out.stderr).is_empty());
+    std::fs::remove_file(file).unwrap();
+}
+
+#[test]
+fn test_usage() {
+    for args in [
+        &["bogus"][..],
+        &["run"],
+        &["check", "a.gos", "b.gos"],
+        &["fmt", "-l"],
+        &["--work-dir"],
+        &["--bogus", "run", "a.gos"],
+    ]
+    .iter()
+    {
+        let out = goscript(args);
+        assert_eq!(out.status.code(), Some(2), "{:?}", args);
+        assert!(String::from_utf8_lossy(&out.stderr).contains("Usage: goscript"));
+    }
+    let out = goscript(&["--help"]);
+    assert!(out.status.success());
+}
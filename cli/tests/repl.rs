@@ -0,0 +1,161 @@
+extern crate goscript_cli as cli;
+
+use cli::{complete, Repl};
+use std::cell::RefCell;
+use std::io::{self, Write};
+use std::rc::Rc;
+
+#[derive(Clone, Default)]
+struct SharedBuf(Rc<RefCell<Vec<u8>>>);
+
+impl SharedBuf {
+    /// take returns what was written since the last take
+    fn take(&self) -> String {
+        String::from_utf8(self.0.replace(vec![])).unwrap()
+    }
+}
+
+impl Write for SharedBuf {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fn repl() -> (Repl, SharedBuf) {
+    let config = goscript_engine::Config {
+        work_dir: Some("./".to_string()),
+        base_path: Some("../engine/std/".to_string()),
+        trace_parser: false,
+        trace_checker: false,
+        trace_vm: false,
+        opcode_stats: false,
+    };
+    let out = SharedBuf::default();
+    (Repl::new(config, Box::new(out.clone())), out)
+}
+
+#[test]
+fn test_expressions() {
+    let (mut repl, out) = repl();
+    assert!(repl.eval("1 + 2"));
+    assert_eq!(out.take(), "3\n");
+    assert!(repl.eval("x := 5"));
+    assert_eq!(out.take(), "");
+    assert!(repl.eval("x * 2"));
+    assert_eq!(out.take(), "10\n");
+    assert!(repl.eval("x = 7"));
+    assert!(repl.eval("x"));
+    assert_eq!(out.take(), "7\n");
+}
+
+#[test]
+fn test_decls() {
+    let (mut repl, out) = repl();
+    assert!(repl.eval("func sq(n int) int { return n * n }"));
+    assert!(repl.eval("sq(3)"));
+    assert_eq!(out.take(), "9\n");
+    // a function defined again replaces the old one
+    assert!(repl.eval("func sq(n int) int { return n + n }"));
+    assert!(repl.eval("sq(3)"));
+    assert_eq!(out.take(), "6\n");
+    assert!(repl.eval("type point struct { x, y int }"));
+    assert!(repl.eval("func (p point) sum() int { return p.x + p.y }"));
+    assert!(repl.eval("point{1, 2}.sum()"));
+    assert_eq!(out.take(), "3\n");
+}
+
+#[test]
+fn test_imports() {
+    let (mut repl, out) = repl();
+    assert!(repl.eval("import \"fmt\""));
+    assert!(repl.eval("fmt.Println(\"hi\")"));
+    assert_eq!(out.take(), "hi\n");
+    // the earlier inputs don't run again
+    assert!(repl.eval("1"));
+    assert_eq!(out.take(), "1\n");
+    // nor is the unused import reported with the errors of a later input
+    assert!(!repl.eval("x"));
+    assert!(out.take().starts_with("1:1: undeclared name: x\n"));
+}
+
+#[test]
+fn test_session_vars() {
+    let (mut repl, out) = repl();
+    assert!(repl.eval("var n int"));
+    // the functions see the variables of the session
+    assert!(repl.eval("func inc() int { n++; return n }"));
+    assert!(repl.eval("inc()"));
+    assert!(repl.eval("inc()"));
+    assert_eq!(out.take(), "1\n2\n");
+    // the initializer runs once
+    assert!(repl.eval("k := inc() + 100"));
+    assert!(repl.eval("k + n"));
+    assert_eq!(out.take(), "106\n");
+    assert!(repl.eval("f := func() int { n += 10; return n }"));
+    assert!(repl.eval("f()"));
+    assert!(repl.eval("n"));
+    assert_eq!(out.take(), "13\n13\n");
+    // a variable defined again starts over
+    assert!(repl.eval("k := \"str\""));
+    assert!(repl.eval("k + k"));
+    assert_eq!(out.take(), "strstr\n");
+    // and the functions using the old one have to be defined again
+    assert!(!repl.eval("n := \"str\""));
+    assert!(out.take().contains("invalid operation: n++"));
+    assert!(repl.eval("f()"));
+    assert_eq!(out.take(), "23\n");
+}
+
+#[test]
+fn test_session_values() {
+    let (mut repl, out) = repl();
+    assert!(repl.eval("type point struct { x, y int }"));
+    assert!(repl.eval("func (p point) sum() int { return p.x + p.y }"));
+    assert!(repl.eval("v := point{5, 6}"));
+    assert!(repl.eval("p := &v"));
+    assert!(repl.eval("p.y = 9"));
+    assert!(repl.eval("v"));
+    assert!(repl.eval("v.sum()"));
+    assert_eq!(out.take(), "{5 9}\n14\n");
+    assert!(repl.eval("var i interface{} = point{1, 2}"));
+    assert!(repl.eval("q := i.(point)"));
+    assert!(repl.eval("q.sum()"));
+    assert_eq!(out.take(), "3\n");
+}
+
+#[test]
+fn test_errors() {
+    let (mut repl, out) = repl();
+    assert!(repl.eval("x := 1"));
+    assert!(!repl.eval("x + y"));
+    assert!(out.take().contains("1:5: undeclared name: y"));
+    // the vm prints the panic itself
+    assert!(!repl.eval("panic(\"boom\")"));
+    out.take();
+    // the session goes on without the inputs that failed
+    assert!(repl.eval("x + 1"));
+    assert_eq!(out.take(), "2\n");
+    assert!(!repl.source().contains("boom"));
+    // a variable whose initializer panics is not kept
+    assert!(!repl.eval("z := []int{}[x]"));
+    out.take();
+    assert!(!repl.eval("z"));
+    assert!(out.take().starts_with("1:1: undeclared name: z\n"));
+}
+
+#[test]
+fn test_complete() {
+    assert!(complete("1 + 2\n"));
+    assert!(!complete("func f() {\n"));
+    assert!(complete("func f() {\n}\n"));
+    assert!(!complete("s := []int{1,\n"));
+    assert!(complete("s := \"{\"\n"));
+    assert!(complete("// {\n"));
+    assert!(!complete("/* {\n"));
+    assert!(!complete("s := `\n"));
+}
@@ -1,9 +1,12 @@
+use std::cell::RefCell;
 use std::collections::HashMap;
 use std::convert::TryFrom;
 use std::rc::Rc;
 
 use super::branch::*;
 use super::emit::*;
+use super::escape::Escapes;
+use super::instance::InstanceMapping;
 use super::interface::IfaceMapping;
 use super::package::PkgUtil;
 use super::types::{TypeCache, TypeLookup};
@@ -22,8 +25,8 @@ use goscript_parser::position::Pos;
 use goscript_parser::token::Token;
 use goscript_parser::visitor::{walk_decl, walk_expr, walk_stmt, ExprVisitor, StmtVisitor};
 use goscript_types::{
-    identical, Builtin, OperandMode, PackageKey as TCPackageKey, TCObjects, TypeInfo,
-    TypeKey as TCTypeKey,
+    identical, lookup_field_or_method, type_str, Builtin, LookupResult, OperandMode,
+    PackageKey as TCPackageKey, SelectionKind, TCObjects, TypeInfo, TypeKey as TCTypeKey,
 };
 
 macro_rules! current_func_mut {
@@ -52,12 +55,17 @@ pub struct CodeGen<'a> {
     dummy_gcv: &'a mut GcoVec,
     tlookup: TypeLookup<'a>,
     iface_mapping: &'a mut IfaceMapping,
+    instances: &'a InstanceMapping<'a>,
     pkg_util: PkgUtil<'a>,
     branch: BranchHelper,
     pkg_key: PackageKey,
     func_stack: Vec<FunctionKey>,
     func_t_stack: Vec<TCTypeKey>, // for casting return values to interfaces
+    escapes: Vec<Escapes>,        // of the functions in func_stack that have a body
+    derefs: HashMap<EntityKey, EntIndex>, // the confined pointers and what they point to
     blank_ident: IdentKey,
+    // the package variables with the values of an earlier program, see keep_vars
+    kept_vars: HashMap<String, Rc<RefCell<GosValue>>>,
 }
 
 impl<'a> CodeGen<'a> {
@@ -69,6 +77,7 @@ impl<'a> CodeGen<'a> {
         ti: &'a TypeInfo,
         type_cache: &'a mut TypeCache,
         mapping: &'a mut IfaceMapping,
+        instances: &'a InstanceMapping<'a>,
         pkg_indices: &'a HashMap<TCPackageKey, OpIndex>,
         pkgs: &'a Vec<PackageKey>,
         pkg: PackageKey,
@@ -81,15 +90,25 @@ impl<'a> CodeGen<'a> {
             dummy_gcv: dummy_gcv,
             tlookup: TypeLookup::new(tco, ti, type_cache),
             iface_mapping: mapping,
+            instances: instances,
             pkg_util: PkgUtil::new(asto, tco, pkg_indices, pkgs, pkg),
             branch: BranchHelper::new(),
             pkg_key: pkg,
             func_stack: Vec::new(),
             func_t_stack: Vec::new(),
+            escapes: Vec::new(),
+            derefs: HashMap::new(),
             blank_ident: bk,
+            kept_vars: HashMap::new(),
         }
     }
 
+    /// keep_vars makes the package variables of these names share the cells
+    /// of an earlier program, they are not initialized again
+    pub fn keep_vars(&mut self, vars: HashMap<String, Rc<RefCell<GosValue>>>) {
+        self.kept_vars = vars;
+    }
+
     pub fn pkg_util(&mut self) -> &mut PkgUtil<'a> {
         &mut self.pkg_util
     }
@@ -171,7 +190,7 @@ impl<'a> CodeGen<'a> {
             let zero_val = zero_val!(meta, self.objects, self.dummy_gcv);
             let func = current_func_mut!(self);
             let ident_key = ident.entity.clone().into_key();
-            let index = func.add_local(ident_key);
+            let index = func.add_local(ident_key, Some(ident.name.clone()));
             func.add_local_zero(zero_val);
             if func.is_ctor() {
                 let pkg_key = func.package;
@@ -250,18 +269,7 @@ impl<'a> CodeGen<'a> {
                         let ind = &ind_expr.as_ref().index;
                         let pos = ind_expr.as_ref().l_brack;
 
-                        let mut index_const = None;
-                        let mut index_typ = None;
-                        if let Some(const_val) = self.tlookup.get_tc_const_value(ind.id()) {
-                            let (ival, _) = const_val.to_int().int_as_i64();
-                            if let Ok(i) = OpIndex::try_from(ival) {
-                                index_const = Some(i);
-                            }
-                        }
-                        if index_const.is_none() {
-                            self.visit_expr(ind);
-                            index_typ = Some(self.tlookup.get_expr_value_type(ind));
-                        }
+                        let (index_const, index_typ) = self.gen_index(obj, ind);
                         (
                             LeftHandSide::IndexSelExpr(IndexSelInfo::new(
                                 0,
@@ -290,16 +298,16 @@ impl<'a> CodeGen<'a> {
                                 )
                             }
                             None => {
-                                let t = self.tlookup.get_meta_by_node_id(
-                                    sexpr.expr.id(),
-                                    self.objects,
-                                    self.dummy_gcv,
-                                );
-                                let name = &self.ast_objs.idents[sexpr.sel].name;
-                                let i = t.field_index(name, &self.objects.metas);
+                                let sel = self.tlookup.get_selection(expr.id()).clone();
+                                let recv = sel.recv().unwrap();
+                                let (i, path) = sel.indices().split_last().unwrap();
+                                let i = *i as OpIndex;
 
                                 self.visit_expr(&sexpr.expr);
-                                let obj_typ = self.tlookup.get_expr_value_type(&sexpr.expr);
+                                self.gen_embedded_path(recv, path, false, Some(pos));
+                                let obj_typ = self
+                                    .tlookup
+                                    .value_type_from_tc(self.tlookup.embedded_path_tc(recv, path));
                                 (
                                     // the true index will be calculated later
                                     LeftHandSide::IndexSelExpr(IndexSelInfo::new(
@@ -316,12 +324,14 @@ impl<'a> CodeGen<'a> {
                         }
                     }
                     Expr::Star(sexpr) => {
-                        self.visit_expr(&sexpr.expr);
-                        (
-                            LeftHandSide::Deref(0), // the true index will be calculated later
-                            Some(self.tlookup.get_expr_tc_type(expr)),
-                            sexpr.star,
-                        )
+                        let lhs = match self.confined_deref(&sexpr.expr) {
+                            Some(index) => LeftHandSide::Primitive(index),
+                            None => {
+                                self.visit_expr(&sexpr.expr);
+                                LeftHandSide::Deref(0) // the true index will be calculated later
+                            }
+                        };
+                        (lhs, Some(self.tlookup.get_expr_tc_type(expr)), sexpr.star)
                     }
                     _ => unreachable!(),
                 }
@@ -532,7 +542,16 @@ impl<'a> CodeGen<'a> {
             let pos = Some(*p);
             match l {
                 LeftHandSide::Primitive(_) => {
-                    current_func_emitter!(self).emit_store(l, rhs_index, None, None, typ, pos);
+                    let mut emitter = current_func_emitter!(self);
+                    let fkey = self.func_stack.last().unwrap();
+                    emitter.emit_store(
+                        l,
+                        rhs_index,
+                        None,
+                        Some((self.pkg_util.pairs_mut(), *fkey)),
+                        typ,
+                        pos,
+                    );
                 }
                 LeftHandSide::IndexSelExpr(info) => {
                     current_func_emitter!(self).emit_store(
@@ -692,14 +711,7 @@ impl<'a> CodeGen<'a> {
         current_func_emitter!(self).emit_pop(1, None);
     }
 
-    fn gen_func_def(
-        &mut self,
-        tc_type: TCTypeKey, // GosMetadata,
-        fkey: FuncTypeKey,
-        recv: Option<FieldList>,
-        body: &BlockStmt,
-    ) -> FunctionKey {
-        let typ = &self.ast_objs.ftypes[fkey];
+    fn new_func(&mut self, tc_type: TCTypeKey) -> FunctionKey {
         let fmeta = self
             .tlookup
             .meta_from_tc(tc_type, &mut self.objects, self.dummy_gcv);
@@ -710,7 +722,368 @@ impl<'a> CodeGen<'a> {
             self.dummy_gcv,
             FuncFlag::Default,
         );
-        let fkey = *f.as_function();
+        *f.as_function()
+    }
+
+    /// gen_method_keys creates the functions of all the methods before generating
+    /// any code, so that methods can be bound before their bodies are generated
+    fn gen_method_keys(&mut self, files: &Vec<File>) {
+        for f in files.iter() {
+            for d in f.decls.iter() {
+                if let Decl::Func(fdecl) = d {
+                    let decl = &self.ast_objs.fdecls[*fdecl];
+                    if self.is_generic_decl(decl.name) {
+                        continue;
+                    }
+                    if let Some(recv) = &decl.recv {
+                        let tc_type = self.tlookup.get_def_tc_type(decl.name);
+                        let fkey = self.new_func(tc_type);
+                        let field = &self.ast_objs.fields[recv.list[0]];
+                        let name = &self.ast_objs.idents[decl.name].name;
+                        let meta = self.tlookup.get_meta_by_node_id(
+                            field.typ.id(),
+                            self.objects,
+                            self.dummy_gcv,
+                        );
+                        meta.set_method_code(name, fkey, &mut self.objects.metas);
+                    }
+                }
+            }
+        }
+    }
+
+    /// is_generic_decl returns true if the function or method declared by 'name'
+    /// has type parameters or is a method of a generic type
+    fn is_generic_decl(&self, name: IdentKey) -> bool {
+        let ti = self.tlookup.type_info();
+        ti.defs.get(&name).map_or(false, |o| {
+            o.map_or(false, |o| ti.generic_decls.contains_key(&o))
+        })
+    }
+
+    /// gen_instances generates the instances of the generic functions and methods
+    /// declared in 'tcpkg', each with the TypeInfo of checking it with its type args
+    fn gen_instances(&mut self, tcpkg: TCPackageKey) {
+        let mapping = self.instances;
+        for (fi, fkey) in mapping.pkg_instances(tcpkg).iter() {
+            let decl = &self.ast_objs.fdecls[fi.decl];
+            let ti = self.tlookup.swap_type_info(&fi.info);
+            self.gen_func_def(
+                Some(*fkey),
+                fi.sig,
+                decl.typ,
+                decl.recv.clone(),
+                decl.body.as_ref().unwrap(),
+            );
+            self.tlookup.swap_type_info(ti);
+            // a member named like Max[int] names the function in the
+            // disassembly and the traces, the code loads it as a constant
+            if decl.recv.is_none() {
+                let targs: Vec<String> =
+                    fi.targs.iter().map(|t| type_str(t, self.tc_objs)).collect();
+                let name = format!("{}[{}]", self.tc_objs.lobjs[fi.obj].name(), targs.join(","));
+                let cls = GosValue::new_closure(*fkey, &self.objects.functions);
+                self.objects.packages[self.pkg_key].add_member(name, cls);
+            }
+        }
+    }
+
+    /// gen_instance loads the function of the instance of a generic function that
+    /// 'ident' refers to, returns false if it's not an instance
+    fn gen_instance(&mut self, ident: IdentKey, pos: Option<Pos>) -> bool {
+        let ti = self.tlookup.type_info();
+        let fkey = match ti.instances.get(&ident) {
+            Some(inst) => self
+                .instances
+                .get(ti.uses[&ident], &inst.targs, self.tc_objs)
+                .unwrap(),
+            None => return false,
+        };
+        let mut emitter = current_func_emitter!(self);
+        let i = emitter.add_const(None, GosValue::Function(fkey));
+        emitter.emit_literal(ValueType::Function, i.into(), pos);
+        true
+    }
+
+    /// is_instance_expr returns true if 'expr' refers to an instance of a generic
+    /// function
+    fn is_instance_expr(&self, expr: &Expr) -> bool {
+        let ident = match expr {
+            Expr::Ident(i) => *i,
+            Expr::Selector(s) => s.sel,
+            _ => return false,
+        };
+        self.tlookup.type_info().instances.contains_key(&ident)
+    }
+
+    /// is_type_param_ident returns true if 'ident' is a type expression referring
+    /// to a type parameter, which is bound to its type argument in an instance
+    fn is_type_param_ident(&self, expr: &Expr, ident: IdentKey) -> bool {
+        if self.tlookup.try_get_expr_mode(expr) != Some(&OperandMode::TypeExpr)
+            || self.ast_objs.idents[ident].entity_key().is_none()
+        {
+            return false;
+        }
+        let ti = self.tlookup.type_info();
+        match ti.uses.get(&ident) {
+            Some(okey) => {
+                let typ = self.tc_objs.lobjs[*okey].typ().unwrap();
+                self.tc_objs.types[typ]
+                    .try_as_named()
+                    .map_or(true, |n| n.obj() != &Some(*okey))
+            }
+            None => false,
+        }
+    }
+
+    /// gen_embedded_path loads the embedded fields in 'path' from the value of type
+    /// 'typ' on top of the stack, a pointer to the last field is loaded if 'ref_last'
+    fn gen_embedded_path(
+        &mut self,
+        typ: TCTypeKey,
+        path: &[usize],
+        ref_last: bool,
+        pos: Option<usize>,
+    ) {
+        let mut typ = typ;
+        for (i, index) in path.iter().enumerate() {
+            let t = self.tlookup.value_type_from_tc(typ);
+            let op = if ref_last && i == path.len() - 1 {
+                Opcode::REF_STRUCT_FIELD
+            } else {
+                Opcode::LOAD_STRUCT_FIELD
+            };
+            current_func_mut!(self).emit_code_with_type_imm(op, t, *index as OpIndex, pos);
+            typ = self.tlookup.field_tc(typ, *index);
+        }
+    }
+
+    /// method_needs_ref returns true if the method has a pointer receiver but the
+    /// value found through the embedded 'path' is not a pointer
+    fn method_needs_ref(&mut self, recv: TCTypeKey, path: &[usize], name: &str) -> bool {
+        let (base, is_ptr) = self
+            .tlookup
+            .try_deref_tc(self.tlookup.embedded_path_tc(recv, path));
+        if is_ptr || self.tlookup.underlying_value_type_from_tc(base) == ValueType::Interface {
+            return false;
+        }
+        let meta = self
+            .tlookup
+            .meta_from_tc(base, self.objects, self.dummy_gcv);
+        let i = meta.method_index(name, &self.objects.metas);
+        let method = meta.get_method(i, &self.objects.metas);
+        let ptr_recv = method.borrow().pointer_recv;
+        ptr_recv
+    }
+
+    /// gen_bind_method binds the method to the receiver on top of the stack, which is
+    /// found through the embedded 'path' and has already been turned into a pointer
+    /// if method_needs_ref returns true. A pointer is dereferenced for value receivers
+    fn gen_bind_method(&mut self, recv: TCTypeKey, path: &[usize], name: &str, pos: Option<usize>) {
+        let (base, is_ptr) = self
+            .tlookup
+            .try_deref_tc(self.tlookup.embedded_path_tc(recv, path));
+        let meta = self
+            .tlookup
+            .meta_from_tc(base, self.objects, self.dummy_gcv);
+        let t = self.tlookup.value_type_from_tc(base);
+        if self.tlookup.underlying_value_type_from_tc(base) == ValueType::Interface {
+            let i = meta.iface_method_index(name, &self.objects.metas);
+            current_func_mut!(self).emit_code_with_type_imm(
+                Opcode::BIND_INTERFACE_METHOD,
+                t,
+                i,
+                pos,
+            );
+        } else {
+            let i = meta.method_index(name, &self.objects.metas);
+            let method = meta.get_method(i, &self.objects.metas);
+            let (ptr_recv, fkey) = {
+                let m = method.borrow();
+                (m.pointer_recv, m.func.unwrap())
+            };
+            let func = current_func_mut!(self);
+            let t0 = if ptr_recv {
+                ValueType::Pointer
+            } else if is_ptr {
+                func.emit_code_with_type(Opcode::DEREF, ValueType::Pointer, pos);
+                t
+            } else {
+                t
+            };
+            let mi = func.add_const(None, GosValue::Function(fkey));
+            func.emit_code_with_type_imm(Opcode::BIND_METHOD, t0, mi.into(), pos);
+        }
+    }
+
+    /// gen_method_wrapper generates a function which calls the method found through
+    /// the embedded 'path' of the receiver. It's used for method expressions like T.m
+    /// or (*T).m, where the receiver is the first parameter of 'tc_type', and for
+    /// promoted methods, where 'recv' is the receiver of the wrapper
+    fn gen_method_wrapper(
+        &mut self,
+        tc_type: TCTypeKey,
+        recv: Option<TCTypeKey>,
+        path: &[usize],
+        name: &str,
+        pos: Option<usize>,
+    ) -> FunctionKey {
+        let fkey = match recv {
+            // interface methods have no receiver in their metadata
+            Some(r) => {
+                let meta = self
+                    .tlookup
+                    .meta_from_tc(tc_type, &mut self.objects, self.dummy_gcv);
+                let recv_meta = self
+                    .tlookup
+                    .meta_from_tc(r, &mut self.objects, self.dummy_gcv);
+                let sig = self.objects.metas[meta.as_non_ptr()].as_signature().clone();
+                let fmeta = GosMetadata::new_sig(
+                    Some(recv_meta),
+                    sig.params,
+                    sig.results,
+                    sig.variadic,
+                    &mut self.objects.metas,
+                );
+                let f = GosValue::new_function(
+                    self.pkg_key,
+                    fmeta,
+                    self.objects,
+                    self.dummy_gcv,
+                    FuncFlag::Default,
+                );
+                *f.as_function()
+            }
+            None => self.new_func(tc_type),
+        };
+        let (mut params, _) = self.tlookup.get_sig_params_tc_types(tc_type);
+        if let Some(r) = recv {
+            params.insert(0, r);
+        }
+        let results = self.tlookup.get_sig_returns_tc_types(tc_type);
+        let func = &mut self.objects.functions[fkey];
+        for _ in 0..(results.len() + params.len()) {
+            func.add_local(None, None);
+        }
+        self.func_stack.push(fkey);
+        self.func_t_stack.push(tc_type);
+
+        let recv = params[0];
+        let recv_index = results.len() as OpIndex;
+        let recv_t = self.tlookup.value_type_from_tc(recv);
+        let needs_ref = self.method_needs_ref(recv, path, name);
+        current_func_emitter!(self).emit_load(EntIndex::LocalVar(recv_index), None, recv_t, pos);
+        self.gen_embedded_path(recv, path, needs_ref, pos);
+        self.gen_bind_method(recv, path, name, pos);
+
+        let mut emitter = current_func_emitter!(self);
+        emitter.emit_pre_call(pos);
+        for (i, p) in params.iter().enumerate().skip(1) {
+            let t = self.tlookup.value_type_from_tc(*p);
+            emitter.emit_load(EntIndex::LocalVar(recv_index + i as OpIndex), None, t, pos);
+        }
+        emitter.emit_call(CallStyle::Default, false, pos);
+        let count = results.len() as OpIndex;
+        for (i, r) in results.iter().enumerate() {
+            let t = self.tlookup.value_type_from_tc(*r);
+            emitter.emit_store(
+                &LeftHandSide::Primitive(EntIndex::LocalVar(i as OpIndex)),
+                i as OpIndex - count,
+                None,
+                None,
+                t,
+                pos,
+            );
+        }
+        if count > 0 {
+            emitter.emit_pop(count, pos);
+        }
+        emitter.emit_return(None, pos);
+
+        self.func_stack.pop();
+        self.func_t_stack.pop();
+        fkey
+    }
+
+    /// gen_promoted_methods generates wrappers for the methods of 'iface' that are
+    /// promoted from embedded fields of 'typ', so they can be found by name when
+    /// the interface mapping is built.
+    /// The wrappers work for both T and *T receivers as the struct field
+    /// instructions dereference pointers
+    fn gen_promoted_methods(&mut self, iface: TCTypeKey, typ: TCTypeKey) {
+        let (base, _) = self.tlookup.try_deref_tc(typ);
+        if self.tc_objs.types[base].try_as_named().is_none()
+            || self.tlookup.underlying_value_type_from_tc(base) == ValueType::Interface
+        {
+            return;
+        }
+        let meta = self
+            .tlookup
+            .meta_from_tc(base, self.objects, self.dummy_gcv);
+        let iface_detail = self.tc_objs.types[self.tlookup.underlying_tc(iface)]
+            .try_as_interface()
+            .unwrap();
+        for m in iface_detail.all_methods().as_ref().unwrap().iter() {
+            let mobj = &self.tc_objs.lobjs[*m];
+            let name = mobj.name();
+            if meta
+                .get_named_metadate(&self.objects.metas)
+                .0
+                .mapping
+                .contains_key(name)
+            {
+                continue;
+            }
+            if let LookupResult::Entry(okey, indices, _) =
+                lookup_field_or_method(typ, false, mobj.pkg(), name, self.tc_objs)
+            {
+                if indices.len() < 2 {
+                    continue;
+                }
+                let path = &indices[..indices.len() - 1];
+                let tc_type = self.tc_objs.lobjs[okey].typ().unwrap();
+                let fkey = self.gen_method_wrapper(tc_type, Some(typ), path, name, None);
+                meta.add_method(name.clone(), false, &mut self.objects.metas);
+                meta.set_method_code(name, fkey, &mut self.objects.metas);
+            }
+        }
+    }
+
+    fn iface_has_methods(&self, t: TCTypeKey) -> bool {
+        let ut = self.tlookup.underlying_tc(t);
+        let detail = self.tc_objs.types[ut].try_as_interface().unwrap();
+        let methods = detail.all_methods();
+        methods.as_ref().map_or(false, |m| !m.is_empty())
+    }
+
+    /// returns the local a confined pointer points to, if expr is one
+    fn confined_deref(&self, expr: &Expr) -> Option<EntIndex> {
+        let ident = expr.try_as_ident()?;
+        let entity_key = self.ast_objs.idents[*ident].entity_key()?;
+        self.derefs.get(&entity_key).copied()
+    }
+
+    fn get_iface_index(&mut self, i_s: &(TCTypeKey, Option<TCTypeKey>)) -> OpIndex {
+        if let Some(typ) = i_s.1 {
+            self.gen_promoted_methods(i_s.0, typ);
+        }
+        self.iface_mapping
+            .get_index(i_s, &mut self.tlookup, self.objects, self.dummy_gcv)
+    }
+
+    fn gen_func_def(
+        &mut self,
+        func: Option<FunctionKey>,
+        tc_type: TCTypeKey, // GosMetadata,
+        fkey: FuncTypeKey,
+        recv: Option<FieldList>,
+        body: &BlockStmt,
+    ) -> FunctionKey {
+        let typ = &self.ast_objs.ftypes[fkey];
+        let fkey = match func {
+            Some(f) => f,
+            None => self.new_func(tc_type),
+        };
         let mut emitter = Emitter::new(&mut self.objects.functions[fkey]);
         if let Some(fl) = &typ.results {
             emitter.add_params(&fl, self.ast_objs);
@@ -725,6 +1098,7 @@ impl<'a> CodeGen<'a> {
         };
         self.func_stack.push(fkey);
         self.func_t_stack.push(tc_type);
+        self.escapes.push(Escapes::analyze(self.ast_objs, body));
         // process function body
         self.visit_stmt_block(body);
         // it will not be executed if it's redundant
@@ -732,6 +1106,7 @@ impl<'a> CodeGen<'a> {
 
         self.func_stack.pop();
         self.func_t_stack.pop();
+        self.escapes.pop();
         fkey
     }
 
@@ -820,18 +1195,11 @@ impl<'a> CodeGen<'a> {
                 // just ignore conversion if it's nil or types are identical
                 if t1 != ValueType::Nil && !identical(utct0, utct1, self.tc_objs) {
                     let iface_index = match t0 {
-                        ValueType::Interface => {
-                            if t1 != ValueType::Nil {
-                                self.iface_mapping.get_index(
-                                    &(tct0, Some(tct1)),
-                                    &mut self.tlookup,
-                                    self.objects,
-                                    self.dummy_gcv,
-                                )
-                            } else {
-                                0
-                            }
-                        }
+                        ValueType::Interface => match t1 {
+                            ValueType::Nil => 0,
+                            ValueType::Interface => self.get_iface_index(&(tct0, None)),
+                            _ => self.get_iface_index(&(tct0, Some(tct1))),
+                        },
                         _ => 0,
                     };
                     // get the type of slice element if we are converting to or from a slice
@@ -872,18 +1240,46 @@ impl<'a> CodeGen<'a> {
 
     fn gen_map_index(&mut self, expr: &Expr, index: &Expr, comma_ok: bool) {
         let t0 = self.tlookup.get_expr_value_type(expr);
-        let t1 = self.tlookup.get_expr_value_type(index);
         self.visit_expr(expr);
         let pos = Some(expr.pos(&self.ast_objs));
-        if let Some(const_val) = self.tlookup.get_tc_const_value(index.id()) {
-            let (ival, _) = const_val.to_int().int_as_i64();
-            if let Ok(i) = OpIndex::try_from(ival) {
-                current_func_emitter!(self).emit_load_index_imm(i, t0, comma_ok, pos);
-                return;
+        match self.gen_index(expr, index) {
+            (Some(i), _) => current_func_emitter!(self).emit_load_index_imm(i, t0, comma_ok, pos),
+            (None, t1) => {
+                current_func_emitter!(self).emit_load_index(t0, t1.unwrap(), comma_ok, pos)
+            }
+        }
+    }
+
+    /// gen_index returns the index as an immediate if it is a constant int index,
+    /// otherwise it generates the index, converted to the key type if it's an
+    /// interface, and returns its value type
+    fn gen_index(
+        &mut self,
+        container: &Expr,
+        index: &Expr,
+    ) -> (Option<OpIndex>, Option<ValueType>) {
+        let key_tc = self
+            .tlookup
+            .map_key_tc(self.tlookup.get_expr_tc_type(container));
+        // map keys of other types are not GosValue::Int
+        let int_key = key_tc.map_or(true, |k| {
+            self.tlookup.value_type_from_tc(k) == ValueType::Int
+        });
+        if int_key {
+            if let Some(const_val) = self.tlookup.get_tc_const_value(index.id()) {
+                let (ival, _) = const_val.to_int().int_as_i64();
+                if let Ok(i) = OpIndex::try_from(ival) {
+                    return (Some(i), None);
+                }
             }
         }
         self.visit_expr(index);
-        current_func_emitter!(self).emit_load_index(t0, t1, comma_ok, pos);
+        let index_tc = self.tlookup.get_expr_tc_type(index);
+        let pos = index.pos(&self.ast_objs);
+        (
+            None,
+            Some(self.try_cast_to_iface(key_tc, Some(index_tc), -1, pos)),
+        )
     }
 
     fn try_cast_to_iface(
@@ -899,17 +1295,24 @@ impl<'a> CodeGen<'a> {
                 let (cast, typ) = match rhs {
                     Some(t1) => {
                         let vt1 = self.tlookup.underlying_value_type_from_tc(t1);
-                        (vt1 != ValueType::Interface && vt1 != ValueType::Nil, vt1)
+                        let cast = match vt1 {
+                            ValueType::Nil => false,
+                            // the methods are looked up at runtime, it's only
+                            // needed if the methods are different
+                            ValueType::Interface => {
+                                !identical(t0, t1, self.tc_objs) && self.iface_has_methods(t0)
+                            }
+                            _ => true,
+                        };
+                        (cast, vt1)
                     }
                     None => (true, ValueType::Slice), // it must be a variadic parameter
                 };
                 if cast {
-                    let index = self.iface_mapping.get_index(
-                        &(t0, rhs),
-                        &mut self.tlookup,
-                        self.objects,
-                        self.dummy_gcv,
-                    );
+                    let index = match typ {
+                        ValueType::Interface => self.get_iface_index(&(t0, None)),
+                        _ => self.get_iface_index(&(t0, rhs)),
+                    };
                     current_func_emitter!(self).emit_cast(
                         ValueType::Interface,
                         typ,
@@ -961,7 +1364,11 @@ impl<'a> CodeGen<'a> {
 
     fn visit_composite_expr(&mut self, expr: &Expr, tctype: TCTypeKey) {
         match expr {
-            Expr::CompositeLit(clit) => self.gen_composite_literal(clit, tctype),
+            Expr::CompositeLit(clit) => match clit.typ {
+                // the type can be omitted only if it's the same as the element type
+                Some(_) => self.visit_expr(expr),
+                None => self.gen_composite_literal(clit, tctype),
+            },
             _ => self.visit_expr(expr),
         }
         let t = self.tlookup.get_expr_tc_type(expr);
@@ -1079,6 +1486,10 @@ impl<'a> CodeGen<'a> {
         for v in vars.iter() {
             for n in v.names.iter() {
                 let ident = &self.ast_objs.idents[*n];
+                if let Some(cell) = self.kept_vars.get(&ident.name) {
+                    self.objects.packages[pkey].add_shared_member(ident.name.clone(), cell.clone());
+                    continue;
+                }
                 let meta = self
                     .tlookup
                     .gen_def_type_meta(*n, self.objects, self.dummy_gcv);
@@ -1109,14 +1520,27 @@ impl<'a> CodeGen<'a> {
 
         self.pkg_util.gen_imports(tcpkg, current_func_mut!(self));
 
+        self.gen_method_keys(files);
+
         for f in files.iter() {
             for d in f.decls.iter() {
                 self.visit_decl(d)
             }
         }
         for v in vars.iter() {
-            self.gen_def_var(v);
+            let names: Vec<&String> = v
+                .names
+                .iter()
+                .map(|n| &self.ast_objs.idents[*n])
+                .filter(|ident| !ident.is_blank())
+                .map(|ident| &ident.name)
+                .collect();
+            // the kept ones have their values already
+            if names.is_empty() || !names.iter().all(|n| self.kept_vars.contains_key(*n)) {
+                self.gen_def_var(v);
+            }
         }
+        self.gen_instances(tcpkg);
 
         let mut emitter = Emitter::new(&mut self.objects.functions[fkey]);
         emitter.emit_return(Some(index), None);
@@ -1138,10 +1562,17 @@ impl<'a> ExprVisitor for CodeGen<'a> {
     }
 
     fn visit_expr_ident(&mut self, expr: &Expr, ident: &IdentKey) {
+        let p = Some(self.ast_objs.idents[*ident].pos);
+        if self.gen_instance(*ident, p) {
+            return;
+        }
+        if self.is_type_param_ident(expr, *ident) {
+            self.gen_type_meta(expr);
+            return;
+        }
         let index = self.resolve_any_ident(ident, Some(expr));
         let t = self.tlookup.get_use_value_type(*ident);
         let fkey = self.func_stack.last().unwrap();
-        let p = Some(self.ast_objs.idents[*ident].pos);
         current_func_emitter!(self).emit_load(
             index,
             Some((self.pkg_util.pairs_mut(), *fkey)),
@@ -1160,12 +1591,27 @@ impl<'a> ExprVisitor for CodeGen<'a> {
 
     /// Add function as a const and then generate a closure of it
     fn visit_expr_func_lit(&mut self, this: &Expr, flit: &FuncLit) {
+        let confined = self
+            .escapes
+            .last()
+            .map_or(false, |e| e.is_confined_closure(&this.id()));
         let tc_type = self.tlookup.get_node_tc_type(this.id());
-        let fkey = self.gen_func_def(tc_type, flit.typ, None, &flit.body);
+        let fkey = self.gen_func_def(None, tc_type, flit.typ, None, &flit.body);
         let mut emitter = current_func_emitter!(self);
         let i = emitter.add_const(None, GosValue::Function(fkey));
         let pos = Some(flit.body.l_brace);
-        emitter.emit_literal(ValueType::Function, i.into(), pos);
+        // FlagA: the closure doesn't outlive the frame creating it
+        let flag = if confined {
+            Some(ValueType::FlagA)
+        } else {
+            None
+        };
+        current_func_mut!(self).emit_inst(
+            Opcode::LITERAL,
+            [Some(ValueType::Function), flag, None],
+            Some(i.into()),
+            pos,
+        );
     }
 
     fn visit_expr_composit_lit(&mut self, _: &Expr, clit: &CompositeLit) {
@@ -1180,6 +1626,9 @@ impl<'a> ExprVisitor for CodeGen<'a> {
     fn visit_expr_selector(&mut self, this: &Expr, expr: &Expr, ident: &IdentKey) {
         let pos = Some(expr.pos(&self.ast_objs));
         if let Some(key) = self.tlookup.try_get_pkg_key(expr) {
+            if self.gen_instance(*ident, pos) {
+                return;
+            }
             let pkg = self.pkg_util.get_vm_pkg(key);
             let t = self.tlookup.get_use_value_type(*ident);
             let fkey = self.func_stack.last().unwrap();
@@ -1192,48 +1641,55 @@ impl<'a> ExprVisitor for CodeGen<'a> {
             return;
         }
 
-        let (t0, t1) = self.tlookup.get_selection_value_types(this.id());
-        let meta = self
-            .tlookup
-            .get_meta_by_node_id(expr.id(), self.objects, self.dummy_gcv);
+        let sel = self.tlookup.get_selection(this.id()).clone();
+        let recv = sel.recv().unwrap();
         let name = &self.ast_objs.idents[*ident].name;
-        if t1 == ValueType::Closure {
-            if meta
-                .get_underlying(&self.objects.metas)
-                .get_value_type(&self.objects.metas)
-                == ValueType::Interface
-            {
-                let i = meta.iface_method_index(name, &self.objects.metas);
+        match sel.kind() {
+            SelectionKind::FieldVal => {
                 self.visit_expr(expr);
-                current_func_mut!(self).emit_code_with_type_imm(
-                    Opcode::BIND_INTERFACE_METHOD,
-                    meta.get_value_type(&self.objects.metas),
-                    i,
-                    pos,
-                );
-            } else {
-                let i = meta.method_index(name, &self.objects.metas);
-                let method = meta.get_method(i, &self.objects.metas);
-                if method.borrow().pointer_recv {
-                    // desugar
-                    self.visit_expr_unary(this, expr, &Token::AND);
+                self.gen_embedded_path(recv, sel.indices(), false, pos);
+            }
+            SelectionKind::MethodVal => {
+                let path = &sel.indices()[..sel.indices().len() - 1];
+                if self.method_needs_ref(recv, path, name) {
+                    if path.len() == 0 {
+                        // desugar
+                        self.visit_expr_unary(this, expr, &Token::AND);
+                    } else {
+                        self.visit_expr(expr);
+                        self.gen_embedded_path(recv, path, true, pos);
+                    }
                 } else {
                     self.visit_expr(expr);
+                    self.gen_embedded_path(recv, path, false, pos);
                 }
-                let func = current_func_mut!(self);
-                // todo: fix this!!!
-                let mi = func.add_const(None, GosValue::Function(method.borrow().func.unwrap()));
-                func.emit_code_with_type_imm(Opcode::BIND_METHOD, t0, mi.into(), pos);
+                self.gen_bind_method(recv, path, name, pos);
+            }
+            SelectionKind::MethodExpr => {
+                let path = &sel.indices()[..sel.indices().len() - 1];
+                let tc_type = self.tlookup.get_node_tc_type(this.id());
+                let fkey = self.gen_method_wrapper(tc_type, None, path, name, pos);
+                let mut emitter = current_func_emitter!(self);
+                let i = emitter.add_const(None, GosValue::Function(fkey));
+                emitter.emit_literal(ValueType::Function, i.into(), pos);
             }
-        } else {
-            self.visit_expr(expr);
-            let i = meta.field_index(name, &self.objects.metas);
-            current_func_emitter!(self).emit_load_struct_field(i, t0, pos);
         }
     }
 
-    fn visit_expr_index(&mut self, _: &Expr, expr: &Expr, index: &Expr) {
-        self.gen_map_index(expr, index, false);
+    fn visit_expr_index(&mut self, this: &Expr, expr: &Expr, index: &Expr) {
+        match self.tlookup.get_expr_mode(this) {
+            OperandMode::TypeExpr => self.gen_type_meta(this),
+            // instantiation of a generic function
+            _ if self.is_instance_expr(expr) => self.visit_expr(expr),
+            _ => self.gen_map_index(expr, index, false),
+        }
+    }
+
+    fn visit_expr_index_list(&mut self, this: &Expr, expr: &Expr, _: &Vec<Expr>) {
+        match self.tlookup.get_expr_mode(this) {
+            OperandMode::TypeExpr => self.gen_type_meta(this),
+            _ => self.visit_expr(expr),
+        }
     }
 
     fn visit_expr_slice(
@@ -1272,7 +1728,7 @@ impl<'a> ExprVisitor for CodeGen<'a> {
         self.gen_call(func_expr, params, ellipsis, CallStyle::Default);
     }
 
-    fn visit_expr_star(&mut self, _: &Expr, expr: &Expr) {
+    fn visit_expr_star(&mut self, this: &Expr, expr: &Expr) {
         let pos = Some(expr.pos(&self.ast_objs));
         match self.tlookup.get_expr_mode(expr) {
             OperandMode::TypeExpr => {
@@ -1289,6 +1745,11 @@ impl<'a> ExprVisitor for CodeGen<'a> {
                 emitter.emit_load(index, None, ValueType::Metadata, pos);
             }
             _ => {
+                if let Some(index) = self.confined_deref(expr) {
+                    let t = self.tlookup.get_expr_value_type(this);
+                    current_func_emitter!(self).emit_load(index, None, t, pos);
+                    return;
+                }
                 self.visit_expr(expr);
                 let t = self.tlookup.get_expr_value_type(expr);
                 current_func_mut!(self).emit_code_with_type(Opcode::DEREF, t, pos);
@@ -1325,6 +1786,12 @@ impl<'a> ExprVisitor for CodeGen<'a> {
                                     Some(i),
                                     pos,
                                 );
+                            } else if let Some(ptr) =
+                                self.escapes.last().and_then(|e| e.confined_ref(&this.id()))
+                            {
+                                // the pointer is never created, *ptr accesses the local
+                                self.derefs.insert(ptr, index);
+                                current_func_mut!(self).emit_code(Opcode::PUSH_NIL, pos);
                             } else {
                                 let ident = &self.ast_objs.idents[*ikey];
                                 let entity_key = ident.entity_key().unwrap();
@@ -1396,19 +1863,8 @@ impl<'a> ExprVisitor for CodeGen<'a> {
                     }
                     None => {
                         self.visit_expr(&sexpr.expr);
-                        let t0 = self.tlookup.get_meta_by_node_id(
-                            sexpr.expr.id(),
-                            &mut self.objects,
-                            self.dummy_gcv,
-                        );
-                        let name = &self.ast_objs.idents[sexpr.sel].name;
-                        let i = t0.field_index(name, &self.objects.metas);
-                        current_func_mut!(self).emit_code_with_type_imm(
-                            Opcode::REF_STRUCT_FIELD,
-                            t0.get_value_type(&self.objects.metas),
-                            i,
-                            pos,
-                        );
+                        let sel = self.tlookup.get_selection(expr.id()).clone();
+                        self.gen_embedded_path(sel.recv().unwrap(), sel.indices(), true, pos);
                     }
                 },
                 Expr::CompositeLit(clit) => {
@@ -1500,6 +1956,7 @@ impl<'a> ExprVisitor for CodeGen<'a> {
             } else {
                 None
             };
+            let code = code.specialise(t).unwrap_or(code);
             current_func_mut!(self).emit_code_with_type2(code, t, t1, pos);
         }
     }
@@ -1557,6 +2014,9 @@ impl<'a> StmtVisitor for CodeGen<'a> {
                     //handled elsewhere
                 }
                 Spec::Type(ts) => {
+                    if ts.type_params.is_some() {
+                        continue;
+                    }
                     let ident = self.ast_objs.idents[ts.name].clone();
                     let m = self
                         .tlookup
@@ -1582,19 +2042,26 @@ impl<'a> StmtVisitor for CodeGen<'a> {
         if decl.body.is_none() {
             unimplemented!()
         }
+        // generic functions are generated per instance
+        if self.is_generic_decl(decl.name) {
+            return;
+        }
         let tc_type = self.tlookup.get_def_tc_type(decl.name);
         let stmt = decl.body.as_ref().unwrap();
-        let fkey = self.gen_func_def(tc_type, decl.typ, decl.recv.clone(), stmt);
-        let cls = GosValue::new_closure(fkey, &self.objects.functions);
-        // this is a struct method
-        if let Some(self_ident) = &decl.recv {
-            let field = &self.ast_objs.fields[self_ident.list[0]];
+        // this is a struct method, the function is created by gen_method_keys
+        let method = decl.recv.as_ref().map(|recv| {
+            let field = &self.ast_objs.fields[recv.list[0]];
             let name = &self.ast_objs.idents[decl.name].name;
             let meta =
                 self.tlookup
                     .get_meta_by_node_id(field.typ.id(), self.objects, self.dummy_gcv);
-            meta.set_method_code(name, fkey, &mut self.objects.metas);
-        } else {
+            let i = meta.method_index(name, &self.objects.metas);
+            let func = meta.get_method(i, &self.objects.metas).borrow().func;
+            func.unwrap()
+        });
+        let fkey = self.gen_func_def(method, tc_type, decl.typ, decl.recv.clone(), stmt);
+        if method.is_none() {
+            let cls = GosValue::new_closure(fkey, &self.objects.functions);
             let ident = &self.ast_objs.idents[decl.name];
             let pkg = &mut self.objects.packages[self.pkg_key];
             pkg.add_member(ident.name.clone(), cls);
@@ -1801,7 +2268,7 @@ impl<'a> StmtVisitor for CodeGen<'a> {
             let ident = &self.ast_objs.idents[*iexpr.try_as_ident().unwrap()];
             let ident_key = ident.entity.clone().into_key();
             let func = current_func_mut!(self);
-            let index = func.add_local(ident_key);
+            let index = func.add_local(ident_key, Some(ident.name.clone()));
             func.add_local_zero(GosValue::new_nil());
             self.visit_expr(v);
             let func = current_func_mut!(self);
@@ -99,14 +99,17 @@ impl<'a> Emitter<'a> {
             .map(|f| {
                 let names = &o.fields[*f].names;
                 if names.len() == 0 {
-                    self.f.add_local(None);
+                    self.f.add_local(None, None);
                     1
                 } else {
                     names
                         .iter()
                         .map(|n| {
                             let ident = &o.idents[*n];
-                            self.f.add_local(ident.entity.clone().into_key());
+                            self.f.add_local(
+                                ident.entity.clone().into_key(),
+                                Some(ident.name.clone()),
+                            );
                         })
                         .count()
                 }
@@ -1,22 +1,72 @@
 #![allow(dead_code)]
 use super::codegen::CodeGen;
 use super::emit::{CallStyle, Emitter};
+use super::inline;
+use super::instance::InstanceMapping;
 use super::interface::IfaceMapping;
 use super::package::PkgVarPairs;
+use super::peephole;
 use super::types::TypeCache;
 use goscript_parser::ast::Ident;
 use goscript_parser::errors::ErrorList;
 use goscript_parser::objects::Objects as AstObjects;
 use goscript_parser::objects::*;
 use goscript_parser::FileSet;
-use goscript_types::{Config, PackageKey as TCPackageKey, TCObjects, TypeInfo};
+use goscript_types::{
+    Config, EntityType, PackageKey as TCPackageKey, TCObjects, Type, TypeInfo, TypeKey as TCTypeKey,
+};
 use goscript_vm::gc::GcoVec;
 use goscript_vm::instruction::*;
+use goscript_vm::metadata::{GosMetadata, MetadataType};
 use goscript_vm::null_key;
 use goscript_vm::value::*;
 use goscript_vm::vm::ByteCode;
-use std::collections::HashMap;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
 use std::pin::Pin;
+use std::rc::Rc;
+
+/// Kept is what a program keeps of the ones run before it in a session, as
+/// the inputs of a REPL are: the objects, so that the values made by the
+/// earlier programs are still good, the variables of package main, and the
+/// named types, so that the values are of the same types as before.
+pub struct Kept {
+    objects: Option<Pin<Box<VMObjects>>>,
+    ifaces: Vec<(GosMetadata, Option<Rc<Vec<FunctionKey>>>)>,
+    vars: HashMap<String, Rc<RefCell<GosValue>>>,
+    // by the path of the package and the name
+    types: HashMap<String, GosMetadata>,
+    // the vars and types of the program being run, kept if it doesn't panic
+    pending: Option<(
+        HashMap<String, Rc<RefCell<GosValue>>>,
+        HashMap<String, GosMetadata>,
+    )>,
+}
+
+impl Kept {
+    pub fn new() -> Kept {
+        Kept {
+            objects: None,
+            ifaces: vec![],
+            vars: HashMap::new(),
+            types: HashMap::new(),
+            pending: None,
+        }
+    }
+
+    /// ran takes back the bytecode after the program ran, its variables and
+    /// types are kept only if it didn't panic, as the input is dropped then
+    pub fn ran(&mut self, code: ByteCode, ok: bool) {
+        self.objects = Some(code.objects);
+        self.ifaces = code.ifaces;
+        if let Some((vars, types)) = self.pending.take() {
+            if ok {
+                self.vars = vars;
+                self.types = types;
+            }
+        }
+    }
+}
 
 pub struct EntryGen<'a> {
     objects: Pin<Box<VMObjects>>,
@@ -77,12 +127,126 @@ impl<'a> EntryGen<'a> {
         *f.as_function()
     }
 
+    /// named_types returns the named types declared in the packages by the
+    /// path of the package and the name, the generic ones are left out
+    fn named_types(
+        &self,
+        checker_result: &HashMap<TCPackageKey, TypeInfo>,
+    ) -> Vec<(String, TCTypeKey)> {
+        let mut types = vec![];
+        for tcpkg in checker_result.keys() {
+            let pkg = &self.tc_objs.pkgs[*tcpkg];
+            for (name, okey) in self.tc_objs.scopes[*pkg.scope()].elems().iter() {
+                let obj = &self.tc_objs.lobjs[*okey];
+                if !obj.entity_type().is_type_name() {
+                    continue;
+                }
+                if let Some(typ) = obj.typ() {
+                    match &self.tc_objs.types[typ] {
+                        // not an alias
+                        Type::Named(n) if *n.obj() == Some(*okey) && n.tparams().is_none() => {
+                            types.push((format!("{}.{}", pkg.path(), name), typ))
+                        }
+                        _ => {}
+                    }
+                }
+            }
+        }
+        types
+    }
+
+    /// keep_types puts the metadata of the named types of an earlier program in
+    /// the cache, with the methods added since then
+    fn keep_types(&mut self, kept: &Kept, named: &[(String, TCTypeKey)], cache: &mut TypeCache) {
+        for (name, typ) in named.iter() {
+            let meta = match kept.types.get(name) {
+                Some(m) => *m,
+                None => continue,
+            };
+            let metas = &mut self.objects.metas;
+            for key in self.tc_objs.types[*typ]
+                .try_as_named()
+                .unwrap()
+                .methods()
+                .iter()
+            {
+                let mobj = &self.tc_objs.lobjs[*key];
+                let known = match &metas[meta.as_non_ptr()] {
+                    MetadataType::Named(m, _) => m.mapping.contains_key(mobj.name()),
+                    _ => unreachable!(),
+                };
+                // the old ones keep their indices, the code of the earlier
+                // programs refers to them
+                if !known {
+                    meta.add_method(
+                        mobj.name().clone(),
+                        mobj.entity_type().func_has_ptr_recv(),
+                        metas,
+                    );
+                }
+            }
+            cache.insert(*typ, meta);
+        }
+    }
+
     pub fn gen(
+        self,
+        checker_result: &HashMap<TCPackageKey, TypeInfo>,
+        main_pkg: TCPackageKey,
+        main_ident: IdentKey,
+    ) -> ByteCode {
+        self.gen_impl(checker_result, main_pkg, main_ident, None)
+    }
+
+    /// gen_kept generates the program on top of the earlier ones kept, the
+    /// variables and types named in fresh are declared again by it, they are
+    /// not kept
+    pub fn gen_kept(
+        self,
+        checker_result: &HashMap<TCPackageKey, TypeInfo>,
+        main_pkg: TCPackageKey,
+        main_ident: IdentKey,
+        kept: &mut Kept,
+        fresh: &[String],
+    ) -> ByteCode {
+        self.gen_impl(checker_result, main_pkg, main_ident, Some((kept, fresh)))
+    }
+
+    fn gen_impl(
         mut self,
         checker_result: &HashMap<TCPackageKey, TypeInfo>,
         main_pkg: TCPackageKey,
         main_ident: IdentKey,
+        mut kept: Option<(&mut Kept, &[String])>,
     ) -> ByteCode {
+        let mut type_cache: TypeCache = HashMap::new();
+        let mut kept_vars = HashMap::new();
+        let mut named = vec![];
+        if let Some((kept, fresh)) = &mut kept {
+            match kept.objects.take() {
+                Some(objects) => {
+                    self.objects = objects;
+                    self.iface_mapping = IfaceMapping::with_kept(kept.ifaces.clone());
+                }
+                // the values are gone with the objects
+                None => {
+                    kept.vars.clear();
+                    kept.types.clear();
+                }
+            }
+            kept_vars = kept.vars.clone();
+            kept_vars.retain(|name, _| !fresh.contains(name));
+            named = self.named_types(checker_result);
+            let main_path = self.tc_objs.pkgs[main_pkg].path();
+            // the types that refer to a type declared again would be stale
+            let redeclared = fresh
+                .iter()
+                .any(|n| kept.types.contains_key(&format!("{}.{}", main_path, n)));
+            if !redeclared {
+                self.keep_types(kept, &named, &mut type_cache);
+            }
+        }
+        let old_funcs: HashSet<FunctionKey> = self.objects.functions.keys().collect();
         let mut main_pkg_idx = None;
         for (&tcpkg, _) in checker_result.iter() {
             // create vm packages and store the indices
@@ -95,7 +259,16 @@ impl<'a> EntryGen<'a> {
                 main_pkg_idx = Some(index);
             }
         }
-        let mut type_cache: TypeCache = HashMap::new();
+        let mut instances = InstanceMapping::new();
+        instances.add_instances(
+            checker_result,
+            self.tc_objs,
+            &self.pkg_indices,
+            &self.packages,
+            &mut type_cache,
+            &mut self.objects,
+            &mut self.dummy_gcv,
+        );
         let mut pairs = PkgVarPairs::new();
         for (i, (tcpkg, ti)) in checker_result.iter().enumerate() {
             let mut cgen = CodeGen::new(
@@ -106,11 +279,15 @@ impl<'a> EntryGen<'a> {
                 &ti,
                 &mut type_cache,
                 &mut self.iface_mapping,
+                &instances,
                 &self.pkg_indices,
                 &self.packages,
                 self.packages[i],
                 self.blank_ident,
             );
+            if *tcpkg == main_pkg {
+                cgen.keep_vars(kept_vars.clone());
+            }
             cgen.gen_with_files(&ti.ast_files, *tcpkg, i as OpIndex);
             pairs.append_from_util(cgen.pkg_util());
         }
@@ -118,6 +295,36 @@ impl<'a> EntryGen<'a> {
         let entry =
             self.gen_entry_func(self.packages[index as usize], index, main_ident, &mut pairs);
         pairs.patch_index(self.ast_objs, &mut self.objects);
+        let new_funcs: Vec<FunctionKey> = self
+            .objects
+            .functions
+            .keys()
+            .filter(|k| !old_funcs.contains(k))
+            .collect();
+        inline::inline_calls(&mut self.objects, &self.dummy_gcv, &new_funcs);
+        for key in new_funcs.iter() {
+            peephole::optimize(&mut self.objects.functions[*key]);
+        }
+        if let Some((kept, _)) = kept {
+            let main = &self.objects.packages[self.packages[index as usize]];
+            let scope = &self.tc_objs.scopes[*self.tc_objs.pkgs[main_pkg].scope()];
+            let vars = scope
+                .elems()
+                .iter()
+                .filter(|(_, o)| match self.tc_objs.lobjs[**o].entity_type() {
+                    EntityType::Var(_) => true,
+                    _ => false,
+                })
+                .filter_map(|(name, _)| main.shared_member(name).map(|c| (name.clone(), c)))
+                .collect();
+            let mut types = kept.types.clone();
+            for (name, typ) in named.iter() {
+                if let Some(meta) = type_cache.get(typ) {
+                    types.insert(name.clone(), *meta);
+                }
+            }
+            kept.pending = Some((vars, types));
+        }
         ByteCode {
             objects: self.objects,
             packages: self.packages,
@@ -142,7 +349,44 @@ pub fn parse_check_gen(
         &mut goscript_types::Importer::new(&config, fset, pkgs, results, asto, tco, el, 0);
     let key = goscript_types::ImportKey::new(path, "./");
     let main_pkg = importer.import(&key);
+    gen_checked(asto, tco, results, main_pkg, el)
+}
+
+/// parse_check_gen_kept is parse_check_gen of a main package of a single
+/// file from its source, on top of the programs kept, see EntryGen::gen_kept
+pub fn parse_check_gen_kept(
+    name: &str,
+    source: &str,
+    config: &Config,
+    fset: &mut FileSet,
+    el: &ErrorList,
+    kept: &mut Kept,
+    fresh: &[String],
+) -> Result<ByteCode, usize> {
+    let asto = &mut AstObjects::new();
+    let tco = &mut goscript_types::TCObjects::new();
+    let results = &mut HashMap::new();
+    let pkgs = &mut HashMap::new();
 
+    let importer =
+        &mut goscript_types::Importer::new(&config, fset, pkgs, results, asto, tco, el, 0);
+    let main_pkg = importer.import_source(name, source);
+    if el.len() > 0 {
+        return Err(el.len());
+    }
+    let blank_ident = asto.idents.insert(Ident::blank(0));
+    let main_ident = asto.idents.insert(Ident::with_str(0, "main"));
+    let gen = EntryGen::new(asto, tco, blank_ident);
+    Ok(gen.gen_kept(results, main_pkg.unwrap(), main_ident, kept, fresh))
+}
+
+fn gen_checked(
+    asto: &mut AstObjects,
+    tco: &TCObjects,
+    results: &HashMap<TCPackageKey, TypeInfo>,
+    main_pkg: Result<TCPackageKey, ()>,
+    el: &ErrorList,
+) -> Result<ByteCode, usize> {
     if el.len() > 0 {
         Err(el.len())
     } else {
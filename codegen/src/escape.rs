@@ -0,0 +1,427 @@
+//! Escape analysis of the locals of a function.
+//!
+//! Taking the address of a local, or capturing it in a closure, makes it an
+//! upvalue: every call allocates the upvalues of the function, registers them
+//! with the frame and closes them when the frame is dropped. That's only
+//! needed when the pointer or the closure can outlive the frame, the analysis
+//! here finds the cases where they provably can't:
+//! - a pointer defined as `p := &x` (or `var p = &x`) that is only ever
+//! dereferenced, codegen accesses x directly for `*p` and doesn't create p
+//! - a function literal that is only ever called in the function, either
+//! directly or through a variable `f := func...` that is only called, its
+//! closure is not registered with the frame
+//!
+//! The analysis is conservative, anything it doesn't understand escapes.
+
+use std::collections::{HashMap, HashSet};
+
+use goscript_parser::ast::*;
+use goscript_parser::objects::Objects as AstObjects;
+use goscript_parser::objects::*;
+use goscript_parser::token::Token;
+use goscript_parser::visitor::{walk_decl, walk_expr, walk_stmt, ExprVisitor, StmtVisitor};
+
+/// Escapes is the result of the analysis of one function body
+#[derive(Debug, Default)]
+pub struct Escapes {
+    /// the `&x` expressions whose pointer doesn't escape, and the pointer
+    refs: HashMap<NodeId, EntityKey>,
+    /// the function literals whose closure doesn't escape
+    closures: HashSet<NodeId>,
+}
+
+impl Escapes {
+    pub fn analyze(ast_objs: &AstObjects, body: &BlockStmt) -> Escapes {
+        let mut a = Analyzer::new(ast_objs);
+        a.visit_stmt_block(body);
+        a.result()
+    }
+
+    /// returns the pointer variable if the address taken by this expression
+    /// doesn't escape
+    pub fn confined_ref(&self, expr: &NodeId) -> Option<EntityKey> {
+        self.refs.get(expr).copied()
+    }
+
+    pub fn is_confined_closure(&self, flit: &NodeId) -> bool {
+        self.closures.contains(flit)
+    }
+}
+
+struct Analyzer<'a> {
+    ast_objs: &'a AstObjects,
+    /// the number of function literals we are in
+    depth: usize,
+    /// the count of `&ident` and function literals seen inside function
+    /// literals, a closure with those inside is never confined
+    nested: usize,
+    /// the variables used inside a function literal
+    captured: HashSet<EntityKey>,
+    /// the variables used other than as `*v`
+    not_deref: HashSet<EntityKey>,
+    /// the variables used other than as the callee of a call
+    not_called: HashSet<EntityKey>,
+    ref_defs: Vec<(NodeId, EntityKey)>,
+    closure_defs: Vec<(NodeId, Option<EntityKey>)>,
+}
+
+impl<'a> Analyzer<'a> {
+    fn new(ast_objs: &'a AstObjects) -> Analyzer<'a> {
+        Analyzer {
+            ast_objs: ast_objs,
+            depth: 0,
+            nested: 0,
+            captured: HashSet::new(),
+            not_deref: HashSet::new(),
+            not_called: HashSet::new(),
+            ref_defs: vec![],
+            closure_defs: vec![],
+        }
+    }
+
+    fn result(self) -> Escapes {
+        let refs = self
+            .ref_defs
+            .iter()
+            .filter(|(_, p)| !self.captured.contains(p) && !self.not_deref.contains(p))
+            .cloned()
+            .collect();
+        let closures = self
+            .closure_defs
+            .iter()
+            .filter(|(_, f)| {
+                f.map_or(true, |f| {
+                    !self.captured.contains(&f) && !self.not_called.contains(&f)
+                })
+            })
+            .map(|(id, _)| id.clone())
+            .collect();
+        Escapes {
+            refs: refs,
+            closures: closures,
+        }
+    }
+
+    fn entity(&self, ident: &IdentKey) -> Option<EntityKey> {
+        self.ast_objs.idents[*ident].entity_key()
+    }
+
+    fn use_ident(&mut self, ident: &IdentKey, deref: bool, call: bool) {
+        if let Some(e) = self.entity(ident) {
+            if self.depth > 0 {
+                self.captured.insert(e);
+            }
+            if !deref {
+                self.not_deref.insert(e);
+            }
+            if !call {
+                self.not_called.insert(e);
+            }
+        }
+    }
+
+    /// visits a function literal, it's a candidate if it's called right away
+    /// or assigned to var
+    fn func_lit(&mut self, this: &Expr, flit: &FuncLit, candidate: bool, var: Option<EntityKey>) {
+        if self.depth > 0 {
+            self.nested += 1;
+        }
+        let nested = self.nested;
+        self.depth += 1;
+        self.visit_stmt_block(&flit.body);
+        self.depth -= 1;
+        if candidate && self.depth == 0 && nested == self.nested {
+            self.closure_defs.push((this.id(), var));
+        }
+    }
+
+    /// handles `v := rhs` and `var v = rhs`, v itself is not a use
+    fn define(&mut self, var: &IdentKey, rhs: &Expr) {
+        match (self.depth, self.entity(var), rhs) {
+            (0, Some(p), Expr::Unary(u))
+                if u.op == Token::AND && u.expr.try_as_ident().is_some() =>
+            {
+                self.ref_defs.push((rhs.id(), p));
+                self.visit_expr(rhs);
+            }
+            (_, Some(f), Expr::FuncLit(flit)) => self.func_lit(rhs, flit, true, Some(f)),
+            _ => self.visit_expr(rhs),
+        }
+    }
+
+    /// the call of a go or defer statement may run after the frame is gone
+    fn deferred_call(&mut self, call: &Expr) {
+        match call {
+            Expr::Call(c) => {
+                self.visit_expr(&c.func);
+                for arg in c.args.iter() {
+                    self.visit_expr(arg);
+                }
+            }
+            _ => self.visit_expr(call),
+        }
+    }
+
+    fn visit_opt_expr(&mut self, expr: &Option<Expr>) {
+        if let Some(e) = expr {
+            self.visit_expr(e);
+        }
+    }
+
+    fn visit_opt_stmt(&mut self, stmt: &Option<Stmt>) {
+        if let Some(s) = stmt {
+            self.visit_stmt(s);
+        }
+    }
+}
+
+impl<'a> ExprVisitor for Analyzer<'a> {
+    type Result = ();
+
+    fn visit_expr(&mut self, expr: &Expr) {
+        walk_expr(self, expr)
+    }
+
+    fn visit_expr_ident(&mut self, _: &Expr, ident: &IdentKey) {
+        self.use_ident(ident, false, false);
+    }
+
+    fn visit_expr_ellipsis(&mut self, _: &Expr, els: &Option<Expr>) {
+        self.visit_opt_expr(els);
+    }
+
+    fn visit_expr_basic_lit(&mut self, _: &Expr, _: &BasicLit) {}
+
+    fn visit_expr_func_lit(&mut self, this: &Expr, flit: &FuncLit) {
+        self.func_lit(this, flit, false, None);
+    }
+
+    fn visit_expr_composit_lit(&mut self, _: &Expr, clit: &CompositeLit) {
+        for e in clit.elts.iter() {
+            self.visit_expr(e);
+        }
+    }
+
+    fn visit_expr_paren(&mut self, _: &Expr, expr: &Expr) {
+        self.visit_expr(expr);
+    }
+
+    fn visit_expr_selector(&mut self, _: &Expr, expr: &Expr, _: &IdentKey) {
+        self.visit_expr(expr);
+    }
+
+    fn visit_expr_index(&mut self, _: &Expr, expr: &Expr, index: &Expr) {
+        self.visit_expr(expr);
+        self.visit_expr(index);
+    }
+
+    fn visit_expr_index_list(&mut self, _: &Expr, expr: &Expr, _: &Vec<Expr>) {
+        self.visit_expr(expr);
+    }
+
+    fn visit_expr_slice(
+        &mut self,
+        _: &Expr,
+        expr: &Expr,
+        low: &Option<Expr>,
+        high: &Option<Expr>,
+        max: &Option<Expr>,
+    ) {
+        self.visit_expr(expr);
+        self.visit_opt_expr(low);
+        self.visit_opt_expr(high);
+        self.visit_opt_expr(max);
+    }
+
+    fn visit_expr_type_assert(&mut self, _: &Expr, expr: &Expr, _: &Option<Expr>) {
+        self.visit_expr(expr);
+    }
+
+    fn visit_expr_call(&mut self, _: &Expr, func: &Expr, args: &Vec<Expr>, _: bool) {
+        match func {
+            Expr::Ident(ident) => self.use_ident(ident, false, true),
+            Expr::FuncLit(flit) => self.func_lit(func, flit, true, None),
+            _ => self.visit_expr(func),
+        }
+        for arg in args.iter() {
+            self.visit_expr(arg);
+        }
+    }
+
+    fn visit_expr_star(&mut self, _: &Expr, expr: &Expr) {
+        match expr {
+            Expr::Ident(ident) => self.use_ident(ident, true, false),
+            _ => self.visit_expr(expr),
+        }
+    }
+
+    fn visit_expr_unary(&mut self, _: &Expr, expr: &Expr, op: &Token) {
+        if op == &Token::AND && self.depth > 0 && expr.try_as_ident().is_some() {
+            self.nested += 1;
+        }
+        self.visit_expr(expr);
+    }
+
+    fn visit_expr_binary(&mut self, _: &Expr, left: &Expr, _: &Token, right: &Expr) {
+        self.visit_expr(left);
+        self.visit_expr(right);
+    }
+
+    fn visit_expr_key_value(&mut self, _: &Expr, key: &Expr, val: &Expr) {
+        self.visit_expr(key);
+        self.visit_expr(val);
+    }
+
+    fn visit_expr_array_type(&mut self, _: &Expr, _: &Option<Expr>, _: &Expr) {}
+
+    fn visit_expr_struct_type(&mut self, _: &Expr, _: &StructType) {}
+
+    fn visit_expr_func_type(&mut self, _: &Expr, _: &FuncTypeKey) {}
+
+    fn visit_expr_interface_type(&mut self, _: &Expr, _: &InterfaceType) {}
+
+    fn visit_map_type(&mut self, _: &Expr, _: &Expr, _: &Expr, _: &Expr) {}
+
+    fn visit_chan_type(&mut self, _: &Expr, _: &Expr, _: &ChanDir) {}
+
+    fn visit_bad_expr(&mut self, _: &Expr, _: &BadExpr) {}
+}
+
+impl<'a> StmtVisitor for Analyzer<'a> {
+    type Result = ();
+
+    fn visit_stmt(&mut self, stmt: &Stmt) {
+        walk_stmt(self, stmt)
+    }
+
+    fn visit_decl(&mut self, decl: &Decl) {
+        walk_decl(self, decl)
+    }
+
+    fn visit_stmt_decl_gen(&mut self, gdecl: &GenDecl) {
+        for s in gdecl.specs.iter() {
+            if let Spec::Value(vs) = &self.ast_objs.specs[*s] {
+                if vs.names.len() == 1 && vs.values.len() == 1 {
+                    self.define(&vs.names[0], &vs.values[0]);
+                } else {
+                    for v in vs.values.iter() {
+                        self.visit_expr(v);
+                    }
+                }
+            }
+        }
+    }
+
+    fn visit_stmt_decl_func(&mut self, _: &FuncDeclKey) {}
+
+    fn visit_stmt_labeled(&mut self, lstmt: &LabeledStmtKey) {
+        let stmt = &self.ast_objs.l_stmts[*lstmt].stmt;
+        self.visit_stmt(stmt);
+    }
+
+    fn visit_stmt_send(&mut self, sstmt: &SendStmt) {
+        self.visit_expr(&sstmt.chan);
+        self.visit_expr(&sstmt.val);
+    }
+
+    fn visit_stmt_incdec(&mut self, idcstmt: &IncDecStmt) {
+        self.visit_expr(&idcstmt.expr);
+    }
+
+    fn visit_stmt_assign(&mut self, astmt: &AssignStmtKey) {
+        let astmt = &self.ast_objs.a_stmts[*astmt];
+        // a single variable defined with := is always a new one
+        if astmt.token == Token::DEFINE && astmt.lhs.len() == 1 && astmt.rhs.len() == 1 {
+            if let Expr::Ident(ident) = &astmt.lhs[0] {
+                self.define(ident, &astmt.rhs[0]);
+                return;
+            }
+        }
+        for e in astmt.lhs.iter().chain(astmt.rhs.iter()) {
+            self.visit_expr(e);
+        }
+    }
+
+    fn visit_stmt_go(&mut self, gostmt: &GoStmt) {
+        self.deferred_call(&gostmt.call);
+    }
+
+    fn visit_stmt_defer(&mut self, dstmt: &DeferStmt) {
+        self.deferred_call(&dstmt.call);
+    }
+
+    fn visit_stmt_return(&mut self, rstmt: &ReturnStmt) {
+        for e in rstmt.results.iter() {
+            self.visit_expr(e);
+        }
+    }
+
+    fn visit_stmt_branch(&mut self, _: &BranchStmt) {}
+
+    fn visit_stmt_block(&mut self, bstmt: &BlockStmt) {
+        for s in bstmt.list.iter() {
+            self.visit_stmt(s);
+        }
+    }
+
+    fn visit_stmt_if(&mut self, ifstmt: &IfStmt) {
+        self.visit_opt_stmt(&ifstmt.init);
+        self.visit_expr(&ifstmt.cond);
+        self.visit_stmt_block(&ifstmt.body);
+        self.visit_opt_stmt(&ifstmt.els);
+    }
+
+    fn visit_stmt_case(&mut self, cclause: &CaseClause) {
+        if let Some(list) = &cclause.list {
+            for e in list.iter() {
+                self.visit_expr(e);
+            }
+        }
+        for s in cclause.body.iter() {
+            self.visit_stmt(s);
+        }
+    }
+
+    fn visit_stmt_switch(&mut self, sstmt: &SwitchStmt) {
+        self.visit_opt_stmt(&sstmt.init);
+        self.visit_opt_expr(&sstmt.tag);
+        self.visit_stmt_block(&sstmt.body);
+    }
+
+    fn visit_stmt_type_switch(&mut self, tstmt: &TypeSwitchStmt) {
+        self.visit_opt_stmt(&tstmt.init);
+        self.visit_stmt(&tstmt.assign);
+        self.visit_stmt_block(&tstmt.body);
+    }
+
+    fn visit_stmt_comm(&mut self, cclause: &CommClause) {
+        self.visit_opt_stmt(&cclause.comm);
+        for s in cclause.body.iter() {
+            self.visit_stmt(s);
+        }
+    }
+
+    fn visit_stmt_select(&mut self, sstmt: &SelectStmt) {
+        self.visit_stmt_block(&sstmt.body);
+    }
+
+    fn visit_stmt_for(&mut self, fstmt: &ForStmt) {
+        self.visit_opt_stmt(&fstmt.init);
+        self.visit_opt_expr(&fstmt.cond);
+        self.visit_opt_stmt(&fstmt.post);
+        self.visit_stmt_block(&fstmt.body);
+    }
+
+    fn visit_stmt_range(&mut self, rstmt: &RangeStmt) {
+        self.visit_opt_expr(&rstmt.key);
+        self.visit_opt_expr(&rstmt.val);
+        self.visit_expr(&rstmt.expr);
+        self.visit_stmt_block(&rstmt.body);
+    }
+
+    fn visit_empty_stmt(&mut self, _: &EmptyStmt) {}
+
+    fn visit_bad_stmt(&mut self, _: &BadStmt) {}
+
+    fn visit_bad_decl(&mut self, _: &BadDecl) {}
+}
@@ -0,0 +1,380 @@
+//! The inliner replaces calls of small functions with the code of the
+//! functions, so that one-line getters and arithmetic helpers don't pay for
+//! PRE_CALL, CALL and RETURN. It runs once the code of all the functions is
+//! generated, before the peephole optimizer, and only inlines:
+//! - calls whose target is known, that is package functions loaded with
+//! LOAD_PKG_FIELD and methods bound with BIND_METHOD, called normally
+//! - leaf functions, which don't call anything and so are not recursive
+//! - functions that are not variadic and have no defer, recover, closures,
+//! upvalues or statements that leave values on the stack, like switch
+//!
+//! The results, params and vars of the callee become locals of the caller,
+//! the arguments are stored to them and a RETURN jumps to where the results
+//! are pushed. Inlined instructions keep their positions in the callee, and
+//! remember the position of the call, which panic traces print as the frame
+//! the call would have had.
+
+use std::collections::HashMap;
+
+use super::peephole::{compact, from_items, to_items, Item};
+
+use goscript_vm::gc::GcoVec;
+use goscript_vm::instruction::*;
+use goscript_vm::metadata::GosMetadata;
+use goscript_vm::objects::{u64_to_key, FuncFlag};
+use goscript_vm::value::*;
+
+/// the most instructions a function can have to be inlined, not counting
+/// the RETURN at the end
+const MAX_SIZE: usize = 16;
+
+struct Inlinee {
+    items: Vec<Item>,
+    consts: Vec<GosValue>,
+    // the receiver is the first param of a method
+    param_types: Vec<ValueType>,
+    ret_types: Vec<ValueType>,
+    // the results that can be read before being set, they are zeroed
+    named_rets: Vec<(OpIndex, GosValue)>,
+    // the zero values and names of all the locals, results and params first
+    zeros: Vec<GosValue>,
+    names: Vec<Option<String>>,
+}
+
+impl Inlinee {
+    fn new(func: &FunctionVal, objs: &VMObjects, gcv: &GcoVec) -> Option<Inlinee> {
+        if func.flag != FuncFlag::Default || !func.up_ptrs.is_empty() {
+            return None;
+        }
+        let sig = objs.metas[func.meta.as_non_ptr()].as_signature();
+        if sig.variadic.is_some() {
+            return None;
+        }
+        let items = to_items(func)?;
+        if items.len() > MAX_SIZE + 1 || !items.iter().all(|x| Inlinee::can_inline(func, x)) {
+            return None;
+        }
+        let params: Vec<&GosMetadata> = sig.recv.iter().chain(sig.params.iter()).collect();
+        let rets = func.ret_count();
+        let count = rets + params.len() + func.local_count();
+        let named_rets = (0..rets)
+            .filter(|i| func.local_name(*i as OpIndex).is_some())
+            .map(|i| (i as OpIndex, func.ret_zeros[i].clone()))
+            .collect();
+        Some(Inlinee {
+            items: items,
+            consts: func.consts.clone(),
+            param_types: params
+                .iter()
+                .map(|m| m.get_value_type(&objs.metas))
+                .collect(),
+            ret_types: sig
+                .results
+                .iter()
+                .map(|m| m.get_value_type(&objs.metas))
+                .collect(),
+            named_rets: named_rets,
+            zeros: func
+                .ret_zeros
+                .iter()
+                .cloned()
+                .chain(params.iter().map(|m| m.zero_val(&objs.metas, gcv)))
+                .chain(func.local_zeros.iter().cloned())
+                .collect(),
+            names: (0..count)
+                .map(|i| func.local_name(i as OpIndex).map(|x| x.to_string()))
+                .collect(),
+        })
+    }
+
+    fn can_inline(func: &FunctionVal, item: &Item) -> bool {
+        match item.op() {
+            Opcode::PUSH_CONST
+            | Opcode::PUSH_NIL
+            | Opcode::PUSH_FALSE
+            | Opcode::PUSH_TRUE
+            | Opcode::PUSH_IMM
+            | Opcode::POP
+            | Opcode::LOAD_LOCAL
+            | Opcode::STORE_LOCAL
+            | Opcode::LOAD_INDEX
+            | Opcode::STORE_INDEX
+            | Opcode::LOAD_INDEX_IMM
+            | Opcode::STORE_INDEX_IMM
+            | Opcode::LOAD_STRUCT_FIELD
+            | Opcode::STORE_STRUCT_FIELD
+            | Opcode::LOAD_PKG_FIELD
+            | Opcode::STORE_PKG_FIELD
+            | Opcode::LOAD_FIELD
+            | Opcode::STORE_FIELD
+            | Opcode::STORE_DEREF
+            | Opcode::CAST
+            | Opcode::ADD
+            | Opcode::SUB
+            | Opcode::MUL
+            | Opcode::QUO
+            | Opcode::REM
+            | Opcode::AND
+            | Opcode::OR
+            | Opcode::XOR
+            | Opcode::SHL
+            | Opcode::SHR
+            | Opcode::AND_NOT
+            | Opcode::UNARY_ADD
+            | Opcode::UNARY_SUB
+            | Opcode::UNARY_XOR
+            | Opcode::REF_LOCAL
+            | Opcode::REF_SLICE_MEMBER
+            | Opcode::REF_STRUCT_FIELD
+            | Opcode::REF_PKG_MEMBER
+            | Opcode::DEREF
+            | Opcode::NOT
+            | Opcode::EQL
+            | Opcode::LSS
+            | Opcode::GTR
+            | Opcode::NEQ
+            | Opcode::LEQ
+            | Opcode::GEQ
+            | Opcode::JUMP
+            | Opcode::JUMP_IF
+            | Opcode::JUMP_IF_NOT
+            | Opcode::SLICE
+            | Opcode::SLICE_FULL
+            | Opcode::NEW
+            | Opcode::MAKE
+            | Opcode::LEN
+            | Opcode::CAP
+            | Opcode::PANIC
+            | Opcode::ASSERT => true,
+            Opcode::RETURN => item.inst.t0() == ValueType::Zero,
+            // a closure would refer to the frame of the callee
+            Opcode::LITERAL => match func.const_val(item.inst.imm()) {
+                GosValue::Function(_) => false,
+                _ => true,
+            },
+            _ => false,
+        }
+    }
+}
+
+/// a call to inline, the indices of its items in the caller
+struct CallSite {
+    closure: usize,
+    pre_call: usize,
+    call: usize,
+    callee: FunctionKey,
+}
+
+/// inline_calls inlines the calls among the functions of keys, the ones just
+/// generated, the others are optimized already
+pub fn inline_calls(objs: &mut VMObjects, gcv: &GcoVec, keys: &[FunctionKey]) {
+    let inlinees: HashMap<FunctionKey, Inlinee> = keys
+        .iter()
+        .filter_map(|k| Inlinee::new(&objs.functions[*k], objs, gcv).map(|x| (*k, x)))
+        .collect();
+    if inlinees.is_empty() {
+        return;
+    }
+    for &key in keys.iter() {
+        let func = &objs.functions[key];
+        // the locals of a package ctor are the package vars, the inlined
+        // results would be taken for one more
+        if func.is_ctor() {
+            continue;
+        }
+        let items = match to_items(func) {
+            Some(items) => items,
+            None => continue,
+        };
+        let sites = call_sites(objs, func, &items, &inlinees);
+        if !sites.is_empty() {
+            inline_sites(&mut objs.functions[key], items, &sites, &inlinees);
+        }
+    }
+}
+
+/// returns the function a closure loaded or bound by the item runs
+fn call_target(objs: &VMObjects, func: &FunctionVal, item: &Item) -> Option<FunctionKey> {
+    match item.op() {
+        Opcode::LOAD_PKG_FIELD => {
+            let pkg = objs.packages.get(u64_to_key(item.pkg?.get_u64()))?;
+            // vars of function types are nil until the package is initialized
+            let member = pkg.member(item.inst.imm());
+            match &*member {
+                GosValue::Closure(c) => c.0.borrow().func,
+                _ => None,
+            }
+        }
+        Opcode::BIND_METHOD => match func.const_val(item.inst.imm()) {
+            GosValue::Function(f) => Some(*f),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+fn call_sites(
+    objs: &VMObjects,
+    func: &FunctionVal,
+    items: &[Item],
+    inlinees: &HashMap<FunctionKey, Inlinee>,
+) -> Vec<CallSite> {
+    let mut sites = vec![];
+    // the PRE_CALLs waiting for their CALLs
+    let mut pending: Vec<Option<CallSite>> = vec![];
+    for (i, item) in items.iter().enumerate() {
+        match item.op() {
+            Opcode::PRE_CALL => pending.push(
+                i.checked_sub(1)
+                    .and_then(|c| Some((c, call_target(objs, func, &items[c])?)))
+                    .filter(|(_, f)| inlinees.contains_key(f))
+                    .map(|(c, f)| CallSite {
+                        closure: c,
+                        pre_call: i,
+                        call: 0,
+                        callee: f,
+                    }),
+            ),
+            Opcode::CALL => {
+                // go and defer calls have a call style, variadic calls FlagA in t1
+                let plain = item.inst.t0() == ValueType::Zero && item.inst.t1() != ValueType::FlagA;
+                if let Some(Some(mut site)) = pending.pop() {
+                    if plain {
+                        site.call = i;
+                        sites.push(site);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    sites
+}
+
+fn inline_sites(
+    func: &mut FunctionVal,
+    items: Vec<Item>,
+    sites: &[CallSite],
+    inlinees: &HashMap<FunctionKey, Inlinee>,
+) {
+    let calls: HashMap<usize, &CallSite> = sites.iter().map(|s| (s.call, s)).collect();
+    // the callees share their locals in a caller, as the calls can't overlap
+    let mut bases: HashMap<FunctionKey, OpIndex> = HashMap::new();
+    let mut out: Vec<Item> = Vec::with_capacity(items.len());
+    // the new indices of the items of the caller, for the jumps
+    let mut new_index = Vec::with_capacity(items.len() + 1);
+    let mut from_caller = vec![];
+    for (i, item) in items.iter().enumerate() {
+        new_index.push(out.len());
+        match calls.get(&i) {
+            Some(site) => {
+                let callee = &inlinees[&site.callee];
+                let base = *bases.entry(site.callee).or_insert_with(|| {
+                    let base = func.local_count() + func.param_count() + func.ret_count();
+                    for (zero, name) in callee.zeros.iter().zip(callee.names.iter()) {
+                        func.add_local(None, name.clone());
+                        func.add_local_zero(zero.clone());
+                    }
+                    base as OpIndex
+                });
+                expand(func, callee, base, item, &mut out);
+                from_caller.resize(out.len(), false);
+            }
+            None => {
+                let mut item = *item;
+                item.removed = sites.iter().any(|s| s.closure == i || s.pre_call == i);
+                out.push(item);
+                from_caller.push(true);
+            }
+        }
+    }
+    new_index.push(out.len());
+    for (item, remap) in out.iter_mut().zip(from_caller.iter()) {
+        if let (Some(t), true) = (item.target, remap) {
+            item.target = Some(new_index[t]);
+        }
+    }
+    compact(&mut out);
+    let (code, pos, calls) = from_items(&out);
+    func.replace_code(code, pos);
+    func.set_inlined_calls(calls);
+}
+
+/// expand pushes the code replacing the CALL of an inlined call
+fn expand(
+    func: &mut FunctionVal,
+    callee: &Inlinee,
+    base: OpIndex,
+    call: &Item,
+    out: &mut Vec<Item>,
+) {
+    let ret_count = callee.ret_types.len() as OpIndex;
+    let param_count = callee.param_types.len() as OpIndex;
+    let store = |t: ValueType, rhs: OpIndex, index: OpIndex| {
+        let mut inst = Instruction::new(Opcode::STORE_LOCAL, Some(t), None, None, None);
+        inst.set_imm824(rhs, index);
+        Item::new(inst, call.pos)
+    };
+    let pop = |n: OpIndex| {
+        Item::new(
+            Instruction::new(Opcode::POP, None, None, None, Some(n)),
+            call.pos,
+        )
+    };
+
+    // the arguments are on the stack, the receiver being the first
+    for (i, t) in callee.param_types.iter().enumerate() {
+        let i = i as OpIndex;
+        out.push(store(*t, i - param_count, base + ret_count + i));
+    }
+    if param_count > 0 {
+        out.push(pop(param_count));
+    }
+    for (i, zero) in callee.named_rets.iter() {
+        let t = callee.ret_types[*i as usize];
+        let c: OpIndex = func.add_const(None, zero.clone()).into();
+        let push = Instruction::new(Opcode::PUSH_CONST, Some(t), None, None, Some(c));
+        out.push(Item::new(push, call.pos));
+        out.push(store(t, -1, base + i));
+        out.push(pop(1));
+    }
+
+    let begin = out.len();
+    let end = begin + callee.items.len();
+    for item in callee.items.iter() {
+        let mut item = *item;
+        item.call = call.pos;
+        item.target = item.target.map(|t| begin + t);
+        let inst = &mut item.inst;
+        match inst.op() {
+            Opcode::LOAD_LOCAL => inst.set_imm(inst.imm() + base),
+            Opcode::REF_LOCAL if inst.imm() >= 0 => inst.set_imm(inst.imm() + base),
+            Opcode::STORE_LOCAL => {
+                let (rhs, index) = inst.imm824();
+                inst.set_imm824(rhs, index + base);
+            }
+            Opcode::PUSH_CONST | Opcode::LITERAL => {
+                let val = callee.consts[inst.imm() as usize].clone();
+                inst.set_imm(func.add_const(None, val).into());
+            }
+            Opcode::RETURN => {
+                *inst = Instruction::new(Opcode::JUMP, None, None, None, Some(0));
+                item.target = Some(end);
+            }
+            _ => {}
+        }
+        out.push(item);
+    }
+
+    // the results are left on the stack, as a call does
+    for (i, t) in callee.ret_types.iter().enumerate() {
+        let load = Instruction::new(
+            Opcode::LOAD_LOCAL,
+            Some(*t),
+            None,
+            None,
+            Some(base + i as OpIndex),
+        );
+        out.push(Item::new(load, call.pos));
+    }
+}
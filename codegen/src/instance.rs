@@ -0,0 +1,102 @@
+use super::types::{TypeCache, TypeLookup};
+use goscript_types::{
+    identical, FuncInstance, ObjKey, PackageKey as TCPackageKey, TCObjects, TypeInfo,
+    TypeKey as TCTypeKey,
+};
+use goscript_vm::gc::GcoVec;
+use goscript_vm::instruction::OpIndex;
+use goscript_vm::objects::{FunctionKey, PackageKey, VMObjects};
+use goscript_vm::value::*;
+use std::collections::HashMap;
+
+/// InstanceMapping maps the instances of generic functions, and of methods of
+/// generic types, to the functions generated for them. Generics are
+/// monomorphised: every instance is generated like a normal function from
+/// the result of checking its body with the type arguments.
+pub struct InstanceMapping<'a> {
+    funcs: HashMap<ObjKey, Vec<(&'a Vec<TCTypeKey>, FunctionKey)>>,
+    // instances to be generated by the package declaring them
+    pkg_instances: HashMap<TCPackageKey, Vec<(&'a FuncInstance, FunctionKey)>>,
+}
+
+impl<'a> InstanceMapping<'a> {
+    pub fn new() -> InstanceMapping<'a> {
+        InstanceMapping {
+            funcs: HashMap::new(),
+            pkg_instances: HashMap::new(),
+        }
+    }
+
+    /// add_instances creates the functions of all the instances before generating
+    /// any code, instances requested by more than one package are generated once
+    pub fn add_instances(
+        &mut self,
+        checker_result: &'a HashMap<TCPackageKey, TypeInfo>,
+        tc_objs: &'a TCObjects,
+        pkg_indices: &HashMap<TCPackageKey, OpIndex>,
+        packages: &Vec<PackageKey>,
+        type_cache: &mut TypeCache,
+        objs: &mut VMObjects,
+        dummy_gcv: &mut GcoVec,
+    ) {
+        for ti in checker_result.values() {
+            for fi in ti.func_instances.iter() {
+                if self.get(fi.obj, &fi.targs, tc_objs).is_some() {
+                    continue;
+                }
+                let tcpkg = tc_objs.lobjs[fi.obj].pkg().unwrap();
+                let mut lookup = TypeLookup::new(tc_objs, &fi.info, type_cache);
+                let fmeta = lookup.meta_from_tc(fi.sig, objs, dummy_gcv);
+                let f = GosValue::new_function(
+                    packages[pkg_indices[&tcpkg] as usize],
+                    fmeta,
+                    objs,
+                    dummy_gcv,
+                    FuncFlag::Default,
+                );
+                let fkey = *f.as_function();
+                let sig = tc_objs.types[fi.sig].try_as_signature().unwrap();
+                if let Some(recv) = sig.recv() {
+                    let recv_type = tc_objs.lobjs[*recv].typ().unwrap();
+                    let meta = lookup.meta_from_tc(recv_type, objs, dummy_gcv);
+                    let name = tc_objs.lobjs[fi.obj].name();
+                    meta.set_method_code(name, fkey, &mut objs.metas);
+                }
+                self.funcs
+                    .entry(fi.obj)
+                    .or_insert(vec![])
+                    .push((&fi.targs, fkey));
+                self.pkg_instances
+                    .entry(tcpkg)
+                    .or_insert(vec![])
+                    .push((fi, fkey));
+            }
+        }
+    }
+
+    pub fn get(
+        &self,
+        obj: ObjKey,
+        targs: &[TCTypeKey],
+        tc_objs: &TCObjects,
+    ) -> Option<FunctionKey> {
+        self.funcs.get(&obj).and_then(|v| {
+            v.iter().find_map(|(ta, fkey)| {
+                let same = ta.len() == targs.len()
+                    && ta
+                        .iter()
+                        .zip(targs.iter())
+                        .all(|(a, b)| identical(*a, *b, tc_objs));
+                if same {
+                    Some(*fkey)
+                } else {
+                    None
+                }
+            })
+        })
+    }
+
+    pub fn pkg_instances(&self, pkg: TCPackageKey) -> &[(&'a FuncInstance, FunctionKey)] {
+        self.pkg_instances.get(&pkg).map_or(&[], |v| &v[..])
+    }
+}
@@ -11,28 +11,35 @@ use std::collections::HashMap;
 use std::rc::Rc;
 
 pub struct IfaceMapping {
+    // the ones of the earlier programs of a session, they come first
+    kept: Vec<(GosMetadata, Option<Rc<Vec<FunctionKey>>>)>,
     ifaces: Vec<(GosMetadata, Option<Vec<Rc<RefCell<MethodDesc>>>>)>,
     iface_indices: HashMap<(TCTypeKey, Option<TCTypeKey>), OpIndex>,
 }
 
 impl IfaceMapping {
     pub fn new() -> IfaceMapping {
+        IfaceMapping::with_kept(vec![])
+    }
+
+    /// with_kept makes a mapping that goes on from the result of another one
+    pub fn with_kept(kept: Vec<(GosMetadata, Option<Rc<Vec<FunctionKey>>>)>) -> IfaceMapping {
         IfaceMapping {
+            kept: kept,
             ifaces: vec![],
             iface_indices: HashMap::new(),
         }
     }
 
     pub fn into_result(self) -> Vec<(GosMetadata, Option<Rc<Vec<FunctionKey>>>)> {
-        self.ifaces
-            .into_iter()
-            .map(|(meta, method)| {
-                (
-                    meta,
-                    method.map(|m| Rc::new(m.iter().map(|x| x.borrow().func.unwrap()).collect())),
-                )
-            })
-            .collect()
+        let mut result = self.kept;
+        result.extend(self.ifaces.into_iter().map(|(meta, method)| {
+            (
+                meta,
+                method.map(|m| Rc::new(m.iter().map(|x| x.borrow().func.unwrap()).collect())),
+            )
+        }));
+        result
     }
 
     pub fn get_index(
@@ -46,7 +53,7 @@ impl IfaceMapping {
             return *i;
         }
         let mapping = IfaceMapping::get_iface_info(i_s, lookup, objs, dummy_gcv);
-        let index = self.ifaces.len() as OpIndex;
+        let index = (self.kept.len() + self.ifaces.len()) as OpIndex;
         self.ifaces.push(mapping);
         self.iface_indices.insert(*i_s, index);
         index
@@ -1,7 +1,11 @@
 mod branch;
 mod emit;
+mod escape;
+mod inline;
+mod instance;
 mod interface;
 mod package;
+mod peephole;
 mod types;
 
 pub mod codegen;
@@ -135,7 +135,9 @@ impl<'a> PkgUtil<'a> {
                                 match spec {
                                     Spec::Value(v) => {
                                         let name = &self.ast_objs.idents[v.names[0]].name;
-                                        let order = orders[name];
+                                        // the ones without values have no order,
+                                        // they go first
+                                        let order = orders.get(name).copied();
                                         decls.push((v.clone(), order));
                                     }
                                     _ => unimplemented!(),
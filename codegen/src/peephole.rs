@@ -0,0 +1,624 @@
+//! The peephole optimizer runs over the code of every function once the code
+//! generation is done. The code is turned into a list of items, so that jump
+//! targets are item indices instead of relative offsets, the passes mark
+//! items as removed and the offsets and the position table are rebuilt at
+//! the end.
+
+use goscript_vm::instruction::{Instruction, OpIndex, Opcode, ValueType};
+use goscript_vm::objects::FunctionVal;
+
+#[derive(Clone, Copy, Debug)]
+pub struct Item {
+    pub inst: Instruction,
+    pub pos: Option<usize>,
+    // the position of the call the instruction is inlined at
+    pub call: Option<usize>,
+    // the package key following LOAD_PKG_FIELD, STORE_PKG_FIELD and REF_PKG_MEMBER
+    pub pkg: Option<Instruction>,
+    // index of the item to jump to, for jumps
+    pub target: Option<usize>,
+    pub removed: bool,
+}
+
+impl Item {
+    pub fn new(inst: Instruction, pos: Option<usize>) -> Item {
+        Item {
+            inst: inst,
+            pos: pos,
+            call: None,
+            pkg: None,
+            target: None,
+            removed: false,
+        }
+    }
+
+    /// op returns the generic form of a type-specialised op, the type is in
+    /// t0 either way, so the passes only have to know one of them
+    #[inline]
+    pub fn op(&self) -> Opcode {
+        self.inst.op().generic()
+    }
+
+    fn set(&mut self, op: Opcode, t: Option<ValueType>, imm: Option<OpIndex>) {
+        self.inst = Instruction::new(op, t, None, None, imm);
+    }
+
+    /// the item replacing other takes its position
+    fn pos_from(&mut self, other: &Item) {
+        self.pos = other.pos;
+        self.call = other.call;
+    }
+}
+
+pub fn optimize(func: &mut FunctionVal) {
+    let mut items = match to_items(func) {
+        Some(items) => items,
+        None => return,
+    };
+    loop {
+        let mut changed = thread_jumps(&mut items);
+        compact(&mut items);
+        changed |= remove_dead_code(&mut items);
+        compact(&mut items);
+        changed |= fold(&mut items);
+        compact(&mut items);
+        if !changed {
+            break;
+        }
+    }
+    let (code, pos, calls) = from_items(&items);
+    func.replace_code(code, pos);
+    func.set_inlined_calls(calls);
+}
+
+#[inline]
+pub fn is_jump(op: Opcode) -> bool {
+    match op {
+        Opcode::JUMP | Opcode::JUMP_IF | Opcode::JUMP_IF_NOT | Opcode::SWITCH | Opcode::RANGE => {
+            true
+        }
+        _ => false,
+    }
+}
+
+#[inline]
+fn has_pkg_key(op: Opcode) -> bool {
+    match op {
+        Opcode::LOAD_PKG_FIELD | Opcode::STORE_PKG_FIELD | Opcode::REF_PKG_MEMBER => true,
+        _ => false,
+    }
+}
+
+/// is_pure_push returns if the op only pushes a value without side effects
+#[inline]
+fn is_pure_push(op: Opcode) -> bool {
+    match op {
+        Opcode::PUSH_CONST
+        | Opcode::PUSH_NIL
+        | Opcode::PUSH_FALSE
+        | Opcode::PUSH_TRUE
+        | Opcode::PUSH_IMM
+        | Opcode::LOAD_LOCAL
+        | Opcode::LOAD_UPVALUE => true,
+        _ => false,
+    }
+}
+
+#[inline]
+fn is_int(t: ValueType) -> bool {
+    t >= ValueType::Int && t <= ValueType::Uint64
+}
+
+/// all_int returns if the items are all on int, the type the register ops
+/// with a constant are for
+#[inline]
+fn all_int(items: &[&Item]) -> bool {
+    items.iter().all(|x| x.inst.t0() == ValueType::Int)
+}
+
+#[inline]
+fn is_struct(t: ValueType) -> bool {
+    t == ValueType::Struct || t == ValueType::Named
+}
+
+/// reg_op returns the register form of a binary op on values of type t,
+/// only the numbers and bools are supported, as they are stored unboxed
+fn reg_op(op: Opcode, t: ValueType) -> Option<Opcode> {
+    let number = t >= ValueType::Int && t <= ValueType::Float64;
+    let int = is_int(t);
+    match op {
+        Opcode::ADD if number => Some(Opcode::ADD_R),
+        Opcode::SUB if number => Some(Opcode::SUB_R),
+        Opcode::MUL if number => Some(Opcode::MUL_R),
+        Opcode::QUO if number => Some(Opcode::QUO_R),
+        Opcode::REM if int => Some(Opcode::REM_R),
+        Opcode::AND if int => Some(Opcode::AND_R),
+        Opcode::OR if int => Some(Opcode::OR_R),
+        Opcode::XOR if int => Some(Opcode::XOR_R),
+        Opcode::AND_NOT if int => Some(Opcode::AND_NOT_R),
+        Opcode::EQL if number || t == ValueType::Bool => Some(Opcode::EQL_R),
+        Opcode::NEQ if number || t == ValueType::Bool => Some(Opcode::NEQ_R),
+        Opcode::LSS if number => Some(Opcode::LSS_R),
+        Opcode::GTR if number => Some(Opcode::GTR_R),
+        Opcode::LEQ if number => Some(Opcode::LEQ_R),
+        Opcode::GEQ if number => Some(Opcode::GEQ_R),
+        _ => None,
+    }
+}
+
+#[inline]
+fn is_reg_op(op: Opcode) -> bool {
+    op as u8 >= Opcode::ADD_R as u8 && op as u8 <= Opcode::GEQ_RI as u8
+}
+
+/// to_items returns None if the function can't be optimized, that is when
+/// the code has SELECTs, whose blocks are located by offsets relative to the
+/// SELECT itself, or a jump into the middle of an item
+pub fn to_items(func: &FunctionVal) -> Option<Vec<Item>> {
+    let code = func.code();
+    let mut items = Vec::with_capacity(code.len());
+    let mut word_to_item = vec![None; code.len() + 1];
+    let mut pc = 0;
+    while pc < code.len() {
+        let inst = code[pc];
+        if inst.op() == Opcode::SELECT {
+            return None;
+        }
+        word_to_item[pc] = Some(items.len());
+        let pkg = if has_pkg_key(inst.op()) {
+            pc += 1;
+            Some(code[pc])
+        } else {
+            None
+        };
+        items.push(Item {
+            inst: inst,
+            pos: func.pos()[pc],
+            call: func.inlined_call(pc),
+            pkg: pkg,
+            target: None,
+            removed: false,
+        });
+        pc += 1;
+    }
+    word_to_item[code.len()] = Some(items.len());
+
+    pc = 0;
+    for item in items.iter_mut() {
+        if is_jump(item.op()) {
+            let target = pc as OpIndex + 1 + item.inst.imm();
+            if target < 0 || target as usize > code.len() {
+                return None;
+            }
+            item.target = Some(word_to_item[target as usize]?);
+        }
+        pc += if item.pkg.is_some() { 2 } else { 1 };
+    }
+    Some(items)
+}
+
+/// from_items returns the code, the positions and the positions of the calls
+/// the instructions are inlined at, which is empty when nothing is inlined
+pub fn from_items(items: &[Item]) -> (Vec<Instruction>, Vec<Option<usize>>, Vec<Option<usize>>) {
+    let mut words = Vec::with_capacity(items.len() + 1);
+    let mut pc = 0;
+    for item in items.iter() {
+        words.push(pc);
+        pc += if item.pkg.is_some() { 2 } else { 1 };
+    }
+    words.push(pc);
+
+    let mut code = Vec::with_capacity(pc);
+    let mut pos = Vec::with_capacity(pc);
+    let mut calls = Vec::with_capacity(pc);
+    for (i, item) in items.iter().enumerate() {
+        let mut inst = item.inst;
+        if let Some(t) = item.target {
+            inst.set_imm(words[t] as OpIndex - (words[i] as OpIndex + 1));
+        }
+        code.push(inst);
+        pos.push(item.pos);
+        calls.push(item.call);
+        if let Some(pkg) = item.pkg {
+            code.push(pkg);
+            pos.push(item.pos);
+            calls.push(item.call);
+        }
+    }
+    if calls.iter().all(|c| c.is_none()) {
+        calls.clear();
+    }
+    (code, pos, calls)
+}
+
+/// compact drops the removed items, a jump to a removed item now goes to the
+/// item after it
+pub fn compact(items: &mut Vec<Item>) {
+    let mut new_index = Vec::with_capacity(items.len() + 1);
+    let mut count = 0;
+    for item in items.iter() {
+        new_index.push(count);
+        if !item.removed {
+            count += 1;
+        }
+    }
+    new_index.push(count);
+    if count == items.len() {
+        return;
+    }
+    items.retain(|x| !x.removed);
+    for item in items.iter_mut() {
+        if let Some(t) = item.target {
+            item.target = Some(new_index[t]);
+        }
+    }
+}
+
+/// thread_jumps makes jumps to unconditional jumps, or to conditional ones
+/// whose condition is pushed as a constant, go to the final target directly,
+/// and removes jumps to the next instruction
+fn thread_jumps(items: &mut Vec<Item>) -> bool {
+    let mut changed = false;
+    for i in 0..items.len() {
+        let mut target = match items[i].target {
+            Some(t) => t,
+            None => continue,
+        };
+        let mut hops = 0;
+        while target < items.len() && hops < items.len() {
+            target = match (items[target].op(), items.get(target + 1).map(|x| x.op())) {
+                (Opcode::JUMP, _) => items[target].target.unwrap(),
+                // the result of a && or || known on this path, which the
+                // next condition tests right away
+                (Opcode::PUSH_TRUE, Some(Opcode::JUMP_IF))
+                | (Opcode::PUSH_FALSE, Some(Opcode::JUMP_IF_NOT)) => {
+                    items[target + 1].target.unwrap()
+                }
+                (Opcode::PUSH_TRUE, Some(Opcode::JUMP_IF_NOT))
+                | (Opcode::PUSH_FALSE, Some(Opcode::JUMP_IF)) => target + 2,
+                _ => break,
+            };
+            hops += 1;
+        }
+        if items[i].target != Some(target) {
+            items[i].target = Some(target);
+            changed = true;
+        }
+        if items[i].op() == Opcode::JUMP && target == i + 1 {
+            items[i].removed = true;
+            changed = true;
+        }
+    }
+    changed
+}
+
+/// remove_dead_code removes what can't be reached from the beginning of the
+/// function. The last instruction is always kept, it's where the VM goes
+/// when the function panics.
+fn remove_dead_code(items: &mut Vec<Item>) -> bool {
+    let mut reachable = vec![false; items.len()];
+    let mut queue = vec![0];
+    while let Some(i) = queue.pop() {
+        if i >= items.len() || reachable[i] {
+            continue;
+        }
+        reachable[i] = true;
+        match items[i].op() {
+            Opcode::JUMP | Opcode::RETURN => {}
+            _ => queue.push(i + 1),
+        }
+        if let Some(t) = items[i].target {
+            queue.push(t);
+        }
+    }
+    let mut changed = false;
+    let last = items.len().saturating_sub(1);
+    for (i, item) in items.iter_mut().enumerate() {
+        if !reachable[i] && i != last {
+            item.removed = true;
+            changed = true;
+        }
+    }
+    changed
+}
+
+/// fold rewrites short sequences of instructions that no jump goes into
+fn fold(items: &mut Vec<Item>) -> bool {
+    let mut is_target = vec![false; items.len() + 1];
+    for item in items.iter() {
+        if let Some(t) = item.target {
+            is_target[t] = true;
+        }
+    }
+    let mut changed = false;
+    let mut i = 0;
+    while i + 1 < items.len() {
+        let (a, b) = (items[i], items[i + 1]);
+        if is_target[i + 1] {
+            i += 1;
+            continue;
+        }
+        match (a.op(), b.op()) {
+            // a value that is pushed and popped right away
+            (op, Opcode::POP) if is_pure_push(op) => {
+                items[i].removed = true;
+                let n = b.inst.imm();
+                if n == 1 {
+                    items[i + 1].removed = true;
+                } else {
+                    items[i + 1].inst.set_imm(n - 1);
+                }
+                changed = true;
+                i += 2;
+                continue;
+            }
+            (Opcode::PUSH_TRUE, Opcode::NOT) | (Opcode::PUSH_FALSE, Opcode::NOT) => {
+                let op = if a.op() == Opcode::PUSH_TRUE {
+                    Opcode::PUSH_FALSE
+                } else {
+                    Opcode::PUSH_TRUE
+                };
+                items[i].set(op, None, None);
+                items[i].pos_from(&b);
+                items[i + 1].removed = true;
+                changed = true;
+                i += 2;
+                continue;
+            }
+            (Opcode::LOAD_LOCAL, Opcode::LOAD_STRUCT_FIELD)
+                if is_struct(a.inst.t0())
+                    && b.inst.t0() == a.inst.t0()
+                    && Instruction::in_reg_range(a.inst.imm())
+                    && Instruction::in_reg_range(b.inst.imm()) =>
+            {
+                items[i].inst = Instruction::new_reg(
+                    Opcode::LOAD_STRUCT_FIELD_R,
+                    a.inst.t0(),
+                    Instruction::REG_STACK,
+                    a.inst.imm(),
+                    b.inst.imm(),
+                );
+                items[i].pos_from(&b);
+                items[i + 1].removed = true;
+                changed = true;
+                i += 2;
+                continue;
+            }
+            // adding a constant to an int, x - k is x + (-k) as it wraps
+            (Opcode::PUSH_IMM, Opcode::ADD) | (Opcode::PUSH_IMM, Opcode::SUB)
+                if a.inst.t0() == ValueType::Int
+                    && b.inst.t0() == ValueType::Int
+                    && (b.op() == Opcode::ADD || a.inst.imm() != std::i32::MIN) =>
+            {
+                let k = a.inst.imm();
+                let k = if b.op() == Opcode::ADD { k } else { -k };
+                items[i].set(Opcode::ADD_IMM_INT, Some(ValueType::Int), Some(k));
+                items[i].pos_from(&b);
+                items[i + 1].removed = true;
+                changed = true;
+                i += 2;
+                continue;
+            }
+            // a condition known at compile time
+            (Opcode::PUSH_TRUE, Opcode::JUMP_IF)
+            | (Opcode::PUSH_TRUE, Opcode::JUMP_IF_NOT)
+            | (Opcode::PUSH_FALSE, Opcode::JUMP_IF)
+            | (Opcode::PUSH_FALSE, Opcode::JUMP_IF_NOT) => {
+                let taken = (a.op() == Opcode::PUSH_TRUE) == (b.op() == Opcode::JUMP_IF);
+                if taken {
+                    items[i].set(Opcode::JUMP, None, None);
+                    items[i].target = b.target;
+                    items[i].pos_from(&b);
+                } else {
+                    items[i].removed = true;
+                }
+                items[i + 1].removed = true;
+                changed = true;
+                i += 2;
+                continue;
+            }
+            _ => {}
+        }
+        if i + 2 < items.len() && !is_target[i + 2] {
+            let c = items[i + 2];
+            if let Some(folded) = fold3(&a, &b, &c).or_else(|| to_reg(&a, &b, &c)) {
+                items[i] = folded;
+                items[i + 1].removed = true;
+                items[i + 2].removed = true;
+                changed = true;
+                i += 3;
+                continue;
+            }
+        }
+        i += 1;
+    }
+    changed
+}
+
+fn fold3(a: &Item, b: &Item, c: &Item) -> Option<Item> {
+    let t = c.inst.t0();
+    if !is_int(t) || b.op() != Opcode::PUSH_IMM || b.inst.t0() != t || a.inst.t0() != t {
+        return None;
+    }
+    let k = b.inst.imm() as i64;
+    match a.op() {
+        // constant folding, only for int as it's the same size as i64 or
+        // smaller and the result is known not to wrap in i64
+        Opcode::PUSH_IMM if t == ValueType::Int => {
+            let x = a.inst.imm() as i64;
+            let r = match c.op() {
+                Opcode::ADD => x + k,
+                Opcode::SUB => x - k,
+                Opcode::MUL => x * k,
+                Opcode::AND => x & k,
+                Opcode::OR => x | k,
+                Opcode::XOR => x ^ k,
+                Opcode::AND_NOT => x & !k,
+                _ => return None,
+            };
+            if r < std::i32::MIN as i64 || r > std::i32::MAX as i64 {
+                return None;
+            }
+            let mut item = *a;
+            item.set(Opcode::PUSH_IMM, Some(t), Some(r as OpIndex));
+            item.pos_from(&c);
+            Some(item)
+        }
+        // integer arithmetic wraps, so x - k is the same as x + (-k)
+        Opcode::LOAD_LOCAL => {
+            let k = match c.op() {
+                Opcode::ADD => k,
+                Opcode::SUB => -k,
+                _ => return None,
+            };
+            let index = a.inst.imm();
+            if !Instruction::in_8bit_range(k as OpIndex) || !Instruction::in_24bit_range(index) {
+                return None;
+            }
+            let mut item = *a;
+            item.set(Opcode::LOAD_LOCAL_ADD_IMM, Some(t), None);
+            item.inst.set_imm824(k as OpIndex, index);
+            item.pos_from(&c);
+            Some(item)
+        }
+        _ => None,
+    }
+}
+
+/// to_reg turns a binary op on two locals into the register form, and makes
+/// a register op put its result to a local directly if it's stored there
+fn to_reg(a: &Item, b: &Item, c: &Item) -> Option<Item> {
+    match (a.op(), b.op(), c.op()) {
+        // s[i] with an int i, a map or a comma-ok load goes through the stack
+        (Opcode::LOAD_LOCAL, Opcode::LOAD_LOCAL, Opcode::LOAD_INDEX) => {
+            let t = c.inst.t0();
+            let (x, y) = (a.inst.imm(), b.inst.imm());
+            let indexable = t == ValueType::Slice || t == ValueType::Str || t == ValueType::Array;
+            if !indexable
+                || a.inst.t0() != t
+                || b.inst.t0() != ValueType::Int
+                || c.inst.t1() != ValueType::Int
+                || c.inst.t2_as_index() != 0
+                || !Instruction::in_reg_range(x)
+                || !Instruction::in_reg_range(y)
+            {
+                return None;
+            }
+            let mut item = *a;
+            item.inst = Instruction::new_reg(Opcode::LOAD_INDEX_R, t, Instruction::REG_STACK, x, y);
+            item.pos_from(&c);
+            Some(item)
+        }
+        (Opcode::LOAD_LOCAL, Opcode::LOAD_LOCAL, op) => {
+            let t = c.inst.t0();
+            let rop = reg_op(op, t)?;
+            let (x, y) = (a.inst.imm(), b.inst.imm());
+            if a.inst.t0() != t
+                || b.inst.t0() != t
+                || !Instruction::in_reg_range(x)
+                || !Instruction::in_reg_range(y)
+            {
+                return None;
+            }
+            let mut item = *a;
+            item.inst = Instruction::new_reg(rop, t, Instruction::REG_STACK, x, y);
+            item.pos_from(&c);
+            Some(item)
+        }
+        // an int compared with or added to a constant
+        (Opcode::LOAD_LOCAL, Opcode::PUSH_IMM, op) => {
+            let x = a.inst.imm();
+            let k = b.inst.imm();
+            let (rop, k) = match op {
+                Opcode::ADD => (Opcode::ADD_RI, k),
+                Opcode::SUB => (Opcode::ADD_RI, k.checked_neg()?),
+                Opcode::EQL => (Opcode::EQL_RI, k),
+                Opcode::NEQ => (Opcode::NEQ_RI, k),
+                Opcode::LSS => (Opcode::LSS_RI, k),
+                Opcode::GTR => (Opcode::GTR_RI, k),
+                Opcode::LEQ => (Opcode::LEQ_RI, k),
+                Opcode::GEQ => (Opcode::GEQ_RI, k),
+                _ => return None,
+            };
+            if !all_int(&[a, b, c])
+                || !Instruction::in_reg_range(x)
+                || !Instruction::in_16bit_range(k)
+            {
+                return None;
+            }
+            let mut item = *a;
+            item.inst = Instruction::new_reg(rop, ValueType::Int, Instruction::REG_STACK, x, k);
+            item.pos_from(&c);
+            Some(item)
+        }
+        // x = x + k, after fold3
+        (Opcode::LOAD_LOCAL_ADD_IMM, Opcode::STORE_LOCAL, Opcode::POP) if c.inst.imm() == 1 => {
+            let (k, x) = a.inst.imm824();
+            let (rhs, index) = b.inst.imm824();
+            if !all_int(&[a, b])
+                || rhs != -1
+                || !Instruction::in_reg_range(x)
+                || !Instruction::in_reg_range(index)
+            {
+                return None;
+            }
+            let mut item = *a;
+            item.inst = Instruction::new_reg(Opcode::ADD_RI, ValueType::Int, index, x, k);
+            item.pos_from(&b);
+            Some(item)
+        }
+        // x += k, x -= k and x++
+        (Opcode::PUSH_IMM, Opcode::STORE_LOCAL, Opcode::POP) if c.inst.imm() == 1 => {
+            let (op, index) = b.inst.imm824();
+            let k = a.inst.imm();
+            let k = match op {
+                _ if op < 0 => return None,
+                _ if Instruction::index2code(op) == Opcode::ADD => k,
+                _ if Instruction::index2code(op) == Opcode::SUB => k.checked_neg()?,
+                _ => return None,
+            };
+            if !all_int(&[a, b])
+                || !Instruction::in_reg_range(index)
+                || !Instruction::in_16bit_range(k)
+            {
+                return None;
+            }
+            let mut item = *a;
+            item.inst = Instruction::new_reg(Opcode::ADD_RI, ValueType::Int, index, index, k);
+            item.pos_from(&b);
+            Some(item)
+        }
+        // x op= y
+        (Opcode::LOAD_LOCAL, Opcode::STORE_LOCAL, Opcode::POP) if c.inst.imm() == 1 => {
+            let t = b.inst.t0();
+            let (op, index) = b.inst.imm824();
+            if op < 0 {
+                return None;
+            }
+            let rop = reg_op(Instruction::index2code(op), t)?;
+            let y = a.inst.imm();
+            if a.inst.t0() != t
+                || !Instruction::in_reg_range(y)
+                || !Instruction::in_reg_range(index)
+            {
+                return None;
+            }
+            let mut item = *a;
+            item.inst = Instruction::new_reg(rop, t, index, index, y);
+            item.pos_from(&b);
+            Some(item)
+        }
+        (op, Opcode::STORE_LOCAL, Opcode::POP) if is_reg_op(op) && c.inst.imm() == 1 => {
+            let (dest, x, y) = a.inst.reg();
+            let (rhs, index) = b.inst.imm824();
+            if dest != Instruction::REG_STACK || rhs != -1 || !Instruction::in_reg_range(index) {
+                return None;
+            }
+            let mut item = *a;
+            item.inst = Instruction::new_reg(op, a.inst.t0(), index, x, y);
+            item.pos_from(&b);
+            Some(item)
+        }
+        _ => None,
+    }
+}
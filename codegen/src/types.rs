@@ -4,7 +4,7 @@ use goscript_parser::ast::{Expr, NodeId};
 use goscript_parser::objects::IdentKey;
 use goscript_types::{
     BasicType, ChanDir, ConstValue, EntityType, ObjKey, OperandMode, PackageKey as TCPackageKey,
-    TCObjects, Type, TypeInfo, TypeKey as TCTypeKey,
+    Selection, TCObjects, Type, TypeInfo, TypeKey as TCTypeKey,
 };
 use goscript_vm::gc::GcoVec;
 use goscript_vm::instruction::{OpIndex, ValueType};
@@ -37,6 +37,12 @@ impl<'a> TypeLookup<'a> {
         self.ti
     }
 
+    /// swap_type_info replaces the TypeInfo and returns the old one, it's used
+    /// for generating the instances of generics, which are checked separately
+    pub fn swap_type_info(&mut self, ti: &'a TypeInfo) -> &'a TypeInfo {
+        std::mem::replace(&mut self.ti, ti)
+    }
+
     pub fn get_tc_const_value(&mut self, id: NodeId) -> Option<&ConstValue> {
         let typ_val = self.ti.types.get(&id).unwrap();
         typ_val.get_const_val()
@@ -159,11 +165,34 @@ impl<'a> TypeLookup<'a> {
         self.tuple_tc_types(typ)
     }
 
-    pub fn get_selection_value_types(&mut self, id: NodeId) -> (ValueType, ValueType) {
-        let sel = &self.ti.selections[&id];
-        let t0 = self.value_type_from_tc(sel.recv().unwrap());
-        let t1 = self.value_type_from_tc(self.tc_objs.lobjs[sel.obj()].typ().unwrap());
-        (t0, t1)
+    pub fn get_selection(&self, id: NodeId) -> &Selection {
+        &self.ti.selections[&id]
+    }
+
+    /// field_tc returns the type of the index-th field of a struct or a pointer to struct
+    pub fn field_tc(&self, typ: TCTypeKey, index: usize) -> TCTypeKey {
+        let typ = match &self.tc_objs.types[self.underlying_tc(typ)] {
+            Type::Pointer(detail) => detail.base(),
+            _ => typ,
+        };
+        let fields = self.tc_objs.types[self.underlying_tc(typ)]
+            .try_as_struct()
+            .unwrap()
+            .fields();
+        self.tc_objs.lobjs[fields[index]].typ().unwrap()
+    }
+
+    /// embedded_path_tc returns the type of the embedded field found through 'path'
+    pub fn embedded_path_tc(&self, typ: TCTypeKey, path: &[usize]) -> TCTypeKey {
+        path.iter().fold(typ, |t, i| self.field_tc(t, *i))
+    }
+
+    /// try_deref_tc returns the base type and true if typ is a pointer
+    pub fn try_deref_tc(&self, typ: TCTypeKey) -> (TCTypeKey, bool) {
+        match &self.tc_objs.types[typ] {
+            Type::Pointer(detail) => (detail.base(), true),
+            _ => (typ, false),
+        }
     }
 
     pub fn meta_from_tc(
@@ -426,6 +455,12 @@ impl<'a> TypeLookup<'a> {
         }
     }
 
+    pub fn map_key_tc(&self, typ: TCTypeKey) -> Option<TCTypeKey> {
+        self.tc_objs.types[self.underlying_tc(typ)]
+            .try_as_map()
+            .map(|m| m.key())
+    }
+
     pub fn underlying_value_type_from_tc(&self, typ: TCTypeKey) -> ValueType {
         self.value_type_from_tc(self.underlying_tc(typ))
     }
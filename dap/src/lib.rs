@@ -0,0 +1,8 @@
+//! A Debug Adapter Protocol server for goscript programs, so that editors
+//! can debug them. It talks to the editor through stdin and stdout.
+
+mod protocol;
+mod server;
+
+pub use protocol::{read_message, write_message};
+pub use server::serve;
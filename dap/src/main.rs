@@ -0,0 +1,5 @@
+use std::io::{self, BufReader};
+
+fn main() {
+    goscript_dap::serve(BufReader::new(io::stdin()), io::stdout());
+}
@@ -0,0 +1,113 @@
+//! The wire format of the Debug Adapter Protocol: JSON messages, each after a
+//! Content-Length header, see
+//! https://microsoft.github.io/debug-adapter-protocol/overview
+
+use serde_json::{json, Value};
+use std::collections::VecDeque;
+use std::io::{self, BufRead, Write};
+use std::sync::mpsc::Receiver;
+
+/// read_message reads the next message, it's None at the end of the input
+pub fn read_message<R: BufRead>(r: &mut R) -> io::Result<Option<Value>> {
+    let mut len = None;
+    loop {
+        let mut line = String::new();
+        if r.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some(v) = line.strip_prefix("Content-Length:") {
+            len = v.trim().parse::<usize>().ok();
+        }
+    }
+    let len = len.ok_or_else(|| invalid("missing Content-Length"))?;
+    let mut buf = vec![0; len];
+    r.read_exact(&mut buf)?;
+    serde_json::from_slice(&buf)
+        .map(Some)
+        .map_err(|e| invalid(&e.to_string()))
+}
+
+pub fn write_message<W: Write>(w: &mut W, msg: &Value) -> io::Result<()> {
+    let body = msg.to_string();
+    write!(w, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    w.flush()
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// Session is the connection to the client, the requests come from a reader
+/// thread so that they can be looked at while the program runs
+pub struct Session {
+    out: Box<dyn Write>,
+    seq: u64,
+    requests: Receiver<Value>,
+    // requests read while the program runs, that have to wait for a stop
+    pending: VecDeque<Value>,
+}
+
+impl Session {
+    pub fn new(requests: Receiver<Value>, out: Box<dyn Write>) -> Session {
+        Session {
+            out,
+            seq: 0,
+            requests,
+            pending: VecDeque::new(),
+        }
+    }
+
+    /// next waits for the next request, it's None when the client is gone
+    pub fn next(&mut self) -> Option<Value> {
+        self.pending
+            .pop_front()
+            .or_else(|| self.requests.recv().ok())
+    }
+
+    /// peek returns the first request that has come in, without waiting
+    pub fn peek(&mut self) -> Option<&Value> {
+        while let Ok(req) = self.requests.try_recv() {
+            self.pending.push_back(req);
+        }
+        self.pending.front()
+    }
+
+    pub fn respond(&mut self, req: &Value, body: Value) {
+        self.send(json!({
+            "type": "response",
+            "request_seq": req["seq"],
+            "command": req["command"],
+            "success": true,
+            "body": body,
+        }));
+    }
+
+    pub fn fail(&mut self, req: &Value, msg: &str) {
+        self.send(json!({
+            "type": "response",
+            "request_seq": req["seq"],
+            "command": req["command"],
+            "success": false,
+            "message": msg,
+        }));
+    }
+
+    pub fn event(&mut self, name: &str, body: Value) {
+        self.send(json!({
+            "type": "event",
+            "event": name,
+            "body": body,
+        }));
+    }
+
+    fn send(&mut self, mut msg: Value) {
+        self.seq += 1;
+        msg["seq"] = json!(self.seq);
+        // the client is gone if this fails, which the reader finds out too
+        let _ = write_message(&mut self.out, &msg);
+    }
+}
@@ -0,0 +1,432 @@
+//! The server runs one program per session, with the debugger of the vm.
+//! Threads are goroutines, and a stop stops them all, as the vm runs them on
+//! one thread. Only the goroutine that stopped has a stack trace.
+
+use super::protocol::{read_message, Session};
+use goscript_engine as engine;
+use goscript_vm::debug::{Breakpoints, Command, DebugHandler, Stop, StopReason};
+use goscript_vm::ffi::{Ffi, FfiResult};
+use goscript_vm::value::GosValue;
+use serde_json::{json, Value};
+use std::cell::{Cell, RefCell};
+use std::io::{BufRead, Write};
+use std::path::Path;
+use std::rc::Rc;
+use std::sync::mpsc;
+use std::thread;
+
+type Shared = Rc<RefCell<Session>>;
+
+// the variables references of a frame are frame * SCOPES + scope + 1
+const SCOPES: usize = 3;
+const LOCALS: usize = 0;
+const CLOSURE: usize = 1;
+const GLOBALS: usize = 2;
+
+/// serve answers the requests from input until the client disconnects or
+/// the input ends
+pub fn serve<R: BufRead + Send + 'static, W: Write + 'static>(mut input: R, output: W) {
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        while let Ok(Some(msg)) = read_message(&mut input) {
+            if tx.send(msg).is_err() {
+                break;
+            }
+        }
+    });
+    let session = Rc::new(RefCell::new(Session::new(rx, Box::new(output))));
+    loop {
+        let req = match session.borrow_mut().next() {
+            Some(r) => r,
+            None => break,
+        };
+        let mut s = session.borrow_mut();
+        match command(&req) {
+            "initialize" => s.respond(
+                &req,
+                json!({
+                    "supportsConfigurationDoneRequest": true,
+                    "supportsTerminateRequest": true,
+                    "supportsEvaluateForHovers": true,
+                }),
+            ),
+            "launch" => {
+                drop(s);
+                if launch(&session, &req) {
+                    break;
+                }
+            }
+            "disconnect" | "terminate" => {
+                s.respond(&req, json!({}));
+                break;
+            }
+            "threads" => s.respond(&req, json!({ "threads": [] })),
+            cmd => s.fail(&req, &format!("{} needs a running program", cmd)),
+        }
+    }
+}
+
+fn command(req: &Value) -> &str {
+    req["command"].as_str().unwrap_or("")
+}
+
+/// launch runs the program and returns true if the client disconnected
+/// before it ended
+fn launch(session: &Shared, req: &Value) -> bool {
+    let args = &req["arguments"];
+    let program = match args["program"].as_str() {
+        Some(p) => p,
+        None => {
+            session.borrow_mut().fail(req, "program is missing");
+            return false;
+        }
+    };
+    let cfg = engine::Config {
+        work_dir: args["cwd"].as_str().map(|s| s.to_string()),
+        base_path: args["basePath"].as_str().map(|s| s.to_string()),
+        trace_parser: false,
+        trace_checker: false,
+        trace_vm: false,
+        opcode_stats: false,
+    };
+    let mut engine = engine::Engine::new(cfg);
+    // stdout is where the messages go, what the program prints has to be
+    // sent as events
+    let out = session.clone();
+    engine.register_extension("fmt", Box::new(move |_| Output::new_ffi(out.clone())));
+
+    let disconnected = Rc::new(Cell::new(false));
+    let stop_on_entry = args["stopOnEntry"].as_bool().unwrap_or(false);
+    let handler = Handler {
+        session: session.clone(),
+        launch: Some(req.clone()),
+        entry: stop_on_entry,
+        fibers: vec![1],
+        disconnected: disconnected.clone(),
+    };
+    let err_cnt = engine.debug(program, Box::new(handler));
+    if err_cnt > 0 {
+        let mut s = session.borrow_mut();
+        s.fail(req, &format!("{} errors in {}", err_cnt, program));
+        s.event("terminated", json!({}));
+    }
+    disconnected.get()
+}
+
+struct Handler {
+    session: Shared,
+    // the launch request, it's answered when the program compiles
+    launch: Option<Value>,
+    // true until the first stop if it's stopOnEntry
+    entry: bool,
+    // the goroutines at the last stop
+    fibers: Vec<usize>,
+    disconnected: Rc<Cell<bool>>,
+}
+
+impl Handler {
+    fn next(&mut self) -> Option<Value> {
+        self.session.borrow_mut().next()
+    }
+
+    fn peek_command(&mut self) -> String {
+        let mut s = self.session.borrow_mut();
+        s.peek().map_or("".to_string(), |r| command(r).to_string())
+    }
+
+    /// request answers a request, returns the command if it resumes the
+    /// program. Inspecting the program only works when stop is Some.
+    fn request(
+        &mut self,
+        req: &Value,
+        stop: Option<&Stop>,
+        bps: &mut Breakpoints,
+    ) -> Option<Command> {
+        let mut s = self.session.borrow_mut();
+        let args = &req["arguments"];
+        let resume = match command(req) {
+            "continue" => Some(Command::Continue),
+            "next" => Some(Command::StepOver),
+            "stepIn" => Some(Command::StepInto),
+            "stepOut" => Some(Command::StepOut),
+            _ => None,
+        };
+        match (command(req), stop) {
+            ("setBreakpoints", _) => {
+                let body = set_breakpoints(args, bps);
+                s.respond(req, body);
+            }
+            ("threads", _) => {
+                let ids = stop.map_or(self.fibers.clone(), |st| st.fibers());
+                let threads: Vec<Value> = ids
+                    .iter()
+                    .map(|id| json!({"id": id, "name": format!("goroutine {}", id)}))
+                    .collect();
+                s.respond(req, json!({ "threads": threads }));
+            }
+            ("configurationDone", _) | ("pause", _) => s.respond(req, json!({})),
+            ("disconnect", _) | ("terminate", _) => {
+                s.respond(req, json!({}));
+                self.disconnected.set(true);
+                return Some(Command::Quit);
+            }
+            (_, Some(_)) if resume.is_some() => {
+                s.respond(req, json!({"allThreadsContinued": true}));
+                return resume;
+            }
+            ("stackTrace", Some(stop)) => s.respond(req, stack_trace(args, stop)),
+            ("scopes", Some(stop)) => match frame_arg(args, stop) {
+                Some(frame) => s.respond(req, scopes(frame, stop)),
+                None => s.fail(req, "no such frame"),
+            },
+            ("variables", Some(stop)) => match variables(args, stop) {
+                Some(body) => s.respond(req, body),
+                None => s.fail(req, "no such variables"),
+            },
+            ("evaluate", Some(stop)) => {
+                let name = args["expression"].as_str().unwrap_or("").trim();
+                match frame_arg(args, stop).and_then(|f| stop.lookup(f, name)) {
+                    Some(v) => s.respond(
+                        req,
+                        json!({"result": v.to_string(), "variablesReference": 0}),
+                    ),
+                    None => s.fail(req, &format!("no variable named {}", name)),
+                }
+            }
+            ("stackTrace", None) | ("scopes", None) | ("variables", None) | ("evaluate", None) => {
+                s.fail(req, "the program is not stopped")
+            }
+            _ if resume.is_some() => s.fail(req, "the program is not stopped"),
+            (cmd, _) => s.fail(req, &format!("{} is not supported", cmd)),
+        }
+        None
+    }
+}
+
+impl DebugHandler for Handler {
+    fn started(&mut self, bps: &mut Breakpoints) -> Command {
+        {
+            let mut s = self.session.borrow_mut();
+            s.respond(self.launch.as_ref().unwrap(), json!({}));
+            s.event("initialized", json!({}));
+        }
+        // the client sets the breakpoints up until configurationDone
+        loop {
+            let req = match self.next() {
+                Some(r) => r,
+                None => return Command::Quit,
+            };
+            if let Some(cmd) = self.request(&req, None, bps) {
+                return cmd;
+            }
+            if command(&req) == "configurationDone" {
+                return if self.entry {
+                    Command::StepInto
+                } else {
+                    Command::Continue
+                };
+            }
+        }
+    }
+
+    fn running(&mut self, bps: &mut Breakpoints) -> bool {
+        loop {
+            match self.peek_command().as_str() {
+                // they are answered in stopped
+                "pause" | "disconnect" | "terminate" => return true,
+                "setBreakpoints" | "threads" => {
+                    let req = self.next().unwrap();
+                    self.request(&req, None, bps);
+                }
+                // the others have to wait for a stop
+                _ => return false,
+            }
+        }
+    }
+
+    fn stopped(&mut self, stop: &Stop, bps: &mut Breakpoints) -> Command {
+        self.fibers = stop.fibers();
+        let first = self.peek_command();
+        if first == "disconnect" || first == "terminate" {
+            let req = self.next().unwrap();
+            return self.request(&req, Some(stop), bps).unwrap();
+        }
+        if stop.reason() == StopReason::Pause && first == "pause" {
+            let req = self.next().unwrap();
+            self.request(&req, Some(stop), bps);
+        }
+
+        let mut body = json!({
+            "threadId": stop.fiber(),
+            "allThreadsStopped": true,
+        });
+        body["reason"] = json!(match stop.reason() {
+            StopReason::Breakpoint(id) => {
+                body["hitBreakpointIds"] = json!([id]);
+                "breakpoint"
+            }
+            StopReason::Step if self.entry => "entry",
+            StopReason::Step => "step",
+            StopReason::Pause => "pause",
+        });
+        self.entry = false;
+        self.session.borrow_mut().event("stopped", body);
+
+        loop {
+            let req = match self.next() {
+                Some(r) => r,
+                None => return Command::Quit,
+            };
+            if let Some(cmd) = self.request(&req, Some(stop), bps) {
+                return cmd;
+            }
+        }
+    }
+
+    fn exited(&mut self) {
+        let mut s = self.session.borrow_mut();
+        s.event("exited", json!({"exitCode": 0}));
+        s.event("terminated", json!({}));
+    }
+}
+
+fn set_breakpoints(args: &Value, bps: &mut Breakpoints) -> Value {
+    let path = args["source"]["path"].as_str().unwrap_or("");
+    bps.clear(path);
+    let empty = vec![];
+    let lines = args["breakpoints"].as_array().unwrap_or(&empty);
+    let list: Vec<Value> = lines
+        .iter()
+        .filter_map(|b| b["line"].as_u64())
+        .map(|line| match bps.set(path, line as usize) {
+            Some(id) => json!({"id": id, "verified": true, "line": line}),
+            None => json!({
+                "verified": false,
+                "line": line,
+                "message": "no code on this line",
+            }),
+        })
+        .collect();
+    json!({ "breakpoints": list })
+}
+
+fn frame_arg(args: &Value, stop: &Stop) -> Option<usize> {
+    let frame = args["frameId"].as_u64().unwrap_or(0) as usize;
+    if frame < stop.frames().len() {
+        Some(frame)
+    } else {
+        None
+    }
+}
+
+fn stack_trace(args: &Value, stop: &Stop) -> Value {
+    if args["threadId"].as_u64() != Some(stop.fiber() as u64) {
+        return json!({"stackFrames": [], "totalFrames": 0});
+    }
+    // the frames with no position are the entry and such
+    let frames: Vec<Value> = (0..stop.frames().len())
+        .filter_map(|i| {
+            let pos = stop.position(i)?;
+            let path = Path::new(pos.filename.as_str());
+            let full = path.canonicalize().unwrap_or(path.to_path_buf());
+            Some(json!({
+                "id": i,
+                "name": stop.func_name(i),
+                "line": pos.line,
+                "column": pos.column,
+                "source": {
+                    "name": path.file_name().map(|n| n.to_string_lossy()),
+                    "path": full.to_string_lossy(),
+                },
+            }))
+        })
+        .collect();
+    let total = frames.len();
+    let start = args["startFrame"].as_u64().unwrap_or(0) as usize;
+    let levels = match args["levels"].as_u64() {
+        Some(n) if n > 0 => n as usize,
+        _ => total,
+    };
+    let frames: Vec<Value> = frames.into_iter().skip(start).take(levels).collect();
+    json!({"stackFrames": frames, "totalFrames": total})
+}
+
+fn scopes(frame: usize, stop: &Stop) -> Value {
+    let scope = |name: &str, kind: usize| {
+        json!({
+            "name": name,
+            "variablesReference": frame * SCOPES + kind + 1,
+            "expensive": false,
+        })
+    };
+    let mut list = vec![scope("Locals", LOCALS)];
+    if !stop.upvalues(frame).is_empty() {
+        list.push(scope("Closure", CLOSURE));
+    }
+    list.push(scope("Globals", GLOBALS));
+    json!({ "scopes": list })
+}
+
+fn variables(args: &Value, stop: &Stop) -> Option<Value> {
+    let r = (args["variablesReference"].as_u64()? as usize).checked_sub(1)?;
+    let frame = r / SCOPES;
+    if frame >= stop.frames().len() {
+        return None;
+    }
+    let var = |name: &str, v: &GosValue| json!({"name": name, "value": v.to_string(), "variablesReference": 0});
+    let list: Vec<Value> = match r % SCOPES {
+        LOCALS => stop
+            .locals(frame)
+            .into_iter()
+            .map(|(n, v)| var(n, v))
+            .collect(),
+        CLOSURE => stop
+            .upvalues(frame)
+            .into_iter()
+            .map(|(n, v)| var(n, v))
+            .collect(),
+        _ => stop.globals().iter().map(|(n, v)| var(n, v)).collect(),
+    };
+    Some(json!({ "variables": list }))
+}
+
+/// Output takes the place of the fmt package, it prints to output events
+struct Output {
+    session: Shared,
+}
+
+impl Output {
+    fn new_ffi(session: Shared) -> FfiResult<Rc<RefCell<dyn Ffi>>> {
+        Ok(Rc::new(RefCell::new(Output { session })))
+    }
+}
+
+impl Ffi for Output {
+    fn call(&self, func_name: &str, params: Vec<GosValue>) -> Vec<GosValue> {
+        match func_name {
+            "println" => {
+                let vec = params[0].as_slice().0.get_vec();
+                let strs: Vec<String> = vec
+                    .iter()
+                    .map(|x| {
+                        if x.is_nil() {
+                            "<nil>".to_string()
+                        } else {
+                            x.iface_underlying().unwrap().to_string()
+                        }
+                    })
+                    .collect();
+                let body = json!({"category": "stdout", "output": strs.join(", ") + "\n"});
+                self.session.borrow_mut().event("output", body);
+            }
+            // printf and anything the fmt package adds later, the client is
+            // told instead of the adapter panicking
+            _ => {
+                let msg = format!("fmt.{} is not supported by the debugger\n", func_name);
+                let body = json!({"category": "stderr", "output": msg});
+                self.session.borrow_mut().event("output", body);
+            }
+        }
+        vec![]
+    }
+}
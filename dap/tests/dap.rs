@@ -0,0 +1,210 @@
+extern crate goscript_dap as dap;
+
+use serde_json::{json, Value};
+use std::cell::RefCell;
+use std::io::{self, Cursor, Write};
+use std::rc::Rc;
+
+#[derive(Clone, Default)]
+struct SharedBuf(Rc<RefCell<Vec<u8>>>);
+
+impl Write for SharedBuf {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// run sends all the requests at once, the server answers them in order as
+/// the program stops, and returns all the messages from the server
+fn run(requests: Vec<(&str, Value)>) -> Vec<Value> {
+    let mut input = vec![];
+    for (i, (cmd, args)) in requests.into_iter().enumerate() {
+        let req = json!({"seq": i + 1, "type": "request", "command": cmd, "arguments": args});
+        dap::write_message(&mut input, &req).unwrap();
+    }
+    let output = SharedBuf::default();
+    dap::serve(Cursor::new(input), output.clone());
+    let buf = output.0.borrow();
+    let mut r = buf.as_slice();
+    let mut msgs = vec![];
+    while let Some(msg) = dap::read_message(&mut r).unwrap() {
+        msgs.push(msg);
+    }
+    msgs
+}
+
+fn response(msgs: &[Value], seq: u64) -> &Value {
+    msgs.iter()
+        .find(|m| m["type"] == "response" && m["request_seq"] == seq)
+        .unwrap()
+}
+
+fn events<'a>(msgs: &'a [Value], name: &str) -> Vec<&'a Value> {
+    msgs.iter()
+        .filter(|m| m["type"] == "event" && m["event"] == name)
+        .collect()
+}
+
+fn launch_args(stop_on_entry: bool) -> Value {
+    json!({
+        "program": "./tests/data/loop.gos",
+        "cwd": "./",
+        "basePath": "../engine/std/",
+        "stopOnEntry": stop_on_entry,
+    })
+}
+
+#[test]
+fn test_session() {
+    let path = "tests/data/loop.gos";
+    let msgs = run(vec![
+        ("initialize", json!({"adapterID": "goscript"})),
+        ("launch", launch_args(true)),
+        (
+            "setBreakpoints",
+            json!({"source": {"path": path}, "breakpoints": [{"line": 16}, {"line": 2}]}),
+        ),
+        ("configurationDone", json!({})),
+        // stopped on entry
+        ("threads", json!({})),
+        ("continue", json!({"threadId": 1})),
+        // stopped at the breakpoint
+        ("stackTrace", json!({"threadId": 1})),
+        ("scopes", json!({"frameId": 0})),
+        ("variables", json!({"variablesReference": 2})),
+        ("evaluate", json!({"expression": "k", "frameId": 1})),
+        ("stepIn", json!({"threadId": 1})),
+        ("stackTrace", json!({"threadId": 1, "levels": 1})),
+        ("next", json!({"threadId": 1})),
+        ("variables", json!({"variablesReference": 1})),
+        ("stepOut", json!({"threadId": 1})),
+        ("stackTrace", json!({"threadId": 1, "levels": 1})),
+        (
+            "setBreakpoints",
+            json!({"source": {"path": path}, "breakpoints": []}),
+        ),
+        ("continue", json!({"threadId": 1})),
+        // runs until it's paused
+        ("pause", json!({"threadId": 1})),
+        ("threads", json!({})),
+        ("disconnect", json!({})),
+    ]);
+    for seq in 1..=21 {
+        assert_eq!(response(&msgs, seq)["success"], true, "request {}", seq);
+    }
+    let bps = &response(&msgs, 3)["body"]["breakpoints"];
+    assert_eq!(bps[0]["verified"], true);
+    assert_eq!(bps[1]["verified"], false);
+
+    let stops: Vec<&Value> = events(&msgs, "stopped")
+        .into_iter()
+        .map(|e| &e["body"]["reason"])
+        .collect();
+    assert_eq!(
+        stops,
+        vec!["entry", "breakpoint", "step", "step", "step", "pause"]
+    );
+    assert_eq!(events(&msgs, "output")[0]["body"]["output"], "start\n");
+
+    let frames = &response(&msgs, 7)["body"]["stackFrames"];
+    assert_eq!(frames[0]["name"], "main.main.func1");
+    assert_eq!(frames[0]["line"], 16);
+    assert_eq!(frames[1]["name"], "main.main");
+    assert_eq!(frames[1]["line"], 19);
+    assert!(frames[0]["source"]["path"]
+        .as_str()
+        .unwrap()
+        .ends_with("tests/data/loop.gos"));
+    let scopes = &response(&msgs, 8)["body"]["scopes"];
+    assert_eq!(scopes[1]["name"], "Closure");
+    let upvalues = &response(&msgs, 9)["body"]["variables"];
+    assert_eq!(
+        upvalues[0],
+        json!({"name": "k", "value": "3", "variablesReference": 0})
+    );
+    assert_eq!(response(&msgs, 10)["body"]["result"], "3");
+
+    // step into sum, then over its first line
+    let frames = &response(&msgs, 12)["body"]["stackFrames"];
+    assert_eq!(frames.as_array().unwrap().len(), 1);
+    assert_eq!(frames[0]["name"], "main.sum");
+    assert_eq!(frames[0]["line"], 6);
+    let locals = &response(&msgs, 14)["body"]["variables"];
+    assert_eq!(
+        locals[0],
+        json!({"name": "n", "value": "3", "variablesReference": 0})
+    );
+    assert_eq!(
+        locals[1],
+        json!({"name": "s", "value": "0", "variablesReference": 0})
+    );
+    let frames = &response(&msgs, 16)["body"]["stackFrames"];
+    assert_eq!(frames[0]["name"], "main.main.func1");
+
+    let threads = &response(&msgs, 20)["body"]["threads"];
+    assert_eq!(threads.as_array().unwrap().len(), 2);
+    assert_eq!(threads[1]["name"], "goroutine 2");
+    assert_eq!(events(&msgs, "terminated").len(), 1);
+    // the sum of 200000 never got printed
+    assert_eq!(events(&msgs, "output").len(), 1);
+}
+
+#[test]
+fn test_run() {
+    let msgs = run(vec![
+        ("initialize", json!({})),
+        ("launch", launch_args(false)),
+        // a disconnect would stop it, the session ends with the input
+        ("configurationDone", json!({})),
+    ]);
+    assert!(events(&msgs, "stopped").is_empty());
+    let output: Vec<&Value> = events(&msgs, "output")
+        .into_iter()
+        .map(|e| &e["body"]["output"])
+        .collect();
+    assert_eq!(output, vec!["start\n", "6, 19999900000\n"]);
+    assert_eq!(events(&msgs, "exited")[0]["body"]["exitCode"], 0);
+
+    let msgs = run(vec![
+        ("initialize", json!({})),
+        ("launch", json!({"program": "./tests/data/bad.gos"})),
+        ("stackTrace", json!({"threadId": 1})),
+        ("disconnect", json!({})),
+    ]);
+    assert_eq!(response(&msgs, 2)["success"], false);
+    assert_eq!(response(&msgs, 3)["success"], false);
+    assert_eq!(events(&msgs, "terminated").len(), 1);
+    assert_eq!(response(&msgs, 4)["success"], true);
+}
+
+#[test]
+fn test_unsupported_output() {
+    let msgs = run(vec![
+        ("initialize", json!({})),
+        (
+            "launch",
+            json!({"program": "./tests/data/printf.gos", "basePath": "../engine/std/"}),
+        ),
+        ("configurationDone", json!({})),
+    ]);
+    let output: Vec<(&Value, &Value)> = events(&msgs, "output")
+        .into_iter()
+        .map(|e| (&e["body"]["category"], &e["body"]["output"]))
+        .collect();
+    assert_eq!(
+        output,
+        vec![
+            (
+                &json!("stderr"),
+                &json!("fmt.printf is not supported by the debugger\n")
+            ),
+            (&json!("stdout"), &json!("done\n")),
+        ]
+    );
+    assert_eq!(events(&msgs, "exited")[0]["body"]["exitCode"], 0);
+}
@@ -0,0 +1,132 @@
+//! The benchmarks of the compiler stages and of the VM, run them with
+//! `cargo bench` in the engine directory.
+//!
+//! The compiler stages are measured cumulatively: `parse` only parses the
+//! main file, `check` parses and type checks the program and the packages it
+//! imports, `codegen` does all of it and generates the bytecode.
+//! `exec` runs the bytecode in a new VM, output of fmt is discarded.
+//!
+//! To compare with another commit, save a baseline there with
+//! `cargo bench -- --save-baseline before`, then run
+//! `cargo bench -- --baseline before` here.
+
+extern crate goscript_codegen as cg;
+extern crate goscript_parser as fe;
+extern crate goscript_types as types;
+extern crate goscript_vm as vm;
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs;
+use std::rc::Rc;
+use vm::ffi::{Ffi, FfiFactory, FfiResult};
+use vm::value::GosValue;
+use vm::vm::{ByteCode, GosVM};
+
+const DEMOS: [&str; 3] = ["fibonacci", "leetcode5", "linked"];
+
+const MICROS: [&str; 6] = ["map", "strcat", "closure", "channel", "iface", "loop"];
+
+fn programs() -> Vec<(&'static str, String)> {
+    DEMOS
+        .iter()
+        .map(|n| (*n, format!("./tests/demo/{}.gos", n)))
+        .chain(
+            MICROS
+                .iter()
+                .map(|n| (*n, format!("./benches/micro/{}.gos", n))),
+        )
+        .collect()
+}
+
+fn config() -> types::Config {
+    types::Config {
+        work_dir: Some("./".to_string()),
+        base_path: Some("./std/".to_string()),
+        trace_parser: false,
+        trace_checker: false,
+    }
+}
+
+struct QuietFmt {}
+
+impl Ffi for QuietFmt {
+    fn call(&self, _func_name: &str, _params: Vec<GosValue>) -> Vec<GosValue> {
+        vec![]
+    }
+}
+
+impl QuietFmt {
+    fn new_ffi(_v: Vec<GosValue>) -> FfiResult<Rc<RefCell<dyn Ffi>>> {
+        Ok(Rc::new(RefCell::new(QuietFmt {})))
+    }
+}
+
+fn parse(path: &str) {
+    let src = fs::read_to_string(path).unwrap();
+    let mut fset = fe::FileSet::new();
+    let objs = &mut fe::objects::Objects::new();
+    let el = &fe::errors::ErrorList::new();
+    let base = fset.base();
+    let file = fset.add_file(path.to_string(), Some(base), src.chars().count());
+    let afile = fe::Parser::new(objs, file, el, &src, false).parse_file();
+    assert!(afile.is_some());
+}
+
+fn check(path: &str) {
+    let config = config();
+    let mut fset = fe::FileSet::new();
+    let asto = &mut fe::objects::Objects::new();
+    let tco = &mut types::TCObjects::new();
+    let el = &fe::errors::ErrorList::new();
+    let results = &mut HashMap::new();
+    let pkgs = &mut HashMap::new();
+    let importer = &mut types::Importer::new(&config, &mut fset, pkgs, results, asto, tco, el, 0);
+    let key = types::ImportKey::new(path, "./");
+    assert!(importer.import(&key).is_ok());
+}
+
+fn codegen(path: &str) -> (ByteCode, fe::FileSet) {
+    let mut fset = fe::FileSet::new();
+    let el = &fe::errors::ErrorList::new();
+    let bc = cg::parse_check_gen(path, &config(), &mut fset, el).unwrap();
+    (bc, fset)
+}
+
+fn bench_compile(c: &mut Criterion) {
+    let mut group = c.benchmark_group("compile");
+    for (name, path) in programs().iter() {
+        group.bench_with_input(BenchmarkId::new("parse", name), path, |b, p| {
+            b.iter(|| parse(p))
+        });
+        group.bench_with_input(BenchmarkId::new("check", name), path, |b, p| {
+            b.iter(|| check(p))
+        });
+        group.bench_with_input(BenchmarkId::new("codegen", name), path, |b, p| {
+            b.iter(|| codegen(p))
+        });
+    }
+    group.finish();
+}
+
+fn bench_exec(c: &mut Criterion) {
+    let mut ffi = FfiFactory::new();
+    ffi.register("fmt", Box::new(QuietFmt::new_ffi));
+    let mut group = c.benchmark_group("exec");
+    for (name, path) in programs().iter() {
+        let (bc, fset) = codegen(path);
+        let mut data = vec![];
+        bc.write_to(&mut data, Some(&fset)).unwrap();
+        group.bench_function(*name, |b| {
+            b.iter_batched(
+                || ByteCode::read_from(&mut data.as_slice()).unwrap(),
+                |(bc, fset)| GosVM::new(bc, &ffi, fset.as_ref()).run(),
+                BatchSize::SmallInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_compile, bench_exec);
+criterion_main!(benches);
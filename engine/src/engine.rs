@@ -2,7 +2,10 @@ extern crate goscript_codegen as cg;
 extern crate goscript_parser as fe;
 extern crate goscript_types as types;
 extern crate goscript_vm as vm;
-use super::std::fmt;
+use super::std::{fmt, os};
+use std::collections::HashMap;
+use std::fmt as std_fmt;
+use std::io::{self, Read, Write};
 
 pub struct Config {
     // working directory
@@ -15,46 +18,255 @@ pub struct Config {
     pub trace_checker: bool,
     // proint debug info for vm
     pub trace_vm: bool,
+    // count the opcodes run and print the statistics at the end
+    pub opcode_stats: bool,
+}
+
+/// RunError is why a program didn't run to its end
+#[derive(Debug)]
+pub enum RunError {
+    /// the number of errors found while compiling it
+    Compile(usize),
+    /// the bytecode can't be read
+    Io(io::Error),
+    /// a goroutine panicked and the panic wasn't recovered
+    Panic,
+}
+
+impl std_fmt::Display for RunError {
+    fn fmt(&self, f: &mut std_fmt::Formatter) -> std_fmt::Result {
+        match self {
+            RunError::Compile(n) => write!(f, "{} errors", n),
+            RunError::Io(e) => write!(f, "{}", e),
+            RunError::Panic => f.write_str("panicked"),
+        }
+    }
+}
+
+/// Session is the programs run one after another by run_session, the later
+/// ones see the variables and the values of the earlier ones
+pub struct Session {
+    fs: fe::FileSet,
+    kept: cg::entry::Kept,
+}
+
+impl Session {
+    pub fn new() -> Session {
+        Session {
+            fs: fe::FileSet::new(),
+            kept: cg::entry::Kept::new(),
+        }
+    }
 }
 
 pub struct Engine {
     config: Config,
     ffi: vm::ffi::FfiFactory,
+    traces: Option<vm::trace::TraceHandle>,
 }
 
 impl Engine {
     pub fn new(config: Config) -> Engine {
         let mut ffi = vm::ffi::FfiFactory::new();
         ffi.register("fmt", Box::new(fmt::Fmt::new));
+        ffi.register("os", Box::new(os::Os::new_ctor(vec![])));
         Engine {
             config: config,
             ffi: ffi,
+            traces: None,
+        }
+    }
+
+    pub fn run(&self, path: &str) -> Result<(), RunError> {
+        let mut fs = fe::FileSet::new();
+        let bc = self.compile_fs(path, &mut fs).map_err(RunError::Compile)?;
+        let mut vm = vm::vm::GosVM::new(bc, &self.ffi, Some(&fs));
+        self.run_vm(&mut vm)
+    }
+
+    /// run_session runs a main package of a single file from its source, on
+    /// top of the programs run before it in the session: the variables of
+    /// package main keep their values and are not initialized again, except
+    /// the ones named in fresh, which are declared again. name is the file
+    /// name in the positions. Returns false if it panicked, or the errors if
+    /// it doesn't compile.
+    pub fn run_session(
+        &self,
+        session: &mut Session,
+        name: &str,
+        source: &str,
+        fresh: &[String],
+    ) -> Result<bool, fe::errors::ErrorList> {
+        let config = self.types_config();
+        let mut el = fe::errors::ErrorList::new();
+        let code = cg::entry::parse_check_gen_kept(
+            name,
+            source,
+            &config,
+            &mut session.fs,
+            &el,
+            &mut session.kept,
+            fresh,
+        );
+        match code {
+            Ok(bc) => {
+                let mut vm = vm::vm::GosVM::new(bc, &self.ffi, Some(&session.fs));
+                if let Some(handle) = &self.traces {
+                    vm.set_trace_handle(handle.clone());
+                }
+                // a panic is printed with its trace
+                let ok = vm.run();
+                session.kept.ran(vm.into_code(), ok);
+                Ok(ok)
+            }
+            Err(_) => {
+                el.sort();
+                Err(el)
+            }
+        }
+    }
+
+    /// check parses and type checks the program at path, without generating
+    /// the code. Returns the errors if there are any.
+    pub fn check(&self, path: &str) -> Result<(), fe::errors::ErrorList> {
+        let mut fs = fe::FileSet::new();
+        let config = self.types_config();
+        let mut el = fe::errors::ErrorList::new();
+        let asto = &mut fe::objects::Objects::new();
+        let tco = &mut types::TCObjects::new();
+        let results = &mut HashMap::new();
+        let pkgs = &mut HashMap::new();
+        let importer =
+            &mut types::Importer::new(&config, &mut fs, pkgs, results, asto, tco, &el, 0);
+        let key = types::ImportKey::new(path, "./");
+        let _ = importer.import(&key);
+        if el.len() > 0 {
+            el.sort();
+            Err(el)
+        } else {
+            Ok(())
         }
     }
 
-    pub fn run(&self, path: &str) -> usize {
-        let config = types::Config {
+    /// debug runs the program at path with a debugger, see
+    /// vm::debug::DebugHandler. Returns the number of errors in the program.
+    pub fn debug(&self, path: &str, handler: Box<dyn vm::debug::DebugHandler>) -> usize {
+        let mut fs = fe::FileSet::new();
+        match self.compile_fs(path, &mut fs) {
+            Ok(bc) => {
+                let vm = vm::vm::GosVM::new(bc, &self.ffi, Some(&fs));
+                vm.debug(handler);
+                0
+            }
+            Err(err_cnt) => err_cnt,
+        }
+    }
+
+    /// compile writes the bytecode of the program at path to w, so that it
+    /// can be run later with run_bytecode, without parsing and type checking.
+    /// Returns the number of errors in the program.
+    pub fn compile<W: Write>(&self, path: &str, w: &mut W) -> io::Result<usize> {
+        let mut fs = fe::FileSet::new();
+        match self.compile_fs(path, &mut fs) {
+            Ok(bc) => bc.write_to(w, Some(&fs)).map(|_| 0),
+            Err(err_cnt) => Ok(err_cnt),
+        }
+    }
+
+    pub fn run_bytecode<R: Read>(&self, r: &mut R) -> Result<(), RunError> {
+        let (bc, fs) = vm::vm::ByteCode::read_from(r).map_err(RunError::Io)?;
+        let mut vm = vm::vm::GosVM::new(bc, &self.ffi, fs.as_ref());
+        self.run_vm(&mut vm)
+    }
+
+    fn run_vm(&self, vm: &mut vm::vm::GosVM) -> Result<(), RunError> {
+        if let Some(handle) = &self.traces {
+            vm.set_trace_handle(handle.clone());
+        }
+        if self.config.opcode_stats {
+            print!("{}", vm.opcode_stats());
+            Ok(())
+        } else if vm.run() {
+            Ok(())
+        } else {
+            Err(RunError::Panic)
+        }
+    }
+
+    /// jit_compiled runs the program at path and returns the names of the
+    /// functions the JIT compiled to native code while it ran
+    #[cfg(feature = "jit")]
+    pub fn jit_compiled(&self, path: &str) -> Result<Vec<String>, RunError> {
+        let mut fs = fe::FileSet::new();
+        let bc = self.compile_fs(path, &mut fs).map_err(RunError::Compile)?;
+        let mut vm = vm::vm::GosVM::new(bc, &self.ffi, Some(&fs));
+        self.run_vm(&mut vm)?;
+        Ok(vm.jit_compiled())
+    }
+
+    /// disassemble returns the bytecode listing of the program at path,
+    /// or the number of errors in the program
+    pub fn disassemble(&self, path: &str) -> Result<String, usize> {
+        let mut fs = fe::FileSet::new();
+        let bc = self.compile_fs(path, &mut fs)?;
+        Ok(vm::disasm::Disassembler::new(&bc, Some(&fs)).to_string())
+    }
+
+    /// profile runs the program at path with the profiler on, and returns
+    /// the profile, or the number of errors in the program. The JIT is off
+    /// while profiling, so the numbers are of the interpreter.
+    pub fn profile(&self, path: &str) -> Result<vm::profile::Profile, usize> {
+        let mut fs = fe::FileSet::new();
+        let bc = self.compile_fs(path, &mut fs)?;
+        let vm = vm::vm::GosVM::new(bc, &self.ffi, Some(&fs));
+        Ok(vm.profile())
+    }
+
+    /// coverage runs the program at path recording the lines and branches
+    /// that ran, and returns the coverage, or the number of errors in the
+    /// program. The JIT is off while recording.
+    pub fn coverage(&self, path: &str) -> Result<vm::coverage::Coverage, usize> {
+        let mut fs = fe::FileSet::new();
+        let bc = self.compile_fs(path, &mut fs)?;
+        let vm = vm::vm::GosVM::new(bc, &self.ffi, Some(&fs));
+        Ok(vm.coverage().unwrap())
+    }
+
+    fn compile_fs(&self, path: &str, fs: &mut fe::FileSet) -> Result<vm::vm::ByteCode, usize> {
+        let config = self.types_config();
+        let el = &mut fe::errors::ErrorList::new();
+        let code = cg::entry::parse_check_gen(path, &config, fs, el);
+        if code.is_err() && self.config.trace_vm {
+            el.sort();
+            print!("{}", el);
+        }
+        code
+    }
+
+    /// trace_handle returns a handle that can be sent to another thread, to
+    /// get the traces of all the goroutines of the program being run, like a
+    /// watchdog does when it fires
+    pub fn trace_handle(&mut self) -> vm::trace::TraceHandle {
+        self.traces
+            .get_or_insert_with(vm::trace::TraceHandle::new)
+            .clone()
+    }
+
+    fn types_config(&self) -> types::Config {
+        types::Config {
             work_dir: self.config.work_dir.clone(),
             base_path: self.config.base_path.clone(),
             trace_parser: self.config.trace_parser,
             trace_checker: self.config.trace_checker,
-        };
-        let mut fs = fe::FileSet::new();
-        let el = &mut fe::errors::ErrorList::new();
-        let code = cg::entry::parse_check_gen(path, &config, &mut fs, el);
-        if let Ok(bc) = code {
-            let vm = vm::vm::GosVM::new(bc, &self.ffi, Some(&fs));
-            vm.run();
-            0
-        } else {
-            if self.config.trace_vm {
-                el.sort();
-                print!("{}", el);
-            }
-            code.unwrap_err()
         }
     }
 
+    /// set_args sets the command line arguments the program gets with
+    /// os.Args, the first one is the name of the program
+    pub fn set_args(&mut self, args: Vec<String>) {
+        self.ffi.register("os", Box::new(os::Os::new_ctor(args)));
+    }
+
     pub fn register_extension(&mut self, name: &'static str, ctor: Box<vm::ffi::Ctor>) {
         self.ffi.register(name, ctor);
     }
@@ -0,0 +1,32 @@
+use goscript_vm::ffi::{Ffi, FfiResult};
+use goscript_vm::value::GosValue;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+pub struct Os {
+    args: Rc<Vec<String>>,
+}
+
+impl Ffi for Os {
+    fn call(&self, func_name: &str, params: Vec<GosValue>) -> Vec<GosValue> {
+        match func_name {
+            "argc" => vec![GosValue::Int(self.args.len() as isize)],
+            "arg" => {
+                let i = *params[0].as_int() as usize;
+                vec![GosValue::new_str(self.args[i].clone())]
+            }
+            _ => unreachable!(),
+        }
+    }
+}
+
+impl Os {
+    /// new_ctor returns the constructor of the ffi with the command line
+    /// arguments of the program
+    pub fn new_ctor(
+        args: Vec<String>,
+    ) -> impl Fn(Vec<GosValue>) -> FfiResult<Rc<RefCell<dyn Ffi>>> {
+        let args = Rc::new(args);
+        move |_| Ok(Rc::new(RefCell::new(Os { args: args.clone() })) as Rc<RefCell<dyn Ffi>>)
+    }
+}
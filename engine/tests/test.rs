@@ -1,17 +1,47 @@
 #[macro_use]
 extern crate time_test;
 extern crate goscript_engine as engine;
+extern crate goscript_vm as vm;
+
+use std::cell::RefCell;
+use std::io::{self, Write};
+use std::rc::Rc;
+use vm::debug::{Breakpoints, Command, Console, DebugHandler, Stop};
+use vm::instruction::{Opcode, ValueType};
+
+/// config is the config of most tests, with the std of the engine and the
+/// vm traces on
+fn config() -> engine::Config {
+    engine::Config {
+        work_dir: Some("./".to_string()),
+        base_path: Some("./std/".to_string()),
+        trace_parser: false,
+        trace_checker: false,
+        trace_vm: true,
+        opcode_stats: false,
+    }
+}
 
 fn run(path: &str, trace: bool) -> usize {
     let cfg = engine::Config {
-        work_dir: Some("./".to_string()),
-        base_path: Some("./std/".to_string()),
         trace_parser: trace,
         trace_checker: trace,
-        trace_vm: true,
+        ..config()
     };
     let engine = engine::Engine::new(cfg);
-    engine.run(path)
+    match engine.run(path) {
+        Ok(()) => 0,
+        Err(engine::RunError::Compile(err_cnt)) => err_cnt,
+        Err(e) => panic!("{}: {}", path, e),
+    }
+}
+
+fn run_bytecode(path: &str) {
+    let engine = engine::Engine::new(config());
+    let mut buf = vec![];
+    let err_cnt = engine.compile(path, &mut buf).unwrap();
+    assert!(err_cnt == 0);
+    engine.run_bytecode(&mut buf.as_slice()).unwrap();
 }
 
 #[test]
@@ -166,6 +196,396 @@ fn test_interface2() {
     assert!(err_cnt == 0);
 }
 
+#[test]
+fn test_method() {
+    let err_cnt = run("./tests/group1/method.gos", true);
+    assert!(err_cnt == 0);
+}
+
+#[test]
+fn test_generics() {
+    let err_cnt = run("./tests/group1/generics.gos", true);
+    assert!(err_cnt == 0);
+    // the instances are named after their type arguments
+    let engine = engine::Engine::new(config());
+    let listing = engine.disassemble("./tests/group1/generics.gos").unwrap();
+    assert!(listing.contains("TEXT main.Double[int] "));
+    assert!(listing.contains("TEXT main.Double[float64] "));
+    assert!(listing.contains("TEXT main.Map[int,string] "));
+    assert!(listing.contains("; Function main.Double[int]\n"));
+}
+
+#[test]
+fn test_constfloat() {
+    let err_cnt = run("./tests/group1/constfloat.gos", true);
+    assert!(err_cnt == 0);
+}
+
+#[test]
+fn test_bytestring() {
+    let err_cnt = run("./tests/group1/bytestring.gos", true);
+    assert!(err_cnt == 0);
+}
+
+#[test]
+fn test_compkey() {
+    let err_cnt = run("./tests/group1/compkey.gos", true);
+    assert!(err_cnt == 0);
+}
+
+#[test]
+fn test_peephole() {
+    let err_cnt = run("./tests/group1/peephole.gos", true);
+    assert!(err_cnt == 0);
+
+    let engine = engine::Engine::new(config());
+    let listing = engine.disassemble("./tests/group1/peephole.gos").unwrap();
+    assert!(listing.contains("LOAD_LOCAL_ADD_IMM"));
+}
+
+#[test]
+fn test_register() {
+    let err_cnt = run("./tests/group1/register.gos", true);
+    assert!(err_cnt == 0);
+    run_bytecode("./tests/group1/register.gos");
+
+    let engine = engine::Engine::new(config());
+    let listing = engine.disassemble("./tests/group1/register.gos").unwrap();
+    assert!(listing.contains("LOAD_STRUCT_FIELD_R"));
+    assert!(listing.contains("LOAD_INDEX_R"));
+    assert!(listing.contains("LSS_RI"));
+    assert!(listing.contains("ADD_RI"));
+}
+
+#[test]
+fn test_specialise() {
+    let err_cnt = run("./tests/group1/specialise.gos", true);
+    assert!(err_cnt == 0);
+    run_bytecode("./tests/group1/specialise.gos");
+
+    let engine = engine::Engine::new(config());
+    let listing = engine.disassemble("./tests/group1/specialise.gos").unwrap();
+    assert!(listing.contains("ADD_IMM_INT"));
+    assert!(listing.contains("GTR_FLOAT64"));
+    assert!(listing.contains("QUO_FLOAT64"));
+}
+
+/// Recorder steps through debug.gos and writes down where it stops
+struct Recorder {
+    log: Rc<RefCell<Vec<String>>>,
+    cmds: Vec<Command>,
+}
+
+impl DebugHandler for Recorder {
+    fn started(&mut self, bps: &mut Breakpoints) -> Command {
+        assert!(bps.set("group1/debug.gos", 23) == Some(1));
+        assert!(bps.set("debug.gos", 30) == Some(2));
+        assert!(bps.set("debug.gos", 19).is_none());
+        Command::Continue
+    }
+
+    fn stopped(&mut self, stop: &Stop, _: &mut Breakpoints) -> Command {
+        let pos = stop.position(0).unwrap();
+        let mut entry = format!(
+            "{:?} {} {} {}",
+            stop.reason(),
+            stop.fiber(),
+            stop.func_name(0),
+            pos.line
+        );
+        for name in ["n", "k", "total", "main.total", "s", "x"].iter() {
+            if let Some(v) = stop.lookup(0, name) {
+                entry.push_str(&format!(" {}={}", name, v));
+            }
+        }
+        self.log.borrow_mut().push(entry);
+        if self.cmds.is_empty() {
+            Command::Continue
+        } else {
+            self.cmds.remove(0)
+        }
+    }
+
+    fn exited(&mut self) {
+        self.log.borrow_mut().push("exited".to_string());
+    }
+}
+
+#[test]
+fn test_debug() {
+    let err_cnt = run("./tests/group1/debug.gos", true);
+    assert!(err_cnt == 0);
+
+    let engine = engine::Engine::new(config());
+    let log = Rc::new(RefCell::new(vec![]));
+    let handler = Recorder {
+        log: log.clone(),
+        cmds: vec![
+            Command::StepOut,
+            Command::StepInto,
+            Command::StepOver,
+            Command::StepOver,
+            Command::StepOut,
+            Command::Continue,
+        ],
+    };
+    let err_cnt = engine.debug("./tests/group1/debug.gos", Box::new(handler));
+    assert!(err_cnt == 0);
+    let expected = vec![
+        "Breakpoint(1) 1 main.main.func1 23 n=55 k=10 total=0 main.total=0",
+        "Step 1 main.main 27 k=10 total=65 main.total=65 s=0",
+        "Step 1 main.addAll 13 total=65 main.total=65 s=0 x=0",
+        "Step 1 main.addAll 14 total=65 main.total=65 s=0 x=0",
+        "Step 1 main.addAll 15 total=65 main.total=65 s=0 x=1",
+        "Step 1 main.main 27 k=10 total=65 main.total=65 s=0",
+        "Breakpoint(2) 2 main.main.func2 30 total=65 main.total=65",
+        "exited",
+    ];
+    assert_eq!(*log.borrow(), expected);
+
+    // the same with the console
+    let input = "break debug.gos:23\nc\nbt\np n\np k\nlocals\nglobals\nq\n";
+    let output = SharedBuf::default();
+    let console = Console::new(input.as_bytes(), output.clone());
+    let err_cnt = engine.debug("./tests/group1/debug.gos", Box::new(console));
+    assert!(err_cnt == 0);
+    let text = String::from_utf8(output.0.borrow().clone()).unwrap();
+    assert!(text.contains("breakpoint 1 at debug.gos:23"));
+    assert!(text.contains("goroutine 1 hit breakpoint 1"));
+    assert!(text.contains("#0 main.main.func1 at tests/group1/debug.gos:23"));
+    assert!(text.contains("#1 main.main at tests/group1/debug.gos:26"));
+    assert!(text.contains("n = 55"));
+    assert!(text.contains("k = 10"));
+    assert!(text.contains("main.total = 0"));
+    assert!(text.ends_with("(gos) the program exited\n"));
+}
+
+#[derive(Clone, Default)]
+struct SharedBuf(Rc<RefCell<Vec<u8>>>);
+
+impl Write for SharedBuf {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn test_profile() {
+    let err_cnt = run("./tests/group1/profile.gos", true);
+    assert!(err_cnt == 0);
+
+    let engine = engine::Engine::new(config());
+    let prof = engine.profile("./tests/group1/profile.gos").unwrap();
+    let funcs = prof.functions();
+    assert_eq!(funcs[0].0, "main.fib");
+    assert_eq!(funcs[1].0, "main.count");
+    assert_eq!(funcs.iter().map(|f| f.1).sum::<u64>(), prof.instructions());
+    // the loop body runs 100 times
+    let lines = prof.lines("main.count");
+    assert!(lines
+        .iter()
+        .any(|(l, n)| *l == 13 && *n >= 100 && *n < 1000));
+
+    let mut folded = vec![];
+    prof.write_folded(&mut folded).unwrap();
+    let folded = String::from_utf8(folded).unwrap();
+    assert!(folded.contains("<entry>;main.main;main.fib;main.fib;main.fib"));
+    let mut pprof = vec![];
+    prof.write_pprof(&mut pprof).unwrap();
+    let text = String::from_utf8_lossy(&pprof);
+    assert!(text.contains("instructions"));
+    assert!(text.contains("main.fib"));
+    assert!(text.contains("profile.gos"));
+}
+
+#[test]
+fn test_opcode_stats() {
+    let cfg = engine::Config {
+        opcode_stats: true,
+        ..config()
+    };
+    let engine = engine::Engine::new(cfg);
+    assert!(engine.run("./tests/group1/opstats.gos").is_ok());
+
+    let mut buf = vec![];
+    engine
+        .compile("./tests/group1/opstats.gos", &mut buf)
+        .unwrap();
+    let (bc, fs) = vm::vm::ByteCode::read_from(&mut buf.as_slice()).unwrap();
+    let ffi = vm::ffi::FfiFactory::new();
+    let stats = vm::vm::GosVM::new(bc, &ffi, fs.as_ref()).opcode_stats();
+    let total = stats.instructions();
+    assert_eq!(stats.ops().iter().map(|x| x.1).sum::<u64>(), total);
+    assert_eq!(stats.types().iter().map(|x| x.1).sum::<u64>(), total);
+    // the loop runs 110 times
+    assert!(stats.ops()[0].1 >= 110);
+    assert!(stats
+        .types()
+        .iter()
+        .any(|((_, t), n)| *t == ValueType::Int && *n >= 110));
+    // the jumps back make no pairs
+    let pairs = stats.pairs();
+    assert!(pairs.iter().map(|x| x.1).sum::<u64>() < total);
+    assert!(pairs
+        .iter()
+        .any(|((_, b), n)| *b == Opcode::JUMP_IF_NOT && *n >= 110));
+    assert!(!pairs
+        .iter()
+        .any(|((a, b), _)| *a == Opcode::JUMP && *b == Opcode::LSS_R));
+    let report = stats.to_string();
+    assert!(report.contains("opcodes by type:"));
+    assert!(report.contains("opcode pairs:"));
+}
+
+#[test]
+fn test_coverage() {
+    let err_cnt = run("./tests/group1/coverage.gos", true);
+    assert!(err_cnt == 0);
+
+    let engine = engine::Engine::new(config());
+    let cov = engine.coverage("./tests/group1/coverage.gos").unwrap();
+    let file = "tests/group1/coverage.gos";
+    assert!(cov.files().contains(&file));
+    let lines = cov.lines(file);
+    let count = |line: usize| lines.iter().find(|l| l.0 == line).unwrap().1;
+    // the loop runs 5 times, sign never gets a negative
+    assert_eq!(count(19), 5);
+    assert_eq!(count(5), 0);
+    assert_eq!(count(9), 4);
+    assert_eq!(count(13), 0);
+    // no closing braces
+    assert!(lines.iter().all(|l| l.0 != 10));
+    let branches = cov.branches(file);
+    assert!(branches.contains(&(4, Some((5, 0)))));
+    assert!(branches.contains(&(6, Some((4, 1)))));
+    assert!(branches.contains(&(25, None)));
+
+    let mut lcov = vec![];
+    cov.write_lcov(&mut lcov).unwrap();
+    let lcov = String::from_utf8(lcov).unwrap();
+    assert!(lcov.contains("SF:tests/group1/coverage.gos\n"));
+    assert!(lcov.contains("FNDA:5,main.sign\n"));
+    assert!(lcov.contains("FNDA:0,main.never\n"));
+    assert!(lcov.contains("BRDA:25,0,0,-\n"));
+    assert!(lcov.contains("DA:18,6\n"));
+    let mut out = vec![];
+    cov.write_go(&mut out).unwrap();
+    let out = String::from_utf8(out).unwrap();
+    assert!(out.starts_with("mode: count\n"));
+    assert!(out.contains("\ntests/group1/coverage.gos:19.9,19.25 1 5\n"));
+}
+
+/// Dump takes the place of the fmt package, Println asks for the traces
+struct Dump {
+    handle: vm::trace::TraceHandle,
+}
+
+impl vm::ffi::Ffi for Dump {
+    fn call(
+        &self,
+        _func_name: &str,
+        _params: Vec<vm::value::GosValue>,
+    ) -> Vec<vm::value::GosValue> {
+        self.handle.request();
+        vec![]
+    }
+}
+
+#[test]
+fn test_traces() {
+    let mut engine = engine::Engine::new(config());
+    let handle = engine.trace_handle();
+    let h = handle.clone();
+    engine.register_extension(
+        "fmt",
+        Box::new(move |_| Ok(Rc::new(RefCell::new(Dump { handle: h.clone() })))),
+    );
+    assert!(engine.run("./tests/group1/traces.gos").is_ok());
+
+    let list = handle.take().unwrap();
+    assert_eq!(
+        list.iter().map(|g| g.id).collect::<Vec<usize>>(),
+        vec![1, 2]
+    );
+    let main = &list[0];
+    assert_eq!(main.state, "runnable");
+    let names: Vec<&str> = main.frames.iter().map(|f| f.func.as_str()).collect();
+    assert_eq!(names[..2], ["main.spin", "main.main"]);
+    assert_eq!(
+        main.frames[0].args,
+        Some(vec!["2000".to_string(), "\"dump\"".to_string()])
+    );
+    assert!(main.frames[1]
+        .position
+        .as_ref()
+        .unwrap()
+        .ends_with("traces.gos:28"));
+
+    let worker = &list[1];
+    assert_eq!(worker.state, "chan receive");
+    assert_eq!(worker.frames[0].func, "main.worker");
+    let args = worker.frames[0].args.as_ref().unwrap();
+    assert_eq!(args[1..3], ["\"worker\"", "7"]);
+    assert!(worker.frames[0]
+        .position
+        .as_ref()
+        .unwrap()
+        .ends_with("traces.gos:7"));
+    let text = worker.to_string();
+    assert!(text.starts_with(
+        "goroutine 2 [chan receive]:\nmain.worker(<channel>, \"worker\", 7, <channel>)\n\t"
+    ));
+}
+
+#[test]
+fn test_inline_cache() {
+    let err_cnt = run("./tests/group1/inlinecache.gos", true);
+    assert!(err_cnt == 0);
+}
+
+#[test]
+fn test_escape() {
+    let err_cnt = run("./tests/group1/escape.gos", true);
+    assert!(err_cnt == 0);
+}
+
+#[test]
+fn test_inline() {
+    let err_cnt = run("./tests/group1/inline.gos", true);
+    assert!(err_cnt == 0);
+}
+
+#[test]
+fn test_jit() {
+    let err_cnt = run("./tests/group1/jit.gos", true);
+    assert!(err_cnt == 0);
+
+    #[cfg(feature = "jit")]
+    {
+        let engine = engine::Engine::new(config());
+        let compiled = engine.jit_compiled("./tests/group1/jit.gos").unwrap();
+        for f in [
+            "fib", "collatz", "floats", "bits", "casts", "count", "divAll", "depth",
+        ]
+        .iter()
+        {
+            let name = format!("main.{}", f);
+            assert!(
+                compiled.contains(&name),
+                "{} not compiled: {:?}",
+                name,
+                compiled
+            );
+        }
+        assert!(!compiled.contains(&"main.noisy".to_string()));
+    }
+}
+
 #[test]
 fn test_initorder() {
     let err_cnt = run("./tests/group1/initorder.gos", true);
@@ -265,3 +685,103 @@ fn test_fibonacci() {
     let err_cnt = run("./tests/demo/fibonacci.gos", true);
     assert!(err_cnt == 0);
 }
+
+#[test]
+fn test_bytecode() {
+    for f in [
+        "closure1",
+        "composite",
+        "compkey",
+        "constfloat",
+        "defer",
+        "generics",
+        "initorder",
+        "interface",
+        "map1",
+        "method",
+        "pkg",
+        "pointer",
+        "recover",
+        "typeswitch",
+    ]
+    .iter()
+    {
+        run_bytecode(&format!("./tests/group1/{}.gos", f));
+    }
+}
+
+#[test]
+fn test_bytecode_invalid() {
+    let engine = engine::Engine::new(engine::Config {
+        work_dir: None,
+        base_path: None,
+        trace_vm: false,
+        ..config()
+    });
+    match engine.run_bytecode(&mut &b"GOSC"[..]) {
+        Err(engine::RunError::Io(e)) => assert_eq!(e.kind(), std::io::ErrorKind::InvalidData),
+        r => panic!("{:?}", r),
+    }
+
+    let mut buf = vec![];
+    engine
+        .compile("./tests/group1/func1.gos", &mut buf)
+        .unwrap();
+    buf.truncate(buf.len() / 2);
+    assert!(engine.run_bytecode(&mut buf.as_slice()).is_err());
+}
+
+#[test]
+fn test_disassemble() {
+    let engine = engine::Engine::new(config());
+    let listing = engine.disassemble("./tests/group1/closure1.gos").unwrap();
+    let func_text = |name: &str| {
+        let begin = listing.find(&format!("TEXT {} ", name)).unwrap();
+        let end = listing[begin..]
+            .find("\n\n")
+            .map_or(listing.len(), |i| begin + i);
+        listing[begin..end].to_string()
+    };
+    let main = func_text("main.main");
+    assert!(main.contains("Function main.main.func1"));
+    assert!(main.contains("var    a"));
+    assert!(main.contains("closure1.gos:7:"));
+    let func1 = func_text("main.main.func1");
+    assert!(func1.contains("main.main.a (Int)"));
+    assert!(func1.contains("LOAD_UPVALUE"));
+    assert!(func_text("main.init").contains("var    a"));
+    assert!(listing.contains("TEXT <entry>"));
+}
+
+#[test]
+fn test_args() {
+    let mut engine = engine::Engine::new(config());
+    engine.set_args(vec![
+        "args.gos".to_string(),
+        "one".to_string(),
+        "two".to_string(),
+    ]);
+    assert!(engine.run("./tests/group1/args.gos").is_ok());
+}
+
+#[test]
+fn test_check() {
+    let engine = engine::Engine::new(engine::Config {
+        trace_vm: false,
+        ..config()
+    });
+    assert!(engine.check("./tests/group1/args.gos").is_ok());
+    let el = engine.check("./tests/group1/check.gos").unwrap_err();
+    let errs = el.to_string();
+    assert_eq!(el.len(), 3);
+    assert!(
+        errs.contains("check.gos:4:20  cannot convert 1"),
+        "{}",
+        errs
+    );
+    assert!(
+        errs.contains("check.gos:5:5  undeclared name: undefined"),
+        "{}",
+        errs
+    );
+}
@@ -0,0 +1,359 @@
+//! The analysis of a package. It's type checked, and what the server answers
+//! with is taken out of the results right away, so that the objects of the
+//! parser and the checker don't have to be kept around.
+
+use goscript_parser::ast::{Decl, Expr, Node, Spec};
+use goscript_parser::errors::ErrorList;
+use goscript_parser::objects::{IdentKey, Objects as AstObjects};
+use goscript_parser::{FileSet, Pos, Token};
+use goscript_types::{obj_str, Config, ObjKey, TCObjects};
+use std::collections::HashMap;
+
+/// Range is a range in a file, lines and columns start at 1, the columns are
+/// in chars and the end is not in it
+#[derive(Clone, Debug, PartialEq)]
+pub struct Range {
+    pub file: String,
+    pub start: (usize, usize),
+    pub end: (usize, usize),
+}
+
+impl Range {
+    fn contains(&self, line: usize, col: usize) -> bool {
+        self.start <= (line, col) && (line, col) <= self.end
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Diagnostic {
+    pub range: Range,
+    pub msg: String,
+    pub soft: bool,
+}
+
+/// Occurrence is an identifier that defines or uses an object
+#[derive(Clone, Debug)]
+pub struct Occurrence {
+    pub range: Range,
+    /// the index of the object in the analysis
+    pub obj: usize,
+    pub def: bool,
+}
+
+#[derive(Clone, Debug)]
+pub struct Object {
+    pub name: String,
+    /// what it is, like "var x int" or "func f(n int) int"
+    pub detail: String,
+    /// the identifier that defines it, None for the builtins
+    pub def: Option<Range>,
+}
+
+/// the kinds of the symbols, with their numbers in the protocol
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SymbolKind {
+    Method = 6,
+    Field = 8,
+    Interface = 11,
+    Function = 12,
+    Variable = 13,
+    Constant = 14,
+    Struct = 23,
+    Type = 26,
+}
+
+#[derive(Clone, Debug)]
+pub struct Symbol {
+    pub name: String,
+    pub kind: SymbolKind,
+    /// all of the declaration
+    pub range: Range,
+    /// the name in the declaration
+    pub selection: Range,
+    pub children: Vec<Symbol>,
+}
+
+pub struct Analysis {
+    /// false if a file doesn't parse, there's nothing but the errors then
+    pub checked: bool,
+    errors: Vec<Diagnostic>,
+    idents: Vec<Occurrence>,
+    objs: Vec<Object>,
+    symbols: HashMap<String, Vec<Symbol>>,
+}
+
+impl Analysis {
+    /// new checks the package at path from its files, which are given with
+    /// their names relative to the working directory of the config
+    pub fn new(config: &Config, path: &str, files: &[(String, String)]) -> Analysis {
+        let fset = &mut FileSet::new();
+        let asto = &mut AstObjects::new();
+        let tco = &mut TCObjects::new();
+        let results = &mut HashMap::new();
+        let pkgs = &mut HashMap::new();
+        let el = ErrorList::new();
+        let importer =
+            &mut goscript_types::Importer::new(config, fset, pkgs, results, asto, tco, &el, 0);
+        let pkg = importer.import_files(path, files);
+
+        let mut analysis = Analysis {
+            checked: false,
+            errors: vec![],
+            idents: vec![],
+            objs: vec![],
+            symbols: HashMap::new(),
+        };
+        if let Some(info) = pkg.ok().and_then(|p| results.get(&p)) {
+            analysis.checked = true;
+            let mut indices = HashMap::new();
+            let defs = info
+                .defs
+                .iter()
+                .filter_map(|(i, o)| o.map(|o| (i, o, true)));
+            let uses = info.uses.iter().map(|(i, o)| (i, *o, false));
+            for (ident, okey, def) in defs.chain(uses) {
+                let obj = *indices.entry(okey).or_insert_with(|| {
+                    analysis.objs.push(object(okey, tco, fset));
+                    analysis.objs.len() - 1
+                });
+                analysis.idents.push(Occurrence {
+                    range: ident_range(*ident, asto, fset),
+                    obj,
+                    def,
+                });
+            }
+            // the order of the maps is random
+            analysis.idents.sort_by(|a, b| {
+                (&a.range.file, a.range.start).cmp(&(&b.range.file, b.range.start))
+            });
+            for file in info.ast_files.iter() {
+                let name = fset.position(file.package).filename.to_string();
+                let symbols = file
+                    .decls
+                    .iter()
+                    .flat_map(|d| decl_symbols(d, asto, fset))
+                    .collect();
+                analysis.symbols.insert(name, symbols);
+            }
+        }
+        for e in el.borrow().iter() {
+            let (line, col) = (e.pos.line, e.pos.column);
+            let file = e.pos.filename.to_string();
+            // an error at an identifier is about all of it
+            let range = analysis
+                .idents
+                .iter()
+                .find(|o| o.range.file == file && o.range.start == (line, col))
+                .map_or(
+                    Range {
+                        file,
+                        start: (line, col),
+                        end: (line, col + 1),
+                    },
+                    |o| o.range.clone(),
+                );
+            analysis.errors.push(Diagnostic {
+                range,
+                msg: e.msg.trim_start().to_string(),
+                soft: e.soft,
+            });
+        }
+        analysis
+    }
+
+    pub fn diagnostics(&self, file: &str) -> Vec<&Diagnostic> {
+        self.errors
+            .iter()
+            .filter(|e| e.range.file == file)
+            .collect()
+    }
+
+    /// error_files returns the files with errors, the position of an error can
+    /// be in a package imported
+    pub fn error_files(&self) -> Vec<&str> {
+        let mut files: Vec<&str> = self.errors.iter().map(|e| e.range.file.as_str()).collect();
+        files.sort();
+        files.dedup();
+        files
+    }
+
+    /// at returns the identifier at the line and column
+    pub fn at(&self, file: &str, line: usize, col: usize) -> Option<&Occurrence> {
+        self.idents
+            .iter()
+            .find(|o| o.range.file == file && o.range.contains(line, col))
+    }
+
+    pub fn object(&self, occ: &Occurrence) -> &Object {
+        &self.objs[occ.obj]
+    }
+
+    /// references returns the identifiers of the object, the definition
+    /// included, in the order of the files and lines
+    pub fn references(&self, obj: usize) -> Vec<&Occurrence> {
+        self.idents.iter().filter(|o| o.obj == obj).collect()
+    }
+
+    pub fn symbols(&self, file: &str) -> &[Symbol] {
+        self.symbols.get(file).map_or(&[], |s| s.as_slice())
+    }
+}
+
+fn object(okey: ObjKey, tco: &TCObjects, fset: &FileSet) -> Object {
+    let obj = &tco.lobjs[okey];
+    let name = obj.name().clone();
+    // the objects of the universe have no package and no position
+    let def = obj
+        .pkg()
+        .map(|_| pos_range(obj.pos(), name.chars().count(), fset));
+    Object {
+        detail: obj_str(&okey, tco),
+        name,
+        def,
+    }
+}
+
+fn pos_range(pos: Pos, len: usize, fset: &FileSet) -> Range {
+    let p = fset.position(pos);
+    Range {
+        file: p.filename.to_string(),
+        start: (p.line, p.column),
+        end: (p.line, p.column + len),
+    }
+}
+
+fn ident_range(ident: IdentKey, asto: &AstObjects, fset: &FileSet) -> Range {
+    let id = &asto.idents[ident];
+    pos_range(id.pos, id.name.chars().count(), fset)
+}
+
+fn node_range(pos: Pos, end: Pos, fset: &FileSet) -> Range {
+    let (p, e) = (fset.position(pos), fset.position(end));
+    Range {
+        file: p.filename.to_string(),
+        start: (p.line, p.column),
+        end: (e.line, e.column),
+    }
+}
+
+fn symbol(
+    ident: IdentKey,
+    name: String,
+    kind: SymbolKind,
+    range: Range,
+    asto: &AstObjects,
+    fset: &FileSet,
+) -> Symbol {
+    Symbol {
+        name,
+        kind,
+        range,
+        selection: ident_range(ident, asto, fset),
+        children: vec![],
+    }
+}
+
+/// decl_symbols returns the symbols of a declaration of the package, the
+/// methods are named like (*T).M, as gopls does
+fn decl_symbols(decl: &Decl, asto: &AstObjects, fset: &FileSet) -> Vec<Symbol> {
+    let range = node_range(decl.pos(asto), decl.end(asto), fset);
+    match decl {
+        Decl::Bad(_) => vec![],
+        Decl::Func(key) => {
+            let fdecl = &asto.fdecls[*key];
+            let name = asto.idents[fdecl.name].name.clone();
+            let recv = fdecl
+                .recv
+                .as_ref()
+                .and_then(|r| r.list.first())
+                .map(|f| &asto.fields[*f].typ);
+            let (name, kind) = match recv {
+                Some(Expr::Star(s)) => (
+                    format!("(*{}).{}", type_name(&s.expr, asto), name),
+                    SymbolKind::Method,
+                ),
+                Some(t) => (
+                    format!("{}.{}", type_name(t, asto), name),
+                    SymbolKind::Method,
+                ),
+                None => (name, SymbolKind::Function),
+            };
+            vec![symbol(fdecl.name, name, kind, range, asto, fset)]
+        }
+        Decl::Gen(gdecl) => {
+            let mut list = vec![];
+            for key in gdecl.specs.iter() {
+                let spec = &asto.specs[*key];
+                // a spec in parentheses is a declaration of its own
+                let range = match gdecl.l_paran {
+                    Some(_) => node_range(spec.pos(asto), spec.end(asto), fset),
+                    None => range.clone(),
+                };
+                match spec {
+                    Spec::Import(_) => {}
+                    Spec::Type(ts) => {
+                        let name = asto.idents[ts.name].name.clone();
+                        let (kind, children) = match &ts.typ {
+                            Expr::Struct(st) => (
+                                SymbolKind::Struct,
+                                field_symbols(&st.fields.list, asto, fset),
+                            ),
+                            Expr::Interface(it) => (
+                                SymbolKind::Interface,
+                                field_symbols(&it.methods.list, asto, fset),
+                            ),
+                            _ => (SymbolKind::Type, vec![]),
+                        };
+                        let mut s = symbol(ts.name, name, kind, range, asto, fset);
+                        s.children = children;
+                        list.push(s);
+                    }
+                    Spec::Value(vs) => {
+                        let kind = match gdecl.token {
+                            Token::CONST => SymbolKind::Constant,
+                            _ => SymbolKind::Variable,
+                        };
+                        for ident in vs.names.iter() {
+                            let name = asto.idents[*ident].name.clone();
+                            if name != "_" {
+                                list.push(symbol(*ident, name, kind, range.clone(), asto, fset));
+                            }
+                        }
+                    }
+                }
+            }
+            list
+        }
+    }
+}
+
+fn field_symbols(
+    fields: &[goscript_parser::objects::FieldKey],
+    asto: &AstObjects,
+    fset: &FileSet,
+) -> Vec<Symbol> {
+    let mut list = vec![];
+    for key in fields.iter() {
+        let range = node_range(key.pos(asto), key.end(asto), fset);
+        let field = &asto.fields[*key];
+        let kind = match field.typ {
+            Expr::Func(_) => SymbolKind::Method,
+            _ => SymbolKind::Field,
+        };
+        for ident in field.names.iter() {
+            let name = asto.idents[*ident].name.clone();
+            list.push(symbol(*ident, name, kind, range.clone(), asto, fset));
+        }
+    }
+    list
+}
+
+fn type_name(expr: &Expr, asto: &AstObjects) -> String {
+    match expr {
+        Expr::Ident(i) => asto.idents[*i].name.clone(),
+        // a generic type, T[K]
+        Expr::Index(i) => type_name(&i.expr, asto),
+        Expr::IndexList(i) => type_name(&i.expr, asto),
+        _ => "?".to_string(),
+    }
+}
@@ -0,0 +1,12 @@
+//! A Language Server Protocol server for goscript, for the editors to show
+//! the errors, the types and the definitions of the identifiers, their
+//! references and the symbols of the files. It talks to the editor through
+//! stdin and stdout.
+
+mod analysis;
+mod protocol;
+mod server;
+
+pub use analysis::{Analysis, Diagnostic, Object, Occurrence, Range, Symbol, SymbolKind};
+pub use protocol::{read_message, write_message};
+pub use server::serve;
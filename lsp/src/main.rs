@@ -0,0 +1,5 @@
+use std::io::{self, BufReader};
+
+fn main() {
+    goscript_lsp::serve(BufReader::new(io::stdin()), io::stdout());
+}
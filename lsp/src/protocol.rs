@@ -0,0 +1,110 @@
+//! The base protocol of LSP: JSON-RPC messages, each after a Content-Length
+//! header, see
+//! https://microsoft.github.io/language-server-protocol/specifications/specification-current
+
+use serde_json::{json, Value};
+use std::collections::VecDeque;
+use std::io::{self, BufRead, Write};
+use std::sync::mpsc::Receiver;
+
+/// the error codes of JSON-RPC and LSP
+pub const INVALID_PARAMS: i64 = -32602;
+pub const METHOD_NOT_FOUND: i64 = -32601;
+pub const INVALID_REQUEST: i64 = -32600;
+pub const SERVER_NOT_INITIALIZED: i64 = -32002;
+
+/// read_message reads the next message, it's None at the end of the input
+pub fn read_message<R: BufRead>(r: &mut R) -> io::Result<Option<Value>> {
+    let mut len = None;
+    loop {
+        let mut line = String::new();
+        if r.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some(v) = line.strip_prefix("Content-Length:") {
+            len = v.trim().parse::<usize>().ok();
+        }
+    }
+    let len = len.ok_or_else(|| invalid("missing Content-Length"))?;
+    let mut buf = vec![0; len];
+    r.read_exact(&mut buf)?;
+    serde_json::from_slice(&buf)
+        .map(Some)
+        .map_err(|e| invalid(&e.to_string()))
+}
+
+pub fn write_message<W: Write>(w: &mut W, msg: &Value) -> io::Result<()> {
+    let body = msg.to_string();
+    write!(w, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    w.flush()
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// Connection is the connection to the client, the messages come from a
+/// reader thread so that the server can tell if more are waiting
+pub struct Connection {
+    out: Box<dyn Write>,
+    messages: Receiver<Value>,
+    pending: VecDeque<Value>,
+}
+
+impl Connection {
+    pub fn new(messages: Receiver<Value>, out: Box<dyn Write>) -> Connection {
+        Connection {
+            out,
+            messages,
+            pending: VecDeque::new(),
+        }
+    }
+
+    /// next waits for the next message, it's None when the client is gone
+    pub fn next(&mut self) -> Option<Value> {
+        self.pending
+            .pop_front()
+            .or_else(|| self.messages.recv().ok())
+    }
+
+    /// waiting returns true if a message has come in and not been read
+    pub fn waiting(&mut self) -> bool {
+        while let Ok(msg) = self.messages.try_recv() {
+            self.pending.push_back(msg);
+        }
+        !self.pending.is_empty()
+    }
+
+    pub fn respond(&mut self, req: &Value, result: Value) {
+        self.send(json!({
+            "jsonrpc": "2.0",
+            "id": req["id"],
+            "result": result,
+        }));
+    }
+
+    pub fn fail(&mut self, req: &Value, code: i64, msg: &str) {
+        self.send(json!({
+            "jsonrpc": "2.0",
+            "id": req["id"],
+            "error": {"code": code, "message": msg},
+        }));
+    }
+
+    pub fn notify(&mut self, method: &str, params: Value) {
+        self.send(json!({
+            "jsonrpc": "2.0",
+            "method": method,
+            "params": params,
+        }));
+    }
+
+    fn send(&mut self, msg: Value) {
+        // the client is gone if this fails, which the reader finds out too
+        let _ = write_message(&mut self.out, &msg);
+    }
+}
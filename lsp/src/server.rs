@@ -0,0 +1,511 @@
+//! The server checks a package again when its files change, the other
+//! packages keep their analyses. The changes that come in together are
+//! checked once, after the last of them. A file of package main is a
+//! program of its own, like goscript runs them, the files of any other
+//! package are checked with the ones in their directory. The columns are
+//! counted in chars, which is what the clients count as well for the text
+//! without characters out of the BMP.
+
+use super::analysis::{Analysis, Range, Symbol};
+use super::protocol::*;
+use goscript_types::Config;
+use serde_json::{json, Value};
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::fs;
+use std::io::{BufRead, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::thread;
+
+/// serve answers the messages from input until the client exits or the
+/// input ends
+pub fn serve<R: BufRead + Send + 'static, W: Write + 'static>(mut input: R, output: W) {
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        while let Ok(Some(msg)) = read_message(&mut input) {
+            if tx.send(msg).is_err() {
+                break;
+            }
+        }
+    });
+    let mut conn = Connection::new(rx, Box::new(output));
+    let mut ws: Option<Workspace> = None;
+    while let Some(msg) = conn.next() {
+        let method = msg["method"].as_str().unwrap_or("");
+        let is_request = !msg["id"].is_null();
+        match (method, &mut ws) {
+            ("exit", _) => break,
+            ("initialize", None) => {
+                ws = Some(Workspace::new(&msg["params"]));
+                conn.respond(
+                    &msg,
+                    json!({
+                        "capabilities": {
+                            "textDocumentSync": {"openClose": true, "change": 2, "save": {"includeText": true}},
+                            "definitionProvider": true,
+                            "hoverProvider": true,
+                            "referencesProvider": true,
+                            "documentSymbolProvider": true,
+                        },
+                        "serverInfo": {"name": "goscript-lsp"},
+                    }),
+                );
+            }
+            ("initialize", Some(_)) => conn.fail(&msg, INVALID_REQUEST, "initialized already"),
+            (_, None) if is_request => conn.fail(&msg, SERVER_NOT_INITIALIZED, "initialize first"),
+            (_, None) => {}
+            ("shutdown", Some(_)) => conn.respond(&msg, Value::Null),
+            (_, Some(ws)) if is_request => {
+                // the answer is from the text as it is now
+                if !ws.dirty.is_empty() {
+                    ws.check_dirty(&mut conn);
+                }
+                match ws.request(method, &msg["params"]) {
+                    Ok(result) => conn.respond(&msg, result),
+                    Err((code, err)) => conn.fail(&msg, code, &err),
+                }
+            }
+            (_, Some(ws)) => ws.notification(method, &msg["params"]),
+        }
+        if let Some(ws) = &mut ws {
+            if !ws.dirty.is_empty() && !conn.waiting() {
+                ws.check_dirty(&mut conn);
+            }
+        }
+    }
+}
+
+type RequestResult = Result<Value, (i64, String)>;
+
+/// the requests answered once initialized, besides shutdown
+const METHODS: &[&str] = &[
+    "textDocument/definition",
+    "textDocument/hover",
+    "textDocument/references",
+    "textDocument/documentSymbol",
+];
+
+struct Workspace {
+    root: PathBuf,
+    config: Config,
+    /// the texts of the open files
+    docs: HashMap<PathBuf, String>,
+    /// the analyses by package, the directory of the package or the file of
+    /// a program. It's the last one that parsed, to find things in while
+    /// the file being edited doesn't.
+    units: HashMap<PathBuf, Analysis>,
+    dirty: HashSet<PathBuf>,
+    /// the files with diagnostics published by package
+    published: HashMap<PathBuf, Vec<PathBuf>>,
+}
+
+impl Workspace {
+    fn new(params: &Value) -> Workspace {
+        let root = params["rootUri"]
+            .as_str()
+            .or(params["workspaceFolders"][0]["uri"].as_str())
+            .map(uri_to_path)
+            .or(params["rootPath"].as_str().map(PathBuf::from))
+            .unwrap_or_else(|| env::current_dir().unwrap());
+        let root = root.canonicalize().unwrap_or(root);
+        let base_path = params["initializationOptions"]["basePath"]
+            .as_str()
+            .map(|s| s.to_string())
+            .or(env::var("GOSCRIPT_PATH").ok())
+            .map_or(root.join("std"), |p| root.join(p));
+        Workspace {
+            config: Config {
+                work_dir: Some(root.to_string_lossy().to_string()),
+                base_path: Some(base_path.to_string_lossy().to_string()),
+                trace_parser: false,
+                trace_checker: false,
+            },
+            root,
+            docs: HashMap::new(),
+            units: HashMap::new(),
+            dirty: HashSet::new(),
+            published: HashMap::new(),
+        }
+    }
+
+    fn notification(&mut self, method: &str, params: &Value) {
+        let doc = &params["textDocument"];
+        let path = match doc["uri"].as_str() {
+            Some(uri) => self.canonical(&uri_to_path(uri)),
+            None => return,
+        };
+        match method {
+            "textDocument/didOpen" => {
+                let text = doc["text"].as_str().unwrap_or("").to_string();
+                self.docs.insert(path.clone(), text);
+            }
+            "textDocument/didChange" => {
+                let text = self.docs.entry(path.clone()).or_default();
+                for change in params["contentChanges"].as_array().unwrap_or(&vec![]) {
+                    apply_change(text, change);
+                }
+            }
+            "textDocument/didSave" => {
+                if let Some(text) = params["text"].as_str() {
+                    self.docs.insert(path.clone(), text.to_string());
+                }
+            }
+            "textDocument/didClose" => {
+                self.docs.remove(&path);
+            }
+            _ => return,
+        }
+        // the file may have moved to another package
+        let unit = self.unit(&path);
+        for (u, _) in self.units.iter() {
+            if *u == path || *u == path.parent().unwrap() {
+                self.dirty.insert(u.clone());
+            }
+        }
+        self.dirty.insert(unit);
+    }
+
+    fn request(&mut self, method: &str, params: &Value) -> RequestResult {
+        if !METHODS.contains(&method) {
+            return Err((METHOD_NOT_FOUND, format!("{} is not supported", method)));
+        }
+        let path = match params["textDocument"]["uri"].as_str() {
+            Some(uri) => self.canonical(&uri_to_path(uri)),
+            None => return Err((INVALID_PARAMS, "textDocument is missing".to_string())),
+        };
+        let file = self.name(&path);
+        let line = params["position"]["line"].as_u64().unwrap_or(0) as usize + 1;
+        let col = params["position"]["character"].as_u64().unwrap_or(0) as usize + 1;
+        let root = self.root.clone();
+        let analysis = self.analysis(&path);
+        let occ = analysis.at(&file, line, col);
+        let result = match method {
+            "textDocument/definition" => occ
+                .and_then(|o| analysis.object(o).def.as_ref())
+                .map_or(Value::Null, |r| location(&root, r)),
+            "textDocument/hover" => occ.map_or(Value::Null, |o| {
+                json!({
+                    "contents": {
+                        "kind": "markdown",
+                        "value": format!("```go\n{}\n```", analysis.object(o).detail),
+                    },
+                    "range": lsp_range(&o.range),
+                })
+            }),
+            "textDocument/references" => {
+                let decl = params["context"]["includeDeclaration"]
+                    .as_bool()
+                    .unwrap_or(true);
+                let refs = occ.map_or(vec![], |o| analysis.references(o.obj));
+                let refs = refs.into_iter().filter(|o| decl || !o.def);
+                Value::Array(refs.map(|o| location(&root, &o.range)).collect())
+            }
+            "textDocument/documentSymbol" => {
+                Value::Array(analysis.symbols(&file).iter().map(symbol_json).collect())
+            }
+            _ => unreachable!(),
+        };
+        Ok(result)
+    }
+
+    /// check_dirty checks the packages changed, and publishes their
+    /// diagnostics, an empty list for the files that have none left
+    fn check_dirty(&mut self, conn: &mut Connection) {
+        let mut dirty: Vec<PathBuf> = self.dirty.drain().collect();
+        dirty.sort();
+        for unit in dirty {
+            let analysis = self.check(&unit);
+            let mut files: Vec<PathBuf> = self.unit_files(&unit);
+            files.extend(
+                analysis
+                    .error_files()
+                    .into_iter()
+                    .map(|f| self.root.join(f)),
+            );
+            let old = self.published.remove(&unit).unwrap_or(vec![]);
+            let mut all = files.clone();
+            all.extend(old);
+            all.sort();
+            all.dedup();
+            for path in all.iter() {
+                let diags: Vec<Value> = analysis
+                    .diagnostics(&self.name(path))
+                    .into_iter()
+                    .map(|d| {
+                        json!({
+                            "range": lsp_range(&d.range),
+                            "severity": if d.soft { 2 } else { 1 },
+                            "source": "goscript",
+                            "message": d.msg,
+                        })
+                    })
+                    .collect();
+                conn.notify(
+                    "textDocument/publishDiagnostics",
+                    json!({"uri": path_to_uri(path), "diagnostics": diags}),
+                );
+            }
+            self.published.insert(unit.clone(), files);
+            if analysis.checked || !self.units.contains_key(&unit) {
+                self.units.insert(unit, analysis);
+            }
+        }
+    }
+
+    fn analysis(&mut self, path: &Path) -> &Analysis {
+        let unit = self.unit(path);
+        if !self.units.contains_key(&unit) {
+            let analysis = self.check(&unit);
+            self.units.insert(unit.clone(), analysis);
+        }
+        &self.units[&unit]
+    }
+
+    fn check(&self, unit: &Path) -> Analysis {
+        let files: Vec<(String, String)> = self
+            .unit_files(unit)
+            .iter()
+            .map(|p| (self.name(p), self.text(p).unwrap_or_default()))
+            .collect();
+        let name = self.name(unit);
+        let path = if unit.is_dir() {
+            format!("./{}", name)
+        } else {
+            name
+        };
+        Analysis::new(&self.config, &path, &files)
+    }
+
+    /// unit returns the package of the file, the file itself if it's a
+    /// program
+    fn unit(&self, path: &Path) -> PathBuf {
+        match self.text(path).as_deref().and_then(package_name) {
+            Some("main") | None => path.to_path_buf(),
+            Some(_) => path.parent().unwrap().to_path_buf(),
+        }
+    }
+
+    /// unit_files returns the files of a package, the open ones included
+    fn unit_files(&self, unit: &Path) -> Vec<PathBuf> {
+        if !unit.is_dir() {
+            return vec![unit.to_path_buf()];
+        }
+        let mut files: Vec<PathBuf> = fs::read_dir(unit)
+            .map(|entries| entries.filter_map(|e| e.ok().map(|e| e.path())).collect())
+            .unwrap_or(vec![]);
+        files.extend(
+            self.docs
+                .keys()
+                .filter(|p| p.parent() == Some(unit))
+                .cloned(),
+        );
+        files.retain(|p| {
+            let ext = p.extension().and_then(|e| e.to_str());
+            let stem = p.file_stem().and_then(|s| s.to_str()).unwrap_or("");
+            (ext == Some("gos") || ext == Some("go"))
+                && !stem.ends_with("_test")
+                && self.unit(p) == unit
+        });
+        files.sort();
+        files.dedup();
+        files
+    }
+
+    fn text(&self, path: &Path) -> Option<String> {
+        self.docs
+            .get(path)
+            .cloned()
+            .or_else(|| fs::read_to_string(path).ok())
+    }
+
+    /// name returns the name of the file in the analyses, relative to the
+    /// root if it's in it
+    fn name(&self, path: &Path) -> String {
+        path.strip_prefix(&self.root)
+            .unwrap_or(path)
+            .to_string_lossy()
+            .to_string()
+    }
+
+    /// canonical resolves the links in the directory of a file, as the
+    /// importer does, the file may not exist yet
+    fn canonical(&self, path: &Path) -> PathBuf {
+        match (path.parent().map(|p| p.canonicalize()), path.file_name()) {
+            (Some(Ok(dir)), Some(name)) => dir.join(name),
+            _ => path.to_path_buf(),
+        }
+    }
+}
+
+/// package_name returns the name in the package clause
+fn package_name(text: &str) -> Option<&str> {
+    let mut comment = false;
+    for line in text.lines() {
+        let mut line = line.trim();
+        if comment {
+            match line.find("*/") {
+                Some(i) => {
+                    comment = false;
+                    line = line[i + 2..].trim();
+                }
+                None => continue,
+            }
+        }
+        while line.starts_with("/*") {
+            match line.find("*/") {
+                Some(i) => line = line[i + 2..].trim(),
+                None => {
+                    comment = true;
+                    line = "";
+                }
+            }
+        }
+        if line.is_empty() || line.starts_with("//") {
+            continue;
+        }
+        return line
+            .strip_prefix("package")
+            .and_then(|s| s.split_whitespace().next())
+            .map(|s| s.trim_end_matches(';'));
+    }
+    None
+}
+
+/// apply_change applies a change of the text from the client, a range in it
+/// replaced, or all of it if there's no range
+fn apply_change(text: &mut String, change: &Value) {
+    let new = change["text"].as_str().unwrap_or("");
+    let range = &change["range"];
+    if range.is_null() {
+        *text = new.to_string();
+        return;
+    }
+    let start = offset(text, &range["start"]);
+    let end = offset(text, &range["end"]).max(start);
+    text.replace_range(start..end, new);
+}
+
+/// offset returns the byte offset of a position, its character counts in
+/// UTF-16 as the protocol does
+fn offset(text: &str, pos: &Value) -> usize {
+    let line = pos["line"].as_u64().unwrap_or(0) as usize;
+    let character = pos["character"].as_u64().unwrap_or(0) as usize;
+    let mut start = 0;
+    for _ in 0..line {
+        match text[start..].find('\n') {
+            Some(i) => start += i + 1,
+            None => return text.len(),
+        }
+    }
+    let mut units = 0;
+    for (i, c) in text[start..].char_indices() {
+        if units >= character || c == '\n' {
+            return start + i;
+        }
+        units += c.len_utf16();
+    }
+    text.len()
+}
+
+fn location(root: &Path, range: &Range) -> Value {
+    json!({
+        "uri": path_to_uri(&root.join(&range.file)),
+        "range": lsp_range(range),
+    })
+}
+
+fn lsp_range(range: &Range) -> Value {
+    let pos = |(line, col): (usize, usize)| json!({"line": line.saturating_sub(1), "character": col.saturating_sub(1)});
+    json!({"start": pos(range.start), "end": pos(range.end)})
+}
+
+fn symbol_json(s: &Symbol) -> Value {
+    json!({
+        "name": s.name,
+        "kind": s.kind as u8,
+        "range": lsp_range(&s.range),
+        "selectionRange": lsp_range(&s.selection),
+        "children": s.children.iter().map(symbol_json).collect::<Vec<_>>(),
+    })
+}
+
+fn uri_to_path(uri: &str) -> PathBuf {
+    let path = uri.strip_prefix("file://").unwrap_or(uri);
+    let bytes = path.as_bytes();
+    let mut decoded = vec![];
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes
+            .get(i + 1..i + 3)
+            .and_then(|h| std::str::from_utf8(h).ok());
+        match (bytes[i], hex.and_then(|h| u8::from_str_radix(h, 16).ok())) {
+            (b'%', Some(b)) => {
+                decoded.push(b);
+                i += 3;
+            }
+            (b, _) => {
+                decoded.push(b);
+                i += 1;
+            }
+        }
+    }
+    PathBuf::from(String::from_utf8_lossy(&decoded).to_string())
+}
+
+fn path_to_uri(path: &Path) -> String {
+    let mut uri = "file://".to_string();
+    for b in path.to_string_lossy().bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'/' | b'-' | b'_' | b'.' | b'~' => {
+                uri.push(b as char)
+            }
+            _ => uri.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    uri
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_package_name() {
+        assert_eq!(package_name("package main\n"), Some("main"));
+        assert_eq!(
+            package_name("// a\n/* b\n c */ package fmt;\n"),
+            Some("fmt")
+        );
+        assert_eq!(package_name("/* a */ /* b */\npackage x // c"), Some("x"));
+        assert_eq!(package_name("func f() {}"), None);
+    }
+
+    #[test]
+    fn test_apply_change() {
+        let mut text = "package main\n\nfunc main() {\n}\n".to_string();
+        let change = json!({
+            "range": {"start": {"line": 2, "character": 5}, "end": {"line": 2, "character": 9}},
+            "text": "f",
+        });
+        apply_change(&mut text, &change);
+        assert_eq!(text, "package main\n\nfunc f() {\n}\n");
+        // a char out of the BMP is two UTF-16 units
+        let mut text = "a😀b\n".to_string();
+        let change = json!({
+            "range": {"start": {"line": 0, "character": 3}, "end": {"line": 0, "character": 4}},
+            "text": "c",
+        });
+        apply_change(&mut text, &change);
+        assert_eq!(text, "a😀c\n");
+        apply_change(&mut text, &json!({"text": "x"}));
+        assert_eq!(text, "x");
+    }
+
+    #[test]
+    fn test_uri() {
+        let path = Path::new("/a b/c%.gos");
+        assert_eq!(path_to_uri(path), "file:///a%20b/c%25.gos");
+        assert_eq!(uri_to_path(&path_to_uri(path)), path);
+    }
+}
@@ -0,0 +1,259 @@
+extern crate goscript_lsp as lsp;
+
+use serde_json::{json, Value};
+use std::cell::RefCell;
+use std::fs;
+use std::io::{self, Cursor, Write};
+use std::path::Path;
+use std::rc::Rc;
+
+#[derive(Clone, Default)]
+struct SharedBuf(Rc<RefCell<Vec<u8>>>);
+
+impl Write for SharedBuf {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fn data_dir() -> String {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/data");
+    dir.canonicalize().unwrap().to_string_lossy().to_string()
+}
+
+fn uri(file: &str) -> String {
+    format!("file://{}/{}", data_dir(), file)
+}
+
+/// run sends the messages after initialize, with ids for the requests, and
+/// returns the responses by id and the notifications
+fn run(messages: Vec<(&str, Value, bool)>) -> (Vec<Value>, Vec<Value>) {
+    let std = Path::new(env!("CARGO_MANIFEST_DIR")).join("../engine/std");
+    let mut all = vec![
+        (
+            "initialize",
+            json!({
+                "rootUri": format!("file://{}", data_dir()),
+                "initializationOptions": {"basePath": std.canonicalize().unwrap()},
+            }),
+            true,
+        ),
+        ("initialized", json!({}), false),
+    ];
+    all.extend(messages);
+    all.push(("shutdown", Value::Null, true));
+    all.push(("exit", Value::Null, false));
+    let mut input = vec![];
+    for (i, (method, params, request)) in all.into_iter().enumerate() {
+        let mut msg = json!({"jsonrpc": "2.0", "method": method, "params": params});
+        if request {
+            msg["id"] = json!(i);
+        }
+        lsp::write_message(&mut input, &msg).unwrap();
+    }
+    let output = SharedBuf::default();
+    lsp::serve(Cursor::new(input), output.clone());
+    let buf = output.0.borrow();
+    let mut r = buf.as_slice();
+    let (mut responses, mut notes) = (vec![], vec![]);
+    while let Some(msg) = lsp::read_message(&mut r).unwrap() {
+        if msg["id"].is_null() {
+            notes.push(msg);
+        } else {
+            responses.push(msg);
+        }
+    }
+    (responses, notes)
+}
+
+fn response(responses: &[Value], id: usize) -> &Value {
+    responses.iter().find(|r| r["id"] == json!(id)).unwrap()
+}
+
+fn at(file: &str, line: u64, character: u64) -> Value {
+    json!({
+        "textDocument": {"uri": uri(file)},
+        "position": {"line": line, "character": character},
+    })
+}
+
+fn open(file: &str) -> (&'static str, Value, bool) {
+    let text = fs::read_to_string(Path::new(&data_dir()).join(file)).unwrap();
+    (
+        "textDocument/didOpen",
+        json!({"textDocument": {"uri": uri(file), "languageId": "go", "version": 1, "text": text}}),
+        false,
+    )
+}
+
+#[test]
+fn test_navigation() {
+    let (responses, notes) = run(vec![
+        open("main.gos"),
+        // the message ids start at 3
+        ("textDocument/hover", at("main.gos", 10, 19), true),
+        ("textDocument/definition", at("main.gos", 10, 19), true),
+        ("textDocument/definition", at("main.gos", 8, 17), true),
+        (
+            "textDocument/references",
+            json!({
+                "textDocument": {"uri": uri("main.gos")},
+                "position": {"line": 8, "character": 4},
+                "context": {"includeDeclaration": true},
+            }),
+            true,
+        ),
+        ("textDocument/hover", at("main.gos", 10, 9), true),
+        ("textDocument/hover", at("main.gos", 7, 0), true),
+    ]);
+    let init = response(&responses, 0);
+    assert_eq!(init["result"]["capabilities"]["hoverProvider"], true);
+
+    // r.Area()
+    let hover = &response(&responses, 3)["result"];
+    let text = hover["contents"]["value"].as_str().unwrap();
+    assert!(text.contains("Area() int"), "{}", text);
+    assert_eq!(
+        hover["range"]["start"],
+        json!({"line": 10, "character": 18})
+    );
+    let def = &response(&responses, 4)["result"];
+    assert_eq!(def["uri"], json!(uri("shapes/shapes.gos")));
+    assert_eq!(def["range"]["start"], json!({"line": 12, "character": 14}));
+    // shapes.Rect
+    let def = &response(&responses, 5)["result"];
+    assert_eq!(def["uri"], json!(uri("shapes/shapes.gos")));
+    assert_eq!(def["range"]["start"], json!({"line": 4, "character": 5}));
+    // r, defined and used 4 times
+    let refs = response(&responses, 6)["result"].as_array().unwrap();
+    let lines: Vec<u64> = refs
+        .iter()
+        .map(|r| r["range"]["start"]["line"].as_u64().unwrap())
+        .collect();
+    assert_eq!(lines, vec![8, 9, 10, 11, 12]);
+    // fmt.Println
+    let hover = &response(&responses, 7)["result"];
+    let text = hover["contents"]["value"].as_str().unwrap();
+    assert!(
+        text.contains("func fmt.Println(a ...interface{})"),
+        "{}",
+        text
+    );
+    // nothing there
+    assert_eq!(response(&responses, 8)["result"], Value::Null);
+    // no errors
+    let diags = notes
+        .iter()
+        .find(|n| n["params"]["uri"] == json!(uri("main.gos")))
+        .unwrap();
+    assert_eq!(diags["params"]["diagnostics"], json!([]));
+}
+
+#[test]
+fn test_symbols() {
+    let (responses, _) = run(vec![(
+        "textDocument/documentSymbol",
+        json!({"textDocument": {"uri": uri("shapes/shapes.gos")}}),
+        true,
+    )]);
+    let symbols = response(&responses, 2)["result"].as_array().unwrap();
+    let names: Vec<(&str, u64)> = symbols
+        .iter()
+        .map(|s| (s["name"].as_str().unwrap(), s["kind"].as_u64().unwrap()))
+        .collect();
+    assert_eq!(
+        names,
+        vec![
+            ("Unit", 14),
+            ("Rect", 23),
+            ("Shape", 11),
+            ("Rect.Area", 6),
+            ("(*Rect).Scale", 6)
+        ]
+    );
+    let rect = &symbols[1];
+    assert_eq!(rect["range"]["start"], json!({"line": 4, "character": 0}));
+    assert_eq!(rect["range"]["end"], json!({"line": 6, "character": 1}));
+    let fields: Vec<&str> = rect["children"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|s| s["name"].as_str().unwrap())
+        .collect();
+    assert_eq!(fields, vec!["W", "H"]);
+}
+
+#[test]
+fn test_diagnostics() {
+    let change = |range: Value, text: &str| {
+        (
+            "textDocument/didChange",
+            json!({
+                "textDocument": {"uri": uri("main.gos"), "version": 2},
+                "contentChanges": [{"range": range, "text": text}],
+            }),
+            false,
+        )
+    };
+    let (responses, notes) = run(vec![
+        open("main.gos"),
+        // r.Scale(3) to r.Scale("3")
+        change(
+            json!({"start": {"line": 9, "character": 12}, "end": {"line": 9, "character": 13}}),
+            "\"3\"",
+        ),
+        ("textDocument/hover", at("main.gos", 10, 19), true),
+        // and back, with another error
+        change(
+            json!({"start": {"line": 9, "character": 12}, "end": {"line": 9, "character": 15}}),
+            "3",
+        ),
+        change(
+            json!({"start": {"line": 12, "character": 28}, "end": {"line": 12, "character": 29}}),
+            "X",
+        ),
+        ("textDocument/hover", at("main.gos", 12, 26), true),
+    ]);
+    let published: Vec<&Value> = notes
+        .iter()
+        .filter(|n| n["params"]["uri"] == json!(uri("main.gos")))
+        .map(|n| &n["params"]["diagnostics"])
+        .collect();
+    // the reader thread may be quick enough for the edits to be checked
+    // one by one, or not
+    let error = |diags: &Value, line: u64, character: u64, text: &str| {
+        let diags = diags.as_array().unwrap();
+        diags.len() == 1
+            && diags[0]["range"]["start"] == json!({"line": line, "character": character})
+            && diags[0]["message"].as_str().unwrap().contains(text)
+    };
+    assert!(published.iter().any(|d| error(d, 9, 12, "cannot")));
+    assert!(error(published.last().unwrap(), 12, 28, "X"));
+    // the answers are from the text edited
+    let hover = &response(&responses, 4)["result"];
+    assert!(hover["contents"]["value"]
+        .as_str()
+        .unwrap()
+        .contains("Area"));
+    assert_eq!(
+        response(&responses, 7)["result"]["range"]["start"]["character"],
+        26
+    );
+}
+
+#[test]
+fn test_not_initialized() {
+    let mut input = vec![];
+    let msg = json!({"jsonrpc": "2.0", "id": 1, "method": "textDocument/hover", "params": {}});
+    lsp::write_message(&mut input, &msg).unwrap();
+    let output = SharedBuf::default();
+    lsp::serve(Cursor::new(input), output.clone());
+    let buf = output.0.borrow();
+    let msg = lsp::read_message(&mut buf.as_slice()).unwrap().unwrap();
+    assert_eq!(msg["error"]["code"], -32002);
+}
@@ -39,6 +39,7 @@ pub enum Expr {
     Paren(Rc<ParenExpr>),
     Selector(Rc<SelectorExpr>),
     Index(Rc<IndexExpr>),
+    IndexList(Rc<IndexListExpr>),
     Slice(Rc<SliceExpr>),
     TypeAssert(Rc<TypeAssertExpr>),
     Call(Rc<CallExpr>),
@@ -179,6 +180,7 @@ impl Node for Expr {
             Expr::Paren(e) => e.l_paren,
             Expr::Selector(e) => e.expr.pos(arena),
             Expr::Index(e) => e.expr.pos(arena),
+            Expr::IndexList(e) => e.expr.pos(arena),
             Expr::Slice(e) => e.expr.pos(arena),
             Expr::TypeAssert(e) => e.expr.pos(arena),
             Expr::Call(e) => e.func.pos(arena),
@@ -203,12 +205,13 @@ impl Node for Expr {
                 Some(expr) => expr.end(arena),
                 None => e.pos + 3,
             },
-            Expr::BasicLit(e) => e.pos + e.token.get_literal().len(),
+            Expr::BasicLit(e) => e.pos + e.token.get_literal().chars().count(),
             Expr::FuncLit(e) => e.body.end(),
             Expr::CompositeLit(e) => e.r_brace + 1,
             Expr::Paren(e) => e.r_paren + 1,
             Expr::Selector(e) => arena.idents[e.sel].end(),
             Expr::Index(e) => e.r_brack + 1,
+            Expr::IndexList(e) => e.r_brack + 1,
             Expr::Slice(e) => e.r_brack + 1,
             Expr::TypeAssert(e) => e.r_paren + 1,
             Expr::Call(e) => e.r_paren + 1,
@@ -236,6 +239,7 @@ impl Node for Expr {
             Expr::Paren(e) => NodeId::Address(&**e as *const ParenExpr as usize),
             Expr::Selector(e) => e.id(),
             Expr::Index(e) => NodeId::Address(&**e as *const IndexExpr as usize),
+            Expr::IndexList(e) => NodeId::Address(&**e as *const IndexListExpr as usize),
             Expr::Slice(e) => NodeId::Address(&**e as *const SliceExpr as usize),
             Expr::TypeAssert(e) => NodeId::Address(&**e as *const TypeAssertExpr as usize),
             Expr::Call(e) => e.id(),
@@ -476,12 +480,14 @@ impl Node for Decl {
 
 #[derive(Debug)]
 pub struct File {
+    pub doc: Option<Rc<CommentGroup>>,
     pub package: position::Pos,
     pub name: IdentKey,
     pub decls: Vec<Decl>,
     pub scope: ScopeKey,
     pub imports: Vec<SpecKey>, //ImportSpec
     pub unresolved: Vec<IdentKey>,
+    pub comments: Vec<Rc<CommentGroup>>, // all the comments in the file
 }
 
 impl Node for File {
@@ -503,6 +509,37 @@ impl Node for File {
     }
 }
 
+// A Comment node represents a single //-style or /*-style comment, the
+// text doesn't have the newline at the end of a //-style comment.
+#[derive(Debug)]
+pub struct Comment {
+    pub slash: position::Pos,
+    pub text: String,
+}
+
+impl Comment {
+    pub fn end(&self) -> position::Pos {
+        self.slash + self.text.chars().count()
+    }
+}
+
+// A CommentGroup node represents a sequence of comments
+// with no other tokens and no empty lines between.
+#[derive(Debug)]
+pub struct CommentGroup {
+    pub list: Vec<Comment>,
+}
+
+impl CommentGroup {
+    pub fn pos(&self) -> position::Pos {
+        self.list[0].slash
+    }
+
+    pub fn end(&self) -> position::Pos {
+        self.list[self.list.len() - 1].end()
+    }
+}
+
 pub struct Package {
     name: String,
     scope: ScopeKey,
@@ -572,7 +609,7 @@ impl Ident {
     }
 
     pub fn end(&self) -> position::Pos {
-        self.pos + self.name.len()
+        self.pos + self.name.chars().count()
     }
 
     pub fn entity_key(&self) -> Option<EntityKey> {
@@ -656,6 +693,16 @@ pub struct IndexExpr {
     pub r_brack: position::Pos,
 }
 
+// An IndexListExpr node represents an expression followed by multiple
+// indices, i.e. the instantiation of a generic function or type.
+#[derive(Debug)]
+pub struct IndexListExpr {
+    pub expr: Expr,
+    pub l_brack: position::Pos,
+    pub indices: Vec<Expr>,
+    pub r_brack: position::Pos,
+}
+
 // An SliceExpr node represents an expression followed by slice indices.
 #[derive(Debug)]
 pub struct SliceExpr {
@@ -751,6 +798,7 @@ pub struct StructType {
 #[derive(Clone, Debug)]
 pub struct FuncType {
     pub func: Option<position::Pos>,
+    pub type_params: Option<FieldList>,
     pub params: FieldList,
     pub results: Option<FieldList>,
 }
@@ -763,6 +811,7 @@ impl FuncType {
     ) -> FuncType {
         FuncType {
             func: func,
+            type_params: None,
             params: params,
             results: results,
         }
@@ -826,26 +875,33 @@ pub struct ChanType {
 // An ImportSpec node represents a single package import.
 #[derive(Debug)]
 pub struct ImportSpec {
+    pub doc: Option<Rc<CommentGroup>>,
     pub name: Option<IdentKey>,
     pub path: BasicLit,
     pub end_pos: Option<position::Pos>,
+    pub comment: Option<Rc<CommentGroup>>,
 }
 
 // A ValueSpec node represents a constant or variable declaration
 // (ConstSpec or VarSpec production).
 #[derive(Debug)]
 pub struct ValueSpec {
+    pub doc: Option<Rc<CommentGroup>>,
     pub names: Vec<IdentKey>,
     pub typ: Option<Expr>,
     pub values: Vec<Expr>,
+    pub comment: Option<Rc<CommentGroup>>,
 }
 
 // A TypeSpec node represents a type declaration (TypeSpec production).
 #[derive(Debug)]
 pub struct TypeSpec {
+    pub doc: Option<Rc<CommentGroup>>,
     pub name: IdentKey,
+    pub type_params: Option<FieldList>,
     pub assign: position::Pos,
     pub typ: Expr,
+    pub comment: Option<Rc<CommentGroup>>,
 }
 
 #[derive(Debug)]
@@ -866,6 +922,7 @@ pub struct BadDecl {
 //	Token::VAR     ValueSpec
 #[derive(Debug)]
 pub struct GenDecl {
+    pub doc: Option<Rc<CommentGroup>>,
     pub token_pos: position::Pos,
     pub token: token::Token,
     pub l_paran: Option<position::Pos>,
@@ -876,6 +933,7 @@ pub struct GenDecl {
 // A FuncDecl node represents a function declaration.
 #[derive(Debug)]
 pub struct FuncDecl {
+    pub doc: Option<Rc<CommentGroup>>,
     pub recv: Option<FieldList>,
     pub name: IdentKey,
     pub typ: FuncTypeKey,
@@ -1099,9 +1157,11 @@ pub struct RangeStmt {
 
 #[derive(Debug)]
 pub struct Field {
+    pub doc: Option<Rc<CommentGroup>>,
     pub names: Vec<IdentKey>,
     pub typ: Expr,
     pub tag: Option<Expr>,
+    pub comment: Option<Rc<CommentGroup>>,
 }
 
 impl Node for FieldKey {
@@ -1156,7 +1216,7 @@ impl FieldList {
 
     pub fn end(&self, arena: &Objects) -> position::Pos {
         match self.closing {
-            Some(c) => c,
+            Some(c) => c + 1,
             None => self.list[self.list.len() - 1].pos(arena),
         }
     }
@@ -4,6 +4,7 @@ pub mod errors;
 pub mod objects;
 mod parser;
 pub mod position;
+pub mod printer;
 mod scanner;
 pub mod scope;
 pub mod token;
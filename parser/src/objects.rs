@@ -239,10 +239,15 @@ macro_rules! field {
 }
 macro_rules! new_field {
     ($owner:ident, $names:expr, $typ:expr, $tag:expr) => {
+        new_field!($owner, None, $names, $typ, $tag, None)
+    };
+    ($owner:ident, $doc:expr, $names:expr, $typ:expr, $tag:expr, $comment:expr) => {
         $owner.objects.fields.insert(Field {
+            doc: $doc,
             names: $names,
             typ: $typ,
             tag: $tag,
+            comment: $comment,
         })
     };
 }
@@ -40,6 +40,10 @@ pub struct Parser<'a> {
 
     label_scope: Option<ScopeKey>,
     target_stack: Vec<Vec<IdentKey>>,
+
+    comments: Vec<Rc<CommentGroup>>,
+    lead_comment: Option<Rc<CommentGroup>>, // last lead comment
+    line_comment: Option<Rc<CommentGroup>>, // last line comment
 }
 
 impl<'a> Parser<'a> {
@@ -67,6 +71,9 @@ impl<'a> Parser<'a> {
             imports: vec![],
             label_scope:None,
             target_stack: vec![],
+            comments: vec![],
+            lead_comment: None,
+            line_comment: None,
         };
         p.next(); // get the first token ready
         p
@@ -284,20 +291,109 @@ impl<'a> Parser<'a> {
         self.print_trace(self.pos, ")");
     }
 
+    fn next0(&mut self) {
+        let (token, pos) = self.scanner.scan();
+        match token {
+            Token::COMMENT(_) => self.print_trace(pos, &format!("{}", token)),
+            _ => self.print_trace(pos, &format!("next: {}", token)),
+        }
+        self.token = token;
+        self.pos = pos;
+    }
+
+    fn line(&self, pos: position::Pos) -> usize {
+        let file = self.file();
+        if pos < file.base() {
+            0
+        } else {
+            file.position(pos).line
+        }
+    }
+
+    // Consume a comment and return it and the line on which it ends.
+    fn consume_comment(&mut self) -> (Comment, usize) {
+        let mut text = match &self.token {
+            Token::COMMENT(lit) => lit.as_str().to_string(),
+            _ => unreachable!(),
+        };
+        // the newline at the end of a //-style comment is not part of it
+        if text.ends_with('\n') {
+            text.pop();
+        }
+        // /*-style comments may end on a different line than where they start.
+        let endline = self.line(self.pos) + text.matches('\n').count();
+        let comment = Comment{slash: self.pos, text: text};
+        self.next0();
+        (comment, endline)
+    }
+
+    // Consume a group of adjacent comments, add it to the parser's
+    // comments list, and return it together with the line at which
+    // the last comment in the group ends. A non-comment token or n
+    // empty lines terminate a comment group.
+    fn consume_comment_group(&mut self, n: usize) -> (Rc<CommentGroup>, usize) {
+        let mut list = vec![];
+        let mut endline = self.line(self.pos);
+        while let Token::COMMENT(_) = self.token {
+            if self.line(self.pos) > endline + n {
+                break;
+            }
+            let (comment, end) = self.consume_comment();
+            list.push(comment);
+            endline = end;
+        }
+        let group = Rc::new(CommentGroup{list: list});
+        self.comments.push(group.clone());
+        (group, endline)
+    }
+
+    // Advance to the next non-comment token. In the process, collect
+    // any comment groups encountered, and remember the last lead and
+    // line comments.
+    //
+    // A lead comment is a comment group that starts and ends in a
+    // line without any other tokens and that is followed by a non-comment
+    // token on the line immediately after the comment group.
+    //
+    // A line comment is a comment group that follows a non-comment
+    // token on the same line, and that has no tokens after it on the line
+    // where it ends.
     fn next(&mut self) {
-        // Get next token and skip comments
-        loop {
-            let (token, pos) = self.scanner.scan();
-            match token {
-                Token::COMMENT(_) => { // Skip comment
-                    self.print_trace(pos, &format!("{}", token));
-                },
-                _ => {
-                    self.print_trace(pos, &format!("next: {}", token));
-                    self.token = token;
-                    self.pos = pos;
-                    break; 
-                },
+        self.lead_comment = None;
+        self.line_comment = None;
+        let prev = self.pos;
+        self.next0();
+
+        if let Token::COMMENT(_) = self.token {
+            let mut comment = None;
+            if self.line(self.pos) == self.line(prev) {
+                // The comment is on same line as the previous token; it
+                // cannot be a lead comment but may be a line comment.
+                let (group, endline) = self.consume_comment_group(0);
+                let end = match self.token {
+                    Token::SEMICOLON(_) | Token::EOF => true,
+                    _ => false,
+                };
+                if self.line(self.pos) != endline || end {
+                    // The next token is on a different line, thus
+                    // the last comment group is a line comment.
+                    self.line_comment = Some(group.clone());
+                }
+                comment = Some(group);
+            }
+
+            // consume successor comments, if any
+            let mut endline = None;
+            while let Token::COMMENT(_) = self.token {
+                let (group, end) = self.consume_comment_group(1);
+                comment = Some(group);
+                endline = Some(end);
+            }
+
+            if endline.map_or(false, |l| l + 1 == self.line(self.pos)) {
+                // The next token is following on the line immediately after the
+                // comment group, thus the last comment group is a lead comment.
+                self.lead_comment = comment;
             }
         }
     }
@@ -550,12 +646,204 @@ impl<'a> Parser<'a> {
         ret
     }
 
+    // parse_type_instance parses the type arguments of a generic type
+    fn parse_type_instance(&mut self, typ: Expr) -> Expr {
+        self.trace_begin("TypeInstance");
+
+        let lbrack = self.expect(&Token::LBRACK);
+        self.expr_level += 1;
+        let mut list = vec![];
+        while self.token != Token::RBRACK && self.token != Token::EOF {
+            list.push(self.parse_type());
+            if !self.at_comma("type argument list", &Token::RBRACK) {
+                break;
+            }
+            self.next();
+        }
+        self.expr_level -= 1;
+        let rbrack = self.expect_closing(&Token::RBRACK, "type argument list");
+        if list.len() == 0 {
+            self.error_str(rbrack, "expected type argument list");
+            list.push(Expr::new_bad(lbrack + 1, rbrack));
+        }
+
+        self.trace_end();
+        Parser::new_index_or_list(typ, lbrack, list, rbrack)
+    }
+
+    fn new_index_or_list(x: Expr, lbrack: position::Pos, mut list: Vec<Expr>,
+        rbrack: position::Pos) -> Expr {
+        if list.len() == 1 {
+            Expr::Index(Rc::new(IndexExpr{
+                expr: x, l_brack: lbrack, index: list.pop().unwrap(), r_brack: rbrack}))
+        } else {
+            Expr::IndexList(Rc::new(IndexListExpr{
+                expr: x, l_brack: lbrack, indices: list, r_brack: rbrack}))
+        }
+    }
+
+    // parse_array_field_or_type_instance parses what follows the name in a
+    // parameter or field declaration of the form "name [".
+    // It returns the type and true if name is the name of the parameter or field,
+    // or the instantiated type and false if the name is a generic type.
+    fn parse_array_field_or_type_instance(&mut self, name: Expr) -> (Expr, bool) {
+        self.trace_begin("ArrayFieldOrTypeInstance");
+
+        let lbrack = self.expect(&Token::LBRACK);
+        let ret = if self.token == Token::RBRACK {
+            // name []T
+            self.next();
+            let elt = self.parse_type();
+            (Expr::Array(Rc::new(ArrayType{l_brack: lbrack, len: None, elt: elt})), true)
+        } else {
+            self.expr_level += 1;
+            let mut list = vec![];
+            while self.token != Token::RBRACK && self.token != Token::EOF {
+                if self.token == Token::ELLIPSIS {
+                    list.push(Expr::new_ellipsis(self.pos, None));
+                    self.next();
+                } else {
+                    list.push(self.parse_rhs_or_type());
+                }
+                if !self.at_comma("type argument list", &Token::RBRACK) {
+                    break;
+                }
+                self.next();
+            }
+            self.expr_level -= 1;
+            let rbrack = self.expect(&Token::RBRACK);
+            if list.len() == 1 {
+                // name [N]T
+                if let Some(elt) = self.try_type() {
+                    let len = list.pop();
+                    return (Expr::Array(Rc::new(ArrayType{
+                        l_brack: lbrack, len: len, elt: elt})), true);
+                }
+            }
+            // name[T1, T2, ...]
+            self.resolve(&name);
+            (Parser::new_index_or_list(name, lbrack, list, rbrack), false)
+        };
+
+        self.trace_end();
+        ret
+    }
+
+    // parse_type_params parses a type parameter list, the opening bracket
+    // and possibly the first parameter have been parsed by the caller.
+    fn parse_type_params(&mut self, lbrack: position::Pos, scope: ScopeKey,
+        mut first: Option<(IdentKey, Option<Expr>)>) -> FieldList {
+        self.trace_begin("TypeParams");
+
+        let mut list = vec![];
+        loop {
+            let (idents, typ) = match first.take() {
+                Some((ident, Some(typ))) => (vec![ident], typ),
+                Some((ident, None)) => {
+                    let mut idents = vec![ident];
+                    while self.token == Token::COMMA {
+                        self.next();
+                        idents.push(self.parse_ident());
+                    }
+                    (idents, self.parse_type_elem())
+                }
+                None => {
+                    let idents = self.parse_ident_list();
+                    (idents, self.parse_type_elem())
+                }
+            };
+            let field = new_field!(self, idents, typ, None);
+            self.declare(DeclObj::Field(field), EntityData::NoData,
+                EntityKind::Typ, &scope);
+            list.push(field);
+            if !self.at_comma("type parameter list", &Token::RBRACK) {
+                break;
+            }
+            self.next();
+            if self.token == Token::RBRACK || self.token == Token::EOF {
+                break;
+            }
+        }
+        let rbrack = self.expect_closing(&Token::RBRACK, "type parameter list");
+
+        self.trace_end();
+        FieldList::new(Some(lbrack), list, Some(rbrack))
+    }
+
+    // parse_type_elem parses a union of type terms: ["~"] Type { "|" ["~"] Type }
+    fn parse_type_elem(&mut self) -> Expr {
+        self.trace_begin("TypeElem");
+
+        let mut x = self.parse_type_term();
+        while self.token == Token::OR {
+            let pos = self.pos;
+            self.next();
+            let y = self.parse_type_term();
+            x = Expr::Binary(Rc::new(BinaryExpr{
+                expr_a: x, op_pos: pos, op: Token::OR, expr_b: y}));
+        }
+
+        self.trace_end();
+        x
+    }
+
+    fn parse_type_term(&mut self) -> Expr {
+        if self.token == Token::TILDE {
+            let pos = self.pos;
+            self.next();
+            let typ = self.parse_type();
+            Expr::new_unary_expr(pos, Token::TILDE, typ)
+        } else {
+            self.parse_type()
+        }
+    }
+
+    // is_type_elem reports whether x is a (possibly parenthesized) type element
+    // expression, i.e. it can't be a value expression.
+    fn is_type_elem(x: &Expr) -> bool {
+        match x {
+            Expr::Array(_) | Expr::Struct(_) | Expr::Func(_) | Expr::Interface(_) |
+            Expr::Map(_) | Expr::Chan(_) => true,
+            Expr::Unary(u) => u.op == Token::TILDE,
+            Expr::Binary(b) => Parser::is_type_elem(&b.expr_a) ||
+                Parser::is_type_elem(&b.expr_b),
+            Expr::Paren(p) => Parser::is_type_elem(&p.expr),
+            _ => false,
+        }
+    }
+
+    // extract_name splits the expression x into (name, expr) if syntactically
+    // x can be written as name expr. The split only happens if expr is a type
+    // element (per is_type_elem) or if force is set.
+    fn extract_name(x: &Expr, force: bool) -> Option<(IdentKey, Option<Expr>)> {
+        match x {
+            Expr::Ident(i) => Some((*i, None)),
+            Expr::Binary(b) if b.op == Token::MUL => {
+                match &b.expr_a {
+                    Expr::Ident(i) if force || Parser::is_type_elem(&b.expr_b) => {
+                        // x = name *T
+                        Some((*i, Some(Expr::Star(Rc::new(StarExpr{
+                            star: b.op_pos, expr: b.expr_b.clone()})))))
+                    }
+                    _ => None,
+                }
+            }
+            _ => None,
+        }
+    }
+
     fn parse_array_type(&mut self) -> Expr {
+        let lpos = self.expect(&Token::LBRACK);
+        self.parse_array_type_rest(lpos, None)
+    }
+
+    // parse_array_type_rest parses an array or slice type after the opening
+    // bracket, len is the length expression if it has already been parsed.
+    fn parse_array_type_rest(&mut self, lpos: position::Pos, len: Option<Expr>) -> Expr {
         self.trace_begin("ArrayType");
 
-        let lpos = self.expect(&Token::LBRACK);
         self.expr_level += 1;
-        let len = match self.token {
+        let len = if len.is_some() { len } else { match self.token {
             // always permit ellipsis for more fault-tolerant parsing
             Token::ELLIPSIS => {
                 let ell = Expr::new_ellipsis(self.pos, None);
@@ -566,7 +854,7 @@ impl<'a> Parser<'a> {
                 Some(self.parse_rhs())
             },
             _ => None,
-        };
+        }};
         self.expr_level -= 1;
         self.expect(&Token::RBRACK);
         let elt = self.parse_type();
@@ -596,11 +884,19 @@ impl<'a> Parser<'a> {
     fn parse_field_decl(&mut self, scope: ScopeKey) -> FieldKey {
         self.trace_begin("FieldDecl");
 
+        let doc = self.lead_comment.clone();
+
         // 1st FieldDecl
 	    // A type name used as an anonymous field looks like a field identifier.
         let mut list = vec![];
+        let mut pending = None;
         loop {
-            list.push(self.parse_var_type(false));
+            let (x, typ) = self.parse_var_type_or_name(false);
+            list.push(x);
+            if typ.is_some() {
+                pending = typ;
+                break;
+            }
             if self.token != Token::COMMA {
                 break;
             }
@@ -608,7 +904,7 @@ impl<'a> Parser<'a> {
         }
 
         let mut idents = vec![];
-        let typ = match self.try_var_type(false) {
+        let typ = match pending.or_else(|| self.try_var_type(false)) {
             Some(t) => {
                 idents = self.make_ident_list(&mut list);
                 t
@@ -640,10 +936,11 @@ impl<'a> Parser<'a> {
             None
         };
 
-        self.expect_semi();
+        self.expect_semi(); // call before accessing self.line_comment
 
         let to_resolve = typ.clone_ident();
-        let field = new_field!(self, idents, typ, tag);
+        let comment = self.line_comment.clone();
+        let field = new_field!(self, doc, idents, typ, tag, comment);
         self.declare(DeclObj::Field(field), EntityData::NoData,
             EntityKind::Var, &scope);
         if let Some(ident) = to_resolve {
@@ -709,6 +1006,26 @@ impl<'a> Parser<'a> {
         self.try_ident_or_type()
     }
 
+    // parse_var_type_or_name is like parse_var_type, but "name [" may start
+    // either an array/slice typed declaration or a generic type instance.
+    // If it is the former, the name and its type are returned.
+    fn parse_var_type_or_name(&mut self, is_param: bool) -> (Expr, Option<Expr>) {
+        if let Token::IDENT(_) = self.token {
+            let x = self.parse_type_name();
+            if self.token != Token::LBRACK {
+                return (x, None);
+            }
+            if let Expr::Ident(_) = x {
+                let (typ, is_name) = self.parse_array_field_or_type_instance(x.clone());
+                if is_name { (x, Some(typ)) } else { (typ, None) }
+            } else {
+                (self.parse_type_instance(x), None)
+            }
+        } else {
+            (self.parse_var_type(is_param), None)
+        }
+    }
+
     fn parse_var_type(&mut self, is_param: bool) -> Expr {
         match self.try_var_type(is_param) {
             Some(typ) => typ,
@@ -728,8 +1045,14 @@ impl<'a> Parser<'a> {
         // 1st ParameterDecl
 	    // A list of identifiers looks like a list of type names.
         let mut list = vec![];
+        let mut pending = None;
         loop {
-            list.push(self.parse_var_type(ellipsis_ok));
+            let (x, typ) = self.parse_var_type_or_name(ellipsis_ok);
+            list.push(x);
+            if typ.is_some() {
+                pending = typ;
+                break;
+            }
             if self.token != Token::COMMA {
                 break;
             }
@@ -739,7 +1062,7 @@ impl<'a> Parser<'a> {
             }
         }
         let mut params = vec![];
-        let typ = self.try_var_type(ellipsis_ok);
+        let typ = pending.or_else(|| self.try_var_type(ellipsis_ok));
         if let Some(t) = typ {
             // IdentifierList Type
             let idents = self.make_ident_list(&mut list);
@@ -844,6 +1167,7 @@ impl<'a> Parser<'a> {
     fn parse_method_spec(&mut self, scope: ScopeKey) -> FieldKey {
         self.trace_begin("MethodSpec");
 
+        let doc = self.lead_comment.clone();
         let mut idents = vec![];
         let mut typ = self.parse_type_name();
         let ident = typ.try_as_ident();
@@ -853,11 +1177,22 @@ impl<'a> Parser<'a> {
             let (params, results) = self.parse_signature(scope);
             typ = Expr::box_func_type(FuncType::new(None, params, results), &mut self.objects);
         } else {
-            // embedded interface
+            // embedded interface or type element
             self.resolve(&typ);
+            if self.token == Token::LBRACK {
+                typ = self.parse_type_instance(typ);
+            }
+            while self.token == Token::OR {
+                let pos = self.pos;
+                self.next();
+                let y = self.parse_type_term();
+                typ = Expr::Binary(Rc::new(BinaryExpr{
+                    expr_a: typ, op_pos: pos, op: Token::OR, expr_b: y}));
+            }
         }
         self.expect_semi();
-        let field = new_field!(self, idents, typ, None);
+        let comment = self.line_comment.clone();
+        let field = new_field!(self, doc, idents, typ, None, comment);
         self.declare(DeclObj::Field(field), EntityData::NoData, EntityKind::Fun, &scope);
 
         self.trace_end();
@@ -872,8 +1207,20 @@ impl<'a> Parser<'a> {
         let scope = new_scope!(self, None);
         let mut list = vec![];
         loop {
-            if let Token::IDENT(_) = self.token {} else {break;}
-            list.push(self.parse_method_spec(scope));
+            match self.token {
+                Token::IDENT(_) => list.push(self.parse_method_spec(scope)),
+                Token::TILDE | Token::LBRACK | Token::MUL | Token::LPAREN |
+                Token::FUNC | Token::MAP | Token::CHAN | Token::ARROW |
+                Token::STRUCT | Token::INTERFACE => {
+                    // type element
+                    let doc = self.lead_comment.clone();
+                    let typ = self.parse_type_elem();
+                    self.expect_semi();
+                    let comment = self.line_comment.clone();
+                    list.push(new_field!(self, doc, vec![], typ, None, comment));
+                }
+                _ => break,
+            }
         }
         let rbrace = self.expect(&Token::RBRACE);
 
@@ -933,7 +1280,15 @@ impl<'a> Parser<'a> {
     // If the result is an identifier, it is not resolved.
     fn try_ident_or_type(&mut self) -> Option<Expr> {
         match self.token {
-            Token::IDENT(_) => Some(self.parse_type_name()),
+            Token::IDENT(_) => {
+                let typ = self.parse_type_name();
+                if self.token == Token::LBRACK {
+                    self.resolve(&typ);
+                    Some(self.parse_type_instance(typ))
+                } else {
+                    Some(typ)
+                }
+            },
             Token::LBRACK => Some(self.parse_array_type()),
             Token::STRUCT => Some(self.parse_struct_type()),
             Token::MUL => Some(self.parse_pointer_type()),
@@ -1123,7 +1478,22 @@ impl<'a> Parser<'a> {
         let mut colons = vec![0, 0, 0];
         let mut ncolons = 0;
         if self.token != Token::COLON {
-            indices[0] = Some(self.parse_rhs());
+            indices[0] = Some(self.parse_rhs_or_type());
+        }
+        if self.token == Token::COMMA {
+            // instance expression
+            let mut list = vec![indices[0].take().unwrap()];
+            while self.token == Token::COMMA {
+                self.next();
+                if self.token == Token::RBRACK {
+                    break;
+                }
+                list.push(self.parse_type());
+            }
+            self.expr_level -= 1;
+            let rbrack = self.expect(&Token::RBRACK);
+            self.trace_end();
+            return Parser::new_index_or_list(x, lbrack, list, rbrack);
         }
         while self.token == Token::COLON && ncolons < N - 1  {
             colons[ncolons] = self.pos;
@@ -1305,6 +1675,7 @@ impl<'a> Parser<'a> {
             Expr::Paren(_) => { unreachable!(); },
             Expr::Selector(_) => x,
             Expr::Index(_) => x,
+            Expr::IndexList(_) => x,
             Expr::Slice(_) => x,
             // If t.Type == nil we have a type assertion of the form
             // y.(type), which is only allowed in type switch expressions.
@@ -1332,6 +1703,8 @@ impl<'a> Parser<'a> {
             Expr::Selector(s) => {
                 if let Expr::Ident(_) = s.expr {true} else {false}
             },
+            Expr::Index(i) => Parser::is_type_name(&i.expr),
+            Expr::IndexList(i) => Parser::is_type_name(&i.expr),
             _ => false
         }
     }
@@ -1344,6 +1717,8 @@ impl<'a> Parser<'a> {
             Expr::Selector(s) => {
                 if let Expr::Ident(_) = s.expr {true} else {false}
             },
+            Expr::Index(i) => Parser::is_type_name(&i.expr),
+            Expr::IndexList(i) => Parser::is_type_name(&i.expr),
             _ => false
         }
     }
@@ -1377,10 +1752,19 @@ impl<'a> Parser<'a> {
         return x;
     }
 
-    fn parse_primary_expr(&mut self, mut lhs: bool) -> Expr {
+    fn parse_primary_expr(&mut self, lhs: bool) -> Expr {
         self.trace_begin("PrimaryExpr");
 
-        let mut x = self.parse_operand(lhs);
+        let x = self.parse_operand(lhs);
+        let ret = self.parse_primary_expr_rest(x, lhs);
+
+        self.trace_end();
+        ret
+    }
+
+    // parse_primary_expr_rest parses the selectors, indices, calls etc.
+    // following the operand x
+    fn parse_primary_expr_rest(&mut self, mut x: Expr, mut lhs: bool) -> Expr {
         loop {
             match self.token {
                 Token::PERIOD => {
@@ -1432,8 +1816,6 @@ impl<'a> Parser<'a> {
             }
             lhs = false; // no need to try to resolve again
         }
-        
-        self.trace_end();
         x
     }
 
@@ -1528,10 +1910,14 @@ impl<'a> Parser<'a> {
         (token, pre)
     }
 
-    fn parse_binary_expr(&mut self, lhs: bool, prec1: usize) -> Expr {
+    // if x is not None, it is the already parsed left operand
+    fn parse_binary_expr(&mut self, x: Option<Expr>, lhs: bool, prec1: usize) -> Expr {
         self.trace_begin("BinaryExpr");
 
-        let mut x = self.parse_unary_expr(lhs);
+        let mut x = match x {
+            Some(x) => x,
+            None => self.parse_unary_expr(lhs),
+        };
         loop {
             let (op, prec) = self.token_prec();
             if prec < prec1 {
@@ -1541,7 +1927,7 @@ impl<'a> Parser<'a> {
             if lhs {
                 self.resolve(&x);
             }
-            let y = self.parse_binary_expr(false, prec+1);
+            let y = self.parse_binary_expr(None, false, prec+1);
             x = Expr::Binary(Rc::new(BinaryExpr{
                 expr_a: x, op_pos: pos, op: op, expr_b: y}))
         }
@@ -1552,7 +1938,7 @@ impl<'a> Parser<'a> {
 
     fn parse_expr(&mut self, lhs: bool) -> Expr {
         self.trace_begin("Expression");
-        let x = self.parse_binary_expr(lhs, LOWEST_PREC+1);
+        let x = self.parse_binary_expr(None, lhs, LOWEST_PREC+1);
         self.trace_end();
         x
     }
@@ -2253,7 +2639,8 @@ impl<'a> Parser<'a> {
             .find(|&x| !x.is_ascii_graphic() || x.is_whitespace() || illegal_chars.contains(&x)).is_none()
     }
 
-    fn parse_import_spec(&mut self, _: &Token, _: isize) -> SpecKey {
+    fn parse_import_spec(&mut self, doc: Option<Rc<CommentGroup>>, _: &Token,
+        _: isize) -> SpecKey {
         self.trace_begin("ImportSpec");
 
         let ident = match self.token {
@@ -2285,18 +2672,21 @@ impl<'a> Parser<'a> {
                 token
             }
         };
-        self.expect_semi();
+        self.expect_semi(); // call before accessing self.line_comment
         let index = specs_mut!(self).insert(Spec::Import(Rc::new(ImportSpec{
+            doc: doc,
             name: ident, 
             path: BasicLit{pos: pos, token: path_token},
-            end_pos: None})));
+            end_pos: None,
+            comment: self.line_comment.clone()})));
         self.imports.push(index);
 
         self.trace_end();
         index
     }
 
-    fn parse_value_spec<'p, 'k>(self_: &'p mut Parser<'a>, keyword: &'k Token, iota: isize) -> SpecKey {
+    fn parse_value_spec<'p, 'k>(self_: &'p mut Parser<'a>, doc: Option<Rc<CommentGroup>>,
+        keyword: &'k Token, iota: isize) -> SpecKey {
         self_.trace_begin(&format!("{}{}", keyword.text(), "Spec"));
 
         let pos = self_.pos;
@@ -2309,7 +2699,8 @@ impl<'a> Parser<'a> {
             }
             _ => vec![],
         };
-        self_.expect_semi();
+        self_.expect_semi(); // call before accessing self_.line_comment
+        let comment = self_.line_comment.clone();
 
         match keyword {
             Token::VAR => {
@@ -2330,7 +2721,7 @@ impl<'a> Parser<'a> {
 	    // the end of the innermost containing block.
 	    // (Global identifiers are resolved in a separate phase after parsing.)
         let spec =  specs_mut!(self_).insert(Spec::Value(Rc::new(ValueSpec{
-            names: idents, typ: typ, values: values})));
+            doc: doc, names: idents, typ: typ, values: values, comment: comment})));
         let kind = if let Token::VAR = keyword {
                 EntityKind::Var
             } else {EntityKind::Con};
@@ -2344,7 +2735,8 @@ impl<'a> Parser<'a> {
         spec
     }
 
-    fn parse_type_spec(&mut self, _: &Token, _: isize) -> SpecKey {
+    fn parse_type_spec(&mut self, doc: Option<Rc<CommentGroup>>, _: &Token,
+        _: isize) -> SpecKey {
         self.trace_begin("TypeSpec");
 
         let ident = self.parse_ident();
@@ -2354,30 +2746,92 @@ impl<'a> Parser<'a> {
 	    // (Global identifiers are resolved in a separate phase after parsing.)
         let placeholder = Expr::new_bad(0, 0);
         let spec_val = Spec::Type(Rc::new(TypeSpec{
-            name: ident, assign: 0, typ: placeholder
+            doc: doc, name: ident, type_params: None, assign: 0, typ: placeholder,
+            comment: None,
         }));
         let index = specs_mut!(self).insert(spec_val);
         let scope = self.top_scope.unwrap();
         self.declare(DeclObj::Spec(index), EntityData::NoData, EntityKind::Typ, &scope);
-        let assign = if self.token == Token::ASSIGN {
+        let mut tparams = None;
+        let mut assign = 0;
+        let typ = if self.token == Token::LBRACK {
+            let lbrack = self.pos;
             self.next();
-            self.pos
-            } else {0};
-        let typ = self.parse_type();
+            if let Token::IDENT(_) = self.token {
+                // We may have an array type or a type parameter list.
+                // In either case we expect an expression x (which may
+                // just be a name, or a more complex expression) which
+                // we can analyze further.
+                let first = self.parse_ident();
+                let mut x = Expr::Ident(first);
+                if self.token != Token::LBRACK {
+                    // To parse the expression starting with name, expand
+                    // the call sequence we would get by passing in name
+                    // to parse_expr, and pass in name to parse_primary_expr_rest.
+                    self.expr_level += 1;
+                    let lhs = self.parse_primary_expr_rest(x, false);
+                    x = self.parse_binary_expr(Some(lhs), false, LOWEST_PREC+1);
+                    self.expr_level -= 1;
+                }
+                // Analyze expression x. If we can split x into a type parameter
+                // name, possibly followed by a type parameter type, we consider
+                // this the start of a type parameter list, with some caveats:
+                // a single name followed by "]" tilts the decision towards an
+                // array declaration; a type parameter type that could also be
+                // an ordinary expression but which is followed by a comma tilts
+                // the decision towards a type parameter list.
+                match Parser::extract_name(&x, self.token == Token::COMMA) {
+                    Some((name, ptype)) if ptype.is_some() ||
+                        self.token != Token::RBRACK => {
+                        // type parameter list
+                        self.open_scope();
+                        let tscope = self.top_scope.unwrap();
+                        tparams = Some(self.parse_type_params(
+                            lbrack, tscope, Some((name, ptype))));
+                        if self.token == Token::ASSIGN {
+                            // type alias, generic aliases are not supported
+                            self.next();
+                            assign = self.pos;
+                        }
+                        let typ = self.parse_type();
+                        self.close_scope();
+                        typ
+                    }
+                    _ => {
+                        // array type
+                        self.resolve(&Expr::Ident(first));
+                        self.parse_array_type_rest(lbrack, Some(x))
+                    }
+                }
+            } else {
+                // array type
+                self.parse_array_type_rest(lbrack, None)
+            }
+        } else {
+            if self.token == Token::ASSIGN {
+                self.next();
+                assign = self.pos;
+            }
+            self.parse_type()
+        };
+        self.expect_semi(); // call before accessing self.line_comment
+        let comment = self.line_comment.clone();
         let spec = if let Spec::Type(boxts) = spec_mut!(self, index) {
             Rc::get_mut(boxts).unwrap()} else {unreachable!()};
+        spec.type_params = tparams;
         spec.assign = assign;
         spec.typ = typ;
-        self.expect_semi();
+        spec.comment = comment;
 
         self.trace_end();
         index
     }
 
     fn parse_gen_decl(&mut self, keyword: &Token, 
-        f: fn (&mut Parser<'a>, &Token, isize) -> SpecKey) -> Decl {
+        f: fn (&mut Parser<'a>, Option<Rc<CommentGroup>>, &Token, isize) -> SpecKey) -> Decl {
         self.trace_begin(&format!("GenDecl({})", keyword.text()));
 
+        let doc = self.lead_comment.clone();
         let pos = self.expect(keyword);
         let (lparen, rparen, list) = if self.token == Token::LPAREN {
             let lp = self.pos;
@@ -2385,18 +2839,20 @@ impl<'a> Parser<'a> {
             let mut list = vec![];
             let mut iota = 0;
             while self.token != Token::RPAREN && self.token != Token::EOF {
-                list.push(f(self, keyword, iota));
+                let doc = self.lead_comment.clone();
+                list.push(f(self, doc, keyword, iota));
                 iota += 1;
             }
             let rp = self.expect(&Token::RPAREN);
             self.expect_semi();
             (Some(lp), Some(rp), list)
         } else {
-            (None, None, vec![f(self, keyword, 0)])
+            (None, None, vec![f(self, None, keyword, 0)])
         };
 
         self.trace_end();
         Decl::Gen(Rc::new(GenDecl{
+            doc: doc,
             token_pos: pos,
             token: keyword.clone(),
             l_paran: lparen,
@@ -2408,6 +2864,7 @@ impl<'a> Parser<'a> {
     fn parse_func_decl(&mut self) -> Decl {
         self.trace_begin("FunctionDecl");
 
+        let doc = self.lead_comment.clone();
         let pos = self.expect(&Token::FUNC);
         let scope = new_scope!(self, self.top_scope);
         let recv = if self.token == Token::LPAREN {
@@ -2416,6 +2873,13 @@ impl<'a> Parser<'a> {
             None
         };
         let ident = self.parse_ident();
+        let tparams = if self.token == Token::LBRACK {
+            let lbrack = self.pos;
+            self.next();
+            Some(self.parse_type_params(lbrack, scope, None))
+        } else {
+            None
+        };
         let (params, results) = self.parse_signature(scope);
         let body = if self.token == Token::LBRACE {
             Some(Rc::new(self.parse_body(scope)))
@@ -2427,10 +2891,12 @@ impl<'a> Parser<'a> {
         let recv_is_none = recv.is_none();
         let typ = self.objects.ftypes.insert(FuncType{
             func: Some(pos),
+            type_params: tparams,
             params: params,
             results: results,
         });
         let decl = self.objects.fdecls.insert(FuncDecl{
+            doc: doc,
             recv: recv,
             name: ident,
             typ: typ,
@@ -2495,6 +2961,7 @@ impl<'a> Parser<'a> {
         self.trace_begin("File");
         
         let err_count = self.errors.len();
+        let doc = self.lead_comment.clone();
         let pos = self.expect(&Token::PACKAGE);
         // Go spec: The package clause is not a declaration;
 	    // the package name does not appear in any scope.
@@ -2542,12 +3009,14 @@ impl<'a> Parser<'a> {
 
         self.trace_end();
         Some(File{
+            doc: doc,
             package: pos,
             name: ident,
             decls: decls,
             scope: self.pkg_scope.unwrap(),
             imports: self.imports.clone(),
             unresolved: self.unresolved.clone(),
+            comments: self.comments.clone(),
         })
     }
 }
@@ -0,0 +1,3237 @@
+//! The printer formats a file the way gofmt does. It's a port of go/printer:
+//! the tokens are written with the white space and the comments in between,
+//! using the positions in the source for the line breaks, and the result goes
+//! through a tabwriter for the alignment and a trimmer for the trailing white
+//! space.
+
+use super::ast::*;
+use super::errors::ErrorList;
+use super::objects::{FieldKey, IdentKey, Objects};
+use super::position::{FileSet, Pos};
+use super::token::{Token, HIGHEST_PREC, LOWEST_PREC, UNARY_PREC};
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::rc::Rc;
+
+const MAX_NEWLINES: usize = 2; // max. number of newlines between source text
+const INFINITY: usize = 1 << 30;
+
+// the escape of the tabwriter, the text in between passes through unchanged,
+// it's not in any utf8 string
+const ESCAPE: u8 = 0xff;
+
+// the modes toggled during printing
+const NO_EXTRA_BLANK: u8 = 1; // don't add an extra blank after a /*-style comment
+const NO_EXTRA_LINEBREAK: u8 = 2; // don't add an extra linebreak after a //-style comment
+
+// the modes of expr_list
+const COMMA_TERM: u8 = 1; // list is optionally terminated by a comma
+const NO_INDENT: u8 = 2; // no extra indentation in multi-line lists
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum WhiteSpace {
+    Ignore,
+    Blank,
+    Vtab,
+    Newline,
+    Formfeed,
+    Indent,
+    Unindent,
+}
+
+impl WhiteSpace {
+    fn byte(&self) -> u8 {
+        match self {
+            WhiteSpace::Blank => b' ',
+            WhiteSpace::Vtab => b'\x0b',
+            WhiteSpace::Newline => b'\n',
+            WhiteSpace::Formfeed => b'\x0c',
+            _ => unreachable!(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum ParamMode {
+    Func,
+    FuncType,
+    TypeType,
+}
+
+/// Position is a position in the source or in the output, the offset and the
+/// column of a source position are in chars, the column in the output is in
+/// bytes
+#[derive(Clone, Copy, Debug, Default)]
+struct Position {
+    offset: usize,
+    line: usize,
+    column: usize,
+}
+
+impl Position {
+    fn new(offset: usize, line: usize, column: usize) -> Position {
+        Position {
+            offset: offset,
+            line: line,
+            column: column,
+        }
+    }
+
+    fn is_valid(&self) -> bool {
+        self.line > 0
+    }
+}
+
+/// the nodes the size of which is computed by printing them
+#[derive(Clone, Copy)]
+enum SizeNode<'a> {
+    Expr(&'a Expr),
+    Stmt(&'a Stmt),
+}
+
+/// the lines of the file being printed
+struct Lines {
+    base: usize,
+    starts: Vec<usize>,
+}
+
+impl Lines {
+    fn position(&self, pos: Pos) -> Position {
+        if pos < self.base {
+            return Position::default();
+        }
+        let offset = pos - self.base;
+        let line = match self.starts.binary_search(&offset) {
+            Ok(i) => i + 1,
+            Err(i) => i,
+        };
+        Position::new(pos, line, offset - self.starts[line - 1] + 1)
+    }
+}
+
+struct Printer<'a> {
+    objs: &'a Objects,
+    lines: Rc<Lines>,
+
+    // Current state
+    output: Vec<u8>,                   // raw printer result
+    indent: isize,                     // current indentation
+    level: usize,                      // nesting level of composite literals
+    mode: u8,                          // current printer mode
+    end_alignment: bool,               // if set, terminate alignment immediately
+    implied_semi: bool,                // if set, a linebreak implies a semicolon
+    last_tok: Token,                   // last token printed (NONE if it's whitespace)
+    prev_open: Token,                  // previous non-brace "open" token (, [, or NONE
+    wsbuf: Vec<WhiteSpace>,            // delayed white space
+    line_ptr: Option<Rc<Cell<usize>>>, // if set, record out.line for the next token in it
+
+    // Positions
+    // The out position differs from the pos position when the result
+    // formatting differs from the source formatting (in the amount of
+    // white space).
+    pos: Position,  // current position in AST (source) space
+    out: Position,  // current position in output space
+    last: Position, // value of pos after calling write_string
+
+    // The list of all source comments, in order of appearance.
+    comments: Vec<Rc<CommentGroup>>,   // may be empty
+    cindex: usize,                     // current comment index
+    comment: Option<Rc<CommentGroup>>, // = comments[cindex-1]; or None
+    comment_offset: usize,             // offset of comment; or INFINITY
+    comment_newline: bool,             // true if the comment group contains newlines
+
+    // Cache of already computed node sizes.
+    node_sizes: Rc<RefCell<HashMap<NodeId, usize>>>,
+}
+
+/// fprint formats the file, which is in fset and the objects of the parser,
+/// the way gofmt does
+pub fn fprint(fset: &FileSet, objs: &Objects, file: &File) -> String {
+    let f = fset.file(file.package).unwrap();
+    let lines = Rc::new(Lines {
+        base: f.base(),
+        starts: f.lines().to_vec(),
+    });
+    let mut p = Printer::new(objs, lines, file.comments.clone(), Default::default());
+    p.file(file);
+    // print outstanding comments
+    p.implied_semi = false; // EOF acts like a newline
+    p.flush(Position::new(INFINITY, INFINITY, 0), &Token::EOF);
+    let aligned = tabwrite(&p.output);
+    String::from_utf8(trim(&aligned)).unwrap()
+}
+
+/// format_source parses the source of a file and formats it, the errors are
+/// those of the parser
+pub fn format_source(name: &str, src: &str) -> Result<String, ErrorList> {
+    let fset = &mut FileSet::new();
+    let objs = &mut Objects::new();
+    let el = ErrorList::new();
+    let (_, file) = super::parse_file(objs, fset, &el, name, src, false);
+    match file {
+        Some(f) if el.len() == 0 => Ok(fprint(fset, objs, &f)),
+        _ => Err(el),
+    }
+}
+
+impl<'a> Printer<'a> {
+    fn new(
+        objs: &'a Objects,
+        lines: Rc<Lines>,
+        comments: Vec<Rc<CommentGroup>>,
+        node_sizes: Rc<RefCell<HashMap<NodeId, usize>>>,
+    ) -> Printer<'a> {
+        let mut p = Printer {
+            objs: objs,
+            lines: lines,
+            output: vec![],
+            indent: 0,
+            level: 0,
+            mode: 0,
+            end_alignment: false,
+            implied_semi: false,
+            last_tok: Token::NONE,
+            prev_open: Token::NONE,
+            wsbuf: vec![],
+            line_ptr: None,
+            pos: Position::new(0, 1, 1),
+            out: Position::new(0, 1, 1),
+            last: Position::default(),
+            comments: comments,
+            cindex: 0,
+            comment: None,
+            comment_offset: INFINITY,
+            comment_newline: false,
+            node_sizes: node_sizes,
+        };
+        // get comments ready for use
+        p.next_comment();
+        p
+    }
+
+    fn pos_for(&self, pos: Pos) -> Position {
+        self.lines.position(pos)
+    }
+
+    fn line_for(&self, pos: Pos) -> usize {
+        self.pos_for(pos).line
+    }
+
+    fn opt_pos_for(&self, pos: Option<Pos>) -> Position {
+        pos.map_or(Position::default(), |p| self.pos_for(p))
+    }
+
+    fn set_pos(&mut self, pos: Pos) {
+        self.pos = self.pos_for(pos);
+    }
+
+    fn next_comment(&mut self) {
+        while self.cindex < self.comments.len() {
+            let c = self.comments[self.cindex].clone();
+            self.cindex += 1;
+            if !c.list.is_empty() {
+                self.comment_offset = self.pos_for(c.pos()).offset;
+                self.comment_newline = self.comments_have_newline(&c.list);
+                self.comment = Some(c);
+                return;
+            }
+        }
+        // no more comments
+        self.comment_offset = INFINITY;
+    }
+
+    // comments_have_newline reports whether a list of comments belonging to
+    // a CommentGroup contains newlines.
+    fn comments_have_newline(&self, list: &[Comment]) -> bool {
+        let line = self.line_for(list[0].slash);
+        for (i, c) in list.iter().enumerate() {
+            if i > 0 && self.line_for(c.slash) != line {
+                // not all comments on the same line
+                return true;
+            }
+            if is_line_comment(c) || c.text.contains('\n') {
+                return true;
+            }
+        }
+        false
+    }
+
+    // commentBefore reports whether the current comment group occurs
+    // before the next position in the source code and printing it does
+    // not introduce implicit semicolons.
+    fn comment_before(&self, next: Position) -> bool {
+        self.comment_offset < next.offset && (!self.implied_semi || !self.comment_newline)
+    }
+
+    // comment_size_before returns the estimated size of the
+    // comments on the same line before the next position.
+    fn comment_size_before(&mut self, next: Position) -> usize {
+        // save/restore comments state
+        let saved = (
+            self.cindex,
+            self.comment.clone(),
+            self.comment_offset,
+            self.comment_newline,
+        );
+        let mut size = 0;
+        while self.comment_before(next) {
+            for c in self.comment.as_ref().unwrap().list.iter() {
+                size += c.text.len();
+            }
+            self.next_comment();
+        }
+        self.cindex = saved.0;
+        self.comment = saved.1;
+        self.comment_offset = saved.2;
+        self.comment_newline = saved.3;
+        size
+    }
+
+    // record_line records the output line number for the next non-whitespace
+    // token in the cell returned
+    fn record_line(&mut self) -> Rc<Cell<usize>> {
+        let line = Rc::new(Cell::new(0));
+        self.line_ptr = Some(line.clone());
+        line
+    }
+
+    // lines_from returns the number of output lines between the current
+    // output line and the line argument, ignoring any pending (not yet
+    // emitted) whitespace or comments. It is used to compute an accurate
+    // size (in number of lines) for a formatted construct.
+    fn lines_from(&self, line: usize) -> usize {
+        self.out.line - line
+    }
+
+    // ----------------------------------------------------------------------------
+    // Writing
+
+    fn write_indent(&mut self) {
+        // use "hard" htabs - indentation columns
+        // must not be discarded by the tabwriter
+        let n = self.indent.max(0) as usize;
+        self.output.extend(std::iter::repeat(b'\t').take(n));
+        // update positions
+        self.pos.offset += n;
+        self.pos.column += n;
+        self.out.column += n;
+    }
+
+    // write_byte writes ch n times to the output and updates the positions.
+    // Only used to write white space.
+    fn write_byte(&mut self, ch: u8, n: usize) {
+        let mut ch = ch;
+        if self.end_alignment {
+            // Ignore any alignment control character;
+            // and at the end of the line, break with
+            // a formfeed to indicate termination of
+            // existing columns.
+            match ch {
+                b'\t' | b'\x0b' => ch = b' ',
+                b'\n' | b'\x0c' => {
+                    ch = b'\x0c';
+                    self.end_alignment = false;
+                }
+                _ => {}
+            }
+        }
+
+        if self.out.column == 1 {
+            self.write_indent();
+        }
+
+        self.output.extend(std::iter::repeat(ch).take(n));
+
+        // update positions
+        self.pos.offset += n;
+        if ch == b'\n' || ch == b'\x0c' {
+            self.pos.line += n;
+            self.out.line += n;
+            self.pos.column = 1;
+            self.out.column = 1;
+            return;
+        }
+        self.pos.column += n;
+        self.out.column += n;
+    }
+
+    // write_string writes the string s to the output and updates the
+    // positions. If is_lit is set, s is escaped w/ ESCAPE so that it
+    // passes through unchanged through the tabwriter.
+    fn write_string(&mut self, pos: Position, s: &str, is_lit: bool) {
+        if self.out.column == 1 {
+            self.write_indent();
+        }
+
+        if pos.is_valid() {
+            // update self.pos (if pos is invalid, continue with existing self.pos)
+            // Note: Must do this after handling line beginnings because
+            // write_indent updates self.pos if there's indentation, but self.pos
+            // is the position of s.
+            self.pos = pos;
+        }
+
+        if is_lit {
+            self.output.push(ESCAPE);
+        }
+        self.output.extend_from_slice(s.as_bytes());
+
+        // update positions
+        self.pos.offset += s.chars().count();
+        match s.rfind('\n') {
+            Some(li) => {
+                let nlines = s.matches('\n').count();
+                self.pos.line += nlines;
+                self.out.line += nlines;
+                self.pos.column = s[li..].chars().count();
+                self.out.column = s.len() - li;
+                self.end_alignment = true;
+            }
+            None => {
+                self.pos.column += s.chars().count();
+                self.out.column += s.len();
+            }
+        }
+
+        if is_lit {
+            self.output.push(ESCAPE);
+        }
+
+        self.last = self.pos;
+    }
+
+    // write_whitespace writes the first n whitespace entries.
+    fn write_whitespace(&mut self, n: usize) {
+        let mut i = 0;
+        while i < n {
+            match self.wsbuf[i] {
+                WhiteSpace::Ignore => {}
+                WhiteSpace::Indent => self.indent += 1,
+                WhiteSpace::Unindent => {
+                    self.indent -= 1;
+                    if self.indent < 0 {
+                        self.indent = 0;
+                    }
+                }
+                ch @ WhiteSpace::Newline | ch @ WhiteSpace::Formfeed => {
+                    // A line break immediately followed by a "correcting"
+                    // unindent is swapped with the unindent - this permits
+                    // proper label positioning. If a comment is between
+                    // the line break and the label, the unindent is not
+                    // part of the comment whitespace prefix and the comment
+                    // will be positioned correctly indented.
+                    if i + 1 < n && self.wsbuf[i + 1] == WhiteSpace::Unindent {
+                        // Use a formfeed to terminate the current section.
+                        // Otherwise, a long label name on the next line leading
+                        // to a wide column may increase the indentation column
+                        // of lines before the label; effectively leading to wrong
+                        // indentation.
+                        self.wsbuf[i] = WhiteSpace::Unindent;
+                        self.wsbuf[i + 1] = WhiteSpace::Formfeed;
+                        continue; // do it again
+                    }
+                    self.write_byte(ch.byte(), 1);
+                }
+                ch => self.write_byte(ch.byte(), 1),
+            }
+            i += 1;
+        }
+        // shift remaining entries down
+        self.wsbuf.drain(..n);
+    }
+
+    // ----------------------------------------------------------------------------
+    // Comments
+
+    // write_comment_prefix writes the whitespace before a comment.
+    // If there is any pending whitespace, it consumes as much of
+    // it as is likely to help position the comment nicely.
+    // pos is the comment position, next the position of the item
+    // after all pending comments, prev is the previous comment in
+    // a group of comments (or None), and tok is the next token.
+    fn write_comment_prefix(
+        &mut self,
+        pos: Position,
+        next: Position,
+        prev: Option<&Comment>,
+        tok: &Token,
+    ) {
+        if self.output.is_empty() {
+            // the comment is the first item to be printed - don't write any whitespace
+            return;
+        }
+
+        if pos.line == self.last.line && prev.map_or(true, |c| !is_line_comment(c)) {
+            // comment on the same line as last item:
+            // separate with at least one separator
+            let mut has_sep = false;
+            if prev.is_none() {
+                // first comment of a comment group
+                let mut j = 0;
+                for i in 0..self.wsbuf.len() {
+                    match self.wsbuf[i] {
+                        WhiteSpace::Blank => {
+                            // ignore any blanks before a comment
+                            self.wsbuf[i] = WhiteSpace::Ignore;
+                            continue;
+                        }
+                        WhiteSpace::Vtab => {
+                            // respect existing tabs - important
+                            // for proper formatting of commented structs
+                            has_sep = true;
+                            continue;
+                        }
+                        WhiteSpace::Indent => {
+                            // apply pending indentation
+                            continue;
+                        }
+                        _ => {}
+                    }
+                    j = i;
+                    break;
+                }
+                self.write_whitespace(j);
+            }
+            // make sure there is at least one separator
+            if !has_sep {
+                if pos.line == next.line {
+                    // next item is on the same line as the comment
+                    // (which must be a /*-style comment): separate
+                    // with a blank instead of a tab
+                    self.write_byte(b' ', 1);
+                } else {
+                    self.write_byte(b'\t', 1);
+                }
+            }
+        } else {
+            // comment on a different line:
+            // separate with at least one line break
+            let mut dropped_linebreak = false;
+            let mut j = 0;
+            for i in 0..self.wsbuf.len() {
+                match self.wsbuf[i] {
+                    WhiteSpace::Blank | WhiteSpace::Vtab => {
+                        // ignore any horizontal whitespace before line breaks
+                        self.wsbuf[i] = WhiteSpace::Ignore;
+                        continue;
+                    }
+                    WhiteSpace::Indent => {
+                        // apply pending indentation
+                        continue;
+                    }
+                    WhiteSpace::Unindent => {
+                        // if this is not the last unindent, apply it
+                        // as it is (likely) belonging to the last
+                        // construct (e.g., a multi-line expression list)
+                        // and is not part of closing a block
+                        if i + 1 < self.wsbuf.len() && self.wsbuf[i + 1] == WhiteSpace::Unindent {
+                            continue;
+                        }
+                        // if the next token is not a closing }, apply the unindent
+                        // if it appears that the comment is aligned with the
+                        // token; otherwise assume the unindent is part of a
+                        // closing block and stop (this scenario appears with
+                        // comments before a case label where the comments
+                        // apply to the next case instead of the current one)
+                        if *tok != Token::RBRACE && pos.column == next.column {
+                            continue;
+                        }
+                    }
+                    WhiteSpace::Newline | WhiteSpace::Formfeed => {
+                        self.wsbuf[i] = WhiteSpace::Ignore;
+                        // record only if first comment of a group
+                        dropped_linebreak = prev.is_none();
+                    }
+                    _ => {}
+                }
+                j = i;
+                break;
+            }
+            self.write_whitespace(j);
+
+            // determine number of linebreaks before the comment
+            let mut n = 0;
+            if pos.is_valid() && self.last.is_valid() && pos.line > self.last.line {
+                n = pos.line - self.last.line;
+            }
+
+            // at the package scope level only (self.indent == 0),
+            // add an extra newline if we dropped one before:
+            // this preserves a blank line before documentation
+            // comments at the package scope level (issue 2570)
+            if self.indent == 0 && dropped_linebreak {
+                n += 1;
+            }
+
+            // make sure there is at least one line break
+            // if the previous comment was a line comment
+            if n == 0 && prev.map_or(false, |c| is_line_comment(c)) {
+                n = 1;
+            }
+
+            if n > 0 {
+                // use formfeeds to break columns before a comment;
+                // this is analogous to using formfeeds to separate
+                // individual lines of /*-style comments
+                self.write_byte(b'\x0c', nlimit(n));
+            }
+        }
+    }
+
+    fn write_comment(&mut self, comment: &Comment) {
+        let text = &comment.text;
+        let mut pos = self.pos_for(comment.slash);
+
+        // shortcut common case of //-style comments
+        if is_line_comment(comment) {
+            self.write_string(pos, text.trim_end(), true);
+            return;
+        }
+
+        // for /*-style comments, print line by line and let the
+        // write function take care of the proper indentation
+        let mut lines: Vec<String> = text.split('\n').map(|l| l.to_string()).collect();
+
+        // The comment started in the first column but is going
+        // to be indented. For an idempotent result, add indentation
+        // to all lines such that they look like they were indented
+        // before - this will make sure the common prefix computation
+        // uses the same amount of indentation for all lines.
+        if pos.is_valid() && pos.column == 1 && self.indent > 0 {
+            for line in lines.iter_mut().skip(1) {
+                line.insert_str(0, "   ");
+            }
+        }
+
+        strip_common_prefix(&mut lines);
+
+        // write comment lines, separated by formfeed,
+        // without a line break after the last line
+        for (i, line) in lines.iter().enumerate() {
+            if i > 0 {
+                self.write_byte(b'\x0c', 1);
+                pos = self.pos;
+            }
+            if !line.is_empty() {
+                self.write_string(pos, line.trim_end(), true);
+            }
+        }
+    }
+
+    // write_comment_suffix writes a line break after a comment if indicated
+    // and processes any leftover indentation information. If a line break
+    // is needed, the kind of break (newline vs formfeed) depends on the
+    // pending whitespace. The write_comment_suffix result indicates if a
+    // newline was written or if a formfeed was dropped from the whitespace
+    // buffer.
+    fn write_comment_suffix(&mut self, needs_linebreak: bool) -> (bool, bool) {
+        let mut needs_linebreak = needs_linebreak;
+        let (mut wrote_newline, mut dropped_ff) = (false, false);
+        for i in 0..self.wsbuf.len() {
+            match self.wsbuf[i] {
+                WhiteSpace::Blank | WhiteSpace::Vtab => {
+                    // ignore trailing whitespace
+                    self.wsbuf[i] = WhiteSpace::Ignore;
+                }
+                WhiteSpace::Newline | WhiteSpace::Formfeed => {
+                    // if we need a line break, keep exactly one
+                    // but remember if we dropped any formfeeds
+                    if needs_linebreak {
+                        needs_linebreak = false;
+                        wrote_newline = true;
+                    } else {
+                        if self.wsbuf[i] == WhiteSpace::Formfeed {
+                            dropped_ff = true;
+                        }
+                        self.wsbuf[i] = WhiteSpace::Ignore;
+                    }
+                }
+                // don't lose indentation information
+                _ => {}
+            }
+        }
+        self.write_whitespace(self.wsbuf.len());
+
+        // make sure we have a line break
+        if needs_linebreak {
+            self.write_byte(b'\n', 1);
+            wrote_newline = true;
+        }
+
+        (wrote_newline, dropped_ff)
+    }
+
+    // contains_linebreak reports whether the whitespace buffer contains any line breaks.
+    fn contains_linebreak(&self) -> bool {
+        self.wsbuf
+            .iter()
+            .any(|ws| *ws == WhiteSpace::Newline || *ws == WhiteSpace::Formfeed)
+    }
+
+    // intersperse_comments consumes all comments that appear before the next token
+    // tok and prints it together with the buffered whitespace (i.e., the whitespace
+    // that needs to be written before the next token). A heuristic is used to mix
+    // the comments and whitespace. The intersperse_comments result indicates if a
+    // newline was written or if a formfeed was dropped from the whitespace buffer.
+    fn intersperse_comments(&mut self, next: Position, tok: &Token) -> (bool, bool) {
+        let mut last: Option<(Rc<CommentGroup>, usize)> = None; // the last comment written
+        while self.comment_before(next) {
+            let group = self.comment.clone().unwrap();
+            for (i, c) in group.list.iter().enumerate() {
+                let pos = self.pos_for(c.slash);
+                let prev = last.as_ref().map(|(g, j)| &g.list[*j]);
+                self.write_comment_prefix(pos, next, prev, tok);
+                self.write_comment(c);
+                last = Some((group.clone(), i));
+            }
+            self.next_comment();
+        }
+
+        let (group, i) = last.unwrap();
+        let (slash, line_comment) = (group.list[i].slash, is_line_comment(&group.list[i]));
+        // If the last comment is a /*-style comment and the next item
+        // follows on the same line but is not a comma, and not a "closing"
+        // token immediately following its corresponding "opening" token,
+        // add an extra separator unless explicitly disabled. Use a blank
+        // as separator unless we have pending linebreaks, they are not
+        // disabled, and we are outside a composite literal, in which case
+        // we want a linebreak (issue 15137).
+        let mut needs_linebreak = false;
+        if self.mode & NO_EXTRA_BLANK == 0
+            && !line_comment
+            && self.line_for(slash) == next.line
+            && *tok != Token::COMMA
+            && (*tok != Token::RPAREN || self.prev_open == Token::LPAREN)
+            && (*tok != Token::RBRACK || self.prev_open == Token::LBRACK)
+        {
+            if self.contains_linebreak() && self.mode & NO_EXTRA_LINEBREAK == 0 && self.level == 0 {
+                needs_linebreak = true;
+            } else {
+                self.write_byte(b' ', 1);
+            }
+        }
+        // Ensure that there is a line break after a //-style comment,
+        // before EOF, and before a closing '}' unless explicitly disabled.
+        if line_comment
+            || *tok == Token::EOF
+            || (*tok == Token::RBRACE && self.mode & NO_EXTRA_LINEBREAK == 0)
+        {
+            needs_linebreak = true;
+        }
+        self.write_comment_suffix(needs_linebreak)
+    }
+
+    // flush prints any pending comments and whitespace occurring textually
+    // before the position of the next token tok. The flush result indicates
+    // if a newline was written or if a formfeed was dropped from the whitespace
+    // buffer.
+    fn flush(&mut self, next: Position, tok: &Token) -> (bool, bool) {
+        if self.comment_before(next) {
+            // if there are comments before the next item, intersperse them
+            self.intersperse_comments(next, tok)
+        } else {
+            // otherwise, write any leftover whitespace
+            self.write_whitespace(self.wsbuf.len());
+            (false, false)
+        }
+    }
+
+    // ----------------------------------------------------------------------------
+    // Printing, one item at a time, as print does in go/printer
+
+    fn update_prev_open(&mut self) {
+        // record previous opening token, if any
+        match self.last_tok {
+            Token::NONE => {} // ignore (white space)
+            Token::LPAREN | Token::LBRACK => self.prev_open = self.last_tok.clone(),
+            // other tokens followed any opening token
+            _ => self.prev_open = Token::NONE,
+        }
+    }
+
+    fn ws(&mut self, ws: WhiteSpace) {
+        self.update_prev_open();
+        if ws == WhiteSpace::Ignore {
+            // don't add ignore's to the buffer; they
+            // may screw up "correcting" unindents (see
+            // LabeledStmt)
+            return;
+        }
+        self.wsbuf.push(ws);
+        if ws == WhiteSpace::Newline || ws == WhiteSpace::Formfeed {
+            // newlines affect the current state (self.implied_semi)
+            // and not the state after printing arg (implied_semi)
+            // because comments can be interspersed before the arg
+            // in this case
+            self.implied_semi = false;
+        }
+        self.last_tok = Token::NONE;
+    }
+
+    fn toggle(&mut self, mode: u8) {
+        self.update_prev_open();
+        self.mode ^= mode;
+    }
+
+    fn token(&mut self, tok: Token) {
+        self.update_prev_open();
+        let s = tok.text().to_string();
+        if may_combine(&self.last_tok, s.as_bytes()[0]) {
+            // the previous and the current token must be
+            // separated by a blank otherwise they combine
+            // into a different incorrect token sequence
+            // (except for INT followed by a '.' this
+            // should never happen because it is taken care
+            // of via binary expression formatting)
+            self.wsbuf.clear();
+            self.wsbuf.push(WhiteSpace::Blank);
+        }
+        // some keywords followed by a newline imply a semicolon
+        let implied_semi = match tok {
+            Token::BREAK
+            | Token::CONTINUE
+            | Token::FALLTHROUGH
+            | Token::RETURN
+            | Token::INC
+            | Token::DEC
+            | Token::RPAREN
+            | Token::RBRACK
+            | Token::RBRACE => true,
+            _ => false,
+        };
+        self.last_tok = tok;
+        self.write_item(&s, false, implied_semi);
+    }
+
+    fn ident(&mut self, key: IdentKey) {
+        self.update_prev_open();
+        let o = self.objs;
+        self.last_tok = Token::IDENT("".to_string().into());
+        self.write_item(&o.idents[key].name, false, true);
+    }
+
+    fn lit(&mut self, lit: &BasicLit) {
+        self.update_prev_open();
+        let s = normalized_number(&lit.token);
+        self.last_tok = lit.token.clone();
+        self.write_item(&s, true, true);
+    }
+
+    // string prints the text of a bad node
+    fn string(&mut self, s: &str) {
+        self.update_prev_open();
+        self.last_tok = Token::STRING("".to_string().into());
+        self.write_item(s, true, true);
+    }
+
+    fn write_item(&mut self, data: &str, is_lit: bool, implied_semi: bool) {
+        let mut implied_semi = implied_semi;
+        let next = self.pos; // estimated/accurate position of next item
+        let tok = self.last_tok.clone();
+        let (wrote_newline, dropped_ff) = self.flush(next, &tok);
+
+        // intersperse extra newlines if present in the source and
+        // if they don't cause extra semicolons (don't do this in
+        // flush as it will cause extra newlines at the end of a file)
+        if !self.implied_semi && next.line > self.pos.line {
+            let mut n = nlimit(next.line - self.pos.line);
+            // don't exceed MAX_NEWLINES if we already wrote one
+            if wrote_newline && n == MAX_NEWLINES {
+                n = MAX_NEWLINES - 1;
+            }
+            if n > 0 {
+                let ch = if dropped_ff { b'\x0c' } else { b'\n' };
+                self.write_byte(ch, n);
+                implied_semi = false;
+            }
+        }
+
+        // the next token starts now - record its line number if requested
+        if let Some(line) = self.line_ptr.take() {
+            line.set(self.out.line);
+        }
+
+        self.write_string(next, data, is_lit);
+        self.implied_semi = implied_semi;
+    }
+
+    // linebreak prints n linebreaks where n is the difference of line and
+    // the current line, at least min and at most MAX_NEWLINES; ws is printed
+    // before them if there are any, and the first one is a formfeed if
+    // new_section is set. It returns the number of linebreaks printed, with
+    // a formfeed counted as 2.
+    fn linebreak(&mut self, line: usize, min: usize, ws: WhiteSpace, new_section: bool) -> usize {
+        let mut n = nlimit(line.saturating_sub(self.pos.line)).max(min);
+        let mut nbreaks = 0;
+        if n > 0 {
+            self.ws(ws);
+            if new_section {
+                self.ws(WhiteSpace::Formfeed);
+                n -= 1;
+                nbreaks = 2;
+            }
+            nbreaks += n;
+            for _ in 0..n {
+                self.ws(WhiteSpace::Newline);
+            }
+        }
+        nbreaks
+    }
+
+    // ----------------------------------------------------------------------------
+    // Sizes
+
+    // node_size determines the size of n in chars after formatting.
+    // The result is <= max_size if the node fits on one line with at
+    // most max_size chars and the formatted output doesn't contain
+    // any control chars. Otherwise, the result is > max_size.
+    fn node_size(&mut self, n: SizeNode, max_size: usize) -> usize {
+        // node_size invokes the printer, which may invoke node_size
+        // recursively. For deep composite literal nests, this can
+        // lead to an exponential algorithm. Remember previous
+        // results to prune the recursion (was issue 1628).
+        let id = match n {
+            SizeNode::Expr(e) => e.id(),
+            SizeNode::Stmt(s) => s.id(),
+        };
+        if let Some(size) = self.node_sizes.borrow().get(&id) {
+            return *size;
+        }
+
+        let mut size = max_size + 1; // assume n doesn't fit
+        self.node_sizes.borrow_mut().insert(id.clone(), size);
+
+        // node_size computation must be independent of particular
+        // style so that we always get the same decision; print
+        // without the comments and the tabwriter
+        let mut p = Printer::new(
+            self.objs,
+            self.lines.clone(),
+            vec![],
+            self.node_sizes.clone(),
+        );
+        match n {
+            SizeNode::Expr(e) => p.expr(e),
+            SizeNode::Stmt(s) => {
+                if let Stmt::Labeled(_) = s {
+                    // A labeled statement will un-indent to position the label.
+                    // Set indent to 1 so we don't get indent "underflow".
+                    p.indent = 1;
+                }
+                p.stmt(s, false);
+            }
+        }
+        p.implied_semi = false;
+        p.flush(Position::new(INFINITY, INFINITY, 0), &Token::EOF);
+        let out = trim(&p.output);
+        if out.len() <= max_size && !out.contains(&b'\n') {
+            // n fits in a single line
+            size = out.len();
+            self.node_sizes.borrow_mut().insert(id, size);
+        }
+        size
+    }
+
+    // num_lines returns the number of lines spanned by the node in the source
+    fn num_lines(&self, pos: Pos, end: Pos) -> usize {
+        self.line_for(end) - self.line_for(pos) + 1
+    }
+
+    // body_size is like node_size but it is specialized for block statements.
+    fn body_size(&mut self, b: &BlockStmt, max_size: usize) -> usize {
+        let pos1 = b.pos();
+        let pos2 = b.r_brace;
+        if self.line_for(pos1) != self.line_for(pos2) {
+            // opening and closing brace are on different lines - don't make it a one-liner
+            return max_size + 1;
+        }
+        if b.list.len() > 5 {
+            // too many statements - don't make it a one-liner
+            return max_size + 1;
+        }
+        // otherwise, estimate body size
+        let mut body_size = self.comment_size_before(self.pos_for(pos2));
+        for (i, s) in b.list.iter().enumerate() {
+            if body_size > max_size {
+                break; // no need to continue
+            }
+            if i > 0 {
+                body_size += 2; // space for a semicolon and blank
+            }
+            body_size += self.node_size(SizeNode::Stmt(s), max_size);
+        }
+        body_size
+    }
+
+    // distance_from returns the column difference between self.out (the current output
+    // position) and start_out_col. If the start position is on a different line from
+    // the current position (or either is unknown), the result is INFINITY.
+    fn distance_from(&self, start_pos: Pos, start_out_col: usize) -> usize {
+        if self.pos.is_valid() && self.line_for(start_pos) == self.pos.line {
+            return self.out.column - start_out_col;
+        }
+        INFINITY
+    }
+
+    // ----------------------------------------------------------------------------
+    // Common AST nodes.
+
+    fn ident_list(&mut self, list: &[IdentKey], indent: bool) {
+        // convert into an expression list so we can re-use expr_list formatting
+        let xlist: Vec<Expr> = list.iter().map(|i| Expr::Ident(*i)).collect();
+        let mode = if indent { 0 } else { NO_INDENT };
+        self.expr_list(None, &xlist, 1, mode, None);
+    }
+
+    // Print a list of expressions. If the list spans multiple
+    // source lines, the original line breaks are respected between
+    // expressions.
+    fn expr_list(
+        &mut self,
+        prev0: Option<Pos>,
+        list: &[Expr],
+        depth: usize,
+        mode: u8,
+        next0: Option<Pos>,
+    ) {
+        if list.is_empty() {
+            return;
+        }
+
+        let o = self.objs;
+        let prev = self.opt_pos_for(prev0);
+        let next = self.opt_pos_for(next0);
+        let mut line = self.line_for(list[0].pos(o));
+        let end_line = self.line_for(list[list.len() - 1].end(o));
+
+        if prev.is_valid() && prev.line == line && line == end_line {
+            // all list entries on a single line
+            for (i, x) in list.iter().enumerate() {
+                if i > 0 {
+                    // use position of expression following the comma as
+                    // comma position for correct comment placement
+                    self.set_pos(x.pos(o));
+                    self.token(Token::COMMA);
+                    self.ws(WhiteSpace::Blank);
+                }
+                self.expr0(x, depth);
+            }
+            return;
+        }
+
+        // list entries span multiple lines;
+        // use source code positions to guide line breaks
+
+        // Don't add extra indentation if NO_INDENT is set;
+        // i.e., pretend that the first line is already indented.
+        let mut ws = if mode & NO_INDENT == 0 {
+            WhiteSpace::Indent
+        } else {
+            WhiteSpace::Ignore
+        };
+
+        // The first linebreak is always a formfeed since this section must not
+        // depend on any previous formatting.
+        let mut prev_break: isize = -1; // index of last expression that was followed by a linebreak
+        if prev.is_valid() && prev.line < line && self.linebreak(line, 0, ws, true) > 0 {
+            ws = WhiteSpace::Ignore;
+            prev_break = 0;
+        }
+
+        // initialize expression/key size: a zero value indicates expr/key doesn't fit on a single line
+        let mut size = 0;
+
+        // We use the ratio between the geometric mean of the previous key sizes and
+        // the current size to determine if there should be a break in the alignment.
+        // To compute the geometric mean we accumulate the ln(size) values (lnsum)
+        // and the number of sizes included (count).
+        let mut lnsum = 0.0;
+        let mut count = 0;
+
+        // print all list elements
+        let mut prev_line = prev.line;
+        for (i, x) in list.iter().enumerate() {
+            line = self.line_for(x.pos(o));
+
+            // Determine if the next linebreak, if any, needs to use formfeed:
+            // in general, use the entire node size to make the decision; for
+            // key:value expressions, use the key size.
+            let mut use_ff = true;
+
+            // Determine element size: All bets are off if we don't have
+            // position information for the previous and next token (likely
+            // generated code - simply ignore the size in this case by setting
+            // it to 0).
+            let prev_size = size;
+            const INFINITY: usize = 1000000; // larger than any source line
+            size = self.node_size(SizeNode::Expr(x), INFINITY);
+            let pair = match x {
+                Expr::KeyValue(kv) => Some(kv),
+                _ => None,
+            };
+            if size <= INFINITY && prev.is_valid() && next.is_valid() {
+                // x fits on a single line
+                if let Some(kv) = pair {
+                    size = self.node_size(SizeNode::Expr(&kv.key), INFINITY); // size <= INFINITY
+                }
+            } else {
+                // size too large or we don't have good layout information
+                size = 0;
+            }
+
+            // If the previous line and the current line had single-
+            // line-expressions and the key sizes are small or the
+            // ratio between the current key and the geometric mean
+            // if the previous key sizes does not exceed a threshold,
+            // align columns and do not use formfeed.
+            if prev_size > 0 && size > 0 {
+                const SMALL_SIZE: usize = 40;
+                if count == 0 || prev_size <= SMALL_SIZE && size <= SMALL_SIZE {
+                    use_ff = false;
+                } else {
+                    const R: f64 = 2.5; // threshold
+                    let geomean = (lnsum / count as f64).exp(); // count > 0
+                    let ratio = size as f64 / geomean;
+                    use_ff = R * ratio <= 1.0 || R <= ratio;
+                }
+            }
+
+            let needs_linebreak = 0 < prev_line && prev_line < line;
+            if i > 0 {
+                // Use position of expression following the comma as
+                // comma position for correct comment placement, but
+                // only if the expression is on the same line.
+                if !needs_linebreak {
+                    self.set_pos(x.pos(o));
+                }
+                self.token(Token::COMMA);
+                let mut needs_blank = true;
+                if needs_linebreak {
+                    // Lines are broken using newlines so comments remain aligned
+                    // unless use_ff is set or there are multiple expressions on
+                    // the same line in which case formfeed is used.
+                    let nbreaks =
+                        self.linebreak(line, 0, ws, use_ff || prev_break + 1 < i as isize);
+                    if nbreaks > 0 {
+                        ws = WhiteSpace::Ignore;
+                        prev_break = i as isize;
+                        needs_blank = false; // we got a line break instead
+                    }
+                    // If there was a new section or more than one new line
+                    // (which means that the tabwriter will implicitly break
+                    // the section), reset the geomean variables since we are
+                    // starting a new group of elements with the next element.
+                    if nbreaks > 1 {
+                        lnsum = 0.0;
+                        count = 0;
+                    }
+                }
+                if needs_blank {
+                    self.ws(WhiteSpace::Blank);
+                }
+            }
+
+            match pair {
+                Some(kv) if list.len() > 1 && size > 0 && needs_linebreak => {
+                    // We have a key:value expression that fits onto one line
+                    // and it's not on the same line as the prior expression:
+                    // Use a column for the key such that consecutive entries
+                    // can align if possible.
+                    // (needs_linebreak is set if we started a new line before)
+                    self.expr(&kv.key);
+                    self.set_pos(kv.colon);
+                    self.token(Token::COLON);
+                    self.ws(WhiteSpace::Vtab);
+                    self.expr(&kv.val);
+                }
+                _ => self.expr0(x, depth),
+            }
+
+            if size > 0 {
+                lnsum += (size as f64).ln();
+                count += 1;
+            }
+
+            prev_line = self.line_for(x.end(o));
+        }
+
+        if mode & COMMA_TERM != 0 && next.is_valid() && self.pos.line < next.line {
+            // Print a terminating comma if the next token is on a new line.
+            self.token(Token::COMMA);
+            if ws == WhiteSpace::Ignore && mode & NO_INDENT == 0 {
+                // unindent if we indented
+                self.ws(WhiteSpace::Unindent);
+            }
+            self.ws(WhiteSpace::Formfeed); // terminating comma needs a line break to look good
+            return;
+        }
+
+        if ws == WhiteSpace::Ignore && mode & NO_INDENT == 0 {
+            // unindent if we indented
+            self.ws(WhiteSpace::Unindent);
+        }
+    }
+
+    fn parameters(&mut self, fields: &FieldList, mode: ParamMode) {
+        let o = self.objs;
+        let (open_tok, close_tok) = match mode {
+            ParamMode::Func => (Token::LPAREN, Token::RPAREN),
+            _ => (Token::LBRACK, Token::RBRACK),
+        };
+        if let Some(p) = fields.openning {
+            self.set_pos(p);
+        }
+        self.token(open_tok);
+        if !fields.list.is_empty() {
+            let mut prev_line = self.opt_pos_for(fields.openning).line;
+            let mut ws = WhiteSpace::Indent;
+            for (i, key) in fields.list.iter().enumerate() {
+                let par = &o.fields[*key];
+                // determine par begin and end line (may be different
+                // if there are multiple parameter names for this par
+                // or the type is on a separate line)
+                let par_line_beg = self.line_for(key.pos(o));
+                let par_line_end = self.line_for(par.typ.pos(o));
+                // separating "," if needed
+                let needs_linebreak = 0 < prev_line && prev_line < par_line_beg;
+                if i > 0 {
+                    // use position of parameter following the comma as
+                    // comma position for correct comma placement, but
+                    // only if the next parameter is on the same line
+                    if !needs_linebreak {
+                        self.set_pos(key.pos(o));
+                    }
+                    self.token(Token::COMMA);
+                }
+                // separator if needed (linebreak or blank)
+                if needs_linebreak && self.linebreak(par_line_beg, 0, ws, true) > 0 {
+                    // break line if the opening "(" or previous parameter ended on a different line
+                    ws = WhiteSpace::Ignore;
+                } else if i > 0 {
+                    self.ws(WhiteSpace::Blank);
+                }
+                // parameter names
+                if !par.names.is_empty() {
+                    // Very subtle: If we indented above (ws == Ignore), ident_list
+                    // won't indent again. If we didn't (ws == Indent), ident_list will
+                    // indent if the ident_list spans multiple lines, and it will outdent
+                    // again at the end (and still ws == Indent). Thus, a subsequent indent
+                    // by a linebreak call after a type, or in the next multi-line ident_list
+                    // will do the right thing.
+                    self.ident_list(&par.names, ws == WhiteSpace::Indent);
+                    self.ws(WhiteSpace::Blank);
+                }
+                // parameter type
+                self.expr(strip_parens_always(&par.typ));
+                prev_line = par_line_end;
+            }
+
+            // if the closing ")" is on a separate line from the last parameter,
+            // print an additional "," and line break
+            let closing = self.opt_pos_for(fields.closing).line;
+            if 0 < prev_line && prev_line < closing {
+                self.token(Token::COMMA);
+                self.linebreak(closing, 0, WhiteSpace::Ignore, true);
+            } else if mode == ParamMode::TypeType
+                && num_fields(fields, o) == 1
+                && combines_with_name(&o.fields[fields.list[0]].typ)
+            {
+                // A type parameter list [P T] where the name P and the type expression T syntactically
+                // combine to another valid (value) expression requires a trailing comma, as in [P *T,]
+                // (or an enclosing interface as in [P interface(*T)]), so that the type parameter list
+                // is not gotten confused with an array length expression [P*T].
+                self.token(Token::COMMA);
+            }
+
+            // unindent if we indented
+            if ws == WhiteSpace::Ignore {
+                self.ws(WhiteSpace::Unindent);
+            }
+        }
+
+        if let Some(p) = fields.closing {
+            self.set_pos(p);
+        }
+        self.token(close_tok);
+    }
+
+    fn signature(&mut self, sig: &FuncType) {
+        let o = self.objs;
+        if let Some(tparams) = &sig.type_params {
+            self.parameters(tparams, ParamMode::FuncType);
+        }
+        self.parameters(&sig.params, ParamMode::Func);
+        if let Some(res) = &sig.results {
+            let n = num_fields(res, o);
+            if n > 0 {
+                self.ws(WhiteSpace::Blank);
+                let first = &o.fields[res.list[0]];
+                if n == 1 && first.names.is_empty() {
+                    // single anonymous res; no ()'s
+                    self.expr(strip_parens_always(&first.typ));
+                    return;
+                }
+                self.parameters(res, ParamMode::Func);
+            }
+        }
+    }
+
+    fn is_one_line_field_list(&mut self, list: &[FieldKey]) -> bool {
+        if list.len() != 1 {
+            return false; // allow only one field
+        }
+        let f = &self.objs.fields[list[0]];
+        if f.tag.is_some() || f.comment.is_some() {
+            return false; // don't allow tags or comments
+        }
+        // only name(s) and type
+        const MAX_SIZE: usize = 30; // adjust as appropriate, this is an approximate value
+        let mut names_size = ident_list_size(&f.names, self.objs, MAX_SIZE);
+        if names_size > 0 {
+            names_size = 1; // blank between names and types
+        }
+        let type_size = self.node_size(SizeNode::Expr(&f.typ), MAX_SIZE);
+        names_size + type_size <= MAX_SIZE
+    }
+
+    fn field_list(&mut self, fields: &FieldList, is_struct: bool) {
+        let o = self.objs;
+        let lbrace = fields.openning.unwrap();
+        let list = &fields.list;
+        let rbrace = fields.closing.unwrap();
+        let has_comments = self.comment_before(self.pos_for(rbrace));
+        let src_is_one_line = self.line_for(lbrace) == self.line_for(rbrace);
+
+        if !has_comments && src_is_one_line {
+            // possibly a one-line struct/interface
+            if list.is_empty() {
+                // no blank between keyword and {} in this case
+                self.set_pos(lbrace);
+                self.token(Token::LBRACE);
+                self.set_pos(rbrace);
+                self.token(Token::RBRACE);
+                return;
+            } else if self.is_one_line_field_list(list) {
+                // small enough - print on one line
+                // (don't use ident_list and ignore source line breaks)
+                self.set_pos(lbrace);
+                self.token(Token::LBRACE);
+                self.ws(WhiteSpace::Blank);
+                let f = &o.fields[list[0]];
+                if is_struct {
+                    for (i, x) in f.names.iter().enumerate() {
+                        if i > 0 {
+                            // no comments so no need for comma position
+                            self.token(Token::COMMA);
+                            self.ws(WhiteSpace::Blank);
+                        }
+                        self.expr(&Expr::Ident(*x));
+                    }
+                    if !f.names.is_empty() {
+                        self.ws(WhiteSpace::Blank);
+                    }
+                    self.expr(&f.typ);
+                } else {
+                    // interface
+                    match (f.names.first(), &f.typ) {
+                        (Some(name), Expr::Func(ft)) => {
+                            // method
+                            self.expr(&Expr::Ident(*name));
+                            self.signature(&o.ftypes[*ft]); // don't print "func"
+                        }
+                        // embedded interface
+                        _ => self.expr(&f.typ),
+                    }
+                }
+                self.ws(WhiteSpace::Blank);
+                self.set_pos(rbrace);
+                self.token(Token::RBRACE);
+                return;
+            }
+        }
+        // has_comments || !src_is_one_line
+
+        self.ws(WhiteSpace::Blank);
+        self.set_pos(lbrace);
+        self.token(Token::LBRACE);
+        self.ws(WhiteSpace::Indent);
+        if has_comments || !list.is_empty() {
+            self.ws(WhiteSpace::Formfeed);
+        }
+
+        if is_struct {
+            let sep = if list.len() == 1 {
+                WhiteSpace::Blank
+            } else {
+                WhiteSpace::Vtab
+            };
+            let mut line = Rc::new(Cell::new(0));
+            for (i, key) in list.iter().enumerate() {
+                let f = &o.fields[*key];
+                if i > 0 {
+                    let new_section = self.lines_from(line.get()) > 0;
+                    self.linebreak(
+                        self.line_for(key.pos(o)),
+                        1,
+                        WhiteSpace::Ignore,
+                        new_section,
+                    );
+                }
+                let mut extra_tabs;
+                line = self.record_line();
+                if !f.names.is_empty() {
+                    // named fields
+                    self.ident_list(&f.names, false);
+                    self.ws(sep);
+                    self.expr(&f.typ);
+                    extra_tabs = 1;
+                } else {
+                    // anonymous field
+                    self.expr(&f.typ);
+                    extra_tabs = 2;
+                }
+                if let Some(tag) = &f.tag {
+                    if !f.names.is_empty() && sep == WhiteSpace::Vtab {
+                        self.ws(sep);
+                    }
+                    self.ws(sep);
+                    self.expr(tag);
+                    extra_tabs = 0;
+                }
+                if f.comment.is_some() {
+                    while extra_tabs > 0 {
+                        self.ws(sep);
+                        extra_tabs -= 1;
+                    }
+                }
+            }
+        } else {
+            // interface
+            let mut line = Rc::new(Cell::new(0));
+            for (i, key) in list.iter().enumerate() {
+                let f = &o.fields[*key];
+                if i > 0 {
+                    let new_section = self.lines_from(line.get()) > 0;
+                    self.linebreak(
+                        self.line_for(key.pos(o)),
+                        1,
+                        WhiteSpace::Ignore,
+                        new_section,
+                    );
+                }
+                line = self.record_line();
+                match (f.names.first(), &f.typ) {
+                    (Some(name), Expr::Func(ft)) => {
+                        // method
+                        self.expr(&Expr::Ident(*name));
+                        self.signature(&o.ftypes[*ft]); // don't print "func"
+                    }
+                    // embedded interface
+                    _ => self.expr(&f.typ),
+                }
+            }
+        }
+        self.ws(WhiteSpace::Unindent);
+        self.ws(WhiteSpace::Formfeed);
+        self.set_pos(rbrace);
+        self.token(Token::RBRACE);
+    }
+
+    // ----------------------------------------------------------------------------
+    // Expressions
+
+    fn binary_expr(&mut self, x: &BinaryExpr, prec1: usize, cutoff: usize, depth: usize) {
+        let o = self.objs;
+        let prec = x.op.precedence();
+        if prec < prec1 {
+            // parenthesis needed
+            // Note: The parser inserts a ParenExpr node; thus this case
+            //       can only occur if the AST is created in a different way.
+            self.token(Token::LPAREN);
+            self.binary_expr(x, LOWEST_PREC, cutoff, reduce_depth(depth)); // parentheses undo one level of depth
+            self.token(Token::RPAREN);
+            return;
+        }
+
+        let mut print_blank = prec < cutoff;
+
+        let mut ws = WhiteSpace::Indent;
+        self.expr1(&x.expr_a, prec, depth + diff_prec(&x.expr_a, prec));
+        if print_blank {
+            self.ws(WhiteSpace::Blank);
+        }
+        let xline = self.pos.line; // before the operator (it may be on the next line!)
+        let yline = self.line_for(x.expr_b.pos(o));
+        self.set_pos(x.op_pos);
+        self.token(x.op.clone());
+        if xline != yline && xline > 0 && yline > 0 {
+            // at least one line break, but respect an extra empty line
+            // in the source
+            if self.linebreak(yline, 1, ws, true) > 0 {
+                ws = WhiteSpace::Ignore;
+                print_blank = false; // no blank after line break
+            }
+        }
+        if print_blank {
+            self.ws(WhiteSpace::Blank);
+        }
+        self.expr1(&x.expr_b, prec + 1, depth + 1);
+        if ws == WhiteSpace::Ignore {
+            self.ws(WhiteSpace::Unindent);
+        }
+    }
+
+    fn expr1(&mut self, expr: &Expr, prec1: usize, depth: usize) {
+        let o = self.objs;
+        self.set_pos(expr.pos(o));
+
+        match expr {
+            Expr::Bad(_) => self.string("BadExpr"),
+            Expr::Ident(i) => self.ident(*i),
+            Expr::Binary(x) => {
+                let depth = depth.max(1);
+                self.binary_expr(x, prec1, cutoff(x, depth), depth);
+            }
+            Expr::KeyValue(x) => {
+                self.expr(&x.key);
+                self.set_pos(x.colon);
+                self.token(Token::COLON);
+                self.ws(WhiteSpace::Blank);
+                self.expr(&x.val);
+            }
+            Expr::Star(x) => {
+                if UNARY_PREC < prec1 {
+                    // parenthesis needed
+                    self.token(Token::LPAREN);
+                    self.token(Token::MUL);
+                    self.expr(&x.expr);
+                    self.token(Token::RPAREN);
+                } else {
+                    // no parenthesis needed
+                    self.token(Token::MUL);
+                    self.expr(&x.expr);
+                }
+            }
+            Expr::Unary(x) => {
+                if UNARY_PREC < prec1 {
+                    // parenthesis needed
+                    self.token(Token::LPAREN);
+                    self.expr(expr);
+                    self.token(Token::RPAREN);
+                } else {
+                    // no parenthesis needed
+                    self.token(x.op.clone());
+                    if x.op == Token::RANGE {
+                        self.ws(WhiteSpace::Blank);
+                    }
+                    self.expr1(&x.expr, UNARY_PREC, depth);
+                }
+            }
+            Expr::BasicLit(x) => self.lit(x),
+            Expr::FuncLit(x) => {
+                let pos = x.typ.pos(o);
+                self.set_pos(pos);
+                self.token(Token::FUNC);
+                // See the comment in func_decl about how the header size is computed.
+                let start_col = self.out.column - "func".len();
+                self.signature(&o.ftypes[x.typ]);
+                let size = self.distance_from(pos, start_col);
+                self.func_body(size, WhiteSpace::Blank, Some(&x.body));
+            }
+            Expr::Paren(x) => {
+                if let Expr::Paren(_) = &x.expr {
+                    // don't print parentheses around an already parenthesized expression
+                    self.expr0(&x.expr, depth);
+                } else {
+                    self.token(Token::LPAREN);
+                    self.expr0(&x.expr, reduce_depth(depth)); // parentheses undo one level of depth
+                    self.set_pos(x.r_paren);
+                    self.token(Token::RPAREN);
+                }
+            }
+            Expr::Selector(x) => {
+                self.selector_expr(x, depth, false);
+            }
+            Expr::TypeAssert(x) => {
+                self.expr1(&x.expr, HIGHEST_PREC, depth);
+                self.token(Token::PERIOD);
+                self.set_pos(x.l_paren);
+                self.token(Token::LPAREN);
+                match &x.typ {
+                    Some(t) => self.expr(t),
+                    None => self.token(Token::TYPE),
+                }
+                self.set_pos(x.r_paren);
+                self.token(Token::RPAREN);
+            }
+            Expr::Index(x) => {
+                self.expr1(&x.expr, HIGHEST_PREC, 1);
+                self.set_pos(x.l_brack);
+                self.token(Token::LBRACK);
+                self.expr0(&x.index, depth + 1);
+                self.set_pos(x.r_brack);
+                self.token(Token::RBRACK);
+            }
+            Expr::IndexList(x) => {
+                self.expr1(&x.expr, HIGHEST_PREC, 1);
+                self.set_pos(x.l_brack);
+                self.token(Token::LBRACK);
+                self.expr_list(
+                    Some(x.l_brack),
+                    &x.indices,
+                    depth + 1,
+                    COMMA_TERM,
+                    Some(x.r_brack),
+                );
+                self.set_pos(x.r_brack);
+                self.token(Token::RBRACK);
+            }
+            Expr::Slice(x) => {
+                self.expr1(&x.expr, HIGHEST_PREC, 1);
+                self.set_pos(x.l_brack);
+                self.token(Token::LBRACK);
+                let mut indices = vec![x.low.as_ref(), x.high.as_ref()];
+                if x.max.is_some() {
+                    indices.push(x.max.as_ref());
+                }
+                // determine if we need extra blanks around ':'
+                let mut needs_blanks = false;
+                if depth <= 1 {
+                    let index_count = indices.iter().filter(|x| x.is_some()).count();
+                    let has_binaries = indices.iter().any(|x| match x {
+                        Some(Expr::Binary(_)) => true,
+                        _ => false,
+                    });
+                    if index_count > 1 && has_binaries {
+                        needs_blanks = true;
+                    }
+                }
+                for (i, x) in indices.iter().enumerate() {
+                    if i > 0 {
+                        if indices[i - 1].is_some() && needs_blanks {
+                            self.ws(WhiteSpace::Blank);
+                        }
+                        self.token(Token::COLON);
+                        if x.is_some() && needs_blanks {
+                            self.ws(WhiteSpace::Blank);
+                        }
+                    }
+                    if let Some(x) = x {
+                        self.expr0(x, depth + 1);
+                    }
+                }
+                self.set_pos(x.r_brack);
+                self.token(Token::RBRACK);
+            }
+            Expr::Call(x) => {
+                let depth = if x.args.len() > 1 { depth + 1 } else { depth };
+
+                // Conversions to literal function types or <-chan
+                // types require parentheses around the type.
+                let paren = match &x.func {
+                    Expr::Func(_) => true,
+                    Expr::Chan(t) => t.dir == ChanDir::Recv,
+                    _ => false,
+                };
+                if paren {
+                    self.token(Token::LPAREN);
+                }
+                let was_indented = match &x.func {
+                    Expr::Selector(s) => self.selector_expr(s, depth, true),
+                    f => {
+                        self.expr1(f, HIGHEST_PREC, depth);
+                        false
+                    }
+                };
+                if paren {
+                    self.token(Token::RPAREN);
+                }
+
+                self.set_pos(x.l_paren);
+                self.token(Token::LPAREN);
+                match x.ellipsis {
+                    Some(ellipsis) => {
+                        self.expr_list(Some(x.l_paren), &x.args, depth, 0, Some(ellipsis));
+                        self.set_pos(ellipsis);
+                        self.token(Token::ELLIPSIS);
+                        if self.line_for(ellipsis) < self.line_for(x.r_paren) {
+                            self.token(Token::COMMA);
+                            self.ws(WhiteSpace::Formfeed);
+                        }
+                    }
+                    None => {
+                        self.expr_list(Some(x.l_paren), &x.args, depth, COMMA_TERM, Some(x.r_paren))
+                    }
+                }
+                self.set_pos(x.r_paren);
+                self.token(Token::RPAREN);
+                if was_indented {
+                    self.ws(WhiteSpace::Unindent);
+                }
+            }
+            Expr::CompositeLit(x) => {
+                // composite literal elements that are composite literals themselves may have the type omitted
+                if let Some(t) = &x.typ {
+                    self.expr1(t, HIGHEST_PREC, depth);
+                }
+                self.level += 1;
+                self.set_pos(x.l_brace);
+                self.token(Token::LBRACE);
+                self.expr_list(Some(x.l_brace), &x.elts, 1, COMMA_TERM, Some(x.r_brace));
+                // do not insert extra line break following a /*-style comment
+                // before the closing '}' as it might break the code if there
+                // is no trailing ','
+                let mut mode = NO_EXTRA_LINEBREAK;
+                // do not insert extra blank following a /*-style comment
+                // before the closing '}' unless the literal is empty
+                if !x.elts.is_empty() {
+                    mode |= NO_EXTRA_BLANK;
+                }
+                // need the initial indent to print lone comments with
+                // the proper level of indentation
+                self.ws(WhiteSpace::Indent);
+                self.ws(WhiteSpace::Unindent);
+                self.toggle(mode);
+                self.set_pos(x.r_brace);
+                self.token(Token::RBRACE);
+                self.toggle(mode);
+                self.level -= 1;
+            }
+            Expr::Ellipsis(x) => {
+                self.token(Token::ELLIPSIS);
+                if let Some(elt) = &x.elt {
+                    self.expr(elt);
+                }
+            }
+            Expr::Array(x) => {
+                self.token(Token::LBRACK);
+                if let Some(len) = &x.len {
+                    self.expr(len);
+                }
+                self.token(Token::RBRACK);
+                self.expr(&x.elt);
+            }
+            Expr::Struct(x) => {
+                self.token(Token::STRUCT);
+                self.field_list(&x.fields, true);
+            }
+            Expr::Func(x) => {
+                self.token(Token::FUNC);
+                self.signature(&o.ftypes[*x]);
+            }
+            Expr::Interface(x) => {
+                self.token(Token::INTERFACE);
+                self.field_list(&x.methods, false);
+            }
+            Expr::Map(x) => {
+                self.token(Token::MAP);
+                self.token(Token::LBRACK);
+                self.expr(&x.key);
+                self.token(Token::RBRACK);
+                self.expr(&x.val);
+            }
+            Expr::Chan(x) => {
+                match x.dir {
+                    ChanDir::SendRecv => self.token(Token::CHAN),
+                    ChanDir::Recv => {
+                        self.token(Token::ARROW);
+                        self.token(Token::CHAN);
+                    }
+                    ChanDir::Send => {
+                        self.token(Token::CHAN);
+                        self.set_pos(x.arrow);
+                        self.token(Token::ARROW);
+                    }
+                }
+                self.ws(WhiteSpace::Blank);
+                self.expr(&x.val);
+            }
+        }
+    }
+
+    // selector_expr handles a SelectorExpr node and reports whether x spans
+    // multiple lines.
+    fn selector_expr(&mut self, x: &SelectorExpr, depth: usize, is_method: bool) -> bool {
+        self.expr1(&x.expr, HIGHEST_PREC, depth);
+        self.token(Token::PERIOD);
+        let sel_pos = self.objs.idents[x.sel].pos;
+        let line = self.line_for(sel_pos);
+        if self.pos.is_valid() && self.pos.line < line {
+            self.ws(WhiteSpace::Indent);
+            self.ws(WhiteSpace::Newline);
+            self.set_pos(sel_pos);
+            self.ident(x.sel);
+            if !is_method {
+                self.ws(WhiteSpace::Unindent);
+            }
+            return true;
+        }
+        self.set_pos(sel_pos);
+        self.ident(x.sel);
+        false
+    }
+
+    fn expr0(&mut self, x: &Expr, depth: usize) {
+        self.expr1(x, LOWEST_PREC, depth);
+    }
+
+    fn expr(&mut self, x: &Expr) {
+        self.expr1(x, LOWEST_PREC, 1);
+    }
+
+    // ----------------------------------------------------------------------------
+    // Statements
+
+    // Print the statement list indented, but without a newline after the last statement.
+    // Extra line breaks between statements in the source are respected but at most one
+    // empty line is printed between statements.
+    fn stmt_list(&mut self, list: &[Stmt], nindent: usize, next_is_rbrace: bool) {
+        let o = self.objs;
+        if nindent > 0 {
+            self.ws(WhiteSpace::Indent);
+        }
+        let mut line = Rc::new(Cell::new(0));
+        let mut i = 0;
+        let count = list
+            .iter()
+            .filter(|s| match s {
+                Stmt::Empty(_) => false,
+                _ => true,
+            })
+            .count();
+        for s in list.iter() {
+            // ignore empty statements (was issue 3466)
+            if let Stmt::Empty(_) = s {
+                continue;
+            }
+            // nindent == 0 only for lists of switch/select case clauses;
+            // in those cases each clause is a new section
+            if !self.output.is_empty() {
+                // only print line break if we are not at the beginning of the output
+                // (i.e., we are not printing only a partial program)
+                let new_section = i == 0 || nindent == 0 || self.lines_from(line.get()) > 0;
+                self.linebreak(self.line_for(s.pos(o)), 1, WhiteSpace::Ignore, new_section);
+            }
+            line = self.record_line();
+            self.stmt(s, next_is_rbrace && i == count - 1);
+            // labeled statements put labels on a separate line, but here
+            // we only care about the start line of the actual statement
+            // without label - correct line for each label
+            let mut t = s;
+            while let Stmt::Labeled(l) = t {
+                line.set(line.get() + 1);
+                t = &o.l_stmts[*l].stmt;
+            }
+            i += 1;
+        }
+        if nindent > 0 {
+            self.ws(WhiteSpace::Unindent);
+        }
+    }
+
+    // block prints an *ast.BlockStmt; it always spans at least two lines.
+    fn block(&mut self, b: &BlockStmt, nindent: usize) {
+        self.set_pos(b.l_brace);
+        self.token(Token::LBRACE);
+        self.stmt_list(&b.list, nindent, true);
+        self.linebreak(self.line_for(b.r_brace), 1, WhiteSpace::Ignore, true);
+        self.set_pos(b.r_brace);
+        self.token(Token::RBRACE);
+    }
+
+    fn control_clause(
+        &mut self,
+        is_for_stmt: bool,
+        init: Option<&Stmt>,
+        expr: Option<&Expr>,
+        post: Option<&Stmt>,
+    ) {
+        self.ws(WhiteSpace::Blank);
+        let mut needs_blank = false;
+        if init.is_none() && post.is_none() {
+            // no semicolons required
+            if let Some(expr) = expr {
+                self.expr(self.strip_parens(expr));
+                needs_blank = true;
+            }
+        } else {
+            // all semicolons required
+            // (they are not separators, print them explicitly)
+            if let Some(init) = init {
+                self.stmt(init, false);
+            }
+            self.token(Token::SEMICOLON(true.into()));
+            self.ws(WhiteSpace::Blank);
+            if let Some(expr) = expr {
+                self.expr(self.strip_parens(expr));
+                needs_blank = true;
+            }
+            if is_for_stmt {
+                self.token(Token::SEMICOLON(true.into()));
+                self.ws(WhiteSpace::Blank);
+                needs_blank = false;
+                if let Some(post) = post {
+                    self.stmt(post, false);
+                    needs_blank = true;
+                }
+            }
+        }
+        if needs_blank {
+            self.ws(WhiteSpace::Blank);
+        }
+    }
+
+    // indent_list reports whether an expression list would look better if it
+    // were broken up into multiple lines.
+    fn indent_list(&self, list: &[Expr]) -> bool {
+        // Heuristic: indent_list reports whether there are more than one multi-
+        // line element in the list, or if there is any element that is not
+        // starting on the same line as the previous one ends.
+        let o = self.objs;
+        if list.len() >= 2 {
+            let b = self.line_for(list[0].pos(o));
+            let e = self.line_for(list[list.len() - 1].end(o));
+            if 0 < b && b < e {
+                // list spans multiple lines
+                let mut n = 0; // multi-line element count
+                let mut line = b;
+                for x in list.iter() {
+                    let xb = self.line_for(x.pos(o));
+                    let xe = self.line_for(x.end(o));
+                    if line < xb {
+                        // x is not starting on the same
+                        // line as the previous one ended
+                        return true;
+                    }
+                    if xb < xe {
+                        // x is a multi-line element
+                        n += 1;
+                    }
+                    line = xe;
+                }
+                return n > 1;
+            }
+        }
+        false
+    }
+
+    fn stmt(&mut self, stmt: &Stmt, next_is_rbrace: bool) {
+        let o = self.objs;
+        self.set_pos(stmt.pos(o));
+
+        match stmt {
+            Stmt::Bad(_) => self.string("BadStmt"),
+            Stmt::Decl(d) => self.decl(d),
+            Stmt::Empty(_) => {} // nothing to do
+            Stmt::Labeled(key) => {
+                let s = &o.l_stmts[*key];
+                // a "correcting" unindent immediately following a line break
+                // is applied before the line break if there is no comment
+                // between (see write_whitespace)
+                self.ws(WhiteSpace::Unindent);
+                self.ident(s.label);
+                self.set_pos(s.colon);
+                self.token(Token::COLON);
+                self.ws(WhiteSpace::Indent);
+                match &s.stmt {
+                    Stmt::Empty(e) => {
+                        if !next_is_rbrace {
+                            self.ws(WhiteSpace::Newline);
+                            self.set_pos(e.semi);
+                            self.token(Token::SEMICOLON(true.into()));
+                            return;
+                        }
+                    }
+                    st => {
+                        self.linebreak(self.line_for(st.pos(o)), 1, WhiteSpace::Ignore, true);
+                    }
+                }
+                self.stmt(&s.stmt, next_is_rbrace);
+            }
+            Stmt::Expr(x) => self.expr0(x, 1),
+            Stmt::Send(s) => {
+                self.expr0(&s.chan, 1);
+                self.ws(WhiteSpace::Blank);
+                self.set_pos(s.arrow);
+                self.token(Token::ARROW);
+                self.ws(WhiteSpace::Blank);
+                self.expr0(&s.val, 1);
+            }
+            Stmt::IncDec(s) => {
+                self.expr0(&s.expr, 2);
+                self.set_pos(s.token_pos);
+                self.token(s.token.clone());
+            }
+            Stmt::Assign(key) => {
+                let s = &o.a_stmts[*key];
+                let depth = if s.lhs.len() > 1 && s.rhs.len() > 1 {
+                    2
+                } else {
+                    1
+                };
+                self.expr_list(Some(s.pos(o)), &s.lhs, depth, 0, Some(s.token_pos));
+                self.ws(WhiteSpace::Blank);
+                self.set_pos(s.token_pos);
+                self.token(s.token.clone());
+                self.ws(WhiteSpace::Blank);
+                self.expr_list(Some(s.token_pos), &s.rhs, depth, 0, None);
+            }
+            Stmt::Go(s) => {
+                self.token(Token::GO);
+                self.ws(WhiteSpace::Blank);
+                self.expr(&s.call);
+            }
+            Stmt::Defer(s) => {
+                self.token(Token::DEFER);
+                self.ws(WhiteSpace::Blank);
+                self.expr(&s.call);
+            }
+            Stmt::Return(s) => {
+                self.token(Token::RETURN);
+                if !s.results.is_empty() {
+                    self.ws(WhiteSpace::Blank);
+                    // Use indent_list heuristic to make corner cases look
+                    // better (issue 1207). A more systematic approach would
+                    // always indent, but this would cause significant
+                    // reformatting of the code base and not necessarily
+                    // lead to more nicely formatted code in general.
+                    if self.indent_list(&s.results) {
+                        self.ws(WhiteSpace::Indent);
+                        // Use None so that a newline never goes before
+                        // the results (see issue #32854).
+                        self.expr_list(None, &s.results, 1, NO_INDENT, None);
+                        self.ws(WhiteSpace::Unindent);
+                    } else {
+                        self.expr_list(None, &s.results, 1, 0, None);
+                    }
+                }
+            }
+            Stmt::Branch(s) => {
+                self.token(s.token.clone());
+                if let Some(label) = s.label {
+                    self.ws(WhiteSpace::Blank);
+                    self.ident(label);
+                }
+            }
+            Stmt::Block(s) => self.block(s, 1),
+            Stmt::If(s) => {
+                self.token(Token::IF);
+                self.control_clause(false, s.init.as_ref(), Some(&s.cond), None);
+                self.block(&s.body, 1);
+                if let Some(els) = &s.els {
+                    self.ws(WhiteSpace::Blank);
+                    self.token(Token::ELSE);
+                    self.ws(WhiteSpace::Blank);
+                    match els {
+                        Stmt::Block(_) | Stmt::If(_) => self.stmt(els, next_is_rbrace),
+                        _ => {
+                            // This can only happen with an incorrectly
+                            // constructed AST. Permit it but print so
+                            // that it can be parsed without errors.
+                            self.token(Token::LBRACE);
+                            self.ws(WhiteSpace::Indent);
+                            self.ws(WhiteSpace::Formfeed);
+                            self.stmt(els, true);
+                            self.ws(WhiteSpace::Unindent);
+                            self.ws(WhiteSpace::Formfeed);
+                            self.token(Token::RBRACE);
+                        }
+                    }
+                }
+            }
+            Stmt::Case(s) => {
+                match &s.list {
+                    Some(list) => {
+                        self.token(Token::CASE);
+                        self.ws(WhiteSpace::Blank);
+                        self.expr_list(Some(s.case), list, 1, 0, Some(s.colon));
+                    }
+                    None => self.token(Token::DEFAULT),
+                }
+                self.set_pos(s.colon);
+                self.token(Token::COLON);
+                self.stmt_list(&s.body, 1, next_is_rbrace);
+            }
+            Stmt::Switch(s) => {
+                self.token(Token::SWITCH);
+                self.control_clause(false, s.init.as_ref(), s.tag.as_ref(), None);
+                self.block(&s.body, 0);
+            }
+            Stmt::TypeSwitch(s) => {
+                self.token(Token::SWITCH);
+                if let Some(init) = &s.init {
+                    self.ws(WhiteSpace::Blank);
+                    self.stmt(init, false);
+                    self.token(Token::SEMICOLON(true.into()));
+                }
+                self.ws(WhiteSpace::Blank);
+                self.stmt(&s.assign, false);
+                self.ws(WhiteSpace::Blank);
+                self.block(&s.body, 0);
+            }
+            Stmt::Comm(s) => {
+                match &s.comm {
+                    Some(comm) => {
+                        self.token(Token::CASE);
+                        self.ws(WhiteSpace::Blank);
+                        self.stmt(comm, false);
+                    }
+                    None => self.token(Token::DEFAULT),
+                }
+                self.set_pos(s.colon);
+                self.token(Token::COLON);
+                self.stmt_list(&s.body, 1, next_is_rbrace);
+            }
+            Stmt::Select(s) => {
+                self.token(Token::SELECT);
+                self.ws(WhiteSpace::Blank);
+                let body = &s.body;
+                if body.list.is_empty() && !self.comment_before(self.pos_for(body.r_brace)) {
+                    // print empty select statement w/o comments on one line
+                    self.set_pos(body.l_brace);
+                    self.token(Token::LBRACE);
+                    self.set_pos(body.r_brace);
+                    self.token(Token::RBRACE);
+                } else {
+                    self.block(body, 0);
+                }
+            }
+            Stmt::For(s) => {
+                self.token(Token::FOR);
+                self.control_clause(true, s.init.as_ref(), s.cond.as_ref(), s.post.as_ref());
+                self.block(&s.body, 1);
+            }
+            Stmt::Range(s) => {
+                self.token(Token::FOR);
+                self.ws(WhiteSpace::Blank);
+                if let Some(key) = &s.key {
+                    self.expr(key);
+                    if let Some(val) = &s.val {
+                        // use position of value following the comma as
+                        // comma position for correct comment placement
+                        self.set_pos(val.pos(o));
+                        self.token(Token::COMMA);
+                        self.ws(WhiteSpace::Blank);
+                        self.expr(val);
+                    }
+                    self.ws(WhiteSpace::Blank);
+                    self.set_pos(s.token_pos);
+                    self.token(s.token.clone());
+                    self.ws(WhiteSpace::Blank);
+                }
+                self.token(Token::RANGE);
+                self.ws(WhiteSpace::Blank);
+                self.expr(self.strip_parens(&s.expr));
+                self.ws(WhiteSpace::Blank);
+                self.block(&s.body, 1);
+            }
+        }
+    }
+
+    // strip_parens removes the parentheses around x, unless they are needed
+    // for composite literals starting with a type name
+    fn strip_parens<'e>(&self, x: &'e Expr) -> &'e Expr {
+        if let Expr::Paren(px) = x {
+            // parentheses must not be stripped if there are any
+            // unparenthesized composite literals starting with
+            // a type name
+            if !has_type_name_lit(&px.expr, self.objs) {
+                return self.strip_parens(&px.expr);
+            }
+        }
+        x
+    }
+
+    // ----------------------------------------------------------------------------
+    // Declarations
+
+    fn value_spec(&mut self, s: &ValueSpec, keep_type: bool) {
+        self.ident_list(&s.names, false); // always present
+        let mut extra_tabs = 3;
+        if s.typ.is_some() || keep_type {
+            self.ws(WhiteSpace::Vtab);
+            extra_tabs -= 1;
+        }
+        if let Some(t) = &s.typ {
+            self.expr(t);
+        }
+        if !s.values.is_empty() {
+            self.ws(WhiteSpace::Vtab);
+            self.token(Token::ASSIGN);
+            self.ws(WhiteSpace::Blank);
+            self.expr_list(None, &s.values, 1, 0, None);
+            extra_tabs -= 1;
+        }
+        if s.comment.is_some() {
+            for _ in 0..extra_tabs {
+                self.ws(WhiteSpace::Vtab);
+            }
+        }
+    }
+
+    // The parameter n is the number of specs in the group. If do_indent is set,
+    // multi-line identifier lists in the spec are indented when the first
+    // linebreak is encountered.
+    fn spec(&mut self, spec: &Spec, n: usize, do_indent: bool) {
+        let o = self.objs;
+        match spec {
+            Spec::Import(s) => {
+                if let Some(name) = s.name {
+                    self.ident(name);
+                    self.ws(WhiteSpace::Blank);
+                }
+                self.set_pos(s.path.pos);
+                self.lit(&s.path);
+                if let Some(end) = s.end_pos {
+                    self.set_pos(end);
+                }
+            }
+            Spec::Value(s) => {
+                self.ident_list(&s.names, do_indent); // always present
+                if let Some(t) = &s.typ {
+                    self.ws(WhiteSpace::Blank);
+                    self.expr(t);
+                }
+                if !s.values.is_empty() {
+                    self.ws(WhiteSpace::Blank);
+                    self.token(Token::ASSIGN);
+                    self.ws(WhiteSpace::Blank);
+                    self.expr_list(None, &s.values, 1, 0, None);
+                }
+            }
+            Spec::Type(s) => {
+                self.set_pos(o.idents[s.name].pos);
+                self.ident(s.name);
+                if let Some(tparams) = &s.type_params {
+                    self.parameters(tparams, ParamMode::TypeType);
+                }
+                if n == 1 {
+                    self.ws(WhiteSpace::Blank);
+                } else {
+                    self.ws(WhiteSpace::Vtab);
+                }
+                if s.assign != 0 {
+                    self.token(Token::ASSIGN);
+                    self.ws(WhiteSpace::Blank);
+                }
+                self.expr(&s.typ);
+            }
+        }
+    }
+
+    fn gen_decl(&mut self, d: &GenDecl) {
+        let o = self.objs;
+        self.set_pos(d.token_pos);
+        self.token(d.token.clone());
+        self.ws(WhiteSpace::Blank);
+
+        if d.l_paran.is_some() || d.specs.len() != 1 {
+            // group of parenthesized declarations
+            if let Some(p) = d.l_paran {
+                self.set_pos(p);
+            }
+            self.token(Token::LPAREN);
+            let n = d.specs.len();
+            if n > 0 {
+                self.ws(WhiteSpace::Indent);
+                self.ws(WhiteSpace::Formfeed);
+                if n > 1 && (d.token == Token::CONST || d.token == Token::VAR) {
+                    // two or more grouped const/var declarations:
+                    // determine if the type column must be kept
+                    let specs: Vec<&ValueSpec> = d
+                        .specs
+                        .iter()
+                        .map(|k| match &o.specs[*k] {
+                            Spec::Value(s) => s.as_ref(),
+                            _ => unreachable!(),
+                        })
+                        .collect();
+                    let keep_type = keep_type_column(&specs);
+                    let mut line = Rc::new(Cell::new(0));
+                    for (i, s) in specs.iter().enumerate() {
+                        if i > 0 {
+                            let new_section = self.lines_from(line.get()) > 0;
+                            let l = self.line_for(o.idents[s.names[0]].pos);
+                            self.linebreak(l, 1, WhiteSpace::Ignore, new_section);
+                        }
+                        line = self.record_line();
+                        self.value_spec(s, keep_type[i]);
+                    }
+                } else {
+                    let mut line = Rc::new(Cell::new(0));
+                    for (i, key) in d.specs.iter().enumerate() {
+                        let s = &o.specs[*key];
+                        if i > 0 {
+                            let new_section = self.lines_from(line.get()) > 0;
+                            self.linebreak(
+                                self.line_for(s.pos(o)),
+                                1,
+                                WhiteSpace::Ignore,
+                                new_section,
+                            );
+                        }
+                        line = self.record_line();
+                        self.spec(s, n, false);
+                    }
+                }
+                self.ws(WhiteSpace::Unindent);
+                self.ws(WhiteSpace::Formfeed);
+            }
+            if let Some(p) = d.r_paren {
+                self.set_pos(p);
+            }
+            self.token(Token::RPAREN);
+        } else if !d.specs.is_empty() {
+            // single declaration
+            self.spec(&o.specs[d.specs[0]], 1, true);
+        }
+    }
+
+    // func_body prints a function body following a function header of given header_size.
+    // If the header's and block's size are "small enough" and the block is "simple enough",
+    // the block is printed on the current line, without line breaks, spaced from the header
+    // by sep. Otherwise the block's opening "{" is printed on the current line, followed by
+    // lines for the block's statements and its closing "}".
+    fn func_body(&mut self, header_size: usize, sep: WhiteSpace, b: Option<&BlockStmt>) {
+        let b = match b {
+            Some(b) => b,
+            None => return,
+        };
+
+        // save/restore composite literal nesting level
+        let level = self.level;
+        self.level = 0;
+
+        const MAX_SIZE: usize = 100;
+        if header_size + self.body_size(b, MAX_SIZE) <= MAX_SIZE {
+            self.ws(sep);
+            self.set_pos(b.l_brace);
+            self.token(Token::LBRACE);
+            if !b.list.is_empty() {
+                self.ws(WhiteSpace::Blank);
+                for (i, s) in b.list.iter().enumerate() {
+                    if i > 0 {
+                        self.token(Token::SEMICOLON(true.into()));
+                        self.ws(WhiteSpace::Blank);
+                    }
+                    self.stmt(s, i == b.list.len() - 1);
+                }
+                self.ws(WhiteSpace::Blank);
+            }
+            self.toggle(NO_EXTRA_LINEBREAK);
+            self.set_pos(b.r_brace);
+            self.token(Token::RBRACE);
+            self.toggle(NO_EXTRA_LINEBREAK);
+        } else {
+            if sep != WhiteSpace::Ignore {
+                self.ws(WhiteSpace::Blank); // always use blank
+            }
+            self.block(b, 1);
+        }
+        self.level = level;
+    }
+
+    fn func_decl(&mut self, d: &FuncDecl) {
+        let o = self.objs;
+        let pos = d.pos(o);
+        self.set_pos(pos);
+        self.token(Token::FUNC);
+        self.ws(WhiteSpace::Blank);
+        // We have to save start_col only after emitting FUNC; otherwise it can be on a
+        // different line (all whitespace preceding the FUNC is emitted only when the
+        // FUNC is emitted).
+        let start_col = self.out.column - "func ".len();
+        if let Some(recv) = &d.recv {
+            self.parameters(recv, ParamMode::Func); // method: print receiver
+            self.ws(WhiteSpace::Blank);
+        }
+        self.ident(d.name);
+        self.signature(&o.ftypes[d.typ]);
+        let size = self.distance_from(pos, start_col);
+        self.func_body(size, WhiteSpace::Vtab, d.body.as_ref().map(|b| b.as_ref()));
+    }
+
+    fn decl(&mut self, decl: &Decl) {
+        match decl {
+            Decl::Bad(d) => {
+                self.set_pos(d.from);
+                self.string("BadDecl");
+            }
+            Decl::Gen(d) => self.gen_decl(d),
+            Decl::Func(d) => self.func_decl(&self.objs.fdecls[*d]),
+        }
+    }
+
+    // ----------------------------------------------------------------------------
+    // Files
+
+    fn decl_list(&mut self, list: &[Decl]) {
+        let o = self.objs;
+        let mut tok = Token::NONE;
+        for d in list.iter() {
+            let prev = tok;
+            let doc = match d {
+                Decl::Bad(_) => {
+                    tok = Token::NONE;
+                    None
+                }
+                Decl::Gen(g) => {
+                    tok = g.token.clone();
+                    g.doc.as_ref()
+                }
+                Decl::Func(f) => {
+                    tok = Token::FUNC;
+                    o.fdecls[*f].doc.as_ref()
+                }
+            };
+            // If the declaration token changed (e.g., from CONST to TYPE)
+            // or the next declaration has documentation associated with it,
+            // print an empty line between top-level declarations.
+            // (because self.linebreak is called with the position of d, which
+            // is past any documentation, the minimum requirement is satisfied
+            // even w/o the extra doc check - leave it in case the
+            // linebreak logic improves - there's already a TODO).
+            if !self.output.is_empty() {
+                // only print line break if we are not at the beginning of the output
+                // (i.e., we are not printing only a partial program)
+                let min = if prev != tok || doc.is_some() { 2 } else { 1 };
+                // start a new section if the next declaration is a function
+                // that spans multiple lines (see also issue #19544)
+                let new_section = tok == Token::FUNC && self.num_lines(d.pos(o), d.end(o)) > 1;
+                self.linebreak(
+                    self.line_for(d.pos(o)),
+                    min,
+                    WhiteSpace::Ignore,
+                    new_section,
+                );
+            }
+            self.decl(d);
+        }
+    }
+
+    fn file(&mut self, src: &File) {
+        self.set_pos(src.package);
+        self.token(Token::PACKAGE);
+        self.ws(WhiteSpace::Blank);
+        self.ident(src.name);
+        self.decl_list(&src.decls);
+        self.ws(WhiteSpace::Newline);
+    }
+}
+
+fn is_line_comment(c: &Comment) -> bool {
+    c.text.as_bytes().get(1) == Some(&b'/')
+}
+
+fn nlimit(n: usize) -> usize {
+    n.min(MAX_NEWLINES)
+}
+
+fn may_combine(prev: &Token, next: u8) -> bool {
+    match prev {
+        Token::INT(_) => next == b'.',              // 1.
+        Token::ADD => next == b'+',                 // ++
+        Token::SUB => next == b'-',                 // --
+        Token::QUO => next == b'*',                 // /*
+        Token::LSS => next == b'-' || next == b'<', // <- or <<
+        Token::AND => next == b'&' || next == b'^', // && or &^
+        _ => false,
+    }
+}
+
+// normalized_number rewrites the prefixes and exponents of the number
+// literals to lower-case, as in 0x1p-2, and leaves the others alone
+fn normalized_number(tok: &Token) -> String {
+    let x = tok.get_literal();
+    match tok {
+        Token::INT(_) | Token::FLOAT(_) | Token::IMAG(_) if x.len() >= 2 => {}
+        Token::IDENT(l) => return l.as_str().to_string(),
+        _ => return x.to_string(),
+    }
+    // We ignore the kind of the token because for an IMAG token the literal
+    // may be an integer or floating-point value, decimal or not. Instead,
+    // just consider the literal pattern.
+    match &x[..2] {
+        "0X" => {
+            // possibly a hexadecimal float
+            format!("0x{}", &x[2..]).replacen('P', "p", 1)
+        }
+        "0x" => x.replacen('P', "p", 1),
+        "0O" => format!("0o{}", &x[2..]),
+        "0B" => format!("0b{}", &x[2..]),
+        "0o" | "0b" => x.to_string(),
+        _ => {
+            // 0-prefix octal, decimal int, or float (possibly with 'i' suffix)
+            if x.contains('E') {
+                return x.replacen('E', "e", 1);
+            }
+            // remove leading 0's from integer (but not floating-point) imaginary literals
+            if x.ends_with('i') && !x.contains('.') && !x.contains('e') {
+                let y = x.trim_start_matches(|c| c == '0' || c == '_');
+                return if y == "i" {
+                    "0i".to_string()
+                } else {
+                    y.to_string()
+                };
+            }
+            x.to_string()
+        }
+    }
+}
+
+fn reduce_depth(depth: usize) -> usize {
+    if depth > 1 {
+        depth - 1
+    } else {
+        1
+    }
+}
+
+fn diff_prec(expr: &Expr, prec: usize) -> usize {
+    match expr {
+        Expr::Binary(x) if x.op.precedence() == prec => 0,
+        _ => 1,
+    }
+}
+
+// Format the binary expression: decide the cutoff and then format.
+// Let's call depth == 1 Normal mode, and depth > 1 Compact mode.
+// (Algorithm suggestion by Russ Cox.)
+//
+// The precedences are:
+//	5             *  /  %  <<  >>  &  &^
+//	4             +  -  |  ^
+//	3             ==  !=  <  <=  >  >=
+//	2             &&
+//	1             ||
+//
+// The only decision is whether there will be spaces around levels 4 and 5.
+// There are never spaces at level 6 (unary), and always spaces at levels 3 and below.
+//
+// To choose the cutoff, look at the whole expression but excluding primary
+// expressions (function calls, parenthesized exprs), and apply these rules:
+//
+//  1. If there is a binary operator with a right side unary operand
+//     that would clash without a space, the cutoff must be (in order):
+//
+//     /*	6
+//     &&	6
+//     &^	6
+//     ++	5
+//     --	5
+//
+//     (Comparison operators always have spaces around them.)
+//
+//  2. If there is a mix of level 5 and level 4 operators, then the cutoff
+//     is 5 (use spaces to distinguish precedence) in Normal mode
+//     and 4 (never use spaces) in Compact mode.
+//
+//  3. If there are no level 4 operators or no level 5 operators, then the
+//     cutoff is 6 (always use spaces) in Normal mode
+//     and 4 (never use spaces) in Compact mode.
+fn walk_binary(e: &BinaryExpr) -> (bool, bool, usize) {
+    let (mut has4, mut has5, mut max_problem) = (false, false, 0);
+    match e.op.precedence() {
+        4 => has4 = true,
+        5 => has5 = true,
+        _ => {}
+    }
+
+    if let Expr::Binary(l) = &e.expr_a {
+        // if the precedence is lower, parens will be inserted,
+        // pretend it's a ParenExpr and do nothing
+        if l.op.precedence() >= e.op.precedence() {
+            let (h4, h5, mp) = walk_binary(l);
+            has4 = has4 || h4;
+            has5 = has5 || h5;
+            max_problem = max_problem.max(mp);
+        }
+    }
+
+    match &e.expr_b {
+        Expr::Binary(r) => {
+            // if the precedence isn't higher, parens will be inserted,
+            // pretend it's a ParenExpr and do nothing
+            if r.op.precedence() > e.op.precedence() {
+                let (h4, h5, mp) = walk_binary(r);
+                has4 = has4 || h4;
+                has5 = has5 || h5;
+                max_problem = max_problem.max(mp);
+            }
+        }
+        Expr::Star(_) => {
+            if e.op == Token::QUO {
+                // `*/`
+                max_problem = 5;
+            }
+        }
+        Expr::Unary(r) => match (e.op.text(), r.op.text()) {
+            ("/", "*") | ("&", "&") | ("&", "^") => max_problem = 5,
+            ("+", "+") | ("-", "-") => max_problem = max_problem.max(4),
+            _ => {}
+        },
+        _ => {}
+    }
+    (has4, has5, max_problem)
+}
+
+fn cutoff(e: &BinaryExpr, depth: usize) -> usize {
+    let (has4, has5, max_problem) = walk_binary(e);
+    if max_problem > 0 {
+        return max_problem + 1;
+    }
+    if has4 && has5 {
+        if depth == 1 {
+            return 5;
+        }
+        return 4;
+    }
+    if depth == 1 {
+        return 6;
+    }
+    4
+}
+
+fn strip_parens_always(x: &Expr) -> &Expr {
+    match x {
+        Expr::Paren(p) => strip_parens_always(&p.expr),
+        _ => x,
+    }
+}
+
+fn is_type_name(x: &Expr) -> bool {
+    match x {
+        Expr::Ident(_) => true,
+        Expr::Selector(s) => match &s.expr {
+            Expr::Ident(_) => true,
+            _ => false,
+        },
+        _ => false,
+    }
+}
+
+// has_type_name_lit reports whether x has a composite literal starting with
+// a type name, which is not in parentheses
+fn has_type_name_lit(x: &Expr, objs: &Objects) -> bool {
+    let any = |list: &[Expr]| list.iter().any(|e| has_type_name_lit(e, objs));
+    match x {
+        // parentheses protect enclosed composite literals
+        Expr::Paren(_) => false,
+        Expr::CompositeLit(c) => c.typ.as_ref().map_or(false, is_type_name),
+        Expr::Selector(s) => has_type_name_lit(&s.expr, objs),
+        Expr::Index(i) => has_type_name_lit(&i.expr, objs) || has_type_name_lit(&i.index, objs),
+        Expr::IndexList(i) => has_type_name_lit(&i.expr, objs) || any(&i.indices),
+        Expr::Slice(s) => {
+            has_type_name_lit(&s.expr, objs)
+                || [&s.low, &s.high, &s.max]
+                    .iter()
+                    .any(|e| e.as_ref().map_or(false, |e| has_type_name_lit(e, objs)))
+        }
+        Expr::TypeAssert(t) => has_type_name_lit(&t.expr, objs),
+        Expr::Call(c) => has_type_name_lit(&c.func, objs) || any(&c.args),
+        Expr::Star(s) => has_type_name_lit(&s.expr, objs),
+        Expr::Unary(u) => has_type_name_lit(&u.expr, objs),
+        Expr::Binary(b) => has_type_name_lit(&b.expr_a, objs) || has_type_name_lit(&b.expr_b, objs),
+        Expr::KeyValue(kv) => has_type_name_lit(&kv.key, objs) || has_type_name_lit(&kv.val, objs),
+        _ => false,
+    }
+}
+
+fn is_type_elem(x: &Expr) -> bool {
+    match x {
+        Expr::Array(_)
+        | Expr::Struct(_)
+        | Expr::Func(_)
+        | Expr::Interface(_)
+        | Expr::Map(_)
+        | Expr::Chan(_) => true,
+        Expr::Unary(u) => u.op == Token::TILDE,
+        Expr::Binary(b) => is_type_elem(&b.expr_a) || is_type_elem(&b.expr_b),
+        Expr::Paren(p) => is_type_elem(&p.expr),
+        _ => false,
+    }
+}
+
+// combines_with_name reports whether a name followed by the expression x
+// syntactically combines to another valid (value) expression. For instance
+// using *T for x, "name *T" syntactically appears as the expression x*T.
+// On the other hand, using  P|Q or *P|~Q for x, "name P|Q" or name *P|~Q"
+// cannot be combined into a valid (value) expression.
+fn combines_with_name(x: &Expr) -> bool {
+    match x {
+        // name *x.X
+        Expr::Star(s) => !is_type_elem(&s.expr),
+        Expr::Binary(b) => combines_with_name(&b.expr_a) && !is_type_elem(&b.expr_b),
+        _ => false,
+    }
+}
+
+fn num_fields(fields: &FieldList, objs: &Objects) -> usize {
+    fields
+        .list
+        .iter()
+        .map(|f| objs.fields[*f].names.len().max(1))
+        .sum()
+}
+
+fn ident_list_size(list: &[IdentKey], objs: &Objects, max_size: usize) -> usize {
+    let mut size = 0;
+    for (i, x) in list.iter().enumerate() {
+        if i > 0 {
+            size += ", ".len();
+        }
+        size += objs.idents[*x].name.chars().count();
+        if size >= max_size {
+            break;
+        }
+    }
+    size
+}
+
+fn keep_type_column(specs: &[&ValueSpec]) -> Vec<bool> {
+    let mut m = vec![false; specs.len()];
+
+    let mut populate = |i: usize, j: usize, keep_type: bool| {
+        if keep_type {
+            for k in i..j {
+                m[k] = true;
+            }
+        }
+    };
+
+    let mut i0 = None; // if i0 is set we are in a run and it's the start of the run
+    let mut keep_type = false;
+    for (i, s) in specs.iter().enumerate() {
+        if !s.values.is_empty() {
+            if i0.is_none() {
+                // start of a run of ValueSpecs with non-empty values
+                i0 = Some(i);
+                keep_type = false;
+            }
+        } else if let Some(start) = i0 {
+            // end of a run
+            populate(start, i, keep_type);
+            i0 = None;
+        }
+        if s.typ.is_some() {
+            keep_type = true;
+        }
+    }
+    if let Some(start) = i0 {
+        // end of a run
+        populate(start, specs.len(), keep_type);
+    }
+    m
+}
+
+// strip_common_prefix removes a common prefix from /*-style comment lines (unless no
+// comment line is indented, all but the first line have some form of space prefix).
+// The prefix is computed using heuristics such that is likely that the comment
+// contents are nicely laid out after re-printing each line using the printer's
+// current indentation.
+fn strip_common_prefix(lines: &mut Vec<String>) {
+    if lines.len() <= 1 {
+        return; // at most one line - nothing to do
+    }
+    // lines.len() > 1
+
+    // Compute maximum common white prefix of all but the first,
+    // last, and blank lines, and replace blank lines with empty
+    // lines (the first line starts with /* and has no prefix).
+    // In cases where only the first and last lines are not blank,
+    // such as two-line comments, or comments where all inner lines
+    // are blank, consider the last line for the prefix computation
+    // since otherwise the prefix would be empty.
+    let n = lines.len();
+    let mut prefix: Option<String> = None;
+    for line in lines[1..n - 1].iter_mut() {
+        if is_blank(line) {
+            line.clear();
+        } else {
+            prefix = Some(match prefix {
+                None => common_prefix(line, line),
+                Some(p) => common_prefix(&p, line),
+            });
+        }
+    }
+    // If we don't have a prefix yet, consider the last line.
+    let mut prefix = prefix.unwrap_or_else(|| common_prefix(&lines[n - 1], &lines[n - 1]));
+
+    // Check for vertical "line of stars" and correct prefix accordingly.
+    let mut line_of_stars = false;
+    if let Some(i) = prefix.find('*') {
+        // remove trailing blank from prefix so stars remain aligned
+        prefix.truncate(i);
+        if prefix.ends_with(' ') {
+            prefix.pop();
+        }
+        line_of_stars = true;
+    } else {
+        // No line of stars present.
+        // Determine the white space on the first line after the /*
+        // and before the beginning of the comment text, assume two
+        // blanks instead of the /* unless the first character after
+        // the /* is a tab. If the first comment line is empty but
+        // for the opening /*, assume up to 3 blanks or a tab. This
+        // whitespace may be found as suffix in the common prefix.
+        let first = lines[0].as_bytes();
+        if is_blank(&lines[0][2..]) {
+            // no comment text on the first line:
+            // reduce prefix by up to 3 blanks or a tab
+            // if present - this keeps comment text indented
+            // relative to the /* and */'s if it was indented
+            // in the first place
+            let p = prefix.as_bytes();
+            let mut i = p.len();
+            let mut k = 0;
+            while k < 3 && i > 0 && p[i - 1] == b' ' {
+                i -= 1;
+                k += 1;
+            }
+            if i == p.len() && i > 0 && p[i - 1] == b'\t' {
+                i -= 1;
+            }
+            prefix.truncate(i);
+        } else {
+            // comment text on the first line
+            let mut suffix = vec![];
+            let mut n = 2; // start after opening /*
+            while n < first.len() && first[n] <= b' ' {
+                suffix.push(first[n]);
+                n += 1;
+            }
+            let suffix = if n > 2 && first[2] == b'\t' {
+                // assume the '\t' compensates for the /*
+                suffix
+            } else {
+                // otherwise assume two blanks
+                let mut s = vec![b' ', b' '];
+                s.extend(suffix);
+                s
+            };
+            // Shorten the computed common prefix by the length of
+            // suffix, if it is found as suffix of the prefix.
+            let suffix = String::from_utf8(suffix).unwrap();
+            if prefix.ends_with(&suffix) {
+                prefix.truncate(prefix.len() - suffix.len());
+            }
+        }
+    }
+
+    // Handle last line: If it only contains a closing */, align it
+    // with the opening /*, otherwise align the text with the other
+    // lines.
+    let last = lines[n - 1].clone();
+    let before = &last[..last.find("*/").unwrap_or(last.len())];
+    if is_blank(before) {
+        // last line only contains closing */
+        let closing = if line_of_stars { " */" } else { "*/" };
+        lines[n - 1] = format!("{}{}", prefix, closing);
+    } else {
+        // last line contains more comment text - assume
+        // it is aligned like the other lines and include
+        // in prefix computation
+        prefix = common_prefix(&prefix, &last);
+    }
+
+    // Remove the common prefix from all but the first and empty lines.
+    for line in lines.iter_mut().skip(1) {
+        if !line.is_empty() {
+            line.replace_range(..prefix.len(), "");
+        }
+    }
+}
+
+fn common_prefix(a: &str, b: &str) -> String {
+    let (a, b) = (a.as_bytes(), b.as_bytes());
+    let mut i = 0;
+    while i < a.len() && i < b.len() && a[i] == b[i] && (a[i] <= b' ' || a[i] == b'*') {
+        i += 1;
+    }
+    String::from_utf8(a[..i].to_vec()).unwrap()
+}
+
+fn is_blank(s: &str) -> bool {
+    s.bytes().all(|b| b <= b' ')
+}
+
+// ----------------------------------------------------------------------------
+// The tabwriter, a port of text/tabwriter with the settings of gofmt: the
+// cells are terminated by '\t' or '\v', padded with blanks, the leading
+// empty cells are indented with tabs and the empty columns of '\v' cells
+// are discarded. A '\f' or a line of a single cell ends a block of columns.
+
+#[derive(Clone, Copy, Default)]
+struct TabCell {
+    size: usize,  // cell size in bytes
+    width: usize, // cell width in runes
+    htab: bool,   // true if the cell is terminated by an htab ('\t')
+}
+
+struct TabWriter {
+    buf: Vec<u8>,             // collected text excluding tabs or line breaks
+    pos: usize, // buffer position up to which cell.width of incomplete cell has been computed
+    cell: TabCell, // current incomplete cell
+    escaped: bool, // inside escaped text
+    lines: Vec<Vec<TabCell>>, // list of lines; each line is a list of cells
+    widths: Vec<usize>, // list of column widths in runes - re-used during formatting
+    out: Vec<u8>,
+}
+
+const TAB_WIDTH: usize = 8;
+const PADDING: usize = 1;
+
+fn tabwrite(input: &[u8]) -> Vec<u8> {
+    let mut w = TabWriter {
+        buf: vec![],
+        pos: 0,
+        cell: TabCell::default(),
+        escaped: false,
+        lines: vec![vec![]],
+        widths: vec![],
+        out: vec![],
+    };
+    let mut n0 = 0;
+    for (n, ch) in input.iter().enumerate() {
+        if !w.escaped {
+            match *ch {
+                b'\t' | b'\x0b' | b'\n' | b'\x0c' => {
+                    // end of cell
+                    w.append(&input[n0..n]);
+                    w.update_width();
+                    n0 = n + 1; // ch consumed
+                    let ncells = w.terminate_cell(*ch == b'\t');
+                    if *ch == b'\n' || *ch == b'\x0c' {
+                        // terminate line
+                        w.lines.push(vec![]);
+                        if *ch == b'\x0c' || ncells == 1 {
+                            // A '\f' always forces a flush. Otherwise, if the previous
+                            // line has only one cell which does not have an impact on
+                            // the formatting of the following lines (the last cell per
+                            // line is ignored by format()), thus we can flush the
+                            // contents.
+                            w.flush();
+                        }
+                    }
+                }
+                ESCAPE => {
+                    // start of escaped sequence
+                    w.append(&input[n0..n]);
+                    w.update_width();
+                    n0 = n;
+                    w.escaped = true;
+                }
+                _ => {}
+            }
+        } else if *ch == ESCAPE {
+            // end of escaped sequence
+            w.append(&input[n0..n + 1]);
+            n0 = n + 1;
+            w.end_escape();
+        }
+    }
+    // append leftover text
+    w.append(&input[n0..]);
+    w.flush();
+    w.out
+}
+
+impl TabWriter {
+    fn append(&mut self, text: &[u8]) {
+        self.buf.extend_from_slice(text);
+        self.cell.size += text.len();
+    }
+
+    // update the cell width
+    fn update_width(&mut self) {
+        self.cell.width += rune_count(&self.buf[self.pos..]);
+        self.pos = self.buf.len();
+    }
+
+    fn end_escape(&mut self) {
+        self.update_width();
+        self.cell.width -= 2; // don't count the escape chars
+        self.escaped = false;
+    }
+
+    // terminate the current cell by adding it to the list of cells of the
+    // current line, returns the number of cells in that line
+    fn terminate_cell(&mut self, htab: bool) -> usize {
+        self.cell.htab = htab;
+        let line = self.lines.last_mut().unwrap();
+        line.push(self.cell);
+        self.cell = TabCell::default();
+        line.len()
+    }
+
+    fn flush(&mut self) {
+        // add current cell if not empty
+        if self.cell.size > 0 {
+            if self.escaped {
+                // inside escape - terminate it even if incomplete
+                self.end_escape();
+            }
+            self.terminate_cell(false);
+        }
+        // format contents of buffer
+        self.format(0, 0, self.lines.len());
+        // reset
+        self.buf.clear();
+        self.pos = 0;
+        self.cell = TabCell::default();
+        self.lines = vec![vec![]];
+        self.widths.clear();
+    }
+
+    fn write_padding(&mut self, textw: usize, cellw: usize, use_tabs: bool) {
+        if use_tabs {
+            // padding is done with tabs
+            // make cellw the smallest multiple of TAB_WIDTH
+            let cellw = (cellw + TAB_WIDTH - 1) / TAB_WIDTH * TAB_WIDTH;
+            let n = cellw - textw; // amount of padding
+            let tabs = (n + TAB_WIDTH - 1) / TAB_WIDTH;
+            self.out.extend(std::iter::repeat(b'\t').take(tabs));
+            return;
+        }
+        // padding is done with blanks
+        self.out.extend(std::iter::repeat(b' ').take(cellw - textw));
+    }
+
+    fn write_lines(&mut self, pos0: usize, line0: usize, line1: usize) -> usize {
+        let mut pos = pos0;
+        for i in line0..line1 {
+            let line = self.lines[i].clone();
+            // use tabs to pad leading empty cells
+            let mut use_tabs = true;
+            for (j, c) in line.iter().enumerate() {
+                if c.size == 0 {
+                    // empty cell
+                    if j < self.widths.len() {
+                        self.write_padding(c.width, self.widths[j], use_tabs);
+                    }
+                } else {
+                    // non-empty cell
+                    use_tabs = false;
+                    self.out.extend_from_slice(&self.buf[pos..pos + c.size]);
+                    pos += c.size;
+                    if j < self.widths.len() {
+                        self.write_padding(c.width, self.widths[j], false);
+                    }
+                }
+            }
+
+            if i + 1 == self.lines.len() {
+                // last buffered line - we don't have a newline, so just write
+                // any outstanding buffered data
+                self.out
+                    .extend_from_slice(&self.buf[pos..pos + self.cell.size]);
+                pos += self.cell.size;
+            } else {
+                // not the last line - write newline
+                self.out.push(b'\n');
+            }
+        }
+        pos
+    }
+
+    // Format the text between line0 and line1 (excluding line1); pos
+    // is the buffer position corresponding to the beginning of line0.
+    // Returns the buffer position corresponding to the beginning of
+    // line1.
+    fn format(&mut self, pos0: usize, line0: usize, line1: usize) -> usize {
+        let mut pos = pos0;
+        let mut line0 = line0;
+        let column = self.widths.len();
+        let mut this = line0;
+        while this < line1 {
+            if column + 1 >= self.lines[this].len() {
+                this += 1;
+                continue;
+            }
+            // cell exists in this column => this line
+            // has more cells than the previous line
+            // (the last cell per line is ignored because cells are
+            // tab-terminated; the last cell per line describes the
+            // text before the newline/formfeed and does not belong
+            // to a column)
+
+            // print unprinted lines until beginning of block
+            pos = self.write_lines(pos, line0, this);
+            line0 = this;
+
+            // column block begin
+            let mut width = 0; // minimal column width
+            let mut discardable = true; // true if all cells in this column are empty and "soft"
+            while this < line1 {
+                let line = &self.lines[this];
+                if column + 1 >= line.len() {
+                    break;
+                }
+                // cell exists in this column
+                let c = line[column];
+                // update width
+                width = width.max(c.width + PADDING);
+                // update discardable
+                if c.width > 0 || c.htab {
+                    discardable = false;
+                }
+                this += 1;
+            }
+            // column block end
+
+            // discard empty columns if necessary
+            if discardable {
+                width = 0;
+            }
+
+            // format and print all columns to the right of this column
+            // (we know the widths of this column and all columns to the left)
+            self.widths.push(width);
+            pos = self.format(pos, line0, this);
+            self.widths.pop();
+            line0 = this;
+        }
+
+        // print unprinted lines until end
+        self.write_lines(pos, line0, line1)
+    }
+}
+
+fn rune_count(b: &[u8]) -> usize {
+    b.iter().filter(|c| (**c & 0xc0) != 0x80).count()
+}
+
+// trim strips the escapes, the trailing blanks and tabs, and converts
+// the formfeeds and vtabs into newlines and htabs
+fn trim(data: &[u8]) -> Vec<u8> {
+    #[derive(PartialEq)]
+    enum State {
+        InSpace,  // inside space
+        InEscape, // inside text bracketed by escapes
+        InText,   // inside text
+    }
+    let mut out = Vec::with_capacity(data.len());
+    let mut space = vec![];
+    let mut state = State::InSpace;
+    let mut m = 0;
+    for (n, b) in data.iter().enumerate() {
+        let b = if *b == b'\x0b' { b'\t' } else { *b }; // convert to htab
+        match state {
+            State::InSpace => match b {
+                b'\t' | b' ' => space.push(b),
+                b'\n' | b'\x0c' => {
+                    space.clear(); // discard trailing space
+                    out.push(b'\n');
+                }
+                ESCAPE => {
+                    out.append(&mut space);
+                    state = State::InEscape;
+                    m = n + 1; // +1: skip escape
+                }
+                _ => {
+                    out.append(&mut space);
+                    state = State::InText;
+                    m = n;
+                }
+            },
+            State::InEscape => {
+                if b == ESCAPE {
+                    out.extend_from_slice(&data[m..n]);
+                    space.clear();
+                    state = State::InSpace;
+                }
+            }
+            State::InText => match b {
+                b'\t' | b' ' => {
+                    out.extend_from_slice(&data[m..n]);
+                    space.clear();
+                    space.push(b);
+                    state = State::InSpace;
+                }
+                b'\n' | b'\x0c' => {
+                    out.extend_from_slice(&data[m..n]);
+                    space.clear();
+                    out.push(b'\n');
+                    state = State::InSpace;
+                }
+                ESCAPE => {
+                    out.extend_from_slice(&data[m..n]);
+                    state = State::InEscape;
+                    m = n + 1; // +1: skip escape
+                }
+                _ => {}
+            },
+        }
+    }
+    if state != State::InSpace {
+        out.extend_from_slice(&data[m..]);
+    }
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::errors::ErrorList;
+    use super::super::position::FileSet;
+    use super::super::scanner::Scanner;
+    use super::super::token::Token;
+    use super::format_source;
+    use std::fs;
+    use std::path::Path;
+
+    fn source_files(dir: &Path, files: &mut Vec<String>) {
+        let mut paths: Vec<_> = fs::read_dir(dir)
+            .unwrap()
+            .map(|p| p.unwrap().path())
+            .collect();
+        paths.sort();
+        for p in paths {
+            if p.is_dir() {
+                source_files(&p, files);
+            } else {
+                let s = p.to_str().unwrap();
+                if s.ends_with(".go") || s.ends_with(".gos") {
+                    files.push(s.to_string());
+                }
+            }
+        }
+    }
+
+    // tokens returns the token stream of the source, without the
+    // semicolons, which are dropped by the printer where they are optional
+    fn tokens(src: &str) -> Vec<String> {
+        let mut fs = FileSet::new();
+        let el = ErrorList::new();
+        let f = fs.add_file("./test".to_string(), None, src.chars().count());
+        let mut s = Scanner::new(f, src, &el);
+        let mut result = vec![];
+        loop {
+            let (tok, _) = s.scan();
+            match &tok {
+                Token::EOF => break,
+                Token::SEMICOLON(_) => {}
+                // the lines of block comments may be indented differently
+                Token::COMMENT(c) => {
+                    result.push(c.as_str().split_whitespace().collect::<Vec<_>>().join(" "))
+                }
+                _ => result.push(tok.to_string()),
+            }
+        }
+        result
+    }
+
+    // the idempotence over the same files is checked by the printer_test,
+    // the scanner is not visible from there
+    #[test]
+    fn test_format_keeps_tokens() {
+        let mut files = vec![];
+        source_files(Path::new("../engine/std"), &mut files);
+        source_files(Path::new("../engine/tests"), &mut files);
+        assert!(!files.is_empty());
+        for f in files.iter() {
+            let src = fs::read_to_string(f).unwrap();
+            let formatted = format_source(f, &src).unwrap();
+            assert!(tokens(&src) == tokens(&formatted), "{}: tokens changed", f);
+        }
+    }
+}
@@ -198,6 +198,7 @@ impl<'a> Scanner<'a> {
             Some('|') => self
                 .scan_switch3(&Token::OR, &Token::OR_ASSIGN, '|', &Token::LOR)
                 .clone(),
+            Some('~') => self.scan_token(Token::TILDE, false),
             Some(&c) => {
                 self.semi2 = self.semi1; // preserve insert semi info
                 self.read_char();
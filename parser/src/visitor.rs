@@ -23,6 +23,13 @@ pub trait ExprVisitor {
 
     fn visit_expr_index(&mut self, this: &Expr, expr: &Expr, index: &Expr) -> Self::Result;
 
+    fn visit_expr_index_list(
+        &mut self,
+        this: &Expr,
+        expr: &Expr,
+        indices: &Vec<Expr>,
+    ) -> Self::Result;
+
     fn visit_expr_slice(
         &mut self,
         this: &Expr,
@@ -152,6 +159,10 @@ pub fn walk_expr<R>(v: &mut dyn ExprVisitor<Result = R>, expr: &Expr) -> R {
             let indexp = e.as_ref();
             v.visit_expr_index(expr, &indexp.expr, &indexp.index)
         }
+        Expr::IndexList(e) => {
+            let indexp = e.as_ref();
+            v.visit_expr_index_list(expr, &indexp.expr, &indexp.indices)
+        }
         Expr::Slice(e) => {
             let slexp = e.as_ref();
             v.visit_expr_slice(expr, &slexp.expr, &slexp.low, &slexp.high, &slexp.max)
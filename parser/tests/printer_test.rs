@@ -0,0 +1,58 @@
+extern crate goscript_parser as fe;
+use fe::printer::format_source;
+use std::fs;
+use std::path::Path;
+
+fn source_files(dir: &Path, files: &mut Vec<String>) {
+    let mut paths: Vec<_> = fs::read_dir(dir)
+        .unwrap()
+        .map(|p| p.unwrap().path())
+        .collect();
+    paths.sort();
+    for p in paths {
+        if p.is_dir() {
+            source_files(&p, files);
+        } else {
+            let s = p.to_str().unwrap();
+            if s.ends_with(".go") || s.ends_with(".gos") {
+                files.push(s.to_string());
+            }
+        }
+    }
+}
+
+#[test]
+fn test_format_basic() {
+    let src = "package main\n\nimport \"fmt\"\n// Point is a point\ntype Point struct {\nX int // x\nLongName   string\n}\n\nfunc main() {\nx:=1+2*3\nif x>3 {fmt.Println( x )}\n}\n";
+    let expected = "package main\n\nimport \"fmt\"\n\n// Point is a point\ntype Point struct {\n\tX        int // x\n\tLongName string\n}\n\nfunc main() {\n\tx := 1 + 2*3\n\tif x > 3 {\n\t\tfmt.Println(x)\n\t}\n}\n";
+    assert_eq!(format_source("basic.gos", src).unwrap(), expected);
+}
+
+#[test]
+fn test_format_error() {
+    assert!(format_source("bad.gos", "package main\nfunc main() {\n").is_err());
+}
+
+#[test]
+fn test_format_idempotent() {
+    let mut files = vec![];
+    source_files(Path::new("../engine/std"), &mut files);
+    source_files(Path::new("../engine/tests"), &mut files);
+    assert!(!files.is_empty());
+    for f in files.iter() {
+        let src = fs::read_to_string(f).unwrap();
+        let formatted = match format_source(f, &src) {
+            Ok(s) => s,
+            Err(el) => panic!("{}: {}", f, el),
+        };
+        let again = match format_source(f, &formatted) {
+            Ok(s) => s,
+            Err(el) => panic!("{}: {}\n{}", f, el, formatted),
+        };
+        assert!(formatted == again, "{}: formatting is not idempotent", f);
+        if f.ends_with(".go") {
+            // the go files are formatted by gofmt already
+            assert!(formatted == src, "{}: differs from gofmt", f);
+        }
+    }
+}
@@ -63,20 +63,45 @@ impl<'a> Checker<'a> {
             }
             _ => {
                 // function/method call
-                let sig_key = typ::underlying_type(x.typ.unwrap(), self.tc_objs);
+                let mut sig_key = typ::underlying_type(x.typ.unwrap(), self.tc_objs);
                 if let Some(sig) = self.otype(sig_key).try_as_signature() {
-                    let sig_results = sig.results();
+                    let mut sig_results = sig.results();
                     let variadic = sig.variadic();
                     let pcount = sig.params_count(self.tc_objs);
+                    let generic = sig.tparams().is_some();
                     let result = self.unpack(&e.args, pcount, false, variadic, fctx);
                     match result {
                         UnpackResult::Error => x.mode = OperandMode::Invalid,
+                        _ if generic => {
+                            // the type arguments are inferred from the arguments,
+                            // which are evaluated only once
+                            let (count, _) = result.rhs_count();
+                            let mut args = Vec::with_capacity(count);
+                            for i in 0..count {
+                                let mut a = Operand::new();
+                                result.get(self, &mut a, i, fctx);
+                                args.push(a);
+                            }
+                            match self.infer_call(x, e, sig_key, &args, fctx) {
+                                Some(inst_sig) => {
+                                    sig_key = inst_sig;
+                                    sig_results = self.otype_signature(inst_sig).results();
+                                    let re = UnpackedResultLeftovers::new(&result, Some(&args));
+                                    self.arguments(x, e, sig_key, &re, count, fctx);
+                                }
+                                None => x.mode = OperandMode::Invalid,
+                            }
+                        }
                         _ => {
                             let (count, _) = result.rhs_count();
                             let re = UnpackedResultLeftovers::new(&result, None);
                             self.arguments(x, e, sig_key, &re, count, fctx);
                         }
                     }
+                    if x.invalid() && generic {
+                        x.expr = expr;
+                        return ExprKind::Statement;
+                    }
 
                     // determine result
                     let sigre = self.tc_objs.types[sig_results].try_as_tuple().unwrap();
@@ -103,6 +128,31 @@ impl<'a> Checker<'a> {
         }
     }
 
+    /// infer_call infers the type arguments of the call of generic function x
+    /// and returns the signature of the instance.
+    fn infer_call(
+        &mut self,
+        x: &Operand,
+        e: &Rc<CallExpr>,
+        sig: TypeKey,
+        args: &Vec<Operand>,
+        fctx: &mut FilesContext,
+    ) -> Option<TypeKey> {
+        let ikey = Checker::instance_ident(&e.func)?;
+        let obj = *self.result.uses.get(&ikey)?;
+        let given = self
+            .result
+            .instances
+            .get(&ikey)
+            .map_or(vec![], |inst| inst.targs.clone());
+        let pos = x.pos(self.ast_objs);
+        let targs = self.infer(sig, &given, args, e.ellipsis.is_some(), pos)?;
+        let inst_sig = self.instantiate_func(ikey, obj, targs, pos, fctx);
+        self.result
+            .record_type_and_value(&e.func, OperandMode::Value, inst_sig);
+        Some(inst_sig)
+    }
+
     /// arguments checks argument passing for the call with the given signature.
     pub fn arguments(
         &mut self,
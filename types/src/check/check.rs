@@ -4,12 +4,13 @@ use super::super::importer::{Config, ImportKey, Importer};
 use super::super::objects::{DeclInfoKey, ObjKey, PackageKey, ScopeKey, TCObjects, TypeKey};
 use super::super::operand::OperandMode;
 use super::super::selection::Selection;
+use super::instantiate::InstanceRequest;
 use super::interface::IfaceInfo;
 use goscript_parser::ast;
 use goscript_parser::ast::Node;
 use goscript_parser::ast::{Expr, NodeId};
 use goscript_parser::errors::{ErrorList, FilePosErrors};
-use goscript_parser::objects::{IdentKey, Objects as AstObjects};
+use goscript_parser::objects::{FuncDeclKey, IdentKey, Objects as AstObjects};
 use goscript_parser::position::Pos;
 use goscript_parser::FileSet;
 use std::cell::RefCell;
@@ -42,6 +43,26 @@ pub struct Initializer {
     pub rhs: Expr,
 }
 
+/// An Instance describes the instantiation of a generic function: the type
+/// arguments and the signature of the resulting function.
+#[derive(Debug, Clone)]
+pub struct Instance {
+    pub targs: Vec<TypeKey>,
+    pub typ: TypeKey,
+}
+
+/// A FuncInstance is a generic function, or a method of a generic type,
+/// type-checked again with its type parameters bound to concrete type
+/// arguments, 'info' is the result of checking the body of the instance.
+#[derive(Debug)]
+pub struct FuncInstance {
+    pub obj: ObjKey,
+    pub targs: Vec<TypeKey>,
+    pub decl: FuncDeclKey,
+    pub sig: TypeKey,
+    pub info: TypeInfo,
+}
+
 /// Types info holds the results of Type Checking
 #[derive(Debug)]
 pub struct TypeInfo {
@@ -117,6 +138,15 @@ pub struct TypeInfo {
     /// in source order. Variables without an initialization expression do not
     /// appear in this list.
     pub init_order: Vec<Initializer>,
+    /// 'instances' maps identifiers denoting generic functions to their
+    /// instantiations, the signature is the one of the instantiated function.
+    pub instances: HashMap<IdentKey, Instance>,
+    /// 'func_instances' are the instantiated generic functions and methods
+    /// of the package to be generated.
+    pub func_instances: Vec<FuncInstance>,
+    /// 'generic_decls' maps generic functions and methods of generic types
+    /// to their declarations, so that other packages can instantiate them.
+    pub generic_decls: HashMap<ObjKey, DeclInfoKey>,
     /// oxfeeefeee: parse result of the package, to be used by code gen
     pub ast_files: Vec<ast::File>,
 }
@@ -131,6 +161,9 @@ impl TypeInfo {
             selections: HashMap::new(),
             scopes: HashMap::new(),
             init_order: Vec::new(),
+            instances: HashMap::new(),
+            func_instances: Vec::new(),
+            generic_decls: HashMap::new(),
             ast_files: Vec::new(),
         }
     }
@@ -204,7 +237,7 @@ pub struct Checker<'a> {
     pub all_pkgs: &'a mut HashMap<String, PackageKey>,
     // all results, i.e. including results collected from
     // previously created Checker instances
-    all_results: &'a mut HashMap<PackageKey, TypeInfo>,
+    pub all_results: &'a mut HashMap<PackageKey, TypeInfo>,
     // this package
     pub pkg: PackageKey,
     // maps package-level objects and (non-interface) methods to declaration info
@@ -219,6 +252,11 @@ pub struct Checker<'a> {
     pub result: TypeInfo,
     // for debug
     pub indent: Rc<RefCell<usize>>,
+    // requested instances of generic functions and methods, all requests
+    // are kept to avoid checking an instance twice
+    pub instance_reqs: Vec<InstanceRequest>,
+    // generic functions and methods checked with concrete type arguments
+    pub func_instances: Vec<FuncInstance>,
 }
 
 impl ObjContext {
@@ -412,6 +450,8 @@ impl<'a> Checker<'a> {
             config: cfg,
             result: TypeInfo::new(),
             indent: Rc::new(RefCell::new(0)),
+            instance_reqs: Vec::new(),
+            func_instances: Vec::new(),
         }
     }
 
@@ -424,13 +464,20 @@ impl<'a> Checker<'a> {
         self.init_order();
         self.unused_imports(fctx);
         self.record_untyped(fctx);
+        self.check_instances();
 
         std::mem::swap(&mut self.result.ast_files, &mut files);
+        self.result.func_instances = std::mem::take(&mut self.func_instances);
+        for (okey, dkey) in self.obj_map.iter() {
+            if self.is_generic_func(*okey) {
+                self.result.generic_decls.insert(*okey, *dkey);
+            }
+        }
         self.all_results.insert(self.pkg, self.result);
         Ok(self.pkg)
     }
 
-    fn record_untyped(&mut self, fctx: &mut FilesContext) {
+    pub fn record_untyped(&mut self, fctx: &mut FilesContext) {
         for (id, info) in fctx.untyped.drain().into_iter() {
             if info.mode != OperandMode::Invalid {
                 self.result
@@ -7,7 +7,7 @@ use super::super::scope::Scope;
 use super::super::typ::{self};
 use super::check::{Checker, FilesContext, ObjContext};
 use super::stmt::BodyContainer;
-use goscript_parser::ast::{self, Expr, Node};
+use goscript_parser::ast::{self, Expr, FieldList, Node};
 use goscript_parser::objects::IdentKey;
 use goscript_parser::position::Pos;
 use goscript_parser::Token;
@@ -129,8 +129,8 @@ impl<'a> Checker<'a> {
                     }
                     EntityType::TypeName => {
                         let cd = d.as_type();
-                        let (typ, alias) = (cd.typ.clone(), cd.alias);
-                        self.type_decl(okey, &typ, def, alias, fctx);
+                        let (typ, alias, tparams) = (cd.typ.clone(), cd.alias, cd.tparams.clone());
+                        self.type_decl(okey, &typ, tparams.as_ref(), def, alias, fctx);
                     }
                     EntityType::Func(_) => {
                         self.func_decl(okey, dkey, fctx);
@@ -339,6 +339,7 @@ impl<'a> Checker<'a> {
         // determine type, if any
         if let Some(texpr) = typ {
             let t = self.type_expr(texpr, fctx);
+            self.valid_var_type(texpr, t, fctx);
             self.lobj_mut(okey).set_type(Some(t));
             // We cannot spread the type to all lhs variables if there
             // are more than one since that would mark them as checked
@@ -389,11 +390,16 @@ impl<'a> Checker<'a> {
         &mut self,
         okey: ObjKey,
         typ: &Expr,
+        tparams: Option<&FieldList>,
         def: Option<TypeKey>,
         alias: bool,
         fctx: &mut FilesContext,
     ) {
         debug_assert!(self.lobj(okey).typ().is_none());
+        if alias && tparams.is_some() {
+            let pos = tparams.unwrap().pos(self.ast_objs);
+            self.error_str(pos, "generic type cannot be alias");
+        }
         if alias {
             let invalid = self.invalid_type();
             self.lobj_mut(okey).set_type(Some(invalid));
@@ -410,8 +416,28 @@ impl<'a> Checker<'a> {
             // make sure recursive type declarations terminate
             self.lobj_mut(okey).set_type(Some(named_key));
 
+            // the type parameters are declared in their own scope enclosing the type
+            let saved_scope = self.octx.scope;
+            if let Some(tps) = tparams {
+                let (pos, end) = (tps.pos(self.ast_objs), typ.end(self.ast_objs));
+                let skey = self.tc_objs.new_scope(
+                    self.octx.scope,
+                    pos,
+                    end,
+                    "type parameters".to_string(),
+                    false,
+                );
+                let tuple = self.declare_type_params(skey, tps, fctx);
+                self.tc_objs.types[named_key]
+                    .try_as_named_mut()
+                    .unwrap()
+                    .set_tparams(Some(tuple));
+                self.octx.scope = Some(skey);
+            }
+
             // determine underlying type of named
             self.defined_type(typ, Some(named_key), fctx);
+            self.octx.scope = saved_scope;
 
             // The underlying type of named may be itself a named type that is
             // incomplete:
@@ -433,6 +459,8 @@ impl<'a> Checker<'a> {
                 .set_underlying(underlying);
         }
         self.add_method_decls(okey, fctx);
+        let t = self.lobj(okey).typ().unwrap();
+        self.instantiate_generic_methods(t, fctx);
     }
 
     pub fn func_decl(&mut self, okey: ObjKey, dkey: DeclInfoKey, fctx: &mut FilesContext) {
@@ -692,7 +720,14 @@ impl<'a> Checker<'a> {
                             // its type is still nil (see Checker.obj_decl)
                             self.lobj_mut(okey)
                                 .set_color(ObjColor::Gray(fctx.push(okey)));
-                            self.type_decl(okey, &ts.typ.clone(), None, ts.assign > 0, fctx);
+                            if let Some(tps) = &ts.type_params {
+                                let pos = tps.pos(self.ast_objs);
+                                self.error_str(
+                                    pos,
+                                    "generic type cannot be declared inside a function",
+                                );
+                            }
+                            self.type_decl(okey, &ts.typ.clone(), None, None, ts.assign > 0, fctx);
                             self.lobj_mut(fctx.pop()).set_color(ObjColor::Black);
                         }
                         _ => self.invalid_ast(spec_pos, "const, type, or var declaration expected"),
@@ -246,6 +246,7 @@ impl<'a> Checker<'a> {
             | Expr::FuncLit(_)
             | Expr::CompositeLit(_)
             | Expr::Index(_)
+            | Expr::IndexList(_)
             | Expr::Slice(_)
             | Expr::TypeAssert(_)
             | Expr::Star(_)
@@ -457,6 +458,30 @@ impl<'a> Checker<'a> {
                     }
                 }
             }
+            Type::TypeParam(detail) => {
+                // x must be convertible to every type in the type set,
+                // a nil stays untyped
+                let xbasic = self.otype(xtype).try_as_basic().unwrap().typ();
+                let ok = detail.all_terms(o, |t| {
+                    let u = t.underlying_val(o);
+                    match (&x.mode, u.try_as_basic()) {
+                        (OperandMode::Constant(v), Some(b)) => v.representable(b, None),
+                        (OperandMode::Constant(_), None) => false,
+                        _ => match xbasic {
+                            BasicType::UntypedBool => u.is_boolean(o),
+                            BasicType::UntypedNil => u.has_nil(o),
+                            _ => u.is_numeric(o),
+                        },
+                    }
+                });
+                if !ok {
+                    None
+                } else if x.is_nil(self.tc_objs.universe()) {
+                    Some(self.basic_type(BasicType::UntypedNil))
+                } else {
+                    Some(target)
+                }
+            }
             Type::Pointer(_)
             | Type::Signature(_)
             | Type::Slice(_)
@@ -795,7 +820,19 @@ impl<'a> Checker<'a> {
                 } else {
                     op
                 };
+                // the operands with errors are unknown
+                if *vx == Value::Unknown || *vy == Value::Unknown {
+                    *vx = Value::Unknown;
+                    return;
+                }
                 *vx = Value::binary_op(vx, op2, vy);
+                if *vx == Value::Unknown {
+                    // the result overflowed
+                    let pos = x.pos(self.ast_objs);
+                    self.error(pos, "constant result is not representable".to_string());
+                    x.mode = OperandMode::Invalid;
+                    return;
+                }
                 // Typed constants must be representable in
                 // their type after each constant operation.
                 if typ::is_typed(ty, o) {
@@ -1281,7 +1318,16 @@ impl<'a> Checker<'a> {
                 self.selector(x, s, fctx);
             }
             Expr::Index(ie) => {
-                self.expr(x, &ie.expr, fctx);
+                self.expr_or_type(x, &ie.expr, fctx);
+                if x.invalid() {
+                    self.use_exprs(&vec![ie.index.clone()], fctx);
+                    return on_err(x);
+                }
+                let indices = [ie.index.clone()];
+                if let Some(kind) = self.generic_index(x, e, &ie.expr, &indices, fctx) {
+                    return kind;
+                }
+                self.expr_value_err(x);
                 if x.invalid() {
                     self.use_exprs(&vec![ie.index.clone()], fctx);
                     return on_err(x);
@@ -1355,6 +1401,20 @@ impl<'a> Checker<'a> {
                 let _ = self.index(&ie.index, length, fctx);
                 // ok to continue
             }
+            Expr::IndexList(il) => {
+                self.expr_or_type(x, &il.expr, fctx);
+                if x.invalid() {
+                    self.use_exprs(&il.indices, fctx);
+                    return on_err(x);
+                }
+                if let Some(kind) = self.generic_index(x, e, &il.expr, &il.indices, fctx) {
+                    return kind;
+                }
+                let xd = self.new_dis(x);
+                self.invalid_op(xd.pos(), &format!("cannot index {}", xd));
+                self.use_exprs(&il.indices, fctx);
+                return on_err(x);
+            }
             Expr::Slice(se) => {
                 self.expr(x, &se.expr, fctx);
                 if x.invalid() {
@@ -1578,6 +1638,15 @@ impl<'a> Checker<'a> {
             OperandMode::NoValue => Some("used as value"),
             OperandMode::Builtin(_) => Some("must be called"),
             OperandMode::TypeExpr => Some("is not an expression"),
+            OperandMode::Value if self.is_generic_operand(x) => {
+                let ed = self.new_dis(x.expr.as_ref().unwrap());
+                self.error(
+                    ed.pos(),
+                    format!("cannot use generic function {} without instantiation", ed),
+                );
+                x.mode = OperandMode::Invalid;
+                return;
+            }
             _ => None,
         };
         if let Some(m) = msg {
@@ -1587,6 +1656,43 @@ impl<'a> Checker<'a> {
         }
     }
 
+    /// is_generic_operand reports whether x is a generic function that
+    /// is not instantiated.
+    pub fn is_generic_operand(&self, x: &Operand) -> bool {
+        x.typ.map_or(false, |t| {
+            self.otype(t)
+                .try_as_signature()
+                .map_or(false, |s| s.tparams().is_some())
+        })
+    }
+
+    /// generic_index handles the instantiation of a generic type or function x
+    /// with the type arguments indices, it returns None if x is not generic.
+    fn generic_index(
+        &mut self,
+        x: &mut Operand,
+        e: &Expr,
+        base: &Expr,
+        indices: &[Expr],
+        fctx: &mut FilesContext,
+    ) -> Option<ExprKind> {
+        let pos = e.pos(self.ast_objs);
+        match x.mode {
+            OperandMode::TypeExpr if self.is_generic_type(x.typ.unwrap()) => {
+                match self.instantiated_type(x, indices, pos, fctx) {
+                    Some(t) => x.typ = Some(t),
+                    None => x.mode = OperandMode::Invalid,
+                }
+            }
+            OperandMode::Value if self.is_generic_operand(x) => {
+                self.func_instance(x, base, indices, pos, fctx);
+            }
+            _ => return None,
+        }
+        x.expr = Some(e.clone());
+        Some(ExprKind::Expression)
+    }
+
     pub fn single_value(&self, x: &mut Operand) {
         if x.mode == OperandMode::Value {
             // tuple types are never named - no need for underlying type below
@@ -131,7 +131,16 @@ impl<'a> Checker<'a> {
             |mut init: HashMap<ObjKey, GraphEdges>, (&x, &decl_key)| {
                 let decl = &self.tc_objs.decls[decl_key];
                 if decl.has_initializer(self.ast_objs) {
-                    let deps: HashSet<ObjKey> = decl.deps().iter().map(|z| *z).collect();
+                    // the ones without initializers are not in the graph, a var
+                    // such as `var n int` has nothing to wait for
+                    let deps: HashSet<ObjKey> = decl
+                        .deps()
+                        .iter()
+                        .filter(|z| {
+                            self.tc_objs.decls[self.obj_map[z]].has_initializer(self.ast_objs)
+                        })
+                        .map(|z| *z)
+                        .collect();
                     init.insert(x, GraphEdges::new(Rc::new(RefCell::new(deps))));
                 }
                 init
@@ -0,0 +1,1175 @@
+#![allow(dead_code)]
+use super::super::lookup;
+use super::super::objects::{DeclInfoKey, ObjKey, ScopeKey, TypeKey};
+use super::super::operand::{Operand, OperandMode};
+use super::super::scope::Scope;
+use super::super::typ::{self, Type};
+use super::check::{Checker, FilesContext, FuncInstance, Instance, ObjContext, TypeInfo};
+use super::stmt::BodyContainer;
+use goscript_parser::ast::{Expr, FieldList, Node};
+use goscript_parser::objects::IdentKey;
+use goscript_parser::{Pos, Token};
+use std::collections::HashMap;
+
+/// An InstanceRequest asks for the body of a generic function, or of a method
+/// of a generic type, to be checked with concrete type arguments, see
+/// Checker::check_instances.
+#[derive(Debug, Clone)]
+pub struct InstanceRequest {
+    pub obj: ObjKey,  // the generic function, or the method of the instantiated type
+    pub orig: ObjKey, // the declared function or method
+    pub targs: Vec<TypeKey>,
+}
+
+type SubstMap = HashMap<TypeKey, TypeKey>;
+
+impl<'a> Checker<'a> {
+    /// declare_type_params declares the type parameters in scope skey and
+    /// returns them as a tuple of LangObj::TypeName.
+    pub fn declare_type_params(
+        &mut self,
+        skey: ScopeKey,
+        tparams: &FieldList,
+        fctx: &mut FilesContext,
+    ) -> TypeKey {
+        // declare all the names first, the constraints may refer to any of them
+        let mut names = vec![];
+        let mut bounds = vec![];
+        for fkey in tparams.list.iter() {
+            let field = &self.ast_objs.fields[*fkey];
+            let (field_names, ftype) = (field.names.clone(), field.typ.clone());
+            let mut tps = vec![];
+            for name in field_names.iter() {
+                let ident = self.ast_ident(*name);
+                let (pos, n) = (ident.pos, ident.name.clone());
+                let okey = self.tc_objs.new_type_name(pos, Some(self.pkg), n, None);
+                let t = self.tc_objs.new_t_type_param(okey, names.len());
+                self.lobj_mut(okey).set_type(Some(t));
+                let scope_pos = self.scope(skey).pos();
+                self.declare(skey, Some(*name), okey, scope_pos);
+                names.push(okey);
+                tps.push(t);
+            }
+            bounds.push((tps, ftype));
+        }
+
+        let saved_scope = self.octx.scope.replace(skey);
+        for (tps, e) in bounds.into_iter() {
+            let b = self.bound_type(&e, fctx);
+            for t in tps.into_iter() {
+                self.tc_objs.types[t]
+                    .try_as_type_param_mut()
+                    .unwrap()
+                    .set_bound(b);
+            }
+        }
+        self.octx.scope = saved_scope;
+        self.tc_objs.new_t_tuple(names)
+    }
+
+    /// bind_type_params declares the type parameters in scope skey as names
+    /// of the type arguments, to check the body of an instance.
+    fn bind_type_params(&mut self, skey: ScopeKey, names: &[IdentKey], targs: &[TypeKey]) {
+        for (name, t) in names.iter().zip(targs.iter()) {
+            let ident = self.ast_ident(*name);
+            let (pos, n) = (ident.pos, ident.name.clone());
+            let okey = self.tc_objs.new_type_name(pos, Some(self.pkg), n, Some(*t));
+            let scope_pos = self.scope(skey).pos();
+            self.declare(skey, Some(*name), okey, scope_pos);
+        }
+    }
+
+    /// func_type_params declares the type parameters of a function in skey,
+    /// or binds them to targs. It returns the type parameters if declared.
+    pub fn func_type_params(
+        &mut self,
+        skey: ScopeKey,
+        tparams: &FieldList,
+        targs: Option<&Vec<TypeKey>>,
+        fctx: &mut FilesContext,
+    ) -> Option<TypeKey> {
+        match targs {
+            Some(ta) => {
+                let names: Vec<IdentKey> = tparams
+                    .list
+                    .iter()
+                    .map(|f| self.ast_objs.fields[*f].names.clone())
+                    .flatten()
+                    .collect();
+                self.bind_type_params(skey, &names, ta);
+                None
+            }
+            None => Some(self.declare_type_params(skey, tparams, fctx)),
+        }
+    }
+
+    /// recv_type_params declares the type parameters of the receiver of a method
+    /// of a generic type, as in 'func (l *List[T]) Push(v T)', or binds them to
+    /// targs. It returns false if the receiver type is not instantiated.
+    pub fn recv_type_params(
+        &mut self,
+        skey: ScopeKey,
+        recv: &FieldList,
+        targs: Option<&Vec<TypeKey>>,
+        fctx: &mut FilesContext,
+    ) -> bool {
+        let texpr = match recv.list.first() {
+            Some(f) => self.ast_objs.fields[*f].typ.clone(),
+            None => return false,
+        };
+        let mut e = Checker::unparen(&texpr);
+        if let Expr::Star(s) = e {
+            e = Checker::unparen(&s.expr);
+        }
+        let (base, indices) = match e {
+            Expr::Index(ie) => (ie.expr.clone(), vec![ie.index.clone()]),
+            Expr::IndexList(il) => (il.expr.clone(), il.indices.clone()),
+            _ => return false,
+        };
+        let mut names = vec![];
+        for index in indices.iter() {
+            match index {
+                Expr::Ident(i) => names.push(*i),
+                _ => {
+                    let pos = index.pos(self.ast_objs);
+                    let ed = self.new_dis(index);
+                    self.error(
+                        pos,
+                        format!("receiver type parameter {} must be an identifier", ed),
+                    );
+                    return false;
+                }
+            }
+        }
+
+        if let Some(ta) = targs {
+            self.bind_type_params(skey, &names, ta);
+            return true;
+        }
+
+        let mut x = Operand::new();
+        self.expr_or_type(&mut x, &base, fctx);
+        if x.invalid() {
+            return false;
+        }
+        let tparams = match x.mode {
+            OperandMode::TypeExpr => self
+                .otype(x.typ.unwrap())
+                .try_as_named()
+                .and_then(|n| n.tparams()),
+            _ => None,
+        };
+        let tps = match tparams {
+            Some(tp) => self.tparam_types(tp),
+            None => {
+                let xd = self.new_dis(&x);
+                self.error(xd.pos(), format!("{} is not a generic type", xd));
+                return false;
+            }
+        };
+        if tps.len() != names.len() {
+            let pos = texpr.pos(self.ast_objs);
+            self.error(
+                pos,
+                format!(
+                    "got {} type parameters, but receiver base type declares {}",
+                    names.len(),
+                    tps.len()
+                ),
+            );
+            return false;
+        }
+        self.bind_type_params(skey, &names, &tps);
+        true
+    }
+
+    /// bound_type returns the constraint of a type parameter, a constraint that
+    /// is not an interface, such as '~int | ~uint', is the type set of an
+    /// implicit interface.
+    fn bound_type(&mut self, e: &Expr, fctx: &mut FilesContext) -> TypeKey {
+        let t = if self.is_type_term(e) {
+            None
+        } else {
+            let t = self.type_expr(e, fctx);
+            if t == self.invalid_type()
+                || self
+                    .otype(t)
+                    .underlying_val(self.tc_objs)
+                    .try_as_interface()
+                    .is_some()
+            {
+                return t;
+            }
+            Some(t)
+        };
+        let line = match t {
+            Some(t) => vec![(false, t)],
+            None => self.type_set_line(e, fctx),
+        };
+        let iface = self.tc_objs.new_t_empty_interface();
+        self.otype_interface_mut(iface)
+            .type_set_mut()
+            .lines
+            .push(line);
+        iface
+    }
+
+    /// is_type_term reports whether the embedded element e of an interface is
+    /// a union, a term or a non-interface type, rather than an embedded interface.
+    pub fn is_type_term(&self, e: &Expr) -> bool {
+        match e {
+            Expr::Unary(u) => u.op == Token::TILDE,
+            Expr::Binary(b) => b.op == Token::OR,
+            Expr::Paren(p) => self.is_type_term(&p.expr),
+            Expr::Array(_)
+            | Expr::Struct(_)
+            | Expr::Func(_)
+            | Expr::Map(_)
+            | Expr::Chan(_)
+            | Expr::Star(_) => true,
+            Expr::Ident(i) => {
+                let name = &self.ast_ident(*i).name;
+                Scope::lookup_parent(&self.octx.scope.unwrap(), name, self.octx.pos, self.tc_objs)
+                    .map_or(false, |(_, okey)| {
+                        let lobj = self.lobj(okey);
+                        lobj.entity_type().is_type_name()
+                            && lobj.typ().map_or(false, |t| {
+                                self.otype(t)
+                                    .try_as_basic()
+                                    .map_or(false, |b| b.typ() != typ::BasicType::Invalid)
+                            })
+                    })
+            }
+            _ => false,
+        }
+    }
+
+    /// type_set_line collects the terms of the union e.
+    pub fn type_set_line(&mut self, e: &Expr, fctx: &mut FilesContext) -> Vec<(bool, TypeKey)> {
+        let mut line = vec![];
+        self.collect_terms(e, &mut line, fctx);
+        line
+    }
+
+    fn collect_terms(
+        &mut self,
+        e: &Expr,
+        line: &mut Vec<(bool, TypeKey)>,
+        fctx: &mut FilesContext,
+    ) {
+        match Checker::unparen(e) {
+            Expr::Binary(b) if b.op == Token::OR => {
+                self.collect_terms(&b.expr_a, line, fctx);
+                self.collect_terms(&b.expr_b, line, fctx);
+            }
+            Expr::Unary(u) if u.op == Token::TILDE => {
+                let t = self.type_expr(&u.expr, fctx);
+                if t == self.invalid_type() {
+                    return;
+                }
+                if typ::underlying_type(t, self.tc_objs) != t {
+                    let pos = u.expr.pos(self.ast_objs);
+                    let td = self.new_dis(&t);
+                    self.error(
+                        pos,
+                        format!(
+                            "invalid use of ~ ({} is not the underlying type of itself)",
+                            td
+                        ),
+                    );
+                    return;
+                }
+                line.push((true, t));
+            }
+            e => {
+                let t = self.type_expr(e, fctx);
+                if t == self.invalid_type() {
+                    return;
+                }
+                let ut = typ::underlying_type(t, self.tc_objs);
+                if let Some(iface) = self.otype(ut).try_as_interface() {
+                    let has_methods = !iface.methods().is_empty() || !iface.embeddeds().is_empty();
+                    let lines = &iface.type_set().lines;
+                    if has_methods || lines.len() > 1 {
+                        let pos = e.pos(self.ast_objs);
+                        let td = self.new_dis(&t);
+                        self.error(
+                            pos,
+                            format!("cannot use {} in union (interface contains methods)", td),
+                        );
+                    } else if lines.len() == 1 {
+                        let terms = lines[0].clone();
+                        line.extend(terms);
+                    }
+                } else {
+                    line.push((false, t));
+                }
+            }
+        }
+    }
+
+    pub fn tparam_types(&self, tparams: TypeKey) -> Vec<TypeKey> {
+        self.otype(tparams)
+            .try_as_tuple()
+            .unwrap()
+            .vars()
+            .iter()
+            .map(|o| self.lobj(*o).typ().unwrap())
+            .collect()
+    }
+
+    /// is_generic_func reports whether okey is a generic function or a method
+    /// of a generic type, whose body is only checked for instances.
+    pub fn is_generic_func(&self, okey: ObjKey) -> bool {
+        let lobj = self.lobj(okey);
+        if !lobj.entity_type().is_func() {
+            return false;
+        }
+        let sig = match lobj
+            .typ()
+            .map(|t| self.otype(t).try_as_signature())
+            .flatten()
+        {
+            Some(s) => s,
+            None => return false,
+        };
+        sig.tparams().is_some()
+            || sig.recv().map_or(false, |r| {
+                let (t, _) = lookup::try_deref(self.lobj(r).typ().unwrap(), self.tc_objs);
+                self.otype(t)
+                    .try_as_named()
+                    .map_or(false, |n| n.tparams().is_some())
+            })
+    }
+
+    /// is_generic_type reports whether t is a generic type that is not instantiated.
+    pub fn is_generic_type(&self, t: TypeKey) -> bool {
+        self.otype(t)
+            .try_as_named()
+            .map_or(false, |n| n.tparams().is_some() && n.orig().is_none())
+    }
+
+    /// has_type_param reports whether t contains a type parameter.
+    pub fn has_type_param(&self, t: TypeKey) -> bool {
+        let var_has = |v: &ObjKey| self.has_type_param(self.lobj(*v).typ().unwrap());
+        match self.otype(t) {
+            Type::Basic(_) => false,
+            Type::TypeParam(_) => true,
+            Type::Array(d) => self.has_type_param(d.elem()),
+            Type::Slice(d) => self.has_type_param(d.elem()),
+            Type::Pointer(d) => self.has_type_param(d.base()),
+            Type::Map(d) => self.has_type_param(d.key()) || self.has_type_param(d.elem()),
+            Type::Chan(d) => self.has_type_param(d.elem()),
+            Type::Tuple(d) => d.vars().iter().any(var_has),
+            Type::Struct(d) => d.fields().iter().any(var_has),
+            Type::Signature(d) => {
+                self.has_type_param(d.params()) || self.has_type_param(d.results())
+            }
+            Type::Interface(d) => {
+                d.methods()
+                    .iter()
+                    .any(|m| self.has_type_param(self.lobj(*m).typ().unwrap()))
+                    || d.embeddeds().iter().any(|e| self.has_type_param(*e))
+                    || d.type_set()
+                        .lines
+                        .iter()
+                        .any(|l| l.iter().any(|(_, t)| self.has_type_param(*t)))
+            }
+            Type::Named(d) => {
+                (d.tparams().is_some() && d.orig().is_none())
+                    || d.targs().iter().any(|t| self.has_type_param(*t))
+            }
+        }
+    }
+
+    /// subst returns t with the type parameters replaced according to smap.
+    pub fn subst(
+        &mut self,
+        t: TypeKey,
+        smap: &SubstMap,
+        pos: Pos,
+        fctx: &mut FilesContext,
+    ) -> TypeKey {
+        if let Some(s) = smap.get(&t) {
+            return *s;
+        }
+        if !self.has_type_param(t) {
+            return t;
+        }
+        match self.otype(t) {
+            Type::Array(d) => {
+                let (elem, len) = (d.elem(), d.len());
+                let elem = self.subst(elem, smap, pos, fctx);
+                self.tc_objs.new_t_array(elem, len)
+            }
+            Type::Slice(d) => {
+                let elem = d.elem();
+                let elem = self.subst(elem, smap, pos, fctx);
+                self.tc_objs.new_t_slice(elem)
+            }
+            Type::Pointer(d) => {
+                let base = d.base();
+                let base = self.subst(base, smap, pos, fctx);
+                self.tc_objs.new_t_pointer(base)
+            }
+            Type::Map(d) => {
+                let (key, elem) = (d.key(), d.elem());
+                let key = self.subst(key, smap, pos, fctx);
+                let elem = self.subst(elem, smap, pos, fctx);
+                self.tc_objs.new_t_map(key, elem)
+            }
+            Type::Chan(d) => {
+                let (dir, elem) = (d.dir(), d.elem());
+                let elem = self.subst(elem, smap, pos, fctx);
+                self.tc_objs.new_t_chan(dir, elem)
+            }
+            Type::Tuple(d) => {
+                let vars = d.vars().clone();
+                let mut new_vars = Vec::with_capacity(vars.len());
+                for v in vars.into_iter() {
+                    new_vars.push(self.subst_var(v, smap, pos, fctx));
+                }
+                self.tc_objs.new_t_tuple(new_vars)
+            }
+            Type::Struct(d) => {
+                let (fields, tags) = (d.fields().clone(), d.tags().clone());
+                let mut new_fields = Vec::with_capacity(fields.len());
+                for f in fields.into_iter() {
+                    new_fields.push(self.subst_var(f, smap, pos, fctx));
+                }
+                self.tc_objs.new_t_struct(new_fields, tags)
+            }
+            Type::Signature(_) => self.subst_sig(t, smap, true, pos, fctx),
+            Type::Interface(d) => {
+                let (methods, embeddeds) = (d.methods().clone(), d.embeddeds().clone());
+                let mut type_set = d.type_set().clone();
+                let mut new_methods = Vec::with_capacity(methods.len());
+                for m in methods.into_iter() {
+                    let mobj = self.lobj(m);
+                    let (mpos, pkg, name) = (mobj.pos(), mobj.pkg(), mobj.name().clone());
+                    let sig = mobj.typ().unwrap();
+                    // the receiver is the interface itself, keep it
+                    let sig = self.subst_sig(sig, smap, false, pos, fctx);
+                    new_methods.push(self.tc_objs.new_func(mpos, pkg, name, Some(sig)));
+                }
+                let mut new_embeddeds = Vec::with_capacity(embeddeds.len());
+                for e in embeddeds.into_iter() {
+                    new_embeddeds.push(self.subst(e, smap, pos, fctx));
+                }
+                for line in type_set.lines.iter_mut() {
+                    for term in line.iter_mut() {
+                        term.1 = self.subst(term.1, smap, pos, fctx);
+                    }
+                }
+                let iface = self.tc_objs.new_t_interface(new_methods, new_embeddeds);
+                let iface_val = self.otype_interface_mut(iface);
+                *iface_val.type_set_mut() = type_set;
+                self.otype_interface(iface).complete(self.tc_objs);
+                iface
+            }
+            Type::Named(d) => {
+                let targs = match d.orig() {
+                    Some(_) => d.targs().clone(),
+                    None => self.tparam_types(d.tparams().unwrap()),
+                };
+                let orig = d.orig().unwrap_or(t);
+                let mut new_targs = Vec::with_capacity(targs.len());
+                for ta in targs.into_iter() {
+                    new_targs.push(self.subst(ta, smap, pos, fctx));
+                }
+                self.instantiate_type(orig, new_targs, pos, fctx)
+            }
+            Type::Basic(_) | Type::TypeParam(_) => t,
+        }
+    }
+
+    fn subst_var(
+        &mut self,
+        okey: ObjKey,
+        smap: &SubstMap,
+        pos: Pos,
+        fctx: &mut FilesContext,
+    ) -> ObjKey {
+        let lobj = self.lobj(okey);
+        let (vpos, pkg, name, t) = (lobj.pos(), lobj.pkg(), lobj.name().clone(), lobj.typ());
+        let is_field = lobj.var_is_field();
+        let embedded = is_field && lobj.var_embedded();
+        let t = t.map(|t| self.subst(t, smap, pos, fctx));
+        if is_field {
+            self.tc_objs.new_field(vpos, pkg, name, t, embedded)
+        } else {
+            self.tc_objs.new_param_var(vpos, pkg, name, t)
+        }
+    }
+
+    /// subst_sig returns a copy of the signature with the type parameters
+    /// replaced, the result is not generic.
+    fn subst_sig(
+        &mut self,
+        sig: TypeKey,
+        smap: &SubstMap,
+        subst_recv: bool,
+        pos: Pos,
+        fctx: &mut FilesContext,
+    ) -> TypeKey {
+        let s = self.otype_signature(sig);
+        let (scope, recv, params, results, variadic) =
+            (s.scope(), *s.recv(), s.params(), s.results(), s.variadic());
+        let recv = match recv {
+            Some(r) if subst_recv => Some(self.subst_var(r, smap, pos, fctx)),
+            _ => recv,
+        };
+        let params = self.subst(params, smap, pos, fctx);
+        let results = self.subst(results, smap, pos, fctx);
+        self.tc_objs
+            .new_t_signature(scope, recv, params, results, variadic)
+    }
+
+    /// instantiated_type checks the type arguments of the generic type denoted
+    /// by x and returns the instance.
+    pub fn instantiated_type(
+        &mut self,
+        x: &Operand,
+        indices: &[Expr],
+        pos: Pos,
+        fctx: &mut FilesContext,
+    ) -> Option<TypeKey> {
+        match x.mode {
+            OperandMode::Invalid => {
+                self.use_exprs(&indices.to_vec(), fctx);
+                return None;
+            }
+            OperandMode::TypeExpr => {}
+            _ => {
+                let xd = self.new_dis(x);
+                self.error(xd.pos(), format!("{} is not a type", xd));
+                return None;
+            }
+        }
+        let t = x.typ.unwrap();
+        let tparams = self
+            .otype(t)
+            .try_as_named()
+            .filter(|n| n.orig().is_none())
+            .and_then(|n| n.tparams());
+        let tps = match tparams {
+            Some(tp) => self.tparam_types(tp),
+            None => {
+                let xd = self.new_dis(x);
+                self.error(xd.pos(), format!("{} is not a generic type", xd));
+                return None;
+            }
+        };
+        let mut targs = Vec::with_capacity(indices.len());
+        for e in indices.iter() {
+            targs.push(self.type_expr(e, fctx));
+        }
+        if targs.iter().any(|t| *t == self.invalid_type()) {
+            return None;
+        }
+        if targs.len() != tps.len() {
+            let td = self.new_dis(&t);
+            self.error(
+                pos,
+                format!(
+                    "got {} type arguments but {} has {} type parameters",
+                    targs.len(),
+                    td,
+                    tps.len()
+                ),
+            );
+            return None;
+        }
+        Some(self.instantiate_type(t, targs, pos, fctx))
+    }
+
+    /// instantiate_type returns the instance of the generic type orig with
+    /// the type arguments targs, instances with identical type arguments
+    /// are shared.
+    pub fn instantiate_type(
+        &mut self,
+        orig: TypeKey,
+        targs: Vec<TypeKey>,
+        pos: Pos,
+        fctx: &mut FilesContext,
+    ) -> TypeKey {
+        let named = self.otype(orig).try_as_named().unwrap();
+        let tps = self.tparam_types(named.tparams().unwrap());
+        // a generic type instantiated with its own type parameters, as in the
+        // receiver of its methods, is the generic type itself
+        if tps == targs {
+            return orig;
+        }
+        let objs = &*self.tc_objs;
+        let existing = named.instances().iter().find(|i| {
+            let ta = objs.types[**i].try_as_named().unwrap().targs();
+            ta.len() == targs.len()
+                && ta
+                    .iter()
+                    .zip(targs.iter())
+                    .all(|(a, b)| typ::identical(*a, *b, objs))
+        });
+        if let Some(inst) = existing {
+            return *inst;
+        }
+
+        let obj = *named.obj();
+        let inst = self.tc_objs.new_t_named(obj, None, vec![]);
+        self.tc_objs.types[inst]
+            .try_as_named_mut()
+            .unwrap()
+            .set_instance_of(orig, targs.clone());
+        self.tc_objs.types[orig]
+            .try_as_named_mut()
+            .unwrap()
+            .instances_mut()
+            .push(inst);
+
+        // the generic type may still be being set up, in that case the instance
+        // is completed later, as are the instances it refers to
+        let complete = move |checker: &mut Checker, fctx: &mut FilesContext| {
+            checker.complete_instance(inst, pos, fctx);
+        };
+        let ready = self
+            .otype(orig)
+            .try_as_named()
+            .unwrap()
+            .try_underlying()
+            .map_or(false, |u| self.otype(u).try_as_named().is_none());
+        if ready && self.lobj(obj.unwrap()).color() == super::super::obj::ObjColor::Black {
+            complete(self, fctx);
+        } else {
+            fctx.later(Box::new(complete));
+        }
+
+        self.verify_targs(tps, targs, pos, fctx);
+        inst
+    }
+
+    fn complete_instance(&mut self, inst: TypeKey, pos: Pos, fctx: &mut FilesContext) {
+        let named = self.otype(inst).try_as_named().unwrap();
+        if named.try_underlying().is_none() {
+            let orig = named.orig().unwrap();
+            let smap = self.instance_subst_map(inst);
+            let u = self.otype(orig).try_as_named().unwrap().underlying();
+            let u = self.subst(u, &smap, pos, fctx);
+            self.tc_objs.types[inst]
+                .try_as_named_mut()
+                .unwrap()
+                .set_underlying(u);
+        }
+        self.instantiate_methods(inst, pos, fctx);
+    }
+
+    fn instance_subst_map(&self, inst: TypeKey) -> SubstMap {
+        let named = self.otype(inst).try_as_named().unwrap();
+        let orig = self.otype(named.orig().unwrap()).try_as_named().unwrap();
+        let tps = self.tparam_types(orig.tparams().unwrap());
+        tps.into_iter().zip(named.targs().iter().cloned()).collect()
+    }
+
+    /// instantiate_methods adds the methods of the generic type that are
+    /// not yet present to the instance, a method is checked again with the
+    /// type arguments if they are concrete.
+    pub fn instantiate_methods(&mut self, inst: TypeKey, pos: Pos, fctx: &mut FilesContext) {
+        let named = self.otype(inst).try_as_named().unwrap();
+        if named.try_underlying().is_none() {
+            return; // completed later
+        }
+        let orig = named.orig().unwrap();
+        let targs = named.targs().clone();
+        let existing: Vec<String> = named
+            .methods()
+            .iter()
+            .map(|m| self.lobj(*m).name().clone())
+            .collect();
+        let methods = self.otype(orig).try_as_named().unwrap().methods().clone();
+        let smap = self.instance_subst_map(inst);
+        let concrete = !targs.iter().any(|t| self.has_type_param(*t));
+        for m in methods.into_iter() {
+            if existing.contains(self.lobj(m).name()) {
+                continue;
+            }
+            if self.lobj(m).typ().is_none() {
+                self.obj_decl(m, None, fctx);
+            }
+            let mobj = self.lobj(m);
+            let (mpos, pkg, name) = (mobj.pos(), mobj.pkg(), mobj.name().clone());
+            let ptr_recv = mobj.entity_type().func_has_ptr_recv();
+            let sig = mobj.typ().unwrap();
+            if self.otype(sig).try_as_signature().is_none() {
+                continue; // invalid
+            }
+            let sig = self.subst_sig(sig, &smap, true, pos, fctx);
+            let okey = self.tc_objs.new_func(mpos, pkg, name, Some(sig));
+            self.lobj_mut(okey)
+                .entity_type_mut()
+                .func_set_has_ptr_recv(ptr_recv);
+            self.tc_objs.types[inst]
+                .try_as_named_mut()
+                .unwrap()
+                .methods_mut()
+                .push(okey);
+            if concrete {
+                self.request_instance(okey, m, targs.clone());
+            }
+        }
+    }
+
+    /// instantiate_generic_methods adds the methods declared so far to
+    /// the instances of the generic type created while declaring it.
+    pub fn instantiate_generic_methods(&mut self, t: TypeKey, fctx: &mut FilesContext) {
+        let instances = match self.otype(t).try_as_named() {
+            Some(n) if n.tparams().is_some() => n.instances().clone(),
+            _ => return,
+        };
+        for inst in instances.into_iter() {
+            let pos = self
+                .lobj(
+                    *self
+                        .otype(t)
+                        .try_as_named()
+                        .unwrap()
+                        .obj()
+                        .as_ref()
+                        .unwrap(),
+                )
+                .pos();
+            let f = move |checker: &mut Checker, fctx: &mut FilesContext| {
+                checker.complete_instance(inst, pos, fctx);
+            };
+            fctx.later(Box::new(f));
+        }
+    }
+
+    /// instantiate_func records the instantiation of the generic function obj
+    /// denoted by identifier ikey and returns the signature of the instance.
+    pub fn instantiate_func(
+        &mut self,
+        ikey: IdentKey,
+        obj: ObjKey,
+        targs: Vec<TypeKey>,
+        pos: Pos,
+        fctx: &mut FilesContext,
+    ) -> TypeKey {
+        let sig = self.lobj(obj).typ().unwrap();
+        let tps = self.tparam_types(self.otype_signature(sig).tparams().unwrap());
+        let smap: SubstMap = tps.iter().cloned().zip(targs.iter().cloned()).collect();
+        let inst_sig = self.subst_sig(sig, &smap, false, pos, fctx);
+        self.result.instances.insert(
+            ikey,
+            Instance {
+                targs: targs.clone(),
+                typ: inst_sig,
+            },
+        );
+        if !targs.iter().any(|t| self.has_type_param(*t)) {
+            self.request_instance(obj, obj, targs.clone());
+        }
+        self.verify_targs(tps, targs, pos, fctx);
+        inst_sig
+    }
+
+    /// func_instance handles the explicit instantiation of the generic
+    /// function x, if not all type arguments are provided, the rest is
+    /// inferred when x is called.
+    pub fn func_instance(
+        &mut self,
+        x: &mut Operand,
+        base: &Expr,
+        indices: &[Expr],
+        pos: Pos,
+        fctx: &mut FilesContext,
+    ) {
+        let sig = x.typ.unwrap();
+        let tps = self.tparam_types(self.otype_signature(sig).tparams().unwrap());
+        let mut targs = Vec::with_capacity(indices.len());
+        for e in indices.iter() {
+            targs.push(self.type_expr(e, fctx));
+        }
+        if targs.iter().any(|t| *t == self.invalid_type()) {
+            x.mode = OperandMode::Invalid;
+            return;
+        }
+        if targs.len() > tps.len() {
+            let xd = self.new_dis(x);
+            self.error(
+                pos,
+                format!(
+                    "got {} type arguments but {} has {} type parameters",
+                    targs.len(),
+                    xd,
+                    tps.len()
+                ),
+            );
+            x.mode = OperandMode::Invalid;
+            return;
+        }
+        let ikey = Checker::instance_ident(base).unwrap();
+        let obj = self.result.uses[&ikey];
+        if targs.len() < tps.len() {
+            // partial instantiation, the signature stays generic
+            let inst = Instance {
+                targs: targs,
+                typ: sig,
+            };
+            self.result.instances.insert(ikey, inst);
+        } else {
+            x.typ = Some(self.instantiate_func(ikey, obj, targs, pos, fctx));
+        }
+    }
+
+    /// instance_ident returns the identifier denoting the generic function
+    /// in expression e.
+    pub fn instance_ident(e: &Expr) -> Option<IdentKey> {
+        match e {
+            Expr::Ident(i) => Some(*i),
+            Expr::Selector(s) => Some(s.sel),
+            Expr::Paren(p) => Checker::instance_ident(&p.expr),
+            Expr::Index(ie) => Checker::instance_ident(&ie.expr),
+            Expr::IndexList(il) => Checker::instance_ident(&il.expr),
+            _ => None,
+        }
+    }
+
+    /// infer infers the type arguments of a call of the generic function with
+    /// signature sig from the arguments, the first type arguments may be given.
+    pub fn infer(
+        &mut self,
+        sig: TypeKey,
+        given: &[TypeKey],
+        args: &[Operand],
+        ellipsis: bool,
+        pos: Pos,
+    ) -> Option<Vec<TypeKey>> {
+        let sig_val = self.otype_signature(sig);
+        let tps = self.tparam_types(sig_val.tparams().unwrap());
+        let variadic = sig_val.variadic();
+        let params: Vec<TypeKey> = self
+            .otype(sig_val.params())
+            .try_as_tuple()
+            .unwrap()
+            .vars()
+            .iter()
+            .map(|v| self.lobj(*v).typ().unwrap())
+            .collect();
+        let mut inferred: Vec<Option<TypeKey>> =
+            (0..tps.len()).map(|i| given.get(i).cloned()).collect();
+
+        let param_type = |checker: &Checker, i: usize| -> Option<TypeKey> {
+            let n = params.len();
+            if variadic && i + 1 >= n && !(ellipsis && i + 1 == n) {
+                let last = params[n - 1];
+                checker.otype(last).try_as_slice().map(|s| s.elem())
+            } else {
+                params.get(i).cloned()
+            }
+        };
+
+        // typed arguments first
+        for (i, a) in args.iter().enumerate() {
+            if a.invalid() {
+                return None;
+            }
+            let (pt, at) = match (param_type(self, i), a.typ) {
+                (Some(pt), Some(at)) => (pt, at),
+                _ => continue, // argument count is checked later
+            };
+            if typ::is_untyped(at, self.tc_objs) {
+                continue;
+            }
+            if !self.unify(pt, at, &tps, &mut inferred) {
+                let (ad, ptd, atd) = (self.new_dis(a), self.new_dis(&pt), self.new_dis(&at));
+                self.error(
+                    ad.pos(),
+                    format!("type {} of {} does not match {}", atd, ad, ptd),
+                );
+                return None;
+            }
+        }
+        // then untyped constants with their default types
+        for (i, a) in args.iter().enumerate() {
+            let (pt, at) = match (param_type(self, i), a.typ) {
+                (Some(pt), Some(at)) => (pt, at),
+                _ => continue,
+            };
+            if !typ::is_untyped(at, self.tc_objs) {
+                continue;
+            }
+            if let Some(j) = tps.iter().position(|t| *t == pt) {
+                if inferred[j].is_none() {
+                    inferred[j] = Some(typ::untyped_default_type(at, self.tc_objs));
+                }
+            }
+        }
+        // then the core types of the constraints, as in '[S ~[]E, E any]'
+        loop {
+            let before = inferred.iter().filter(|t| t.is_some()).count();
+            for i in 0..tps.len() {
+                let (tilde, core) = match self.single_term(tps[i]) {
+                    Some(term) => term,
+                    None => continue,
+                };
+                if let Some(t) = inferred[i] {
+                    let t = if tilde {
+                        typ::underlying_type(t, self.tc_objs)
+                    } else {
+                        t
+                    };
+                    if !self.unify(core, t, &tps, &mut inferred) {
+                        return None; // reported when verifying the type arguments
+                    }
+                }
+            }
+            if inferred.iter().filter(|t| t.is_some()).count() == before {
+                break;
+            }
+        }
+
+        let mut targs = Vec::with_capacity(tps.len());
+        for (i, t) in inferred.into_iter().enumerate() {
+            match t {
+                Some(t) => targs.push(t),
+                None => {
+                    let td = self.new_dis(&tps[i]);
+                    self.error(pos, format!("cannot infer {}", td));
+                    return None;
+                }
+            }
+        }
+        Some(targs)
+    }
+
+    fn single_term(&self, tp: TypeKey) -> Option<(bool, TypeKey)> {
+        let bound = self
+            .otype(tp)
+            .try_as_type_param()?
+            .bound_val(self.tc_objs)?;
+        match &bound.type_set().lines[..] {
+            [line] if line.len() == 1 => Some(line[0]),
+            _ => None,
+        }
+    }
+
+    /// unify unifies the parameter type x with the argument type y, recording the
+    /// types of the type parameters tps in inferred. It's lenient on types without
+    /// type parameters, those are checked by assignment later.
+    fn unify(
+        &self,
+        x: TypeKey,
+        y: TypeKey,
+        tps: &[TypeKey],
+        inferred: &mut Vec<Option<TypeKey>>,
+    ) -> bool {
+        if let Some(i) = tps.iter().position(|t| *t == x) {
+            return match inferred[i] {
+                Some(t) => typ::identical(t, y, self.tc_objs),
+                None => {
+                    inferred[i] = Some(y);
+                    true
+                }
+            };
+        }
+        if !self.has_type_param(x) {
+            return true;
+        }
+        let vars_unify =
+            |xs: &Vec<ObjKey>, ys: &Vec<ObjKey>, inferred: &mut Vec<Option<TypeKey>>| {
+                xs.len() == ys.len()
+                    && xs.iter().zip(ys.iter()).all(|(a, b)| {
+                        let (at, bt) = (self.lobj(*a).typ().unwrap(), self.lobj(*b).typ().unwrap());
+                        self.unify(at, bt, tps, inferred)
+                    })
+            };
+        match (self.otype(x), self.otype(y)) {
+            (Type::Named(nx), Type::Named(ny)) => {
+                let ox = nx.orig().unwrap_or(x);
+                let oy = ny.orig().unwrap_or(y);
+                ox == oy
+                    && nx.targs().len() == ny.targs().len()
+                    && nx
+                        .targs()
+                        .iter()
+                        .zip(ny.targs().iter())
+                        .all(|(a, b)| self.unify(*a, *b, tps, inferred))
+            }
+            (_, Type::Named(_)) => {
+                self.unify(x, typ::underlying_type(y, self.tc_objs), tps, inferred)
+            }
+            (Type::Array(ax), Type::Array(ay)) => {
+                ax.len() == ay.len() && self.unify(ax.elem(), ay.elem(), tps, inferred)
+            }
+            (Type::Slice(sx), Type::Slice(sy)) => self.unify(sx.elem(), sy.elem(), tps, inferred),
+            (Type::Pointer(px), Type::Pointer(py)) => {
+                self.unify(px.base(), py.base(), tps, inferred)
+            }
+            (Type::Map(mx), Type::Map(my)) => {
+                self.unify(mx.key(), my.key(), tps, inferred)
+                    && self.unify(mx.elem(), my.elem(), tps, inferred)
+            }
+            (Type::Chan(cx), Type::Chan(cy)) => self.unify(cx.elem(), cy.elem(), tps, inferred),
+            (Type::Tuple(tx), Type::Tuple(ty)) => vars_unify(tx.vars(), ty.vars(), inferred),
+            (Type::Struct(sx), Type::Struct(sy)) => vars_unify(sx.fields(), sy.fields(), inferred),
+            (Type::Signature(sx), Type::Signature(sy)) => {
+                sx.variadic() == sy.variadic()
+                    && self.unify(sx.params(), sy.params(), tps, inferred)
+                    && self.unify(sx.results(), sy.results(), tps, inferred)
+            }
+            _ => typ::identical(x, y, self.tc_objs),
+        }
+    }
+
+    /// verify_targs checks, once all types are set up, that the type arguments
+    /// satisfy the constraints of the type parameters.
+    fn verify_targs(
+        &mut self,
+        tps: Vec<TypeKey>,
+        targs: Vec<TypeKey>,
+        pos: Pos,
+        fctx: &mut FilesContext,
+    ) {
+        let f = move |checker: &mut Checker, _: &mut FilesContext| {
+            for (tp, ta) in tps.iter().zip(targs.iter()) {
+                if let Some(msg) = checker.satisfies(*ta, *tp) {
+                    checker.error(pos, msg);
+                    return;
+                }
+            }
+        };
+        fctx.later(Box::new(f));
+    }
+
+    /// satisfies checks that type argument targ satisfies the constraint of the
+    /// type parameter tp, it returns the error message if it does not.
+    fn satisfies(&self, targ: TypeKey, tp: TypeKey) -> Option<String> {
+        let bound = self.otype(tp).try_as_type_param()?.bound()?;
+        let iface_t = typ::underlying_type(bound, self.tc_objs);
+        let iface = self.otype(iface_t).try_as_interface()?;
+        let ts = iface.type_set();
+        let not_satisfied = |msg: String| {
+            Some(format!(
+                "{} does not satisfy {}{}",
+                self.new_dis(&targ),
+                self.new_dis(&bound),
+                msg
+            ))
+        };
+        if !ts.lines.is_empty() {
+            let included = match self.otype(targ).try_as_type_param() {
+                // all types of the type set of the type argument must be included
+                Some(p) => p.bound_val(self.tc_objs).map_or(false, |b| {
+                    b.type_set().lines.iter().any(|line| {
+                        line.iter()
+                            .all(|(tilde, t)| ts.includes_term(*tilde, *t, self.tc_objs))
+                    })
+                }),
+                None => ts.includes(targ, self.tc_objs),
+            };
+            if !included {
+                return not_satisfied(String::new());
+            }
+        }
+        if ts.comparable && !typ::comparable(targ, self.tc_objs) {
+            return not_satisfied(String::new());
+        }
+        if iface.all_methods().is_some() {
+            if let Some((m, _)) = lookup::missing_method(targ, iface_t, true, self.tc_objs) {
+                let name = self.lobj(m).name();
+                return not_satisfied(format!(" (missing method {})", name));
+            }
+        }
+        None
+    }
+
+    fn request_instance(&mut self, obj: ObjKey, orig: ObjKey, targs: Vec<TypeKey>) {
+        let objs = &*self.tc_objs;
+        let same = |o: ObjKey, ta: &Vec<TypeKey>| {
+            o == obj
+                && ta.len() == targs.len()
+                && ta
+                    .iter()
+                    .zip(targs.iter())
+                    .all(|(a, b)| typ::identical(*a, *b, objs))
+        };
+        if self.instance_reqs.iter().any(|r| same(r.obj, &r.targs))
+            || self
+                .all_results
+                .values()
+                .any(|ti| ti.func_instances.iter().any(|fi| same(fi.obj, &fi.targs)))
+        {
+            return;
+        }
+        self.instance_reqs.push(InstanceRequest {
+            obj: obj,
+            orig: orig,
+            targs: targs,
+        });
+    }
+
+    fn generic_decl(&self, okey: ObjKey) -> DeclInfoKey {
+        match self.obj_map.get(&okey) {
+            Some(d) => *d,
+            None => {
+                let pkg = self.lobj(okey).pkg().unwrap();
+                self.all_results[&pkg].generic_decls[&okey]
+            }
+        }
+    }
+
+    /// check_instances checks the bodies of the requested instances with the
+    /// type parameters bound to the type arguments, so that they can be
+    /// generated like any other function. Instances are only checked if the
+    /// package has no errors.
+    pub fn check_instances(&mut self) {
+        let mut i = 0;
+        while i < self.instance_reqs.len() && self.errors().len() == 0 {
+            let req = self.instance_reqs[i].clone();
+            self.check_instance(req);
+            i += 1;
+        }
+    }
+
+    fn check_instance(&mut self, req: InstanceRequest) {
+        let dkey = self.generic_decl(req.orig);
+        let (file_scope, fdecl_key) = {
+            let d = self.tc_objs.decls[dkey].as_func();
+            (d.file_scope, d.fdecl)
+        };
+        let fdecl = &self.ast_objs.fdecls[fdecl_key];
+        let (recv, ftype) = (fdecl.recv.clone(), fdecl.typ);
+        let name = self.lobj(req.orig).name().clone();
+
+        // check the declaration as part of its own package
+        let pkg = self.lobj(req.orig).pkg().unwrap();
+        let saved_pkg = std::mem::replace(&mut self.pkg, pkg);
+        let saved_result = std::mem::replace(&mut self.result, TypeInfo::new());
+        let mut octx = ObjContext::new();
+        octx.scope = Some(file_scope);
+        let saved_octx = std::mem::replace(&mut self.octx, octx);
+
+        let files = vec![];
+        let fctx = &mut FilesContext::new(&files);
+        let sig = self.func_type_impl(recv.as_ref(), ftype, Some(&req.targs), fctx);
+        self.func_body(
+            Some(dkey),
+            &name,
+            sig,
+            BodyContainer::FuncDecl(fdecl_key),
+            None,
+            fctx,
+        );
+        fctx.process_delayed(0, self);
+        self.record_untyped(fctx);
+
+        let info = std::mem::replace(&mut self.result, saved_result);
+        self.octx = saved_octx;
+        self.pkg = saved_pkg;
+        self.func_instances.push(FuncInstance {
+            obj: req.obj,
+            targs: req.targs,
+            decl: fdecl_key,
+            sig: sig,
+            info: info,
+        });
+    }
+}
@@ -9,6 +9,7 @@ mod conversion;
 mod decl;
 mod expr;
 mod initorder;
+mod instantiate;
 mod interface;
 mod label;
 mod resolver;
@@ -17,6 +18,6 @@ mod stmt;
 mod typexpr;
 
 pub use check::Checker;
-pub use check::TypeInfo;
+pub use check::{FuncInstance, Instance, TypeInfo};
 pub use interface::{IfaceInfo, MethodInfo};
 pub use resolver::DeclInfo;
@@ -4,7 +4,7 @@ use super::super::importer::ImportKey;
 use super::super::obj::EntityType;
 use super::super::objects::{DeclInfoKey, ObjKey, PackageKey, ScopeKey};
 use super::check::{Checker, FilesContext};
-use goscript_parser::ast::{self, Expr, Node};
+use goscript_parser::ast::{self, Expr, FieldList, Node};
 use goscript_parser::objects::IdentKey;
 use goscript_parser::objects::{FuncDeclKey, Objects as AstObjects};
 use goscript_parser::{Pos, Token};
@@ -29,9 +29,10 @@ pub struct DeclInfoVar {
 
 #[derive(Debug)]
 pub struct DeclInfoType {
-    pub file_scope: ScopeKey, // scope of file containing this declaration
-    pub typ: Expr,            // type
-    pub alias: bool,          // type alias declaration
+    pub file_scope: ScopeKey,       // scope of file containing this declaration
+    pub typ: Expr,                  // type
+    pub alias: bool,                // type alias declaration
+    pub tparams: Option<FieldList>, // type parameters of a generic type, or None
 }
 
 #[derive(Debug)]
@@ -75,11 +76,17 @@ impl DeclInfo {
         })
     }
 
-    pub fn new_type(file_scope: ScopeKey, typ: Expr, alias: bool) -> DeclInfo {
+    pub fn new_type(
+        file_scope: ScopeKey,
+        typ: Expr,
+        alias: bool,
+        tparams: Option<FieldList>,
+    ) -> DeclInfo {
         DeclInfo::Type(DeclInfoType {
             file_scope: file_scope,
             typ: typ,
             alias: alias,
+            tparams: tparams,
         })
     }
 
@@ -388,6 +395,7 @@ impl<'a> Checker<'a> {
                                         file_scope,
                                         tspec.typ.clone(),
                                         tspec.assign > 0,
+                                        tspec.type_params.clone(),
                                     ));
                                     let _ = self.declare_pkg_obj(tspec.name, okey, di);
                                 }
@@ -641,6 +649,12 @@ impl<'a> Checker<'a> {
                 ptr = true;
                 typ = Checker::unparen(&t.expr);
             }
+            // strip the type arguments of a generic receiver type
+            typ = match typ {
+                Expr::Index(ie) => &ie.expr,
+                Expr::IndexList(ie) => &ie.expr,
+                _ => typ,
+            };
 
             // typ must be the name
             if let Expr::Ident(i) = typ {
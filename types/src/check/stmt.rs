@@ -56,7 +56,7 @@ impl GoVal {
                     _ => GoVal::Invalid,
                 },
             },
-            Value::Float(_) => match v.num_as_f64() {
+            Value::Rat(_) => match v.num_as_f64() {
                 (f, true) => GoVal::Float64(f),
                 _ => GoVal::Invalid,
             },
@@ -61,10 +61,9 @@ impl<'a> Checker<'a> {
             // (This code is only needed for dot-imports. Without them,
             // we only have to mark variables, see Var case below).
             if pkg.is_some() && pkg != Some(self.pkg) {
-                fctx.unused_dot_imports
-                    .get_mut(&skey)
-                    .unwrap()
-                    .remove(&pkg.unwrap());
+                if let Some(imports) = fctx.unused_dot_imports.get_mut(&skey) {
+                    imports.remove(&pkg.unwrap());
+                }
             }
 
             let lobj = self.lobj(okey);
@@ -169,23 +168,79 @@ impl<'a> Checker<'a> {
         t
     }
 
+    /// valid_var_type reports an error if t, the type of e, is a constraint interface,
+    /// which may only be used as the bound of type parameters.
+    pub fn valid_var_type(&mut self, e: &Expr, t: TypeKey, fctx: &mut FilesContext) {
+        let pos = e.pos(self.ast_objs);
+        let f = move |checker: &mut Checker, _: &mut FilesContext| {
+            let iface = match checker.otype(t).underlying_val(checker.tc_objs) {
+                Type::Interface(i) => i,
+                _ => return,
+            };
+            if !iface.type_set().is_all() {
+                let td = checker.new_dis(&t);
+                checker.error(
+                    pos,
+                    format!(
+                        "cannot use type {} outside a type constraint: interface contains type constraints",
+                        td
+                    ),
+                );
+            }
+        };
+        fctx.later(Box::new(f));
+    }
+
     /// func_type type-checks a function or method type.
     pub fn func_type(
         &mut self,
         recv: Option<&FieldList>,
         ftype: FuncTypeKey,
         fctx: &mut FilesContext,
+    ) -> TypeKey {
+        self.func_type_impl(recv, ftype, None, fctx)
+    }
+
+    /// func_type_impl is func_type, if targs is_some(), the type parameters of
+    /// the function or of the receiver are bound to them instead of declared.
+    pub fn func_type_impl(
+        &mut self,
+        recv: Option<&FieldList>,
+        ftype: FuncTypeKey,
+        targs: Option<&Vec<TypeKey>>,
+        fctx: &mut FilesContext,
     ) -> TypeKey {
         let skey = self
             .tc_objs
             .new_scope(self.octx.scope, 0, 0, "function".to_string(), true);
         self.result.record_scope(&ftype, skey);
 
+        // the types of the parameters may refer to the type parameters,
+        // which are declared in the function scope
+        let saved_scope = self.octx.scope;
+        let mut tparams = None;
+        let ftype_val = &self.ast_objs.ftypes[ftype];
+        if let Some(tps) = ftype_val.type_params.clone() {
+            if recv.is_some() {
+                let pos = tps.pos(self.ast_objs);
+                self.error_str(pos, "methods cannot have type parameters");
+            } else {
+                tparams = self.func_type_params(skey, &tps, targs, fctx);
+                self.octx.scope = Some(skey);
+            }
+        }
+        if let Some(r) = recv {
+            if self.recv_type_params(skey, r, targs, fctx) {
+                self.octx.scope = Some(skey);
+            }
+        }
+
         let (recv_list, _) = self.collect_params(skey, recv, false, fctx);
         let ftype_val = &self.ast_objs.ftypes[ftype];
         let (p, r) = (ftype_val.params.clone(), ftype_val.results.clone());
         let (params, variadic) = self.collect_params(skey, Some(&p), true, fctx);
         let (results, _) = self.collect_params(skey, r.as_ref(), false, fctx);
+        self.octx.scope = saved_scope;
 
         let mut recv_okey = None;
         if recv.is_some() {
@@ -251,8 +306,15 @@ impl<'a> Checker<'a> {
 
         let params_tuple = self.tc_objs.new_t_tuple(params);
         let results_tuple = self.tc_objs.new_t_tuple(results);
-        self.tc_objs
-            .new_t_signature(Some(skey), recv_okey, params_tuple, results_tuple, variadic)
+        let sig = self.tc_objs.new_t_signature(
+            Some(skey),
+            recv_okey,
+            params_tuple,
+            results_tuple,
+            variadic,
+        );
+        self.otype_signature_mut(sig).set_tparams(tparams);
+        sig
     }
 
     /// type_internal drives type checking of types.
@@ -278,6 +340,10 @@ impl<'a> Checker<'a> {
                 let mut x = Operand::new();
                 self.ident(&mut x, *i, def, true, fctx);
                 match x.mode {
+                    OperandMode::TypeExpr if self.is_generic_type(x.typ.unwrap()) => {
+                        error_operand!(x, "cannot use generic type {} without instantiation", self);
+                        None
+                    }
                     OperandMode::TypeExpr => {
                         set_underlying(x.typ, self.tc_objs);
                         x.typ
@@ -297,6 +363,10 @@ impl<'a> Checker<'a> {
                 let mut x = Operand::new();
                 self.selector(&mut x, s, fctx);
                 match x.mode {
+                    OperandMode::TypeExpr if self.is_generic_type(x.typ.unwrap()) => {
+                        error_operand!(x, "cannot use generic type {} without instantiation", self);
+                        None
+                    }
                     OperandMode::TypeExpr => {
                         set_underlying(x.typ, self.tc_objs);
                         x.typ
@@ -312,6 +382,25 @@ impl<'a> Checker<'a> {
                     }
                 }
             }
+            Expr::Index(ie) => {
+                let mut x = Operand::new();
+                self.expr_or_type(&mut x, &ie.expr, fctx);
+                let indices = [ie.index.clone()];
+                let t = self.instantiated_type(&x, &indices, pos, fctx);
+                if t.is_some() {
+                    set_underlying(t, self.tc_objs);
+                }
+                t
+            }
+            Expr::IndexList(il) => {
+                let mut x = Operand::new();
+                self.expr_or_type(&mut x, &il.expr, fctx);
+                let t = self.instantiated_type(&x, &il.indices, pos, fctx);
+                if t.is_some() {
+                    set_underlying(t, self.tc_objs);
+                }
+                t
+            }
             Expr::Paren(p) => Some(self.defined_type(&p.expr, def, fctx)),
             Expr::Array(a) => {
                 if let Some(l) = &a.len {
@@ -476,6 +565,7 @@ impl<'a> Checker<'a> {
                 }
                 let ftype = &ftype.clone();
                 let ty = self.indirect_type(ftype, fctx);
+                self.valid_var_type(ftype, ty, fctx);
                 // The parser ensures that f.Tag is nil and we don't
                 // care if a constructed AST contains a non-nil tag.
                 if field_names.len() > 0 {
@@ -549,6 +639,23 @@ impl<'a> Checker<'a> {
         }
 
         let itype = self.tc_objs.new_t_interface(vec![], vec![]);
+        // collect the type set of a constraint interface right away, type
+        // parameters constrained by it may be used before delayed actions run
+        let mut terms = vec![];
+        for fkey in iface.methods.list.iter() {
+            let field = &self.ast_objs.fields[*fkey];
+            if field.names.len() == 0 && self.is_type_term(&field.typ) {
+                terms.push(*fkey);
+            }
+        }
+        for fkey in terms.iter() {
+            let texpr = self.ast_objs.fields[*fkey].typ.clone();
+            let line = self.type_set_line(&texpr, fctx);
+            self.otype_interface_mut(itype)
+                .type_set_mut()
+                .lines
+                .push(line);
+        }
         // collect embedded interfaces
         // Only needed for printing and API. Delay collection
         // to end of type-checking (for package-global interfaces)
@@ -570,7 +677,7 @@ impl<'a> Checker<'a> {
             let mut embeds = vec![];
             for fkey in iface_clone.methods.list.iter() {
                 let field = &checker.ast_objs.fields[*fkey];
-                if field.names.len() == 0 {
+                if field.names.len() == 0 && !terms.contains(fkey) {
                     let texpr = field.typ.clone();
                     let ty = checker.indirect_type(&texpr, fctx);
                     // ty should be a named type denoting an interface
@@ -583,11 +690,24 @@ impl<'a> Checker<'a> {
                         typ::Type::Interface(embed) => {
                             // Correct embedded interfaces must be complete
                             assert!(embed.all_methods().is_some());
+                            let type_set = embed.type_set().clone();
+                            let ts = checker.otype_interface_mut(itype).type_set_mut();
+                            ts.lines.extend(type_set.lines);
+                            ts.comparable |= type_set.comparable;
                         }
-                        _ => {
+                        typ::Type::TypeParam(_) => {
                             let pos = texpr.pos(checker.ast_objs);
                             let td = checker.new_dis(&ty);
-                            checker.error(pos, format!("{} is not an interface", td));
+                            checker.error(pos, format!("cannot embed a type parameter {}", td));
+                            continue;
+                        }
+                        _ => {
+                            // a defined type as the single term of the type set
+                            checker
+                                .otype_interface_mut(itype)
+                                .type_set_mut()
+                                .lines
+                                .push(vec![(false, ty)]);
                             continue;
                         }
                     }
@@ -830,6 +950,7 @@ impl<'a> Checker<'a> {
             let ftag = self.tag(&field.tag);
             let ftype = field.typ.clone();
             let ty = self.type_expr(&ftype, fctx);
+            self.valid_var_type(&ftype, ty, fctx);
             if fnames.len() > 0 {
                 // named fields
                 for name in fnames.iter() {
@@ -5,15 +5,21 @@ use num_rational::BigRational;
 use num_traits::cast::FromPrimitive;
 use num_traits::cast::ToPrimitive;
 use num_traits::sign::Signed;
-use num_traits::Num;
+use num_traits::{Num, One, Zero};
 use ordered_float;
 use std::borrow::Borrow;
 use std::borrow::Cow;
+use std::f64::consts::LOG10_2;
 use std::fmt;
 
 type F32 = ordered_float::OrderedFloat<f32>;
 type F64 = ordered_float::OrderedFloat<f64>;
 
+/// MAX_EXP bounds the binary exponent of the float values, the spec asks for
+/// at least 16 bits with the sign, the ones beyond are unknown. go/constant
+/// gives up on them too, only much later, and a float64 overflows long before.
+const MAX_EXP: u64 = 1 << 15;
+
 /// constant implements Values representing untyped
 /// Go constants and their corresponding operations.
 ///
@@ -22,10 +28,8 @@ type F64 = ordered_float::OrderedFloat<f64>;
 /// values produce unknown values unless specified
 /// otherwise.
 ///
-/// Because BigFloat library is not available at the moment(2020/5)
-/// float numbers arbitrary precision is not supported for now
-/// float numbers is simply represented as f64
-/// todo: This is against the Go specs.
+/// Float values are represented exactly as big rationals, they are
+/// rounded (to nearest even) only when converted to float32 or float64.
 
 /// All the values involved in the evaluation
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
@@ -35,7 +39,6 @@ pub enum Value {
     Str(String),
     Int(BigInt),
     Rat(BigRational),
-    Float(F64),
     Complex(Box<Value>, Box<Value>),
 }
 
@@ -60,11 +63,7 @@ impl fmt::Display for Value {
             }
             Value::Rat(r) => {
                 //f.write_str("rat: ")?;
-                r.fmt(f)
-            }
-            Value::Float(s) => {
-                //f.write_str("float: ")?;
-                s.fmt(f)
+                f.write_str(&rat_to_string(r))
             }
             Value::Complex(r, i) => {
                 //f.write_str("complex: ")?;
@@ -92,7 +91,10 @@ impl Value {
     }
 
     pub fn with_f64(f: f64) -> Value {
-        Value::Float(f.into())
+        match BigRational::from_f64(f) {
+            Some(r) => Value::Rat(r),
+            None => Value::Unknown,
+        }
     }
 
     pub fn with_literal(tok: &Token) -> Value {
@@ -102,7 +104,7 @@ impl Value {
             Token::IMAG(imlit) => {
                 let s = imlit.as_str();
                 let v = float_from_literal(&s[..(s.len() - 1)]);
-                if let Value::Float(_) = &v {
+                if let Value::Rat(_) = &v {
                     Value::Complex(Box::new(Value::with_f64(0.0)), Box::new(v))
                 } else {
                     Value::Unknown
@@ -136,19 +138,19 @@ impl Value {
         let float_representable =
             |val: &Value, btype: BasicType, rounded: Option<&mut Value>| -> bool {
                 match val.to_float() {
-                    Value::Float(f) => match btype {
-                        BasicType::Float64 => true,
-                        BasicType::Float32 => {
-                            let f32_ = *f as f32;
-                            let ok = !f32_.is_infinite();
-                            if let Some(r) = rounded {
-                                *r = Value::Float(((*f as f32) as f64).into());
-                            }
-                            ok
+                    Value::Rat(r) => {
+                        let (f, _) = match btype {
+                            BasicType::Float64 => rat_to_f64(&r),
+                            BasicType::Float32 => rat_to_f32(&r),
+                            BasicType::UntypedFloat => return true,
+                            _ => unreachable!(),
+                        };
+                        let ok = !f.is_infinite();
+                        if let (true, Some(rv)) = (ok, rounded) {
+                            *rv = Value::with_f64(f);
                         }
-                        BasicType::UntypedFloat => true,
-                        _ => unreachable!(),
-                    },
+                        ok
+                    }
                     _ => false,
                 }
             };
@@ -221,18 +223,6 @@ impl Value {
     }
 
     pub fn to_int(&self) -> Cow<Value> {
-        let f64_to_int = |x| -> Cow<Value> {
-            match BigRational::from_f64(x) {
-                Some(v) => {
-                    if v.is_integer() {
-                        Cow::Owned(Value::Int(v.to_integer()))
-                    } else {
-                        Cow::Owned(Value::Unknown)
-                    }
-                }
-                None => Cow::Owned(Value::Unknown),
-            }
-        };
         match self {
             Value::Int(_) => Cow::Borrowed(self),
             Value::Rat(r) => {
@@ -242,7 +232,6 @@ impl Value {
                     Cow::Owned(Value::Unknown)
                 }
             }
-            Value::Float(f) => f64_to_int(**f),
             Value::Complex(r, i) => {
                 let (ival, ok) = i.to_int().int_as_i64();
                 if ok && ival == 0 {
@@ -256,32 +245,24 @@ impl Value {
     }
 
     pub fn to_float(&self) -> Value {
-        let v = match self {
-            Value::Int(i) => i.to_f64(),
-            Value::Rat(r) => rat_to_f64(r),
-            Value::Float(f) => Some(**f),
+        match self {
+            Value::Int(i) => Value::Rat(BigRational::from_integer(i.clone())),
+            Value::Rat(_) => self.clone(),
             Value::Complex(r, i) => {
-                let (ival, ok) = i.to_float().num_as_f64();
-                if ok && ival == 0.0 {
-                    let (rval, ok) = r.to_float().num_as_f64();
-                    if ok {
-                        Some(*rval)
-                    } else {
-                        None
-                    }
+                if i.sign() == 0 {
+                    r.to_float()
                 } else {
-                    None
+                    Value::Unknown
                 }
             }
-            _ => None,
-        };
-        v.map_or(Value::Unknown, |x| Value::Float(x.into()))
+            _ => Value::Unknown,
+        }
     }
 
     pub fn to_complex(&self) -> Value {
         match self {
-            Value::Int(_) | Value::Rat(_) | Value::Float(_) => {
-                Value::Complex(Box::new(self.clone()), Box::new(Value::with_f64(0.0)))
+            Value::Int(_) | Value::Rat(_) => {
+                Value::Complex(Box::new(self.clone()), Box::new(Value::with_i64(0)))
             }
             Value::Complex(_, _) => self.clone(),
             _ => Value::Unknown,
@@ -293,8 +274,8 @@ impl Value {
     // If x is Unknown, the result is Unknown.
     pub fn make_imag(&self) -> Value {
         match self {
-            Value::Int(_) | Value::Float(_) | Value::Rat(_) => {
-                Value::Complex(Box::new(Value::with_f64(0.0)), Box::new(self.clone()))
+            Value::Int(_) | Value::Rat(_) => {
+                Value::Complex(Box::new(Value::with_i64(0)), Box::new(self.clone()))
             }
             Value::Unknown => Value::Unknown,
             _ => panic!("{} not Int or Float", self),
@@ -305,7 +286,7 @@ impl Value {
     /// If x is Unknown, the result is Unknown.
     pub fn real(&self) -> Value {
         match self {
-            Value::Int(_) | Value::Float(_) | Value::Rat(_) | Value::Unknown => self.clone(),
+            Value::Int(_) | Value::Rat(_) | Value::Unknown => self.clone(),
             Value::Complex(r, _) => *r.clone(),
             _ => panic!("{} not numeric", self),
         }
@@ -315,7 +296,7 @@ impl Value {
     /// If x is Unknown, the result is Unknown.
     pub fn imag(&self) -> Value {
         match self {
-            Value::Int(_) | Value::Float(_) | Value::Rat(_) => Value::with_f64(0.0),
+            Value::Int(_) | Value::Rat(_) => Value::with_i64(0),
             Value::Complex(_, i) => *i.clone(),
             Value::Unknown => Value::Unknown,
             _ => panic!("{} not numeric", self),
@@ -341,16 +322,6 @@ impl Value {
                     0
                 }
             }
-            Value::Float(v) => {
-                let f: f64 = **v;
-                if f > 0.0 {
-                    1
-                } else if f < 0.0 {
-                    -1
-                } else {
-                    0
-                }
-            }
             Value::Complex(r, i) => r.sign() | i.sign(),
             Value::Unknown => 1, // avoid spurious division by zero errors
             _ => panic!("{} not numeric", self),
@@ -386,7 +357,7 @@ impl Value {
                     Token::ADD => Value::Int(a + b),
                     Token::SUB => Value::Int(a - b),
                     Token::MUL => Value::Int(a * b),
-                    Token::QUO => Value::Rat(BigRational::new(a.clone(), b.clone())),
+                    Token::QUO => rat_value(BigRational::new(a.clone(), b.clone())),
                     Token::QUO_ASSIGN => Value::Int(a / b), // force integer division
                     Token::REM => Value::Int(a % b),
                     Token::AND => Value::Int(a & b),
@@ -399,15 +370,8 @@ impl Value {
             (Value::Rat(a), Value::Rat(b)) => match op {
                 Token::ADD => Value::Rat(a + b),
                 Token::SUB => Value::Rat(a - b),
-                Token::MUL => Value::Rat(a * b),
-                Token::QUO => Value::Rat(a / b),
-                _ => unreachable!(),
-            },
-            (Value::Float(a), Value::Float(b)) => match op {
-                Token::ADD => Value::Float(*a + *b),
-                Token::SUB => Value::Float(*a - *b),
-                Token::MUL => Value::Float(*a * *b),
-                Token::QUO => Value::Float(*a / *b),
+                Token::MUL => rat_value(a * b),
+                Token::QUO => rat_value(a / b),
                 _ => unreachable!(),
             },
             (Value::Complex(ar, ai), Value::Complex(br, bi)) => match op {
@@ -459,7 +423,6 @@ impl Value {
                 Value::Unknown => Value::Unknown,
                 Value::Int(i) => Value::Int(-i),
                 Value::Rat(r) => Value::Rat(-r),
-                Value::Float(f) => Value::Float(-(*f)),
                 Value::Complex(r, i) => Value::Complex(
                     Box::new(Value::unary_op(op, r, 0)),
                     Box::new(Value::unary_op(op, i, 0)),
@@ -517,15 +480,6 @@ impl Value {
                 Token::GEQ => a >= b,
                 _ => unreachable!(),
             },
-            (Value::Float(a), Value::Float(b)) => match op {
-                Token::EQL => a == b,
-                Token::NEQ => a != b,
-                Token::LSS => a < b,
-                Token::LEQ => a <= b,
-                Token::GTR => a > b,
-                Token::GEQ => a >= b,
-                _ => unreachable!(),
-            },
             (Value::Complex(ar, ai), Value::Complex(br, bi)) => {
                 let r = Value::compare(ar, op, br);
                 let i = Value::compare(ai, op, bi);
@@ -616,59 +570,40 @@ impl Value {
     /// matches the sign of x, even for 0.
     /// If x is Unknown, the result is (0, false).
     pub fn num_as_f64(&self) -> (F64, bool) {
-        match self {
-            Value::Int(_) | Value::Rat(_) => {
-                let vf = self.to_float();
-                if vf == Value::Unknown {
-                    (
-                        if self.sign() > 0 {
-                            std::f64::MAX.into()
-                        } else {
-                            std::f64::MIN.into()
-                        },
-                        false,
-                    )
-                } else {
-                    vf.num_as_f64()
-                }
-            }
-            Value::Float(f) => (*f, true),
-            Value::Unknown => (0.0.into(), false),
+        match self.to_float() {
+            Value::Rat(r) => match rat_to_f64(&r) {
+                (f, _) if f.is_infinite() => (
+                    if f > 0.0 {
+                        std::f64::MAX
+                    } else {
+                        std::f64::MIN
+                    }
+                    .into(),
+                    false,
+                ),
+                (f, exact) => (f.into(), exact),
+            },
+            Value::Unknown if self == &Value::Unknown => (0.0.into(), false),
             _ => panic!("not a number"),
         }
     }
 
     /// num_as_f32 is like num_as_f64 but for float32 instead of float64.
     pub fn num_as_f32(&self) -> (F32, bool) {
-        match self {
-            Value::Int(_) | Value::Rat(_) => {
-                let vf = self.to_float();
-                if vf == Value::Unknown {
-                    (
-                        if self.sign() > 0 {
-                            std::f32::MAX.into()
-                        } else {
-                            std::f32::MIN.into()
-                        },
-                        false,
-                    )
-                } else {
-                    vf.num_as_f32()
-                }
-            }
-            Value::Float(v) => {
-                let min: f64 = std::f32::MIN as f64;
-                let max: f64 = std::f32::MAX as f64;
-                let f: f64 = v.into_inner();
-                if f > min && f < max {
-                    ((f as f32).into(), true)
-                } else if f < min {
-                    (std::f32::MIN.into(), false)
-                } else {
-                    (std::f32::MAX.into(), false)
-                }
-            }
-            Value::Unknown => (0.0.into(), false),
+        match self.to_float() {
+            Value::Rat(r) => match rat_to_f32(&r) {
+                (f, _) if f.is_infinite() => (
+                    if f > 0.0 {
+                        std::f32::MAX
+                    } else {
+                        std::f32::MIN
+                    }
+                    .into(),
+                    false,
+                ),
+                (f, exact) => ((f as f32).into(), exact),
+            },
+            Value::Unknown if self == &Value::Unknown => (0.0.into(), false),
             _ => panic!("not a number"),
         }
     }
@@ -701,8 +636,7 @@ impl Value {
             Value::Bool(_) | Value::Str(_) => 1,
             Value::Int(_) => 2,
             Value::Rat(_) => 3,
-            Value::Float(_) => 4,
-            Value::Complex(_, _) => 5,
+            Value::Complex(_, _) => 4,
         }
     }
 
@@ -723,42 +657,22 @@ impl Value {
                     Cow::Owned(Value::Rat(BigRational::new(iv.clone(), 1.into()))),
                     y,
                 ),
-                Value::Float(_) => match iv.to_f64() {
-                    Some(f) => (Cow::Owned(Value::Float(f.into())), y),
-                    None => (Cow::Owned(Value::Unknown), Cow::Owned(Value::Unknown)),
-                },
                 Value::Complex(_, _) => (
                     Cow::Owned(Value::Complex(
                         Box::new(x.into_owned()),
-                        Box::new(Value::with_f64(0.0)),
+                        Box::new(Value::with_i64(0)),
                     )),
                     y,
                 ),
                 Value::Unknown => (x.clone(), x),
                 _ => unreachable!(),
             },
-            Value::Rat(rv) => match &*y {
+            Value::Rat(_) => match &*y {
                 Value::Rat(_) => (x, y),
-                Value::Float(_) => match rat_to_f64(rv) {
-                    Some(f) => (Cow::Owned(Value::Float(f.into())), y),
-                    None => (Cow::Owned(Value::Unknown), Cow::Owned(Value::Unknown)),
-                },
-                Value::Complex(_, _) => (
-                    Cow::Owned(Value::Complex(
-                        Box::new(x.into_owned()),
-                        Box::new(Value::with_f64(0.0)),
-                    )),
-                    y,
-                ),
-                Value::Unknown => (x.clone(), x),
-                _ => unreachable!(),
-            },
-            Value::Float(_) => match &*y {
-                Value::Float(_) => (x, y),
                 Value::Complex(_, _) => (
                     Cow::Owned(Value::Complex(
                         Box::new(x.into_owned()),
-                        Box::new(Value::with_f64(0.0)),
+                        Box::new(Value::with_i64(0)),
                     )),
                     y,
                 ),
@@ -794,10 +708,66 @@ pub fn int_from_literal(lit: &str) -> Value {
     }
 }
 
+/// float_from_literal converts a decimal or hexadecimal floating-point literal
+/// to its exact value.
 pub fn float_from_literal(lit: &str) -> Value {
-    match lit.parse::<f64>() {
-        Ok(f) => Value::with_f64(f),
-        Err(_) => Value::Unknown,
+    let lit = lit.replace('_', "");
+    let (hex, lit) = if lit.starts_with("0x") || lit.starts_with("0X") {
+        (true, &lit[2..])
+    } else {
+        (false, &lit[..])
+    };
+    let exp_chars: &[char] = if hex { &['p', 'P'] } else { &['e', 'E'] };
+    let (mant, exp) = match lit.find(exp_chars) {
+        Some(i) => match lit[i + 1..].parse::<i64>() {
+            Ok(e) => (&lit[..i], e),
+            Err(_) => return Value::Unknown,
+        },
+        None => (lit, 0),
+    };
+    let (int_part, frac_part) = match mant.find('.') {
+        Some(i) => (&mant[..i], &mant[i + 1..]),
+        None => (mant, ""),
+    };
+    let digits = format!("{}{}", int_part, frac_part);
+    if digits.is_empty() {
+        return Value::Unknown;
+    }
+    let (radix, scale) = if hex { (16, 4) } else { (10, 1) };
+    let m = match BigInt::from_str_radix(&digits, radix) {
+        Ok(m) => m,
+        Err(_) => return Value::Unknown,
+    };
+    if m.is_zero() {
+        return Value::Rat(BigRational::zero());
+    }
+    // the value is m * base^e, with base 2 for hex and 10 for decimal literals
+    let e = exp.saturating_sub((frac_part.len() as i64) * scale);
+    // 10^e has e * log2(10) bits
+    let bits = if hex {
+        e.unsigned_abs()
+    } else {
+        e.unsigned_abs().saturating_mul(10) / 3
+    };
+    if bits > MAX_EXP {
+        return Value::Unknown;
+    }
+    let base = BigInt::from_u32(if hex { 2 } else { 10 }).unwrap();
+    let p = num_traits::pow(base, e.abs() as usize);
+    if e >= 0 {
+        Value::Rat(BigRational::from_integer(m * p))
+    } else {
+        Value::Rat(BigRational::new(m, p))
+    }
+}
+
+/// rat_value returns r, or unknown if its exponent is beyond MAX_EXP
+fn rat_value(r: BigRational) -> Value {
+    let exp = r.numer().bits() as i64 - r.denom().bits() as i64;
+    if exp.unsigned_abs() > MAX_EXP {
+        Value::Unknown
+    } else {
+        Value::Rat(r)
     }
 }
 
@@ -812,18 +782,226 @@ fn shorten_with_ellipsis(s: String, max: usize) -> String {
     }
 }
 
-fn rat_to_f64(r: &BigRational) -> Option<f64> {
-    match (r.numer().to_f64(), r.denom().to_f64()) {
-        (Some(n), Some(d)) => Some(n / d),
-        _ => None,
+fn rat_to_f64(r: &BigRational) -> (f64, bool) {
+    rat_to_float(r, 53, -1022, 1023)
+}
+
+/// rat_to_f32 returns the nearest float32 as an f64, which is exact.
+fn rat_to_f32(r: &BigRational) -> (f64, bool) {
+    rat_to_float(r, 24, -126, 127)
+}
+
+/// rat_to_float rounds r to the nearest (ties to even) binary floating-point number
+/// with a 'prec' bits mantissa and an exponent range of [emin, emax], and reports
+/// whether the result is exact. The result is infinite if r overflows.
+fn rat_to_float(r: &BigRational, prec: usize, emin: i64, emax: i64) -> (f64, bool) {
+    if r.is_zero() {
+        return (0.0, true);
+    }
+    let neg = r.is_negative();
+    let (n, d) = (r.numer().abs(), r.denom().abs());
+    // scale r by 2^s so that the quotient q has prec + 2 or prec + 3 bits
+    let s = (prec + 2) as i64 - (n.bits() as i64 - d.bits() as i64);
+    let (n, d) = if s >= 0 {
+        (n << s as usize, d)
+    } else {
+        (n, d << (-s) as usize)
+    };
+    let q = &n / &d;
+    let sticky = !(&n % &d).is_zero();
+    let qbits = q.bits() as i64;
+    // exponent of the leading bit, denormals have fewer bits of precision
+    let top = qbits - 1 - s;
+    let keep = if top >= emin {
+        prec as i64
+    } else {
+        prec as i64 - (emin - top)
+    };
+    let drop = (qbits - keep) as usize;
+    let low = &q & ((BigInt::one() << drop) - BigInt::one());
+    let mut m = &q >> drop;
+    let half = BigInt::one() << (drop - 1);
+    if low > half || (low == half && (sticky || !(&m & BigInt::one()).is_zero())) {
+        m += BigInt::one();
+    }
+    let exact = low.is_zero() && !sticky;
+    let k = drop as i64 - s;
+    let f = if m.is_zero() {
+        0.0
+    } else if m.bits() as i64 - 1 + k > emax {
+        return (if neg { -1.0 } else { 1.0 } * std::f64::INFINITY, false);
+    } else {
+        ldexp(m.to_f64().unwrap(), k)
+    };
+    (if neg { -f } else { f }, exact)
+}
+
+/// ldexp returns f * 2^k, it's exact if the result is representable.
+fn ldexp(f: f64, k: i64) -> f64 {
+    let (mut f, mut k) = (f, k);
+    while k > 1000 {
+        f *= 2f64.powi(1000);
+        k -= 1000;
+    }
+    while k < -1000 {
+        f *= 2f64.powi(-1000);
+        k += 1000;
+    }
+    f * 2f64.powi(k as i32)
+}
+
+/// rat_to_string formats r like Go's %.6g verb.
+fn rat_to_string(r: &BigRational) -> String {
+    let (f, _) = rat_to_f64(r);
+    if !f.is_infinite() && (f != 0.0 || r.is_zero()) {
+        return fmt_g6(f);
+    }
+    // too large or too small for float64: compute 6 significant digits directly
+    let ten = BigInt::from_u32(10).unwrap();
+    let abs = r.abs();
+    let bits = abs.numer().bits() as f64 - abs.denom().bits() as f64;
+    let mut e = (bits * LOG10_2) as i64;
+    // the power of ten is as large as r, which is within MAX_EXP
+    loop {
+        let shift = 5 - e;
+        let p = BigRational::from_integer(num_traits::pow(ten.clone(), shift.abs() as usize));
+        let scaled = if shift >= 0 { &abs * &p } else { &abs / &p };
+        let digits = scaled.round().to_integer();
+        let n = digits.to_string();
+        if n.len() > 6 {
+            e += 1;
+        } else if n.len() < 6 {
+            e -= 1;
+        } else {
+            let mant = format!("{}.{}", &n[..1], &n[1..]);
+            let mant = mant.trim_end_matches('0').trim_end_matches('.');
+            let sign = if r.is_negative() { "-" } else { "" };
+            let esign = if e < 0 { "-" } else { "+" };
+            return format!("{}{}e{}{:02}", sign, mant, esign, e.abs());
+        }
+    }
+}
+
+/// fmt_g6 formats f like Go's %.6g verb.
+fn fmt_g6(f: f64) -> String {
+    let sci = format!("{:.5e}", f);
+    let (mant, exp) = sci.split_at(sci.find('e').unwrap());
+    let exp: i64 = exp[1..].parse().unwrap();
+    if exp < -4 || exp >= 6 {
+        let mant = mant.trim_end_matches('0').trim_end_matches('.');
+        let esign = if exp < 0 { "-" } else { "+" };
+        format!("{}e{}{:02}", mant, esign, exp.abs())
+    } else {
+        let s = format!("{:.*}", (5 - exp) as usize, f);
+        if s.contains('.') {
+            s.trim_end_matches('0').trim_end_matches('.').to_string()
+        } else {
+            s
+        }
     }
 }
 
 #[cfg(test)]
 mod test {
+    use super::*;
+
     #[test]
     fn test_str_unquote() {
         let s = "\\111";
         dbg!(s);
     }
+
+    #[test]
+    fn test_float_literal() {
+        let cases = [
+            ("0.1", 0.1),
+            ("1e10", 1e10),
+            ("1.5e-3", 1.5e-3),
+            (".25", 0.25),
+            ("6.", 6.0),
+            ("0x1p-2", 0.25),
+            ("0x1.8p1", 3.0),
+            ("4.9e-324", 4.9e-324),
+            ("1.7976931348623157e308", std::f64::MAX),
+        ];
+        for (lit, expected) in cases.iter() {
+            let (f, _) = float_from_literal(lit).num_as_f64();
+            assert_eq!(*f, *expected, "{}", lit);
+        }
+        let (_, exact) = float_from_literal("0.1").num_as_f64();
+        assert!(!exact);
+        let (_, exact) = float_from_literal("0.5").num_as_f64();
+        assert!(exact);
+    }
+
+    #[test]
+    fn test_float_precision() {
+        // 1 << 100 / 3.0 * 3 is exactly 1 << 100
+        let big = Value::shift(&Value::with_i64(1), &Token::SHL, 100);
+        let third = Value::binary_op(&big, &Token::QUO, &float_from_literal("3.0"));
+        let back = Value::binary_op(&third, &Token::MUL, &Value::with_i64(3));
+        assert!(Value::compare(&back, &Token::EQL, &big));
+        // more than 53 bits of precision survive intermediate results
+        let x = float_from_literal("1.00000000000000000001");
+        let y = Value::binary_op(&x, &Token::SUB, &Value::with_i64(1));
+        let (f, _) = y.num_as_f64();
+        assert_eq!(*f, 1e-20);
+    }
+
+    #[test]
+    fn test_float_rounding() {
+        // 2^53 + 1 is halfway between two float64s, rounds to even
+        let v = int_from_literal("9007199254740993");
+        assert_eq!(*v.num_as_f64().0, 9007199254740992.0);
+        let v = int_from_literal("9007199254740995");
+        assert_eq!(*v.num_as_f64().0, 9007199254740996.0);
+        // float32 rounds directly, not through float64
+        let v = float_from_literal("16777217");
+        let (f, exact) = v.num_as_f32();
+        assert_eq!(*f, 16777216.0);
+        assert!(!exact);
+        let v = float_from_literal("0.1");
+        assert_eq!(*v.num_as_f32().0, 0.1f32);
+
+        let f64_type = BasicDetail::new(BasicType::Float64, BasicInfo::IsFloat, "float64");
+        let f32_type = BasicDetail::new(BasicType::Float32, BasicInfo::IsFloat, "float32");
+        let big = float_from_literal("1e300");
+        assert!(big.representable(&f64_type, None));
+        assert!(!big.representable(&f32_type, None));
+        assert!(!float_from_literal("1e309").representable(&f64_type, None));
+        let mut rounded = Value::Unknown;
+        assert!(float_from_literal("0.1").representable(&f32_type, Some(&mut rounded)));
+        assert_eq!(rounded, Value::with_f64(0.1f32 as f64));
+    }
+
+    #[test]
+    fn test_float_exp() {
+        // the exponents beyond MAX_EXP are unknown, instead of taking forever
+        assert_eq!(float_from_literal("1e100000000"), Value::Unknown);
+        assert_eq!(float_from_literal("1e-100000000"), Value::Unknown);
+        assert_eq!(float_from_literal("0x1p100000000"), Value::Unknown);
+        assert_eq!(float_from_literal("1e9223372036854775807"), Value::Unknown);
+        assert_eq!(
+            float_from_literal("0e100000000"),
+            Value::with_i64(0).to_float()
+        );
+        assert_eq!(float_from_literal("1e9000").to_string(), "1e+9000");
+        assert_eq!(float_from_literal("-1e-9000").to_string(), "-1e-9000");
+        let big = float_from_literal("1e6000");
+        assert_eq!(Value::binary_op(&big, &Token::MUL, &big), Value::Unknown);
+    }
+
+    #[test]
+    fn test_float_string() {
+        assert_eq!(float_from_literal("0.5").to_string(), "0.5");
+        assert_eq!(float_from_literal("991.5").to_string(), "991.5");
+        assert_eq!(float_from_literal("2.0").to_string(), "2");
+        assert_eq!(float_from_literal("1e100").to_string(), "1e+100");
+        assert_eq!(float_from_literal("1.5e-7").to_string(), "1.5e-07");
+        assert_eq!(float_from_literal("1e1000").to_string(), "1e+1000");
+        assert_eq!(
+            float_from_literal("-1.234567e-1000").to_string(),
+            "-1.23457e-1000"
+        );
+    }
 }
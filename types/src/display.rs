@@ -17,6 +17,10 @@ pub fn type_str(t: &TypeKey, tco: &TCObjects) -> String {
     format!("{}", Displayer::new(t, None, Some(tco)))
 }
 
+pub fn obj_str(o: &ObjKey, tco: &TCObjects) -> String {
+    format!("{}", Displayer::new(o, None, Some(tco)))
+}
+
 pub trait Display {
     fn format(
         &self,
@@ -11,6 +11,7 @@ use std::env;
 use std::fs;
 use std::io;
 use std::path::{Path, PathBuf};
+use std::rc::Rc;
 
 pub struct Config {
     // working directory
@@ -113,6 +114,52 @@ impl<'a> Importer<'a> {
         .check(files)
     }
 
+    /// import_source checks a main package of a single file from its source,
+    /// name is the file name in the positions, its imports are local to the
+    /// working directory
+    pub fn import_source(&mut self, name: &str, source: &str) -> Result<PackageKey, ()> {
+        self.import_files(name, &[(name.to_string(), source.to_string())])
+    }
+
+    /// import_files checks the package at path from the sources of its files,
+    /// given with their names relative to the working directory, for the
+    /// files that are not saved yet
+    pub fn import_files(
+        &mut self,
+        path: &str,
+        files: &[(String, String)],
+    ) -> Result<PackageKey, ()> {
+        let pkg = self.tc_objs.new_package(path.to_string());
+        self.pkgs.insert(path.to_string(), pkg);
+        let mut afiles = vec![];
+        for (name, source) in files.iter() {
+            let mut pfile =
+                self.fset
+                    .add_file(name.clone(), Some(self.fset.base()), source.chars().count());
+            let afile = Parser::new(
+                self.ast_objs,
+                &mut pfile,
+                self.errors,
+                source,
+                self.config.trace_parser,
+            )
+            .parse_file()
+            .ok_or(())?;
+            afiles.push(afile);
+        }
+        Checker::new(
+            self.tc_objs,
+            self.ast_objs,
+            self.fset,
+            self.errors,
+            self.pkgs,
+            self.all_results,
+            pkg,
+            self.config,
+        )
+        .check(afiles)
+    }
+
     fn validate_path(&mut self, key: &'a ImportKey) -> Result<(PathBuf, String), ()> {
         let mut import_path = key.path.clone();
         let path = if is_local(&key.path) {
@@ -209,8 +256,19 @@ impl<'a> Importer<'a> {
     }
 
     fn error(&self, err: String) {
-        let pos_file = self.fset.file(self.pos).unwrap();
-        FilePosErrors::new(pos_file, self.errors).add(self.pos, err, false);
+        match self.fset.file(self.pos) {
+            Some(pos_file) => FilePosErrors::new(pos_file, self.errors).add(self.pos, err, false),
+            // the main package is imported before any file is read
+            None => {
+                let pos = position::Position {
+                    filename: Rc::new(String::new()),
+                    offset: 0,
+                    line: 0,
+                    column: 0,
+                };
+                self.errors.add(pos, err, false, false)
+            }
+        }
     }
 }
 
@@ -254,5 +312,9 @@ fn read_content(p: &Path) -> io::Result<Vec<(PathBuf, String)>> {
 }
 
 fn is_local(path: &str) -> bool {
-    path == "." || path == ".." || path.starts_with("./") || path.starts_with("../")
+    path == "."
+        || path == ".."
+        || path.starts_with("./")
+        || path.starts_with("../")
+        || Path::new(path).is_absolute()
 }
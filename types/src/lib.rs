@@ -26,11 +26,14 @@ mod check;
 
 mod importer;
 
-pub use check::{DeclInfo, TypeInfo};
+pub use check::{DeclInfo, FuncInstance, Instance, TypeInfo};
 pub use constant::Value as ConstValue;
+pub use display::{obj_str, type_str};
 pub use importer::{Config, ImportKey, Importer};
+pub use lookup::{lookup_field_or_method, LookupResult};
 pub use obj::EntityType;
 pub use objects::{DeclInfoKey, ObjKey, PackageKey, ScopeKey, TCObjects, TypeKey};
 pub use operand::OperandMode;
+pub use selection::{Selection, SelectionKind};
 pub use typ::{identical, BasicType, ChanDir, Type};
 pub use universe::{Builtin, Universe};
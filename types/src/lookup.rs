@@ -9,7 +9,7 @@ use std::fmt::Write;
 
 macro_rules! lookup_on_found {
     ($indices:ident, $i:ident, $target:expr, $et:ident, $indirect:ident, $found:expr) => {
-        $indices = concat_vec($indices, $i);
+        $indices = concat_vec($et.indices.clone(), $i);
         if $target.is_some() || $et.multiples {
             return LookupResult::Ambiguous($indices.unwrap());
         }
@@ -93,6 +93,12 @@ impl MethodSet {
                     // continue with underlying type
                     tobj = &objs.types[detail.underlying()];
                 }
+                // the methods of a type parameter are the methods of its constraint
+                if let typ::Type::TypeParam(detail) = tobj {
+                    if let Some(b) = detail.bound() {
+                        tobj = &objs.types[typ::underlying_type(b, objs)];
+                    }
+                }
                 match tobj {
                     typ::Type::Struct(detail) => {
                         for (i, f) in detail.fields().iter().enumerate() {
@@ -421,6 +427,11 @@ fn lookup_field_or_method_impl(
                 // continue with underlying type
                 tobj = &objs.types[detail.underlying()];
             }
+            if let typ::Type::TypeParam(detail) = tobj {
+                if let Some(b) = detail.bound() {
+                    tobj = &objs.types[typ::underlying_type(b, objs)];
+                }
+            }
             match tobj {
                 typ::Type::Struct(detail) => {
                     for (i, &f) in detail.fields().iter().enumerate() {
@@ -445,7 +456,7 @@ fn lookup_field_or_method_impl(
                                 | typ::Type::Struct(_)
                                 | typ::Type::Interface(_) => next.push(EmbeddedType::new(
                                     tkey,
-                                    concat_vec(indices.clone(), i),
+                                    concat_vec(et.indices.clone(), i),
                                     et.indirect || is_ptr,
                                     et.multiples,
                                 )),
@@ -263,4 +263,9 @@ impl TCObjects {
             obj, underlying, methods, self,
         )))
     }
+
+    pub fn new_t_type_param(&mut self, obj: ObjKey, index: usize) -> TypeKey {
+        self.types
+            .insert(Type::TypeParam(TypeParamDetail::new(obj, index)))
+    }
 }
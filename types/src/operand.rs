@@ -106,7 +106,13 @@ impl Operand {
             Token::STRING(_) => BasicType::UntypedString,
             _ => unreachable!(),
         };
-        self.mode = OperandMode::Constant(constant::Value::with_literal(t));
+        let val = constant::Value::with_literal(t);
+        if val == constant::Value::Unknown {
+            self.mode = OperandMode::Invalid;
+            self.typ = Some(u.types()[&BasicType::Invalid]);
+            return;
+        }
+        self.mode = OperandMode::Constant(val);
         self.typ = Some(u.types()[&bt]);
     }
 
@@ -388,6 +394,23 @@ impl<'a, 'b> ExprVisitor for ExprFormater<'a, 'b> {
         self.f.write_char(']')
     }
 
+    fn visit_expr_index_list(
+        &mut self,
+        _: &Expr,
+        expr: &Expr,
+        indices: &Vec<Expr>,
+    ) -> Self::Result {
+        self.visit_expr(expr)?;
+        self.f.write_char('[')?;
+        for (i, index) in indices.iter().enumerate() {
+            if i > 0 {
+                self.f.write_str(", ")?;
+            }
+            self.visit_expr(index)?;
+        }
+        self.f.write_char(']')
+    }
+
     fn visit_expr_slice(
         &mut self,
         _: &Expr,
@@ -49,6 +49,7 @@ pub enum Type {
     Map(MapDetail),
     Chan(ChanDetail),
     Named(NamedDetail),
+    TypeParam(TypeParamDetail),
 }
 
 impl Type {
@@ -171,6 +172,20 @@ impl Type {
         }
     }
 
+    pub fn try_as_type_param(&self) -> Option<&TypeParamDetail> {
+        match self {
+            Type::TypeParam(t) => Some(t),
+            _ => None,
+        }
+    }
+
+    pub fn try_as_type_param_mut(&mut self) -> Option<&mut TypeParamDetail> {
+        match self {
+            Type::TypeParam(t) => Some(t),
+            _ => None,
+        }
+    }
+
     pub fn underlying(&self) -> Option<TypeKey> {
         match self {
             Type::Named(detail) => detail.underlying,
@@ -178,9 +193,16 @@ impl Type {
         }
     }
 
+    /// underlying_val returns the underlying type, for a type parameter
+    /// it's the core type if there is one, otherwise the type parameter itself
     pub fn underlying_val<'a>(&'a self, objs: &'a TCObjects) -> &'a Type {
         if let Some(k) = self.underlying() {
             &objs.types[k]
+        } else if let Type::TypeParam(detail) = self {
+            match detail.core_type(objs) {
+                Some(k) => objs.types[k].underlying_val(objs),
+                None => &self,
+            }
         } else {
             &self
         }
@@ -188,7 +210,7 @@ impl Type {
 
     pub fn is_named(&self) -> bool {
         match self {
-            Type::Basic(_) | Type::Named(_) => true,
+            Type::Basic(_) | Type::Named(_) | Type::TypeParam(_) => true,
             _ => false,
         }
     }
@@ -201,42 +223,49 @@ impl Type {
     pub fn is_boolean(&self, objs: &TCObjects) -> bool {
         match self.underlying_val(objs) {
             Type::Basic(b) => b.info() == BasicInfo::IsBoolean,
+            Type::TypeParam(p) => p.all_terms(objs, |t| t.is_boolean(objs)),
             _ => false,
         }
     }
     pub fn is_integer(&self, objs: &TCObjects) -> bool {
         match self.underlying_val(objs) {
             Type::Basic(b) => b.info() == BasicInfo::IsInteger,
+            Type::TypeParam(p) => p.all_terms(objs, |t| t.is_integer(objs)),
             _ => false,
         }
     }
     pub fn is_unsigned(&self, objs: &TCObjects) -> bool {
         match self.underlying_val(objs) {
             Type::Basic(b) => b.typ().is_unsigned(),
+            Type::TypeParam(p) => p.all_terms(objs, |t| t.is_unsigned(objs)),
             _ => false,
         }
     }
     pub fn is_float(&self, objs: &TCObjects) -> bool {
         match self.underlying_val(objs) {
             Type::Basic(b) => b.info() == BasicInfo::IsFloat,
+            Type::TypeParam(p) => p.all_terms(objs, |t| t.is_float(objs)),
             _ => false,
         }
     }
     pub fn is_complex(&self, objs: &TCObjects) -> bool {
         match self.underlying_val(objs) {
             Type::Basic(b) => b.info() == BasicInfo::IsComplex,
+            Type::TypeParam(p) => p.all_terms(objs, |t| t.is_complex(objs)),
             _ => false,
         }
     }
     pub fn is_numeric(&self, objs: &TCObjects) -> bool {
         match self.underlying_val(objs) {
             Type::Basic(b) => b.info().is_numeric(),
+            Type::TypeParam(p) => p.all_terms(objs, |t| t.is_numeric(objs)),
             _ => false,
         }
     }
     pub fn is_string(&self, objs: &TCObjects) -> bool {
         match self.underlying_val(objs) {
             Type::Basic(b) => b.info() == BasicInfo::IsString,
+            Type::TypeParam(p) => p.all_terms(objs, |t| t.is_string(objs)),
             _ => false,
         }
     }
@@ -255,12 +284,14 @@ impl Type {
     pub fn is_ordered(&self, objs: &TCObjects) -> bool {
         match self.underlying_val(objs) {
             Type::Basic(b) => b.info().is_ordered(),
+            Type::TypeParam(p) => p.all_terms(objs, |t| t.is_ordered(objs)),
             _ => false,
         }
     }
     pub fn is_const_type(&self, objs: &TCObjects) -> bool {
         match self.underlying_val(objs) {
             Type::Basic(b) => b.info().is_const_type(),
+            Type::TypeParam(p) => p.all_terms(objs, |t| t.is_const_type(objs)),
             _ => false,
         }
     }
@@ -280,6 +311,7 @@ impl Type {
             | Type::Interface(_)
             | Type::Map(_)
             | Type::Chan(_) => true,
+            Type::TypeParam(p) => p.all_terms(objs, |t| t.has_nil(objs)),
             _ => false,
         }
     }
@@ -293,6 +325,10 @@ impl Type {
                 .iter()
                 .any(|f| !comparable(objs.lobjs[*f].typ().unwrap(), objs)),
             Type::Array(a) => comparable(a.elem(), objs),
+            Type::TypeParam(p) => {
+                p.bound_val(objs).map_or(false, |b| b.type_set().comparable)
+                    || p.all_terms(objs, |t| t.comparable(objs))
+            }
             _ => false,
         }
     }
@@ -534,6 +570,10 @@ impl StructDetail {
         &self.fields
     }
 
+    pub fn tags(&self) -> &Option<Vec<Option<String>>> {
+        &self.tags
+    }
+
     pub fn tag(&self, i: usize) -> Option<&String> {
         self.tags
             .as_ref()
@@ -586,6 +626,7 @@ impl TupleDetail {
 pub struct SignatureDetail {
     scope: Option<ScopeKey>, // function scope, present for package-local signatures
     recv: Option<ObjKey>,    // None if not a method
+    tparams: Option<TypeKey>, // type parameters(a tuple of LangObj::TypeName), if generic
     params: TypeKey,
     results: TypeKey,
     variadic: bool,
@@ -618,6 +659,7 @@ impl SignatureDetail {
         SignatureDetail {
             scope: scope,
             recv: recv,
+            tparams: None,
             params: params,
             results: results,
             variadic: variadic,
@@ -642,6 +684,14 @@ impl SignatureDetail {
         self.recv = r
     }
 
+    pub fn tparams(&self) -> Option<TypeKey> {
+        self.tparams
+    }
+
+    pub fn set_tparams(&mut self, t: Option<TypeKey>) {
+        self.tparams = t;
+    }
+
     pub fn params(&self) -> TypeKey {
         self.params
     }
@@ -676,12 +726,49 @@ impl SignatureDetail {
     }
 }
 
+/// A TypeSet describes the types an interface used as a constraint
+/// permits besides its methods: each line is a union of terms, and a
+/// type is in the set if it's in every line. A term (tilde, T) stands
+/// for T, or all types whose underlying type is T if tilde is set.
+#[derive(Clone, Debug, Default)]
+pub struct TypeSet {
+    pub lines: Vec<Vec<(bool, TypeKey)>>,
+    pub comparable: bool,
+}
+
+impl TypeSet {
+    pub fn is_all(&self) -> bool {
+        self.lines.is_empty() && !self.comparable
+    }
+
+    /// includes reports whether type t is in every line of the type set
+    pub fn includes(&self, t: TypeKey, objs: &TCObjects) -> bool {
+        self.includes_term(false, t, objs)
+    }
+
+    /// includes_term reports whether the types of term (tilde, t) are
+    /// in every line of the type set
+    pub fn includes_term(&self, tilde: bool, t: TypeKey, objs: &TCObjects) -> bool {
+        let ut = underlying_type(t, objs);
+        self.lines.iter().all(|line| {
+            line.iter().any(|(ltilde, term)| {
+                if *ltilde {
+                    identical(ut, underlying_type(*term, objs), objs)
+                } else {
+                    !tilde && identical(t, *term, objs)
+                }
+            })
+        })
+    }
+}
+
 /// An InterfaceDetail represents an interface type.
 #[derive(Debug)]
 pub struct InterfaceDetail {
     methods: Vec<ObjKey>,
     embeddeds: Vec<TypeKey>,
     all_methods: Rc<RefCell<Option<Vec<ObjKey>>>>,
+    type_set: TypeSet,
 }
 
 impl InterfaceDetail {
@@ -718,6 +805,7 @@ impl InterfaceDetail {
             methods: methods,
             embeddeds: embeddeds,
             all_methods: Rc::new(RefCell::new(None)),
+            type_set: TypeSet::default(),
         }
     }
 
@@ -726,9 +814,18 @@ impl InterfaceDetail {
             methods: Vec::new(),
             embeddeds: Vec::new(),
             all_methods: Rc::new(RefCell::new(Some(Vec::new()))),
+            type_set: TypeSet::default(),
         }
     }
 
+    pub fn type_set(&self) -> &TypeSet {
+        &self.type_set
+    }
+
+    pub fn type_set_mut(&mut self) -> &mut TypeSet {
+        &mut self.type_set
+    }
+
     pub fn methods(&self) -> &Vec<ObjKey> {
         &self.methods
     }
@@ -762,7 +859,7 @@ impl InterfaceDetail {
     }
 
     pub fn is_empty(&self) -> bool {
-        self.all_methods().as_ref().unwrap().len() == 0
+        self.all_methods().as_ref().unwrap().len() == 0 && self.type_set.is_all()
     }
 
     pub fn set_empty_complete(&self) {
@@ -842,6 +939,10 @@ pub struct NamedDetail {
     obj: Option<ObjKey>,         // corresponding declared object
     underlying: Option<TypeKey>, // possibly a Named during setup; never a Named once set up completely
     methods: Vec<ObjKey>, // methods declared for this type (not the method set of this type); signatures are type-checked lazily
+    tparams: Option<TypeKey>, // type parameters(a tuple of LangObj::TypeName) of a generic type
+    targs: Vec<TypeKey>,  // type arguments of an instance
+    orig: Option<TypeKey>, // the generic type of an instance
+    instances: Vec<TypeKey>, // instances of a generic type
 }
 
 impl NamedDetail {
@@ -860,6 +961,10 @@ impl NamedDetail {
             obj: obj,
             underlying: underlying,
             methods: methods,
+            tparams: None,
+            targs: vec![],
+            orig: None,
+            instances: vec![],
         }
     }
 
@@ -883,9 +988,115 @@ impl NamedDetail {
         self.underlying.unwrap()
     }
 
+    pub fn try_underlying(&self) -> Option<TypeKey> {
+        self.underlying
+    }
+
     pub fn set_underlying(&mut self, t: TypeKey) {
         self.underlying = Some(t);
     }
+
+    pub fn tparams(&self) -> Option<TypeKey> {
+        self.tparams
+    }
+
+    pub fn set_tparams(&mut self, t: Option<TypeKey>) {
+        self.tparams = t;
+    }
+
+    pub fn targs(&self) -> &Vec<TypeKey> {
+        &self.targs
+    }
+
+    pub fn orig(&self) -> Option<TypeKey> {
+        self.orig
+    }
+
+    pub fn set_instance_of(&mut self, orig: TypeKey, targs: Vec<TypeKey>) {
+        self.orig = Some(orig);
+        self.targs = targs;
+    }
+
+    pub fn instances(&self) -> &Vec<TypeKey> {
+        &self.instances
+    }
+
+    pub fn instances_mut(&mut self) -> &mut Vec<TypeKey> {
+        &mut self.instances
+    }
+}
+
+/// A TypeParamDetail represents a type parameter of a generic function or type.
+#[derive(Debug)]
+pub struct TypeParamDetail {
+    obj: ObjKey,            // corresponding LangObj::TypeName
+    index: usize,           // index in the type parameter list
+    bound: Option<TypeKey>, // the constraint, set up lazily
+}
+
+impl TypeParamDetail {
+    pub fn new(obj: ObjKey, index: usize) -> TypeParamDetail {
+        TypeParamDetail {
+            obj: obj,
+            index: index,
+            bound: None,
+        }
+    }
+
+    pub fn obj(&self) -> ObjKey {
+        self.obj
+    }
+
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    pub fn bound(&self) -> Option<TypeKey> {
+        self.bound
+    }
+
+    pub fn set_bound(&mut self, t: TypeKey) {
+        self.bound = Some(t);
+    }
+
+    /// bound_val returns the underlying interface of the constraint
+    pub fn bound_val<'a>(&self, objs: &'a TCObjects) -> Option<&'a InterfaceDetail> {
+        self.bound
+            .map(|b| objs.types[underlying_type(b, objs)].try_as_interface())
+            .flatten()
+    }
+
+    /// all_terms reports whether f holds for all types in the type set
+    /// of the constraint, it's false if the type set is not restricted.
+    /// As the type set is the intersection of all lines, it's sufficient
+    /// for f to hold for all terms in any of the lines.
+    pub fn all_terms<F: Fn(&Type) -> bool>(&self, objs: &TCObjects, f: F) -> bool {
+        match self.bound_val(objs) {
+            Some(b) => b
+                .type_set()
+                .lines
+                .iter()
+                .any(|line| line.iter().all(|(_, t)| f(&objs.types[*t]))),
+            None => false,
+        }
+    }
+
+    /// core_type returns the single underlying type of all types in the
+    /// type set, if there is one.
+    pub fn core_type(&self, objs: &TCObjects) -> Option<TypeKey> {
+        let b = self.bound_val(objs)?;
+        b.type_set().lines.iter().find_map(|line| {
+            let first = underlying_type(line.first()?.1, objs);
+            if line
+                .iter()
+                .all(|(_, t)| identical(underlying_type(*t, objs), first, objs))
+            {
+                Some(first)
+            } else {
+                None
+            }
+        })
+    }
 }
 
 // ----------------------------------------------------------------------------
@@ -905,12 +1116,18 @@ pub fn size_of(t: &TypeKey, objs: &TCObjects) -> usize {
 /// underlying_type returns the underlying type of type 't'
 pub fn underlying_type(t: TypeKey, objs: &TCObjects) -> TypeKey {
     let typ = &objs.types[t];
-    match typ.underlying() {
-        Some(ut) => ut,
-        None => t,
+    match typ {
+        Type::Named(n) => n.underlying.unwrap_or(t),
+        Type::TypeParam(p) => p.core_type(objs).unwrap_or(t),
+        _ => t,
     }
 }
 
+/// is_type_param reports whether t is a type parameter
+pub fn is_type_param(t: TypeKey, objs: &TCObjects) -> bool {
+    objs.types[t].try_as_type_param().is_some()
+}
+
 /// deep_underlying_type returns the 'deep' underlying type of type 't'
 /// chains only exist while named types are incomplete.
 pub fn deep_underlying_type(t: TypeKey, objs: &TCObjects) -> TypeKey {
@@ -1142,7 +1359,15 @@ fn identical_impl(
         (Type::Chan(cx), Type::Chan(cy)) => {
             cx.dir() == cy.dir() && identical_impl(cx.elem(), cy.elem(), cmp_tags, dup, objs)
         }
-        (Type::Named(nx), Type::Named(ny)) => nx.obj() == ny.obj(),
+        (Type::Named(nx), Type::Named(ny)) => {
+            nx.obj() == ny.obj()
+                && nx.targs().len() == ny.targs().len()
+                && nx
+                    .targs()
+                    .iter()
+                    .zip(ny.targs().iter())
+                    .all(|(a, b)| identical_impl(*a, *b, cmp_tags, dup, objs))
+        }
         _ => false,
     }
 }
@@ -1165,6 +1390,10 @@ fn fmt_type_impl(
         return f.write_str("<nil>");
     }
     let tkey = t.unwrap();
+    // type parameters can't be part of a cycle
+    if let Type::TypeParam(detail) = &objs.types[tkey] {
+        return f.write_str(objs.lobjs[detail.obj()].name());
+    }
     if visited.get(&tkey).is_some() {
         return tkey.fmt(f);
     }
@@ -1226,12 +1455,29 @@ fn fmt_type_impl(
                 f.write_str(mobj.name())?;
                 fmt_signature_impl(mobj.typ().unwrap(), f, visited, objs)?;
             }
-            for (i, k) in detail.embeddeds().iter().enumerate() {
-                if i > 0 || detail.methods().len() > 0 {
+            let mut count = detail.methods().len();
+            for k in detail.embeddeds().iter() {
+                if count > 0 {
                     f.write_str("; ")?;
                 }
+                count += 1;
                 fmt_type_impl(Some(*k), f, visited, objs)?;
             }
+            for line in detail.type_set().lines.iter() {
+                if count > 0 {
+                    f.write_str("; ")?;
+                }
+                count += 1;
+                for (i, (tilde, t)) in line.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(" | ")?;
+                    }
+                    if *tilde {
+                        f.write_char('~')?;
+                    }
+                    fmt_type_impl(Some(*t), f, visited, objs)?;
+                }
+            }
             if detail.all_methods().is_none() {
                 f.write_str(" /* incomplete */")?;
             }
@@ -1276,7 +1522,18 @@ fn fmt_type_impl(
             } else {
                 f.write_str("<Named w/o object>")?;
             }
+            if detail.targs().len() > 0 {
+                f.write_char('[')?;
+                for (i, t) in detail.targs().iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(", ")?;
+                    }
+                    fmt_type_impl(Some(*t), f, visited, objs)?;
+                }
+                f.write_char(']')?;
+            }
         }
+        Type::TypeParam(_) => unreachable!(),
     }
     Ok(())
 }
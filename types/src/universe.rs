@@ -90,6 +90,7 @@ impl Universe {
             objs,
         );
         Universe::def_error_type(&types, &uskey, &unsafe_, objs);
+        Universe::def_constraint_types(&uskey, &unsafe_, objs);
         // consts
         Universe::def_consts(&types, &uskey, &unsafe_, objs);
         Universe::def_nil(&types, &uskey, &unsafe_, objs);
@@ -220,6 +221,20 @@ impl Universe {
         Universe::def(type_name, universe, unsafe_, objs);
     }
 
+    fn def_constraint_types(universe: &ScopeKey, unsafe_: &PackageKey, objs: &mut TCObjects) {
+        // type any = interface{}
+        let empty = objs.new_t_empty_interface();
+        let any = objs.new_type_name(0, None, "any".to_owned(), Some(empty));
+        Universe::def(any, universe, unsafe_, objs);
+        // type comparable interface{ /* all comparable types */ }
+        let mut detail = InterfaceDetail::new_empty();
+        detail.type_set_mut().comparable = true;
+        let underlying = objs.types.insert(Type::Interface(detail));
+        let typ = objs.new_t_named(None, Some(underlying), vec![]);
+        let type_name = objs.new_type_name(0, None, "comparable".to_owned(), Some(typ));
+        Universe::def(type_name, universe, unsafe_, objs);
+    }
+
     fn def_basic_types(
         types: &HashMap<BasicType, TypeKey>,
         universe: &ScopeKey,
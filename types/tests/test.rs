@@ -197,6 +197,16 @@ fn test_auto() {
     test_file("./tests/data/vardecl.src", trace);
 }
 
+#[test]
+fn test_constexp() {
+    test_file("./tests/data/constexp.gos", false);
+}
+
+#[test]
+fn test_typeparams() {
+    test_file("./tests/data/typeparams.src", false);
+}
+
 #[test]
 fn test_temp() {
     test_file("./tests/data/temp.gos", true);
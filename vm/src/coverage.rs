@@ -0,0 +1,231 @@
+//! Code coverage. Every instruction run is counted by function and pc, and
+//! every conditional jump by whether it jumped or not. The Coverage it makes
+//! maps the counts to the source lines with the positions the code generator
+//! keeps for every instruction, and can be written in the LCOV format or in
+//! the coverage.out format of Go.
+
+use super::disasm::func_names;
+use super::instruction::Opcode;
+use super::objects::FunctionKey;
+use super::vm::ByteCode;
+use goscript_parser::FileSet;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::io::{self, Write};
+
+pub struct CoverageCounter {
+    counts: RefCell<HashMap<(FunctionKey, usize), u64>>,
+    // how many times every conditional jump jumped and didn't
+    jumps: RefCell<HashMap<(FunctionKey, usize), (u64, u64)>>,
+    // the conditional jump every fiber has just run
+    last_jump: RefCell<HashMap<usize, (FunctionKey, usize)>>,
+}
+
+impl CoverageCounter {
+    pub fn new() -> CoverageCounter {
+        CoverageCounter {
+            counts: RefCell::new(HashMap::new()),
+            jumps: RefCell::new(HashMap::new()),
+            last_jump: RefCell::new(HashMap::new()),
+        }
+    }
+
+    /// count counts the instruction at pc of func, run by the fiber
+    pub fn count(&self, fiber: usize, func: FunctionKey, pc: usize, op: Opcode) {
+        *self.counts.borrow_mut().entry((func, pc)).or_insert(0) += 1;
+        let mut last_jump = self.last_jump.borrow_mut();
+        // the instruction after a jump is in the same frame
+        if let Some((jfunc, jpc)) = last_jump.remove(&fiber) {
+            let mut jumps = self.jumps.borrow_mut();
+            let entry = jumps.entry((jfunc, jpc)).or_insert((0, 0));
+            if jfunc == func && pc == jpc + 1 {
+                entry.1 += 1;
+            } else {
+                entry.0 += 1;
+            }
+        }
+        if is_branch(op) {
+            last_jump.insert(fiber, (func, pc));
+        }
+    }
+
+    /// finish maps what was counted to the source lines, the lines of the
+    /// functions that never ran included
+    pub fn finish(self, code: &ByteCode, fs: &FileSet) -> Coverage {
+        let counts = self.counts.into_inner();
+        let jumps = self.jumps.into_inner();
+        let names = func_names(code);
+        let mut files: BTreeMap<String, FileCoverage> = BTreeMap::new();
+        for (key, func) in code.objects.functions.iter() {
+            let mut first = None;
+            let last = func.code().len() - 1;
+            for (pc, pos) in func.pos().iter().enumerate() {
+                let pos = match pos {
+                    Some(p) => *p,
+                    None => continue,
+                };
+                // the return added at the closing brace is not a statement
+                if pc == last && func.code()[pc].op() == Opcode::RETURN {
+                    continue;
+                }
+                let p = fs.position(pos);
+                if !p.is_valid() {
+                    continue;
+                }
+                first.get_or_insert(p.clone());
+                let file = files
+                    .entry(p.filename.to_string())
+                    .or_insert_with(FileCoverage::default);
+                let line = file.lines.entry(p.line).or_insert(LineCoverage {
+                    count: 0,
+                    column: p.column,
+                    end_column: line_end(fs, pos),
+                    branches: vec![],
+                });
+                let count = counts.get(&(key, pc)).copied().unwrap_or(0);
+                line.count = line.count.max(count);
+                line.column = line.column.min(p.column);
+                if is_branch(func.code()[pc].op()) {
+                    let jumped = jumps.get(&(key, pc)).copied().unwrap_or((0, 0));
+                    line.branches
+                        .push(if count > 0 { Some(jumped) } else { None });
+                }
+            }
+            if let (Some(p), Some(name)) = (first, names.get(&key)) {
+                let calls = counts.get(&(key, 0)).copied().unwrap_or(0);
+                let file = files.get_mut(p.filename.as_str()).unwrap();
+                file.funcs.push((name.clone(), p.line, calls));
+            }
+        }
+        for file in files.values_mut() {
+            file.funcs.sort_by(|a, b| a.1.cmp(&b.1).then(a.0.cmp(&b.0)));
+        }
+        Coverage { files: files }
+    }
+}
+
+fn is_branch(op: Opcode) -> bool {
+    match op {
+        Opcode::JUMP_IF | Opcode::JUMP_IF_NOT | Opcode::SWITCH => true,
+        _ => false,
+    }
+}
+
+/// line_end returns the column after the last char of the line of pos
+fn line_end(fs: &FileSet, pos: usize) -> usize {
+    let file = fs.file(pos).unwrap();
+    let p = file.position(pos);
+    let lines = file.lines();
+    // the next line starts after the newline, the last one has none
+    let next = lines.get(p.line).copied().unwrap_or(file.size() + 1);
+    next - lines[p.line - 1]
+}
+
+struct LineCoverage {
+    // the most times an instruction of the line ran
+    count: u64,
+    column: usize,
+    end_column: usize,
+    // (jumped, didn't jump) of every branch, None if it never ran
+    branches: Vec<Option<(u64, u64)>>,
+}
+
+#[derive(Default)]
+struct FileCoverage {
+    lines: BTreeMap<usize, LineCoverage>,
+    // the name, the first line and the calls of every function
+    funcs: Vec<(String, usize, u64)>,
+}
+
+pub struct Coverage {
+    files: BTreeMap<String, FileCoverage>,
+}
+
+impl Coverage {
+    pub fn files(&self) -> Vec<&str> {
+        self.files.keys().map(|f| f.as_str()).collect()
+    }
+
+    /// lines returns the lines with code in the file, and how many times
+    /// they ran
+    pub fn lines(&self, file: &str) -> Vec<(usize, u64)> {
+        self.files.get(file).map_or(vec![], |f| {
+            f.lines.iter().map(|(l, c)| (*l, c.count)).collect()
+        })
+    }
+
+    /// branches returns the line of every branch in the file, with how many
+    /// times it jumped and didn't, or None if it never ran
+    pub fn branches(&self, file: &str) -> Vec<(usize, Option<(u64, u64)>)> {
+        self.files.get(file).map_or(vec![], |f| {
+            f.lines
+                .iter()
+                .flat_map(|(l, c)| c.branches.iter().map(move |b| (*l, *b)))
+                .collect()
+        })
+    }
+
+    /// percent returns the percentage of the lines with code that ran
+    pub fn percent(&self) -> f64 {
+        let lines = self.files.values().flat_map(|f| f.lines.values());
+        let (hit, total) = lines.fold((0, 0), |(h, t), l| (h + (l.count > 0) as usize, t + 1));
+        hit as f64 * 100.0 / total.max(1) as f64
+    }
+
+    /// write_lcov writes the lines, functions and branches in the LCOV
+    /// format of genhtml and most coverage services
+    pub fn write_lcov<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        for (name, file) in self.files.iter() {
+            writeln!(w, "TN:")?;
+            writeln!(w, "SF:{}", name)?;
+            for (func, line, _) in file.funcs.iter() {
+                writeln!(w, "FN:{},{}", line, func)?;
+            }
+            for (func, _, calls) in file.funcs.iter() {
+                writeln!(w, "FNDA:{},{}", calls, func)?;
+            }
+            writeln!(w, "FNF:{}", file.funcs.len())?;
+            let hit = file.funcs.iter().filter(|f| f.2 > 0).count();
+            writeln!(w, "FNH:{}", hit)?;
+            let (mut found, mut hit) = (0, 0);
+            for (line, cov) in file.lines.iter() {
+                for (i, b) in cov.branches.iter().enumerate() {
+                    let (jumped, not) = match b {
+                        Some((j, n)) => (j.to_string(), n.to_string()),
+                        None => ("-".to_string(), "-".to_string()),
+                    };
+                    writeln!(w, "BRDA:{},{},0,{}", line, i, jumped)?;
+                    writeln!(w, "BRDA:{},{},1,{}", line, i, not)?;
+                    found += 2;
+                    hit += b.map_or(0, |(j, n)| (j > 0) as usize + (n > 0) as usize);
+                }
+            }
+            writeln!(w, "BRF:{}", found)?;
+            writeln!(w, "BRH:{}", hit)?;
+            for (line, cov) in file.lines.iter() {
+                writeln!(w, "DA:{},{}", line, cov.count)?;
+            }
+            writeln!(w, "LF:{}", file.lines.len())?;
+            let hit = file.lines.values().filter(|l| l.count > 0).count();
+            writeln!(w, "LH:{}", hit)?;
+            writeln!(w, "end_of_record")?;
+        }
+        Ok(())
+    }
+
+    /// write_go writes the lines in the coverage.out format of Go, for
+    /// `go tool cover`, every line is a block of one statement
+    pub fn write_go<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        writeln!(w, "mode: count")?;
+        for (name, file) in self.files.iter() {
+            for (line, cov) in file.lines.iter() {
+                writeln!(
+                    w,
+                    "{}:{}.{},{}.{} 1 {}",
+                    name, line, cov.column, line, cov.end_column, cov.count
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,691 @@
+//! Debugger support. When a Debugger is attached, every fiber asks it before
+//! running an instruction whether to stop there, for a breakpoint or a step.
+//! A stop hands a snapshot of the fiber to the DebugHandler, and the whole
+//! program waits until the handler says how to go on.
+//!
+//! Breakpoints and steps work on lines: a fiber stops at the first
+//! instruction it runs of a line, each frame remembers the line it was last
+//! on, so returning from a call in the middle of a line is not a new line.
+//! Steps belong to the fiber that stopped, the other fibers keep running
+//! until they hit a breakpoint.
+
+use super::disasm::func_names;
+use super::instruction::OpIndex;
+use super::objects::FunctionKey;
+use super::value::GosValue;
+use super::vm::ByteCode;
+use goscript_parser::{FileSet, Position};
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::io::{BufRead, Write};
+use std::path::Path;
+use std::rc::Rc;
+
+/// the handler is asked whether to pause every POLL_INTERVAL instructions
+const POLL_INTERVAL: usize = 1 << 14;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StopReason {
+    Breakpoint(usize),
+    Step,
+    Pause,
+}
+
+/// Command is what the handler wants the program to do after a stop
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Command {
+    Continue,
+    StepInto,
+    StepOver,
+    StepOut,
+    Quit,
+}
+
+/// Action is what a fiber does before running an instruction
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Action {
+    Run,
+    Stop(StopReason),
+    Quit,
+}
+
+pub trait DebugHandler {
+    /// started is called before the program runs, stepping from here stops
+    /// at the first line of the program
+    fn started(&mut self, _bps: &mut Breakpoints) -> Command {
+        Command::Continue
+    }
+
+    /// running is called now and then while the program runs, returning true
+    /// pauses the fiber that is running
+    fn running(&mut self, _bps: &mut Breakpoints) -> bool {
+        false
+    }
+
+    fn stopped(&mut self, stop: &Stop, bps: &mut Breakpoints) -> Command;
+
+    /// exited is called when all the fibers are done
+    fn exited(&mut self) {}
+}
+
+/// a line in a file, the file is an index to LineTable::files
+type Line = (usize, usize);
+
+/// LineTable maps every instruction to its line
+struct LineTable {
+    files: Vec<String>,
+    lines: HashMap<FunctionKey, Vec<Option<Line>>>,
+}
+
+impl LineTable {
+    fn new(code: &ByteCode, fs: Option<&FileSet>) -> LineTable {
+        let mut files: Vec<String> = vec![];
+        let mut lines = HashMap::new();
+        for (key, func) in code.objects.functions.iter() {
+            let func_lines = func
+                .pos()
+                .iter()
+                .map(|p| {
+                    let pos = fs?.position((*p)?);
+                    let file = match files.iter().position(|f| f == pos.filename.as_str()) {
+                        Some(i) => i,
+                        None => {
+                            files.push(pos.filename.to_string());
+                            files.len() - 1
+                        }
+                    };
+                    Some((file, pos.line))
+                })
+                .collect();
+            lines.insert(key, func_lines);
+        }
+        LineTable {
+            files: files,
+            lines: lines,
+        }
+    }
+
+    #[inline]
+    fn line(&self, func: FunctionKey, pc: usize) -> Option<Line> {
+        self.lines.get(&func)?.get(pc).copied().flatten()
+    }
+}
+
+/// same_file compares the paths as canonical paths if the files exist, or
+/// else if one is a suffix of the other, so that a breakpoint can be set on
+/// x.gos or on the full path of it
+fn same_file(a: &str, b: &str) -> bool {
+    match (std::fs::canonicalize(a), std::fs::canonicalize(b)) {
+        (Ok(x), Ok(y)) => x == y,
+        _ => Path::new(a).ends_with(b) || Path::new(b).ends_with(a),
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Breakpoint {
+    pub id: usize,
+    pub file: String,
+    pub line: usize,
+}
+
+pub struct Breakpoints {
+    table: Rc<LineTable>,
+    list: Vec<Breakpoint>,
+    pcs: HashMap<(FunctionKey, usize), usize>,
+    next_id: usize,
+}
+
+impl Breakpoints {
+    fn new(table: Rc<LineTable>) -> Breakpoints {
+        Breakpoints {
+            table: table,
+            list: vec![],
+            pcs: HashMap::new(),
+            next_id: 1,
+        }
+    }
+
+    /// set adds a breakpoint and returns its id, or None if there is no code
+    /// on the line. The same line of an inlined function is in every function
+    /// it's inlined to.
+    pub fn set(&mut self, file: &str, line: usize) -> Option<usize> {
+        let files: Vec<usize> = (0..self.table.files.len())
+            .filter(|i| same_file(&self.table.files[*i], file))
+            .collect();
+        let files = &files;
+        let pcs: Vec<(FunctionKey, usize)> = self
+            .table
+            .lines
+            .iter()
+            .flat_map(|(func, lines)| {
+                lines.iter().enumerate().filter_map(move |(pc, l)| match l {
+                    Some((f, n)) if *n == line && files.contains(f) => Some((*func, pc)),
+                    _ => None,
+                })
+            })
+            .collect();
+        if pcs.is_empty() {
+            return None;
+        }
+        let id = self.next_id;
+        self.next_id += 1;
+        for pc in pcs.into_iter() {
+            self.pcs.insert(pc, id);
+        }
+        self.list.push(Breakpoint {
+            id: id,
+            file: file.to_string(),
+            line: line,
+        });
+        Some(id)
+    }
+
+    pub fn remove(&mut self, id: usize) -> bool {
+        let count = self.list.len();
+        self.list.retain(|b| b.id != id);
+        self.pcs.retain(|_, b| *b != id);
+        self.list.len() != count
+    }
+
+    /// clear removes the breakpoints in the file
+    pub fn clear(&mut self, file: &str) {
+        let ids: Vec<usize> = self
+            .list
+            .iter()
+            .filter(|b| same_file(&b.file, file))
+            .map(|b| b.id)
+            .collect();
+        for id in ids.into_iter() {
+            self.remove(id);
+        }
+    }
+
+    pub fn list(&self) -> &[Breakpoint] {
+        &self.list
+    }
+
+    #[inline]
+    fn at(&self, func: FunctionKey, pc: usize) -> Option<usize> {
+        self.pcs.get(&(func, pc)).copied()
+    }
+}
+
+struct Step {
+    cmd: Command,
+    depth: usize,
+    line: Option<Line>,
+}
+
+#[derive(Default)]
+struct FiberState {
+    // the line each frame was last on, by depth
+    lines: Vec<Option<Line>>,
+    step: Option<Step>,
+}
+
+pub struct Debugger<'a> {
+    code: &'a ByteCode,
+    fs: Option<&'a FileSet>,
+    names: HashMap<FunctionKey, String>,
+    table: Rc<LineTable>,
+    handler: RefCell<Box<dyn DebugHandler>>,
+    bps: RefCell<Breakpoints>,
+    fibers: RefCell<HashMap<usize, FiberState>>,
+    ticks: Cell<usize>,
+    quit: Cell<bool>,
+}
+
+impl<'a> Debugger<'a> {
+    pub fn new(
+        code: &'a ByteCode,
+        fs: Option<&'a FileSet>,
+        handler: Box<dyn DebugHandler>,
+    ) -> Debugger<'a> {
+        let table = Rc::new(LineTable::new(code, fs));
+        Debugger {
+            code: code,
+            fs: fs,
+            names: func_names(code),
+            table: table.clone(),
+            handler: RefCell::new(handler),
+            bps: RefCell::new(Breakpoints::new(table)),
+            fibers: RefCell::new(HashMap::new()),
+            ticks: Cell::new(0),
+            quit: Cell::new(false),
+        }
+    }
+
+    /// start asks the handler what to do before the main fiber runs, returns
+    /// false if the program shouldn't run at all
+    pub fn start(&self, main_fiber: usize) -> bool {
+        let cmd = self
+            .handler
+            .borrow_mut()
+            .started(&mut self.bps.borrow_mut());
+        self.resume(main_fiber, 0, None, cmd)
+    }
+
+    pub fn exit(&self) {
+        self.handler.borrow_mut().exited();
+    }
+
+    /// quitting is true after the handler quits, the fibers blocked on
+    /// channels are left as they are
+    pub fn quitting(&self) -> bool {
+        self.quit.get()
+    }
+
+    /// fiber_done is called when a fiber returns or panics
+    pub fn fiber_done(&self, fiber: usize) {
+        self.fibers.borrow_mut().remove(&fiber);
+    }
+
+    /// check is called by a fiber before it runs the instruction at pc of
+    /// func, depth is the number of its frames
+    pub fn check(&self, fiber: usize, depth: usize, func: FunctionKey, pc: usize) -> Action {
+        if self.quit.get() {
+            return Action::Quit;
+        }
+        let mut fibers = self.fibers.borrow_mut();
+        let state = fibers.entry(fiber).or_default();
+        let ticks = self.ticks.get() + 1;
+        self.ticks.set(ticks);
+        if ticks % POLL_INTERVAL == 0
+            && self
+                .handler
+                .borrow_mut()
+                .running(&mut self.bps.borrow_mut())
+        {
+            state.step = None;
+            return Action::Stop(StopReason::Pause);
+        }
+
+        let line = match self.table.line(func, pc) {
+            Some(l) => l,
+            None => return Action::Run,
+        };
+        // the frames deeper than this one are gone
+        state.lines.resize(depth + 1, None);
+        let new_line = state.lines[depth] != Some(line);
+        state.lines[depth] = Some(line);
+
+        if let Some(step) = &state.step {
+            let done = match step.cmd {
+                Command::StepInto => new_line && (depth != step.depth || Some(line) != step.line),
+                Command::StepOver => {
+                    depth < step.depth
+                        || (depth == step.depth && new_line && Some(line) != step.line)
+                }
+                Command::StepOut => depth < step.depth,
+                _ => false,
+            };
+            if done {
+                state.step = None;
+                return Action::Stop(StopReason::Step);
+            }
+        }
+        if new_line {
+            if let Some(id) = self.bps.borrow().at(func, pc) {
+                state.step = None;
+                return Action::Stop(StopReason::Breakpoint(id));
+            }
+        }
+        Action::Run
+    }
+
+    /// stop hands the frames of the stopped fiber to the handler, the
+    /// innermost first, and returns what the fiber does next
+    pub fn stop(&self, fiber: usize, reason: StopReason, frames: Vec<Frame>) -> Action {
+        let stop = Stop {
+            debugger: self,
+            fiber: fiber,
+            reason: reason,
+            frames: frames,
+        };
+        let cmd = self
+            .handler
+            .borrow_mut()
+            .stopped(&stop, &mut self.bps.borrow_mut());
+        let line = stop
+            .frames
+            .first()
+            .and_then(|f| self.table.line(f.func, f.pc));
+        if self.resume(fiber, stop.frames.len(), line, cmd) {
+            Action::Run
+        } else {
+            Action::Quit
+        }
+    }
+
+    fn resume(&self, fiber: usize, depth: usize, line: Option<Line>, cmd: Command) -> bool {
+        let mut fibers = self.fibers.borrow_mut();
+        let state = fibers.entry(fiber).or_default();
+        state.step = match cmd {
+            Command::Continue => None,
+            Command::Quit => {
+                self.quit.set(true);
+                return false;
+            }
+            _ => Some(Step {
+                cmd: cmd,
+                depth: depth,
+                line: line,
+            }),
+        };
+        true
+    }
+}
+
+/// Frame is a call frame of a stopped fiber
+pub struct Frame {
+    pub func: FunctionKey,
+    // the instruction to run, or the call in progress in the frames below
+    pub pc: usize,
+    // results, params and vars, indexed like FunctionVal::local_name
+    pub locals: Vec<GosValue>,
+    // the values of FunctionVal::up_ptrs, empty if it's not a closure
+    pub upvalues: Vec<GosValue>,
+}
+
+pub struct Stop<'a, 'b> {
+    debugger: &'b Debugger<'a>,
+    fiber: usize,
+    reason: StopReason,
+    frames: Vec<Frame>,
+}
+
+impl<'a, 'b> Stop<'a, 'b> {
+    pub fn fiber(&self) -> usize {
+        self.fiber
+    }
+
+    /// fibers returns the ids of the fibers that have run and are not done
+    pub fn fibers(&self) -> Vec<usize> {
+        let mut ids: Vec<usize> = self.debugger.fibers.borrow().keys().copied().collect();
+        ids.sort();
+        ids
+    }
+
+    pub fn reason(&self) -> StopReason {
+        self.reason
+    }
+
+    /// frames returns the frames of the fiber, the innermost first
+    pub fn frames(&self) -> &[Frame] {
+        &self.frames
+    }
+
+    pub fn func_name(&self, frame: usize) -> &str {
+        self.debugger
+            .names
+            .get(&self.frames[frame].func)
+            .map_or("?", |n| n.as_str())
+    }
+
+    pub fn position(&self, frame: usize) -> Option<Position> {
+        let f = &self.frames[frame];
+        let pos = self.debugger.code.objects.functions[f.func].pos()[f.pc]?;
+        Some(self.debugger.fs?.position(pos))
+    }
+
+    /// locals returns the named results, params and vars of the frame, all
+    /// of them, as the scopes of the vars are not known here
+    pub fn locals(&self, frame: usize) -> Vec<(&str, &GosValue)> {
+        let f = &self.frames[frame];
+        let func = &self.debugger.code.objects.functions[f.func];
+        f.locals
+            .iter()
+            .enumerate()
+            .filter_map(|(i, v)| Some((func.local_name(i as OpIndex)?, v)))
+            .filter(|(n, _)| *n != "_")
+            .collect()
+    }
+
+    pub fn upvalues(&self, frame: usize) -> Vec<(&str, &GosValue)> {
+        let f = &self.frames[frame];
+        let objs = &self.debugger.code.objects;
+        objs.functions[f.func]
+            .up_ptrs
+            .iter()
+            .zip(f.upvalues.iter())
+            .filter(|(d, _)| d.is_up_value)
+            .filter_map(|(d, v)| Some((objs.functions.get(d.func)?.local_name(d.index)?, v)))
+            .collect()
+    }
+
+    /// globals returns the vars and consts of the packages, named like
+    /// main.count
+    pub fn globals(&self) -> Vec<(String, GosValue)> {
+        let objs = &self.debugger.code.objects;
+        let mut vars = vec![];
+        for key in self.debugger.code.packages.iter() {
+            let pkg = &objs.packages[*key];
+            let mut members: Vec<(&String, &OpIndex)> = pkg
+                .member_indices()
+                .iter()
+                .filter(|(n, _)| !n.is_empty())
+                .collect();
+            members.sort();
+            for (name, i) in members.into_iter() {
+                let val = pkg.member(*i);
+                if let GosValue::Closure(_) = &*val {
+                    continue;
+                }
+                vars.push((format!("{}.{}", pkg.name(), name), val.clone()));
+            }
+        }
+        vars
+    }
+
+    /// lookup finds what the name means in the frame: a local, an upvalue, a
+    /// member of the package of the function, or a member of a package when
+    /// it's like pkg.name
+    pub fn lookup(&self, frame: usize, name: &str) -> Option<GosValue> {
+        if let Some((_, v)) = self
+            .locals(frame)
+            .into_iter()
+            .rev()
+            .find(|(n, _)| *n == name)
+        {
+            return Some(v.clone());
+        }
+        if let Some((_, v)) = self.upvalues(frame).into_iter().find(|(n, _)| *n == name) {
+            return Some(v.clone());
+        }
+        let objs = &self.debugger.code.objects;
+        let pkg = &objs.packages[objs.functions[self.frames[frame].func].package];
+        if let Some(i) = pkg.get_member_index(name) {
+            return Some(pkg.member(*i).clone());
+        }
+        let full = if name.contains('.') {
+            name.to_string()
+        } else {
+            format!("{}.{}", pkg.name(), name)
+        };
+        self.globals()
+            .into_iter()
+            .find(|(n, _)| *n == full)
+            .map(|(_, v)| v)
+    }
+}
+
+const HELP: &str = "\
+break file:line  set a breakpoint (b)
+delete id        delete a breakpoint
+breakpoints      list the breakpoints
+continue         run until a breakpoint (c)
+next             step over calls (n)
+step             step into calls (s)
+out              step out of the function (o)
+backtrace        list the frames (bt)
+frame n          select a frame (f)
+print name       print a variable (p)
+locals           print the local variables
+globals          print the package variables
+quit             stop the program (q)";
+
+/// Console is a DebugHandler with gdb like commands, reading from input and
+/// writing to output, which are usually stdin and stdout
+pub struct Console<R: BufRead, W: Write> {
+    input: R,
+    output: W,
+    frame: usize,
+}
+
+impl<R: BufRead, W: Write> Console<R, W> {
+    pub fn new(input: R, output: W) -> Console<R, W> {
+        Console {
+            input: input,
+            output: output,
+            frame: 0,
+        }
+    }
+
+    /// read prompts for a command, it's None at the end of the input
+    fn read(&mut self) -> Option<Vec<String>> {
+        write!(self.output, "(gos) ").ok()?;
+        self.output.flush().ok()?;
+        let mut line = String::new();
+        match self.input.read_line(&mut line) {
+            Ok(0) | Err(_) => None,
+            Ok(_) => Some(line.split_whitespace().map(|s| s.to_string()).collect()),
+        }
+    }
+
+    fn where_(&mut self, stop: &Stop, frame: usize) {
+        let pos = stop
+            .position(frame)
+            .map_or("<no debug info available>".to_string(), |p| {
+                format!("{}:{}", p.filename, p.line)
+            });
+        let _ = writeln!(
+            self.output,
+            "#{} {} at {}",
+            frame,
+            stop.func_name(frame),
+            pos
+        );
+    }
+
+    /// commands runs the commands until one resumes the program, the stop is
+    /// None before it runs
+    fn commands(&mut self, stop: Option<&Stop>, bps: &mut Breakpoints) -> Command {
+        loop {
+            let words = match self.read() {
+                Some(w) => w,
+                None => return Command::Quit,
+            };
+            let arg = words.get(1).map(|s| s.as_str());
+            let out = &mut self.output;
+            match words.first().map(|s| s.as_str()) {
+                None => {}
+                Some("c") | Some("continue") => return Command::Continue,
+                Some("n") | Some("next") => return Command::StepOver,
+                Some("s") | Some("step") => return Command::StepInto,
+                Some("o") | Some("out") => return Command::StepOut,
+                Some("q") | Some("quit") => return Command::Quit,
+                Some("b") | Some("break") => {
+                    let target = arg.and_then(|a| {
+                        let i = a.rfind(':')?;
+                        Some((&a[..i], a[i + 1..].parse::<usize>().ok()?))
+                    });
+                    let _ = match target {
+                        Some((file, line)) => match bps.set(file, line) {
+                            Some(id) => writeln!(out, "breakpoint {} at {}:{}", id, file, line),
+                            None => writeln!(out, "no code at {}:{}", file, line),
+                        },
+                        None => writeln!(out, "usage: break file:line"),
+                    };
+                }
+                Some("delete") => {
+                    let _ = match arg.and_then(|a| a.parse().ok()) {
+                        Some(id) if bps.remove(id) => Ok(()),
+                        _ => writeln!(out, "no such breakpoint"),
+                    };
+                }
+                Some("breakpoints") => {
+                    for b in bps.list().iter() {
+                        let _ = writeln!(out, "{} {}:{}", b.id, b.file, b.line);
+                    }
+                }
+                Some("help") => {
+                    let _ = writeln!(out, "{}", HELP);
+                }
+                Some(cmd) => match stop {
+                    Some(stop) => self.inspect(stop, cmd, arg),
+                    None => {
+                        let _ = writeln!(out, "the program is not running");
+                    }
+                },
+            }
+        }
+    }
+
+    fn inspect(&mut self, stop: &Stop, cmd: &str, arg: Option<&str>) {
+        match cmd {
+            "bt" | "backtrace" => {
+                for i in 0..stop.frames().len() {
+                    self.where_(stop, i);
+                }
+            }
+            "f" | "frame" => match arg.and_then(|a| a.parse().ok()) {
+                Some(i) if i < stop.frames().len() => {
+                    self.frame = i;
+                    self.where_(stop, i);
+                }
+                _ => {
+                    let _ = writeln!(self.output, "no such frame");
+                }
+            },
+            "p" | "print" => {
+                let _ = match arg.and_then(|a| Some((a, stop.lookup(self.frame, a)?))) {
+                    Some((name, val)) => writeln!(self.output, "{} = {}", name, val),
+                    None => writeln!(self.output, "no such variable"),
+                };
+            }
+            "locals" => {
+                for (name, val) in stop
+                    .locals(self.frame)
+                    .into_iter()
+                    .chain(stop.upvalues(self.frame).into_iter())
+                {
+                    let _ = writeln!(self.output, "{} = {}", name, val);
+                }
+            }
+            "globals" => {
+                for (name, val) in stop.globals().iter() {
+                    let _ = writeln!(self.output, "{} = {}", name, val);
+                }
+            }
+            _ => {
+                let _ = writeln!(self.output, "unknown command {}, try help", cmd);
+            }
+        }
+    }
+}
+
+impl<R: BufRead, W: Write> DebugHandler for Console<R, W> {
+    fn started(&mut self, bps: &mut Breakpoints) -> Command {
+        self.commands(None, bps)
+    }
+
+    fn stopped(&mut self, stop: &Stop, bps: &mut Breakpoints) -> Command {
+        self.frame = 0;
+        let _ = match stop.reason() {
+            StopReason::Breakpoint(id) => writeln!(
+                self.output,
+                "goroutine {} hit breakpoint {}",
+                stop.fiber(),
+                id
+            ),
+            StopReason::Step => writeln!(self.output, "goroutine {}", stop.fiber()),
+            StopReason::Pause => writeln!(self.output, "goroutine {} paused", stop.fiber()),
+        };
+        self.where_(stop, 0);
+        self.commands(Some(stop), bps)
+    }
+
+    fn exited(&mut self) {
+        let _ = writeln!(self.output, "the program exited");
+    }
+}
@@ -0,0 +1,391 @@
+//! Disassembler for ByteCode, lists every function with its constants, local
+//! variables and decoded instructions, in the spirit of `go tool objdump`.
+
+use super::instruction::{Instruction, OpIndex, Opcode, ValueType};
+use super::metadata::{GosMetadata, MetaCategory, MetadataType};
+use super::objects::{u64_to_key, FuncFlag, FunctionKey, FunctionVal, PackageKey};
+use super::value::GosValue;
+use super::vm::ByteCode;
+use goscript_parser::FileSet;
+use std::collections::HashMap;
+use std::fmt::{self, Display, Write};
+
+pub struct Disassembler<'a> {
+    bc: &'a ByteCode,
+    fs: Option<&'a FileSet>,
+    names: HashMap<FunctionKey, String>,
+    ids: HashMap<FunctionKey, usize>,
+}
+
+impl<'a> Disassembler<'a> {
+    pub fn new(bc: &'a ByteCode, fs: Option<&'a FileSet>) -> Disassembler<'a> {
+        let ids = bc
+            .objects
+            .functions
+            .keys()
+            .enumerate()
+            .map(|(i, k)| (k, i))
+            .collect();
+        Disassembler {
+            bc: bc,
+            fs: fs,
+            names: func_names(bc),
+            ids: ids,
+        }
+    }
+
+    /// func_name returns a Go style name for the function, function literals
+    /// are named after the function they are in, like main.main.func1
+    pub fn func_name(&self, key: FunctionKey) -> String {
+        match self.names.get(&key) {
+            Some(n) => n.clone(),
+            None => format!("#{}", self.ids[&key]),
+        }
+    }
+
+    pub fn write_func(&self, w: &mut dyn Write, key: FunctionKey) -> fmt::Result {
+        let func = &self.bc.objects.functions[key];
+        writeln!(
+            w,
+            "TEXT {} #{} params={} results={} locals={}",
+            self.func_name(key),
+            self.ids[&key],
+            func.param_count(),
+            func.ret_count(),
+            func.local_count(),
+        )?;
+        if func.consts.len() > 0 {
+            writeln!(w, "  consts:")?;
+            for (i, c) in func.consts.iter().enumerate() {
+                writeln!(w, "    {:<5} {}", i, self.value_repr(c))?;
+            }
+        }
+        let local_total = func.param_count() + func.ret_count() + func.local_count();
+        if local_total > 0 {
+            writeln!(w, "  locals:")?;
+            for i in 0..local_total {
+                let kind = if i < func.ret_count() {
+                    "result"
+                } else if i < func.ret_count() + func.param_count() {
+                    "param"
+                } else {
+                    "var"
+                };
+                let name = func.local_name(i as OpIndex).unwrap_or("_");
+                writeln!(w, "    {:<5} {:<6} {}", i, kind, name)?;
+            }
+        }
+        if func.up_ptrs.len() > 0 {
+            writeln!(w, "  upvalues:")?;
+            for (i, d) in func.up_ptrs.iter().enumerate() {
+                let owner = self.bc.objects.functions.get(d.func);
+                let name = owner.and_then(|f| f.local_name(d.index)).unwrap_or("_");
+                writeln!(
+                    w,
+                    "    {:<5} {}.{} ({:?})",
+                    i,
+                    self.func_name(d.func),
+                    name,
+                    d.typ
+                )?;
+            }
+        }
+        writeln!(w, "  code:")?;
+        let code = func.code();
+        let mut pc = 0;
+        while pc < code.len() {
+            let inst = &code[pc];
+            let pos = match (func.pos()[pc], self.fs) {
+                (Some(p), Some(fs)) => fs.position(p).to_string(),
+                (Some(p), None) => p.to_string(),
+                (None, _) => "-".to_string(),
+            };
+            let (operands, comment) = self.operands(func, pc);
+            let mut line = format!(
+                "    {:<24} {:<5} {:<22} {:<24} {}",
+                pos,
+                pc,
+                inst.op().text(),
+                types_repr(inst),
+                operands
+            );
+            if let Some(c) = comment {
+                write!(line, " ; {}", c)?;
+            }
+            writeln!(w, "{}", line.trim_end())?;
+            // the package key is stored in the next "instruction"
+            pc += if has_pkg_key(inst.op()) { 2 } else { 1 };
+        }
+        Ok(())
+    }
+
+    fn operands(&self, func: &FunctionVal, pc: usize) -> (String, Option<String>) {
+        let code = func.code();
+        let inst = &code[pc];
+        let op = inst.op();
+        if op.is_reg_form() {
+            return self.reg_operands(func, inst);
+        }
+        let mut operands = if uses_imm824(op) {
+            let (i0, i1) = inst.imm824();
+            if i0 >= 0 && op != Opcode::CAST && op != Opcode::LOAD_LOCAL_ADD_IMM {
+                format!("{} {}", Instruction::index2code(i0).text(), i1)
+            } else {
+                format!("{}, {}", i0, i1)
+            }
+        } else {
+            inst.imm().to_string()
+        };
+        if op.uses_t2_as_index() {
+            write!(operands, ", {}", inst.t2_as_index()).unwrap();
+        }
+
+        let imm = if uses_imm824(op) {
+            inst.imm824().1
+        } else {
+            inst.imm()
+        };
+        let comment = match op {
+            Opcode::PUSH_CONST | Opcode::LITERAL | Opcode::BIND_METHOD | Opcode::TYPE_ASSERT => {
+                func.consts.get(imm as usize).map(|c| self.value_repr(c))
+            }
+            Opcode::LOAD_LOCAL
+            | Opcode::STORE_LOCAL
+            | Opcode::REF_LOCAL
+            | Opcode::LOAD_LOCAL_ADD_IMM => func.local_name(imm).map(|x| x.to_string()),
+            Opcode::LOAD_UPVALUE | Opcode::STORE_UPVALUE | Opcode::REF_UPVALUE => {
+                func.up_ptrs.get(imm as usize).and_then(|d| {
+                    let owner = self.bc.objects.functions.get(d.func)?;
+                    owner.local_name(d.index).map(|x| x.to_string())
+                })
+            }
+            Opcode::LOAD_PKG_FIELD | Opcode::STORE_PKG_FIELD | Opcode::REF_PKG_MEMBER => {
+                let key: PackageKey = u64_to_key(code[pc + 1].get_u64());
+                self.bc.objects.packages.get(key).map(|pkg| {
+                    // the constructor of the package is the member without a name
+                    let name = pkg
+                        .member_indices()
+                        .iter()
+                        .find_map(|(n, i)| if *i == imm { Some(n.as_str()) } else { None })
+                        .map_or("?", |n| if n.is_empty() { "init" } else { n });
+                    format!("{}.{}", pkg.name(), name)
+                })
+            }
+            Opcode::IMPORT => self
+                .bc
+                .packages
+                .get(imm as usize)
+                .map(|k| format!("import {}", self.bc.objects.packages[*k].name())),
+            Opcode::JUMP
+            | Opcode::JUMP_IF
+            | Opcode::JUMP_IF_NOT
+            | Opcode::SWITCH
+            | Opcode::RANGE => Some(format!("-> {}", pc as OpIndex + 1 + imm)),
+            _ => None,
+        };
+        (operands, comment)
+    }
+
+    fn reg_operands(&self, func: &FunctionVal, inst: &Instruction) -> (String, Option<String>) {
+        let op = inst.op();
+        // the ops after ADD_RI take a constant as the second operand
+        let imm = op as u8 >= Opcode::ADD_RI as u8;
+        let (d, a, b) = if imm { inst.reg_imm() } else { inst.reg() };
+        let name = |i| func.local_name(i).unwrap_or("_");
+        let dest = if d == Instruction::REG_STACK {
+            "stack"
+        } else {
+            name(d)
+        };
+        let comment = match op {
+            Opcode::LOAD_STRUCT_FIELD_R => format!("{} <- {}.#{}", dest, name(a), b),
+            Opcode::LOAD_INDEX_R => format!("{} <- {}[{}]", dest, name(a), name(b)),
+            _ if imm => format!("{} <- {}, {}", dest, name(a), b),
+            _ => format!("{} <- {}, {}", dest, name(a), name(b)),
+        };
+        (format!("{}, {}, {}", d, a, b), Some(comment))
+    }
+
+    fn value_repr(&self, val: &GosValue) -> String {
+        match val {
+            GosValue::Str(s) => format!("Str {:?}", s.as_str()),
+            GosValue::Function(k) => format!("Function {}", self.func_name(*k)),
+            GosValue::Metadata(m) => format!("Metadata {}", self.meta_repr(m)),
+            GosValue::Nil(m) => format!("Nil {}", self.meta_repr(m)),
+            _ => format!("{:?} {}", val.get_type(), val),
+        }
+    }
+
+    /// meta_repr describes a type, named types are not expanded since their
+    /// names are not kept in the metadata and they can be recursive
+    fn meta_repr(&self, m: &GosMetadata) -> String {
+        let (key, category, ptrs) = match m {
+            GosMetadata::Untyped => return "untyped".to_string(),
+            GosMetadata::NonPtr(k, c) => (k, c, 0),
+            GosMetadata::Ptr1(k, c) => (k, c, 1),
+            GosMetadata::Ptr2(k, c) => (k, c, 2),
+            GosMetadata::Ptr3(k, c) => (k, c, 3),
+            GosMetadata::Ptr4(k, c) => (k, c, 4),
+            GosMetadata::Ptr5(k, c) => (k, c, 5),
+            GosMetadata::Ptr6(k, c) => (k, c, 6),
+            GosMetadata::Ptr7(k, c) => (k, c, 7),
+        };
+        let metas = &self.bc.objects.metas;
+        let t = match &metas[*key] {
+            MetadataType::Bool => "bool".to_string(),
+            MetadataType::Int => "int".to_string(),
+            MetadataType::Int8 => "int8".to_string(),
+            MetadataType::Int16 => "int16".to_string(),
+            MetadataType::Int32 => "int32".to_string(),
+            MetadataType::Int64 => "int64".to_string(),
+            MetadataType::Uint => "uint".to_string(),
+            MetadataType::Uint8 => "uint8".to_string(),
+            MetadataType::Uint16 => "uint16".to_string(),
+            MetadataType::Uint32 => "uint32".to_string(),
+            MetadataType::Uint64 => "uint64".to_string(),
+            MetadataType::Float32 => "float32".to_string(),
+            MetadataType::Float64 => "float64".to_string(),
+            MetadataType::Complex64 => "complex64".to_string(),
+            MetadataType::Complex128 => "complex128".to_string(),
+            MetadataType::Str(_) => "string".to_string(),
+            MetadataType::SliceOrArray(elem, size) => match category {
+                MetaCategory::Array | MetaCategory::ArrayType => {
+                    format!("[{}]{}", size, self.meta_repr(elem))
+                }
+                _ => format!("[]{}", self.meta_repr(elem)),
+            },
+            MetadataType::Struct(f, _) => format!("struct{{{} fields}}", f.fields.len()),
+            MetadataType::Signature(s) => format!(
+                "func({}) ({})",
+                s.params
+                    .iter()
+                    .map(|x| self.meta_repr(x))
+                    .collect::<Vec<String>>()
+                    .join(", "),
+                s.results
+                    .iter()
+                    .map(|x| self.meta_repr(x))
+                    .collect::<Vec<String>>()
+                    .join(", "),
+            ),
+            MetadataType::Map(k, v) => format!("map[{}]{}", self.meta_repr(k), self.meta_repr(v)),
+            MetadataType::Interface(f) => format!("interface{{{} methods}}", f.fields.len()),
+            MetadataType::Channel(_, elem) => format!("chan {}", self.meta_repr(elem)),
+            MetadataType::Named(_, _) => {
+                let index = metas.keys().position(|k| k == *key).unwrap();
+                format!("named#{}", index)
+            }
+        };
+        let t = format!("{}{}", "*".repeat(ptrs), t);
+        match category {
+            MetaCategory::Type | MetaCategory::ArrayType => format!("type {}", t),
+            _ => t,
+        }
+    }
+}
+
+impl<'a> Display for Disassembler<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, key) in self.bc.objects.functions.keys().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            self.write_func(f, key)?;
+        }
+        Ok(())
+    }
+}
+
+#[inline]
+fn has_pkg_key(op: Opcode) -> bool {
+    match op {
+        Opcode::LOAD_PKG_FIELD | Opcode::STORE_PKG_FIELD | Opcode::REF_PKG_MEMBER => true,
+        _ => false,
+    }
+}
+
+#[inline]
+fn uses_imm824(op: Opcode) -> bool {
+    match op {
+        Opcode::STORE_LOCAL
+        | Opcode::STORE_UPVALUE
+        | Opcode::STORE_INDEX
+        | Opcode::STORE_INDEX_IMM
+        | Opcode::STORE_FIELD
+        | Opcode::STORE_STRUCT_FIELD
+        | Opcode::STORE_PKG_FIELD
+        | Opcode::STORE_DEREF
+        | Opcode::CAST
+        | Opcode::LOAD_LOCAL_ADD_IMM => true,
+        _ => false,
+    }
+}
+
+/// types_repr reads the raw bytes, as some ops keep other data in the type
+/// slots, which must not be turned into a ValueType
+fn types_repr(inst: &Instruction) -> String {
+    (0..inst.op().type_count())
+        .map(|i| (inst.get_u64() >> (8 * (2 - i) + 32)) as u8)
+        .filter(|t| *t != ValueType::Zero as u8)
+        .map(|t| {
+            if t <= ValueType::FlagE as u8 {
+                let vt: ValueType = unsafe { std::mem::transmute(t) };
+                format!("{:?}", vt)
+            } else {
+                t.to_string()
+            }
+        })
+        .collect::<Vec<String>>()
+        .join(",")
+}
+
+/// func_names names every function it can find a name for, the functions
+/// without one are left out, see Disassembler::func_name
+pub fn func_names(bc: &ByteCode) -> HashMap<FunctionKey, String> {
+    let objs = &bc.objects;
+    let mut names = HashMap::new();
+    names.insert(bc.entry, "<entry>".to_string());
+    for (key, f) in objs.functions.iter() {
+        if f.flag == FuncFlag::PkgCtor {
+            names.insert(key, format!("{}.init", objs.packages[f.package].name()));
+        }
+    }
+    for pkey in bc.packages.iter() {
+        let pkg = &objs.packages[*pkey];
+        for (name, i) in pkg.member_indices().iter() {
+            if let GosValue::Closure(c) = &*pkg.member(*i) {
+                if let Some(f) = c.0.borrow().func {
+                    names.entry(f).or_insert(format!("{}.{}", pkg.name(), name));
+                }
+            }
+        }
+    }
+    for m in objs.metas.values() {
+        if let MetadataType::Named(methods, _) = m {
+            for (name, i) in methods.mapping.iter() {
+                if let Some(f) = methods.members[*i as usize].borrow().func {
+                    let pkg = objs.packages[objs.functions[f].package].name();
+                    names
+                        .entry(f)
+                        .or_insert(format!("{}.(method).{}", pkg, name));
+                }
+            }
+        }
+    }
+    // function literals are constants of the function they are in
+    let mut queue: Vec<FunctionKey> = names.keys().copied().collect();
+    while let Some(parent) = queue.pop() {
+        let mut count = 0;
+        for c in objs.functions[parent].consts.iter() {
+            if let GosValue::Function(f) = c {
+                if !names.contains_key(f) {
+                    count += 1;
+                    let name = format!("{}.func{}", names[&parent], count);
+                    names.insert(*f, name);
+                    queue.push(*f);
+                }
+            }
+        }
+    }
+    names
+}
@@ -181,27 +181,17 @@ fn break_cycle(obj: &mut GosValue) {
     };
 }
 
-/// put the non-zero-rc on the left, and the others on the right
+/// put the non-zero-rc on the left, and the others on the right, returns
+/// where the others start
 fn partition_to_scan(to_scan: &mut Vec<GosValue>) -> usize {
-    let len = to_scan.len();
-    if len == 0 {
-        return 0;
-    }
-    let mut p0 = 0;
-    let mut p1 = len - 1;
-    loop {
-        while p0 < len - 1 && to_scan[p0].rc() > 0 {
-            p0 += 1;
-        }
-        while p1 > 1 && to_scan[p1].rc() <= 0 {
-            p1 -= 1;
-        }
-        if p0 >= p1 {
-            break;
+    let mut boundary = 0;
+    for i in 0..to_scan.len() {
+        if to_scan[i].rc() > 0 {
+            to_scan.swap(boundary, i);
+            boundary += 1;
         }
-        to_scan.swap(p0, p1);
     }
-    p0
+    boundary
 }
 
 pub fn gc(objs: &GcoVec) {
@@ -0,0 +1,92 @@
+//! Inline caches remember the results of lookups that depend on the dynamic
+//! type of a value, so that the instructions doing them only pay for the
+//! lookup the first time they see a type.
+
+use super::instruction::{Instruction, Opcode};
+use super::metadata::GosMetadata;
+use super::objects::FunctionKey;
+use super::vm::ByteCode;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+/// the number of types a cache remembers, a site seeing more types than this
+/// is megamorphic and does the lookup every time for the types not cached
+const ENTRIES: usize = 4;
+
+/// the ic index of the instructions that don't have a cache
+pub const NO_CACHE: usize = 0xffff;
+
+#[derive(Debug)]
+pub struct InlineCache<T: Clone> {
+    entries: RefCell<Vec<(GosMetadata, T)>>,
+}
+
+impl<T: Clone> InlineCache<T> {
+    pub fn new() -> InlineCache<T> {
+        InlineCache {
+            entries: RefCell::new(Vec::with_capacity(ENTRIES)),
+        }
+    }
+
+    #[inline]
+    pub fn get(&self, meta: &GosMetadata) -> Option<T> {
+        self.entries
+            .borrow()
+            .iter()
+            .find_map(|(m, v)| if m == meta { Some(v.clone()) } else { None })
+    }
+
+    #[inline]
+    pub fn insert(&self, meta: GosMetadata, val: T) {
+        let mut entries = self.entries.borrow_mut();
+        if entries.len() < ENTRIES {
+            entries.push((meta, val));
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.borrow().len()
+    }
+}
+
+/// InlineCaches are the caches of a running program:
+/// - every TYPE has its own cache, from the type of the value to the index of
+/// the instruction the type switch goes to
+/// - converting an interface to another interface looks up the methods of
+/// the dynamic type of the value, there's a cache for every target interface
+/// shared by the conversions to it, from the named type to the methods
+#[derive(Debug)]
+pub struct InlineCaches {
+    pub types: Vec<InlineCache<usize>>,
+    pub ifaces: Vec<InlineCache<Rc<Vec<FunctionKey>>>>,
+}
+
+impl InlineCaches {
+    /// new creates the caches of the program, and links the instructions to
+    /// their caches
+    pub fn new(bc: &mut ByteCode) -> InlineCaches {
+        let mut types = vec![];
+        for func in bc.objects.functions.values_mut() {
+            for i in 0..func.code().len() {
+                if func.code()[i].op() == Opcode::TYPE {
+                    let index = if types.len() < NO_CACHE {
+                        types.push(InlineCache::new());
+                        types.len() - 1
+                    } else {
+                        NO_CACHE
+                    };
+                    func.instruction_mut(i).set_ic_index(index);
+                }
+            }
+        }
+        InlineCaches {
+            types: types,
+            ifaces: bc.ifaces.iter().map(|_| InlineCache::new()).collect(),
+        }
+    }
+
+    #[inline]
+    pub fn type_cache(&self, inst: &Instruction) -> Option<&InlineCache<usize>> {
+        self.types.get(inst.ic_index())
+    }
+}
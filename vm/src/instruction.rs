@@ -1,6 +1,8 @@
 #![allow(dead_code)]
 #![allow(non_camel_case_types)]
+use super::serialize::{invalid_data, Decode, Decoder, Encode, Encoder};
 use std::fmt;
+use std::io;
 
 pub type OpIndex = i32;
 
@@ -100,6 +102,58 @@ pub enum Opcode {
     RECOVER,    // for built-in function recover
     ASSERT,     // for built-in function assert
     FFI,        // for built-in function native
+
+    // fused instructions, only emitted by the peephole optimizer
+    LOAD_LOCAL_ADD_IMM, // LOAD_LOCAL + PUSH_IMM + ADD
+
+    // register form, the operands are locals, see Instruction::new_reg
+    ADD_R,
+    SUB_R,
+    MUL_R,
+    QUO_R,
+    REM_R,
+    AND_R,
+    OR_R,
+    XOR_R,
+    AND_NOT_R,
+    EQL_R,
+    NEQ_R,
+    LSS_R,
+    GTR_R,
+    LEQ_R,
+    GEQ_R,
+    LOAD_STRUCT_FIELD_R, // the third operand is the field index
+    LOAD_INDEX_R,        // a slice, array or string indexed by an int
+    // register form with an int constant as the second operand
+    ADD_RI,
+    EQL_RI,
+    NEQ_RI,
+    LSS_RI,
+    GTR_RI,
+    LEQ_RI,
+    GEQ_RI,
+
+    // type-specialised forms of the stack ops, see Opcode::specialise
+    ADD_INT,
+    SUB_INT,
+    MUL_INT,
+    EQL_INT,
+    NEQ_INT,
+    LSS_INT,
+    GTR_INT,
+    LEQ_INT,
+    GEQ_INT,
+    ADD_IMM_INT, // PUSH_IMM + ADD_INT
+    ADD_FLOAT64,
+    SUB_FLOAT64,
+    MUL_FLOAT64,
+    QUO_FLOAT64,
+    EQL_FLOAT64,
+    NEQ_FLOAT64,
+    LSS_FLOAT64,
+    GTR_FLOAT64,
+    LEQ_FLOAT64,
+    GEQ_FLOAT64,
 }
 
 impl Opcode {
@@ -119,7 +173,7 @@ impl Opcode {
             Opcode::POP => ("POP", -1),
             Opcode::LOAD_LOCAL => ("LOAD_LOCAL", 1),
             Opcode::STORE_LOCAL => ("STORE_LOCAL", 0),
-            Opcode::LOAD_UPVALUE => ("LOAD_LOCAL", 1),
+            Opcode::LOAD_UPVALUE => ("LOAD_UPVALUE", 1),
             Opcode::STORE_UPVALUE => ("STORE_UPVALUE", 0),
             Opcode::LOAD_INDEX => ("LOAD_INDEX", -1),
             Opcode::STORE_INDEX => ("STORE_INDEX", 0),
@@ -197,6 +251,99 @@ impl Opcode {
             Opcode::RECOVER => ("RECOVER", 1),
             Opcode::ASSERT => ("ASSERT", 0),
             Opcode::FFI => ("FFI", 0),
+
+            Opcode::LOAD_LOCAL_ADD_IMM => ("LOAD_LOCAL_ADD_IMM", 1),
+
+            Opcode::ADD_R => ("ADD_R", 0),
+            Opcode::SUB_R => ("SUB_R", 0),
+            Opcode::MUL_R => ("MUL_R", 0),
+            Opcode::QUO_R => ("QUO_R", 0),
+            Opcode::REM_R => ("REM_R", 0),
+            Opcode::AND_R => ("AND_R", 0),
+            Opcode::OR_R => ("OR_R", 0),
+            Opcode::XOR_R => ("XOR_R", 0),
+            Opcode::AND_NOT_R => ("AND_NOT_R", 0),
+            Opcode::EQL_R => ("EQL_R", 0),
+            Opcode::NEQ_R => ("NEQ_R", 0),
+            Opcode::LSS_R => ("LSS_R", 0),
+            Opcode::GTR_R => ("GTR_R", 0),
+            Opcode::LEQ_R => ("LEQ_R", 0),
+            Opcode::GEQ_R => ("GEQ_R", 0),
+            Opcode::LOAD_STRUCT_FIELD_R => ("LOAD_STRUCT_FIELD_R", 0),
+            Opcode::LOAD_INDEX_R => ("LOAD_INDEX_R", 0),
+            Opcode::ADD_RI => ("ADD_RI", 0),
+            Opcode::EQL_RI => ("EQL_RI", 0),
+            Opcode::NEQ_RI => ("NEQ_RI", 0),
+            Opcode::LSS_RI => ("LSS_RI", 0),
+            Opcode::GTR_RI => ("GTR_RI", 0),
+            Opcode::LEQ_RI => ("LEQ_RI", 0),
+            Opcode::GEQ_RI => ("GEQ_RI", 0),
+
+            Opcode::ADD_INT => ("ADD_INT", -1),
+            Opcode::SUB_INT => ("SUB_INT", -1),
+            Opcode::MUL_INT => ("MUL_INT", -1),
+            Opcode::EQL_INT => ("EQL_INT", -1),
+            Opcode::NEQ_INT => ("NEQ_INT", -1),
+            Opcode::LSS_INT => ("LSS_INT", -1),
+            Opcode::GTR_INT => ("GTR_INT", -1),
+            Opcode::LEQ_INT => ("LEQ_INT", -1),
+            Opcode::GEQ_INT => ("GEQ_INT", -1),
+            Opcode::ADD_IMM_INT => ("ADD_IMM_INT", 0),
+            Opcode::ADD_FLOAT64 => ("ADD_FLOAT64", -1),
+            Opcode::SUB_FLOAT64 => ("SUB_FLOAT64", -1),
+            Opcode::MUL_FLOAT64 => ("MUL_FLOAT64", -1),
+            Opcode::QUO_FLOAT64 => ("QUO_FLOAT64", -1),
+            Opcode::EQL_FLOAT64 => ("EQL_FLOAT64", -1),
+            Opcode::NEQ_FLOAT64 => ("NEQ_FLOAT64", -1),
+            Opcode::LSS_FLOAT64 => ("LSS_FLOAT64", -1),
+            Opcode::GTR_FLOAT64 => ("GTR_FLOAT64", -1),
+            Opcode::LEQ_FLOAT64 => ("LEQ_FLOAT64", -1),
+            Opcode::GEQ_FLOAT64 => ("GEQ_FLOAT64", -1),
+        }
+    }
+
+    /// specialise returns the form of a stack op that only works on values of
+    /// type t, so that the VM doesn't have to look at the type when running it.
+    /// The specialised instruction still carries t in t0.
+    pub fn specialise(&self, t: ValueType) -> Option<Opcode> {
+        match (self, t) {
+            (Opcode::ADD, ValueType::Int) => Some(Opcode::ADD_INT),
+            (Opcode::SUB, ValueType::Int) => Some(Opcode::SUB_INT),
+            (Opcode::MUL, ValueType::Int) => Some(Opcode::MUL_INT),
+            (Opcode::EQL, ValueType::Int) => Some(Opcode::EQL_INT),
+            (Opcode::NEQ, ValueType::Int) => Some(Opcode::NEQ_INT),
+            (Opcode::LSS, ValueType::Int) => Some(Opcode::LSS_INT),
+            (Opcode::GTR, ValueType::Int) => Some(Opcode::GTR_INT),
+            (Opcode::LEQ, ValueType::Int) => Some(Opcode::LEQ_INT),
+            (Opcode::GEQ, ValueType::Int) => Some(Opcode::GEQ_INT),
+            (Opcode::ADD, ValueType::Float64) => Some(Opcode::ADD_FLOAT64),
+            (Opcode::SUB, ValueType::Float64) => Some(Opcode::SUB_FLOAT64),
+            (Opcode::MUL, ValueType::Float64) => Some(Opcode::MUL_FLOAT64),
+            (Opcode::QUO, ValueType::Float64) => Some(Opcode::QUO_FLOAT64),
+            (Opcode::EQL, ValueType::Float64) => Some(Opcode::EQL_FLOAT64),
+            (Opcode::NEQ, ValueType::Float64) => Some(Opcode::NEQ_FLOAT64),
+            (Opcode::LSS, ValueType::Float64) => Some(Opcode::LSS_FLOAT64),
+            (Opcode::GTR, ValueType::Float64) => Some(Opcode::GTR_FLOAT64),
+            (Opcode::LEQ, ValueType::Float64) => Some(Opcode::LEQ_FLOAT64),
+            (Opcode::GEQ, ValueType::Float64) => Some(Opcode::GEQ_FLOAT64),
+            _ => None,
+        }
+    }
+
+    /// generic is the reverse of specialise, other ops are returned as they are
+    pub fn generic(&self) -> Opcode {
+        match self {
+            Opcode::ADD_INT | Opcode::ADD_FLOAT64 => Opcode::ADD,
+            Opcode::SUB_INT | Opcode::SUB_FLOAT64 => Opcode::SUB,
+            Opcode::MUL_INT | Opcode::MUL_FLOAT64 => Opcode::MUL,
+            Opcode::QUO_FLOAT64 => Opcode::QUO,
+            Opcode::EQL_INT | Opcode::EQL_FLOAT64 => Opcode::EQL,
+            Opcode::NEQ_INT | Opcode::NEQ_FLOAT64 => Opcode::NEQ,
+            Opcode::LSS_INT | Opcode::LSS_FLOAT64 => Opcode::LSS,
+            Opcode::GTR_INT | Opcode::GTR_FLOAT64 => Opcode::GTR,
+            Opcode::LEQ_INT | Opcode::LEQ_FLOAT64 => Opcode::LEQ,
+            Opcode::GEQ_INT | Opcode::GEQ_FLOAT64 => Opcode::GEQ,
+            op => *op,
         }
     }
 
@@ -204,6 +351,41 @@ impl Opcode {
         let (t, _) = self.property();
         t
     }
+
+    /// is_reg_form returns true for the register forms, whose t1 and t2 are
+    /// the space of the destination register
+    #[inline]
+    pub fn is_reg_form(&self) -> bool {
+        *self as u8 >= Opcode::ADD_R as u8 && *self as u8 <= Opcode::GEQ_RI as u8
+    }
+
+    /// uses_t2_as_index returns true for the ops that use the space of t2
+    /// for a small integer instead of a type
+    #[inline]
+    pub fn uses_t2_as_index(&self) -> bool {
+        match self {
+            Opcode::LOAD_INDEX
+            | Opcode::LOAD_INDEX_IMM
+            | Opcode::STORE_INDEX_IMM
+            | Opcode::STORE_FIELD
+            | Opcode::STORE_STRUCT_FIELD
+            | Opcode::TYPE_ASSERT
+            | Opcode::TYPE => true,
+            _ => false,
+        }
+    }
+
+    /// type_count returns how many of t0, t1 and t2 are types
+    #[inline]
+    pub fn type_count(&self) -> usize {
+        if self.is_reg_form() {
+            1
+        } else if self.uses_t2_as_index() {
+            2
+        } else {
+            3
+        }
+    }
 }
 
 impl fmt::Display for Opcode {
@@ -215,7 +397,7 @@ impl fmt::Display for Opcode {
 
 pub const COPYABLE_END: ValueType = ValueType::Package;
 
-#[derive(Copy, Clone, Eq, PartialEq, Debug, Ord, PartialOrd)]
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Ord, PartialOrd, Hash)]
 #[repr(u8)]
 pub enum ValueType {
     Zero, //place holder
@@ -279,6 +461,9 @@ impl ValueType {
 /// or
 /// |    8bit   |    8bit   |    8bit   |    8bit   |    8bit      |    24bit     |
 /// |  Opcode   |  <TypeA>  |  <TypeB>  |    ext    |     ext      |   immediate  |
+/// or, for the register form
+/// |    8bit   |    8bit   |    16bit     |    16bit     |    16bit     |
+/// |  Opcode   |  <TypeA>  |    dest      |   operand a  |   operand b  |
 /// or
 /// | package_key|
 #[derive(Clone, Copy)]
@@ -287,6 +472,8 @@ pub struct Instruction {
 }
 
 impl Instruction {
+    pub const REG_STACK: OpIndex = 0xffff;
+
     pub fn new(
         op: Opcode,
         type0: Option<ValueType>,
@@ -311,6 +498,16 @@ impl Instruction {
         inst
     }
 
+    /// new_reg creates an instruction in the register form, where `a` and
+    /// `b` are indices of locals and the result goes to the local `dest`, or
+    /// onto the stack if `dest` is REG_STACK
+    pub fn new_reg(op: Opcode, t: ValueType, dest: OpIndex, a: OpIndex, b: OpIndex) -> Instruction {
+        let val = (op as u64) << (8 * 3 + 32) | (t as u64) << (8 * 2 + 32);
+        Instruction {
+            val: val | (dest as u64 & 0xffff) << 32 | (a as u64 & 0xffff) << 16 | b as u64 & 0xffff,
+        }
+    }
+
     #[inline]
     pub fn from_u64(v: u64) -> Instruction {
         Instruction { val: v }
@@ -344,6 +541,14 @@ impl Instruction {
         self.val = (self.val & 0xffff_ff00_ffff_ffff) | val64;
     }
 
+    /// set_ic_index sets the index of the inline cache of the instruction to
+    /// the space of t0 and t1, used by TYPE
+    #[inline]
+    pub fn set_ic_index(&mut self, index: usize) {
+        let val64 = (index as u64 & 0xffff) << (8 + 32);
+        self.val = (self.val & 0xff00_00ff_ffff_ffff) | val64;
+    }
+
     #[inline]
     pub fn get_u64(&self) -> u64 {
         self.val
@@ -379,6 +584,11 @@ impl Instruction {
         ival as OpIndex
     }
 
+    #[inline]
+    pub fn ic_index(&self) -> usize {
+        ((self.val >> (8 + 32)) & 0xffff) as usize
+    }
+
     #[inline]
     pub fn imm(&self) -> OpIndex {
         unsafe { std::mem::transmute((self.val & 0xffff_ffff) as u32) }
@@ -395,6 +605,22 @@ impl Instruction {
         (i0 as OpIndex, i1)
     }
 
+    #[inline]
+    pub fn reg(&self) -> (OpIndex, OpIndex, OpIndex) {
+        (
+            ((self.val >> 32) & 0xffff) as OpIndex,
+            ((self.val >> 16) & 0xffff) as OpIndex,
+            (self.val & 0xffff) as OpIndex,
+        )
+    }
+
+    /// reg_imm is reg for the ops whose second operand is a 16bit constant
+    #[inline]
+    pub fn reg_imm(&self) -> (OpIndex, OpIndex, OpIndex) {
+        let (d, a, _) = self.reg();
+        (d, a, (self.val & 0xffff) as u16 as i16 as OpIndex)
+    }
+
     #[inline]
     pub fn code2index(op: Opcode) -> OpIndex {
         op as OpIndex
@@ -410,10 +636,20 @@ impl Instruction {
         -(1 << 7) <= i && i < (1 << 7)
     }
 
+    #[inline]
+    pub fn in_16bit_range(i: OpIndex) -> bool {
+        -(1 << 15) <= i && i < (1 << 15)
+    }
+
     #[inline]
     pub fn in_24bit_range(i: OpIndex) -> bool {
         -(1 << 23) <= i && i < (1 << 23)
     }
+
+    #[inline]
+    pub fn in_reg_range(i: OpIndex) -> bool {
+        0 <= i && i < Instruction::REG_STACK
+    }
 }
 
 impl fmt::Debug for Instruction {
@@ -442,6 +678,45 @@ impl fmt::Debug for Instruction {
     }
 }
 
+impl Encode for ValueType {
+    fn encode(&self, e: &mut Encoder) {
+        e.write(&(*self as u8));
+    }
+}
+
+impl Decode for ValueType {
+    fn decode(d: &mut Decoder) -> io::Result<Self> {
+        let v: u8 = d.read()?;
+        if v > ValueType::FlagE as u8 {
+            return Err(invalid_data("invalid value type"));
+        }
+        Ok(unsafe { std::mem::transmute(v) })
+    }
+}
+
+impl Encode for Instruction {
+    fn encode(&self, e: &mut Encoder) {
+        e.write(&self.val);
+    }
+}
+
+impl Decode for Instruction {
+    /// the opcode and the types are checked, as they are transmuted later,
+    /// the rest of the bits depend on the opcode
+    fn decode(d: &mut Decoder) -> io::Result<Self> {
+        let inst = Instruction::from_u64(d.read()?);
+        if (inst.val >> (8 * 3 + 32)) as u8 > Opcode::GEQ_FLOAT64 as u8 {
+            return Err(invalid_data("invalid opcode"));
+        }
+        for i in 0..inst.op().type_count() {
+            if (inst.val >> (8 * (2 - i) + 32)) as u8 > ValueType::FlagE as u8 {
+                return Err(invalid_data("invalid value type"));
+            }
+        }
+        Ok(inst)
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -481,8 +756,56 @@ mod test {
         assert_eq!(i.t2_as_index(), 90);
         assert_eq!(i.t0(), ValueType::Str);
         assert_eq!(i.t1(), ValueType::Closure);
-        assert_ne!(i.t2(), ValueType::Int);
+        assert_ne!(i.t2_as_index(), ValueType::Int as OpIndex);
         assert_eq!(i.imm824().0, 127);
         assert_eq!(i.imm824().1, 1 << 23 - 1);
     }
+
+    #[test]
+    fn test_reg() {
+        let i = Instruction::new_reg(Opcode::LSS_R, ValueType::Uint8, 7, 65534, 0);
+        assert_eq!(i.op(), Opcode::LSS_R);
+        assert_eq!(i.t0(), ValueType::Uint8);
+        assert_eq!(i.reg(), (7, 65534, 0));
+        let i = Instruction::new_reg(Opcode::ADD_R, ValueType::Int, Instruction::REG_STACK, 1, 2);
+        assert_eq!(i.reg().0, Instruction::REG_STACK);
+        assert!(!Instruction::in_reg_range(Instruction::REG_STACK));
+        let i = Instruction::new_reg(Opcode::LSS_RI, ValueType::Int, 3, 4, -5);
+        assert_eq!(i.reg_imm(), (3, 4, -5));
+        assert!(Instruction::in_16bit_range(-(1 << 15)));
+        assert!(!Instruction::in_16bit_range(1 << 15));
+    }
+
+    #[test]
+    fn test_specialise() {
+        for t in [ValueType::Int, ValueType::Float64].iter() {
+            for op in [
+                Opcode::ADD,
+                Opcode::SUB,
+                Opcode::MUL,
+                Opcode::LSS,
+                Opcode::GEQ,
+            ]
+            .iter()
+            {
+                let sop = op.specialise(*t).unwrap();
+                assert_eq!(sop.generic(), *op);
+                assert_eq!(sop.property().1, op.property().1);
+            }
+        }
+        assert_eq!(Opcode::QUO.specialise(ValueType::Int), None);
+        assert_eq!(Opcode::ADD.specialise(ValueType::Str), None);
+        assert_eq!(Opcode::ADD_IMM_INT.generic(), Opcode::ADD_IMM_INT);
+    }
+
+    #[test]
+    fn test_ic_index() {
+        let mut i = Instruction::new(Opcode::TYPE, None, None, Some(ValueType::FlagA), Some(-3));
+        i.set_t2_with_index(1);
+        i.set_ic_index(0xabcd);
+        assert_eq!(i.op(), Opcode::TYPE);
+        assert_eq!(i.ic_index(), 0xabcd);
+        assert_eq!(i.t2_as_index(), 1);
+        assert_eq!(i.imm(), -3);
+    }
 }
@@ -0,0 +1,1133 @@
+//! The JIT compiles hot functions to native code with Cranelift, it's only
+//! built with the `jit` feature.
+//!
+//! Every call and every loop iteration of a function counts towards its
+//! hotness, a function is compiled at the call that makes it hot, together
+//! with the functions it calls, and runs natively from then on. Only numeric
+//! code is compiled: all the values it works on are GosValue64 copyable
+//! (bools, integers and floats), it touches nothing but its own locals, and
+//! it only calls package functions that can be compiled too. Anything else
+//! stays in the interpreter.
+//!
+//! As compiled functions have no side effects, native code that runs into
+//! something it doesn't handle, a division by zero, a failed assert or too
+//! deep a recursion, bails out, and the interpreter runs the call again from
+//! the start and panics the usual way if it has to. A function that bailed
+//! out stays interpreted.
+//!
+//! A compiled function takes a pointer to its frame on the stack of the VM,
+//! results first and then params as the interpreter lays them out, and the
+//! depth of the native calls, it returns OK or BAIL. Its locals and the
+//! operand stack are Cranelift variables.
+
+use super::instruction::{Instruction, OpIndex, Opcode, ValueType};
+use super::metadata::SigMetadata;
+use super::objects::{u64_to_key, FuncFlag};
+use super::stack::Stack;
+use super::value::*;
+use super::vm::ByteCode;
+use cranelift_codegen::entity::EntityRef;
+use cranelift_codegen::ir::condcodes::{FloatCC, IntCC};
+use cranelift_codegen::ir::{
+    self, types, AbiParam, Block, InstBuilder, MemFlags, Signature, StackSlotData, StackSlotKind,
+    UserFuncName, Value,
+};
+use cranelift_codegen::settings::{self, Configurable};
+use cranelift_codegen::{verify_function, Context};
+use cranelift_frontend::{FunctionBuilder, FunctionBuilderContext, Variable};
+use cranelift_jit::{JITBuilder, JITModule};
+use cranelift_module::{default_libcall_names, FuncId, Linkage, Module};
+use slotmap::SecondaryMap;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::convert::TryFrom;
+use std::mem;
+
+/// the number of calls and loop iterations that makes a function hot
+const HOT: u32 = 1000;
+
+/// native frames live on the stack of the thread, recursing deeper than
+/// this bails out
+const MAX_DEPTH: i64 = 1000;
+
+const OK: i64 = 0;
+const BAIL: i64 = 1;
+
+/// the number of Cranelift types a slot of the operand stack can have
+const SLOT_TYPES: usize = 6;
+
+type NativeFunc = unsafe extern "C" fn(*mut GosValue64, u32) -> u32;
+
+#[derive(Clone, Copy)]
+enum State {
+    Counting(u32),
+    Native(NativeFunc),
+    Interpreted,
+}
+
+pub struct Jit {
+    // the package members that are function declarations, they never change
+    // so the calls to them have static targets
+    pkg_funcs: HashMap<(PackageKey, OpIndex), FunctionKey>,
+    states: RefCell<SecondaryMap<FunctionKey, State>>,
+    // the functions compiled so far, in the order they were compiled, the
+    // ones that bailed out later too
+    compiled: RefCell<Vec<FunctionKey>>,
+    // None if Cranelift doesn't support the host
+    compiler: RefCell<Option<Compiler>>,
+}
+
+impl Jit {
+    pub fn new(bc: &ByteCode) -> Jit {
+        let mut pkg_funcs = HashMap::new();
+        for (key, pkg) in bc.objects.packages.iter() {
+            for index in pkg.member_indices().values() {
+                if let GosValue::Closure(c) = &*pkg.member(*index) {
+                    let cls = c.0.borrow();
+                    if let (Some(f), None, None) = (cls.func, &cls.uvs, &cls.recv) {
+                        pkg_funcs.insert((key, *index), f);
+                    }
+                }
+            }
+        }
+        Jit {
+            pkg_funcs: pkg_funcs,
+            states: RefCell::new(SecondaryMap::new()),
+            compiled: RefCell::new(vec![]),
+            compiler: RefCell::new(Compiler::new()),
+        }
+    }
+
+    /// on_loop counts an iteration of a loop in the function
+    #[inline]
+    pub fn on_loop(&self, f: FunctionKey) {
+        let mut states = self.states.borrow_mut();
+        match states.get_mut(f) {
+            Some(State::Counting(n)) => *n = n.saturating_add(1),
+            Some(_) => {}
+            None => {
+                states.insert(f, State::Counting(1));
+            }
+        }
+    }
+
+    /// call runs a call natively if the function is compiled, or is hot
+    /// enough to be compiled now. The frame of the call starts at `base` on
+    /// the stack, with the params pushed. Returns false if the interpreter
+    /// has to run the call.
+    pub fn call(&self, f: FunctionKey, objs: &VMObjects, stack: &mut Stack, base: usize) -> bool {
+        let state = self.states.borrow().get(f).copied();
+        let native = match state.unwrap_or(State::Counting(0)) {
+            State::Native(native) => native,
+            State::Interpreted => return false,
+            State::Counting(n) if n < HOT => {
+                self.states.borrow_mut().insert(f, State::Counting(n + 1));
+                return false;
+            }
+            State::Counting(_) => match self.compile(f, objs) {
+                Some(native) => native,
+                None => return false,
+            },
+        };
+        let ok = unsafe { native(stack.c_ptr(base), 0) } == OK as u32;
+        if !ok {
+            self.states.borrow_mut().insert(f, State::Interpreted);
+        }
+        ok
+    }
+
+    /// compiled returns the functions compiled to native code so far
+    pub fn compiled(&self) -> Vec<FunctionKey> {
+        self.compiled.borrow().clone()
+    }
+
+    fn compile(&self, f: FunctionKey, objs: &VMObjects) -> Option<NativeFunc> {
+        let mut states = self.states.borrow_mut();
+        let compiled = self
+            .compiler
+            .borrow_mut()
+            .as_mut()
+            .and_then(|c| c.compile(f, objs, &self.pkg_funcs, &states));
+        match compiled {
+            Some(funcs) => {
+                for (key, native) in funcs.into_iter() {
+                    states.insert(key, State::Native(native));
+                    self.compiled.borrow_mut().push(key);
+                }
+            }
+            None => {
+                states.insert(f, State::Interpreted);
+            }
+        }
+        match states[f] {
+            State::Native(native) => Some(native),
+            _ => None,
+        }
+    }
+}
+
+struct Compiler {
+    module: JITModule,
+    ids: HashMap<FunctionKey, FuncId>,
+}
+
+impl Compiler {
+    fn new() -> Option<Compiler> {
+        let mut flags = settings::builder();
+        flags.set("opt_level", "speed").ok()?;
+        flags.set("use_colocated_libcalls", "false").ok()?;
+        flags.set("is_pic", "false").ok()?;
+        let isa = cranelift_native::builder()
+            .ok()?
+            .finish(settings::Flags::new(flags))
+            .ok()?;
+        Some(Compiler {
+            module: JITModule::new(JITBuilder::with_isa(isa, default_libcall_names())),
+            ids: HashMap::new(),
+        })
+    }
+
+    fn signature(&self) -> Signature {
+        let mut sig = self.module.make_signature();
+        let ptr = self.module.target_config().pointer_type();
+        sig.params.push(AbiParam::new(ptr));
+        sig.params.push(AbiParam::new(types::I32));
+        sig.returns.push(AbiParam::new(types::I32));
+        sig
+    }
+
+    fn func_id(&mut self, f: FunctionKey) -> Option<FuncId> {
+        if let Some(id) = self.ids.get(&f) {
+            return Some(*id);
+        }
+        let sig = self.signature();
+        let name = format!("gos_func_{}", self.ids.len());
+        let id = self
+            .module
+            .declare_function(&name, Linkage::Local, &sig)
+            .ok()?;
+        self.ids.insert(f, id);
+        Some(id)
+    }
+
+    /// compile compiles the function and the functions it calls that are
+    /// not compiled yet, all or nothing
+    fn compile(
+        &mut self,
+        f: FunctionKey,
+        objs: &VMObjects,
+        pkg_funcs: &HashMap<(PackageKey, OpIndex), FunctionKey>,
+        states: &SecondaryMap<FunctionKey, State>,
+    ) -> Option<Vec<(FunctionKey, NativeFunc)>> {
+        let mut batch = vec![f];
+        let mut ctxs = vec![];
+        while ctxs.len() < batch.len() {
+            let (ctx, callees) = self.translate(batch[ctxs.len()], objs, pkg_funcs)?;
+            for c in callees.into_iter() {
+                match states.get(c) {
+                    Some(State::Native(_)) => {}
+                    Some(State::Interpreted) => return None,
+                    _ => {
+                        if !batch.contains(&c) {
+                            batch.push(c);
+                        }
+                    }
+                }
+            }
+            ctxs.push(ctx);
+        }
+        // a function that fails to be defined can't be defined again, so
+        // nothing is defined unless all of them are good
+        let isa = self.module.isa();
+        if ctxs.iter().any(|c| verify_function(&c.func, isa).is_err()) {
+            return None;
+        }
+        for (key, ctx) in batch.iter().zip(ctxs.iter_mut()) {
+            let id = self.ids[key];
+            self.module.define_function(id, ctx).ok()?;
+        }
+        self.module.finalize_definitions().ok()?;
+        Some(
+            batch
+                .iter()
+                .map(|key| {
+                    let code = self.module.get_finalized_function(self.ids[key]);
+                    (*key, unsafe {
+                        mem::transmute::<*const u8, NativeFunc>(code)
+                    })
+                })
+                .collect(),
+        )
+    }
+
+    /// translate translates the code of a function to Cranelift IR, returns
+    /// None if it can't be compiled, or the IR and the functions it calls
+    fn translate(
+        &mut self,
+        key: FunctionKey,
+        objs: &VMObjects,
+        pkg_funcs: &HashMap<(PackageKey, OpIndex), FunctionKey>,
+    ) -> Option<(Context, Vec<FunctionKey>)> {
+        let func = &objs.functions[key];
+        let sig = objs.metas[func.meta.as_non_ptr()].as_signature();
+        if func.flag != FuncFlag::Default
+            || !func.up_ptrs.is_empty()
+            || sig.recv.is_some()
+            || sig.variadic.is_some()
+        {
+            return None;
+        }
+        let locals = local_types(func, sig)?;
+        let id = self.func_id(key)?;
+        let mut ctx = self.module.make_context();
+        ctx.func.signature = self.signature();
+        ctx.func.name = UserFuncName::user(0, id.as_u32());
+        let mut fctx = FunctionBuilderContext::new();
+        let mut b = FunctionBuilder::new(&mut ctx.func, &mut fctx);
+
+        let entry = b.create_block();
+        b.append_block_params_for_function_params(entry);
+        b.switch_to_block(entry);
+        let frame = b.block_params(entry)[0];
+        let depth = b.block_params(entry)[1];
+        // the params are loaded from the frame, the rest are zeros
+        let params = func.ret_count()..func.ret_count() + func.param_count();
+        for (i, t) in locals.iter().enumerate() {
+            let ty = cl_type(*t)?;
+            let var = Variable::new(i);
+            b.declare_var(var, ty);
+            let val = if params.contains(&i) {
+                b.ins().load(ty, MemFlags::trusted(), frame, (i * 8) as i32)
+            } else {
+                zero(&mut b, ty)
+            };
+            b.def_var(var, val);
+        }
+        let bail = b.create_block();
+        let start = b.create_block();
+        let max_depth = b.ins().iconst(types::I32, MAX_DEPTH);
+        let too_deep = b.ins().icmp(IntCC::UnsignedGreaterThan, depth, max_depth);
+        b.ins().brif(too_deep, bail, &[], start, &[]);
+        b.switch_to_block(bail);
+        let status = b.ins().iconst(types::I32, BAIL);
+        b.ins().return_(&[status]);
+
+        let mut t = Translator {
+            objs: objs,
+            func: func,
+            pkg_funcs: pkg_funcs,
+            locals: locals,
+            slots: HashSet::new(),
+            frame: frame,
+            depth: depth,
+            bail: bail,
+            callees: vec![],
+        };
+        t.translate(&mut b, self, start)?;
+        b.seal_all_blocks();
+        b.finalize();
+        Some((ctx, t.callees))
+    }
+}
+
+/// Operands are what the translator knows about the operand stack at a
+/// point of the code
+#[derive(Clone, PartialEq)]
+struct Operands {
+    types: Vec<ValueType>,
+    // the callees of the calls being set up
+    calls: Vec<FunctionKey>,
+}
+
+struct Translator<'a> {
+    objs: &'a VMObjects,
+    func: &'a FunctionVal,
+    pkg_funcs: &'a HashMap<(PackageKey, OpIndex), FunctionKey>,
+    locals: Vec<ValueType>,
+    // the variables of the operand stack declared so far
+    slots: HashSet<usize>,
+    frame: Value,
+    depth: Value,
+    bail: Block,
+    callees: Vec<FunctionKey>,
+}
+
+impl<'a> Translator<'a> {
+    fn translate(&mut self, b: &mut FunctionBuilder, c: &mut Compiler, start: Block) -> Option<()> {
+        let code = self.func.code();
+        let mut blocks: HashMap<usize, Block> = HashMap::new();
+        blocks.insert(0, start);
+        let mut pc = 0;
+        while pc < code.len() {
+            let inst = code[pc];
+            match inst.op() {
+                Opcode::JUMP | Opcode::JUMP_IF | Opcode::JUMP_IF_NOT => {
+                    let target = Stack::offset(pc + 1, inst.imm());
+                    if target >= code.len() {
+                        return None;
+                    }
+                    blocks.entry(target).or_insert_with(|| b.create_block());
+                }
+                Opcode::LOAD_PKG_FIELD => pc += 1,
+                _ => {}
+            }
+            pc += 1;
+        }
+
+        let mut entries: HashMap<usize, Operands> = HashMap::new();
+        entries.insert(
+            0,
+            Operands {
+                types: vec![],
+                calls: vec![],
+            },
+        );
+        let mut current: Option<Operands> = None;
+        let mut pc = 0;
+        while pc < code.len() {
+            if let Some(block) = blocks.get(&pc) {
+                if let Some(ops) = current.take() {
+                    merge(&mut entries, pc, ops, false)?;
+                    b.ins().jump(*block, &[]);
+                }
+                b.switch_to_block(*block);
+                current = entries.get(&pc).cloned();
+                if current.is_none() {
+                    // only reachable from code that isn't reachable
+                    b.ins().jump(self.bail, &[]);
+                }
+            }
+            let inst = code[pc];
+            let width = if inst.op() == Opcode::LOAD_PKG_FIELD {
+                3
+            } else {
+                1
+            };
+            if let Some(ops) = current.as_mut() {
+                let next = pc + width;
+                let end = self.translate_inst(b, c, &blocks, &mut entries, ops, pc, inst)?;
+                if end {
+                    current = None;
+                } else if next >= code.len() {
+                    return None;
+                }
+            }
+            pc += width;
+        }
+        Some(())
+    }
+
+    /// translate_inst translates an instruction, returns true if the code
+    /// after it isn't reached from it
+    fn translate_inst(
+        &mut self,
+        b: &mut FunctionBuilder,
+        c: &mut Compiler,
+        blocks: &HashMap<usize, Block>,
+        entries: &mut HashMap<usize, Operands>,
+        ops: &mut Operands,
+        pc: usize,
+        inst: Instruction,
+    ) -> Option<bool> {
+        let t = inst.t0();
+        let op = inst.op().generic();
+        match op {
+            Opcode::PUSH_CONST => {
+                let val = self.func.const_val(inst.imm());
+                let typ = val.get_type();
+                let ty = cl_type(typ)?;
+                let v = match val {
+                    GosValue::Bool(x) => iconst(b, ty, *x as i64),
+                    GosValue::Int(x) => iconst(b, ty, *x as i64),
+                    GosValue::Int8(x) => iconst(b, ty, *x as i64),
+                    GosValue::Int16(x) => iconst(b, ty, *x as i64),
+                    GosValue::Int32(x) => iconst(b, ty, *x as i64),
+                    GosValue::Int64(x) => iconst(b, ty, *x),
+                    GosValue::Uint(x) => iconst(b, ty, *x as i64),
+                    GosValue::Uint8(x) => iconst(b, ty, *x as i64),
+                    GosValue::Uint16(x) => iconst(b, ty, *x as i64),
+                    GosValue::Uint32(x) => iconst(b, ty, *x as i64),
+                    GosValue::Uint64(x) => iconst(b, ty, *x as i64),
+                    GosValue::Float32(x) => b.ins().f32const(x.into_inner()),
+                    GosValue::Float64(x) => b.ins().f64const(x.into_inner()),
+                    _ => return None,
+                };
+                self.push(b, ops, typ, v);
+            }
+            Opcode::PUSH_FALSE | Opcode::PUSH_TRUE => {
+                let v = iconst(b, types::I8, (inst.op() == Opcode::PUSH_TRUE) as i64);
+                self.push(b, ops, ValueType::Bool, v);
+            }
+            Opcode::PUSH_IMM => {
+                if !is_int(t) {
+                    return None;
+                }
+                let v = iconst(b, cl_type(t)?, inst.imm() as i64);
+                self.push(b, ops, t, v);
+            }
+            Opcode::POP => {
+                for _ in 0..inst.imm() {
+                    ops.types.pop()?;
+                }
+            }
+            Opcode::LOAD_LOCAL => {
+                let i = self.local(inst.imm())?;
+                let v = b.use_var(Variable::new(i));
+                self.push(b, ops, self.locals[i], v);
+            }
+            Opcode::LOAD_LOCAL_ADD_IMM => {
+                let (imm, index) = inst.imm824();
+                let i = self.local(index)?;
+                if !is_int(t) || !same(t, self.locals[i]) {
+                    return None;
+                }
+                let x = b.use_var(Variable::new(i));
+                let v = b.ins().iadd_imm(x, imm as i64);
+                self.push(b, ops, t, v);
+            }
+            Opcode::ADD_IMM_INT => {
+                let (tx, x) = self.pop(b, ops)?;
+                if !same(t, tx) {
+                    return None;
+                }
+                let v = b.ins().iadd_imm(x, inst.imm() as i64);
+                self.push(b, ops, t, v);
+            }
+            Opcode::STORE_LOCAL => {
+                let (rhs, index) = inst.imm824();
+                let i = self.local(index)?;
+                if !same(t, self.locals[i]) {
+                    return None;
+                }
+                let v = if rhs < 0 {
+                    let (typ, v) = self.get(b, ops, rhs)?;
+                    if !same(t, typ) {
+                        return None;
+                    }
+                    v
+                } else {
+                    let op = Instruction::index2code(rhs);
+                    let (typ, y) = self.get(b, ops, -1)?;
+                    let x = b.use_var(Variable::new(i));
+                    match op {
+                        Opcode::SHL | Opcode::SHR => {
+                            // the count is read as uint32 whatever its type is
+                            let y = match cl_type(typ)?.bits() {
+                                64 if is_int(typ) => b.ins().ireduce(types::I32, y),
+                                32 if is_int(typ) => y,
+                                _ => return None,
+                            };
+                            self.shift(b, op, t, x, y)?
+                        }
+                        _ => {
+                            if !same(t, typ) {
+                                return None;
+                            }
+                            self.binary(b, op, t, x, y)?
+                        }
+                    }
+                };
+                b.def_var(Variable::new(i), v);
+            }
+            Opcode::CAST => {
+                let (target, _) = inst.imm824();
+                let (typ, v) = self.get(b, ops, target)?;
+                if !same(typ, inst.t1()) {
+                    return None;
+                }
+                let v = convert(b, inst.t1(), t, v)?;
+                self.set(b, ops, target, t, v);
+            }
+            Opcode::ADD
+            | Opcode::SUB
+            | Opcode::MUL
+            | Opcode::QUO
+            | Opcode::REM
+            | Opcode::AND
+            | Opcode::OR
+            | Opcode::XOR
+            | Opcode::AND_NOT => {
+                let (ty, y) = self.pop(b, ops)?;
+                let (tx, x) = self.pop(b, ops)?;
+                if !same(t, tx) || !same(t, ty) {
+                    return None;
+                }
+                let v = self.binary(b, op, t, x, y)?;
+                self.push(b, ops, t, v);
+            }
+            Opcode::SHL | Opcode::SHR => {
+                let (ty, y) = self.pop(b, ops)?;
+                let (tx, x) = self.pop(b, ops)?;
+                if !same(t, tx) || !same(inst.t1(), ty) || !is_int(ty) {
+                    return None;
+                }
+                // the count is converted to uint32
+                let y = convert(b, ty, ValueType::Uint32, y)?;
+                let v = self.shift(b, op, t, x, y)?;
+                self.push(b, ops, t, v);
+            }
+            Opcode::UNARY_ADD | Opcode::UNARY_SUB | Opcode::UNARY_XOR => {
+                let (tx, x) = self.pop(b, ops)?;
+                if !same(t, tx) {
+                    return None;
+                }
+                let v = match op {
+                    Opcode::UNARY_ADD => x,
+                    Opcode::UNARY_SUB if is_float(t) => b.ins().fneg(x),
+                    Opcode::UNARY_SUB if is_int(t) => b.ins().ineg(x),
+                    Opcode::UNARY_XOR if is_int(t) => b.ins().bnot(x),
+                    _ => return None,
+                };
+                self.push(b, ops, t, v);
+            }
+            Opcode::NOT => {
+                let (tx, x) = self.pop(b, ops)?;
+                if tx != ValueType::Bool {
+                    return None;
+                }
+                let v = b.ins().bxor_imm(x, 1);
+                self.push(b, ops, tx, v);
+            }
+            Opcode::EQL | Opcode::NEQ | Opcode::LSS | Opcode::GTR | Opcode::LEQ | Opcode::GEQ => {
+                let (ty, y) = self.pop(b, ops)?;
+                let (tx, x) = self.pop(b, ops)?;
+                if !same(t, tx) || !same(t, ty) {
+                    return None;
+                }
+                let v = self.compare(b, op, t, x, y)?;
+                self.push(b, ops, ValueType::Bool, v);
+            }
+            Opcode::ADD_R
+            | Opcode::SUB_R
+            | Opcode::MUL_R
+            | Opcode::QUO_R
+            | Opcode::REM_R
+            | Opcode::AND_R
+            | Opcode::OR_R
+            | Opcode::XOR_R
+            | Opcode::AND_NOT_R
+            | Opcode::EQL_R
+            | Opcode::NEQ_R
+            | Opcode::LSS_R
+            | Opcode::GTR_R
+            | Opcode::LEQ_R
+            | Opcode::GEQ_R
+            | Opcode::ADD_RI
+            | Opcode::EQL_RI
+            | Opcode::NEQ_RI
+            | Opcode::LSS_RI
+            | Opcode::GTR_RI
+            | Opcode::LEQ_RI
+            | Opcode::GEQ_RI => {
+                let (d, x, y) = if op as u8 >= Opcode::ADD_RI as u8 {
+                    // the second operand is an int constant
+                    let (d, a, k) = inst.reg_imm();
+                    let a = self.local(a)?;
+                    if t != ValueType::Int || self.locals[a] != ValueType::Int {
+                        return None;
+                    }
+                    let x = b.use_var(Variable::new(a));
+                    (d, x, iconst(b, types::I64, k as i64))
+                } else {
+                    let (d, a, bi) = inst.reg();
+                    let (a, bi) = (self.local(a)?, self.local(bi)?);
+                    if !same(t, self.locals[a]) || !same(t, self.locals[bi]) {
+                        return None;
+                    }
+                    let x = b.use_var(Variable::new(a));
+                    (d, x, b.use_var(Variable::new(bi)))
+                };
+                let (typ, v) = match reg_base(op) {
+                    op @ Opcode::EQL
+                    | op @ Opcode::NEQ
+                    | op @ Opcode::LSS
+                    | op @ Opcode::GTR
+                    | op @ Opcode::LEQ
+                    | op @ Opcode::GEQ => (ValueType::Bool, self.compare(b, op, t, x, y)?),
+                    op => (t, self.binary(b, op, t, x, y)?),
+                };
+                if d == Instruction::REG_STACK {
+                    self.push(b, ops, typ, v);
+                } else {
+                    let d = self.local(d)?;
+                    if !same(typ, self.locals[d]) {
+                        return None;
+                    }
+                    b.def_var(Variable::new(d), v);
+                }
+            }
+            // the container is a slice, an array or a string, local_types
+            // turns the function down before it gets here
+            Opcode::LOAD_INDEX_R => return None,
+            Opcode::LOAD_PKG_FIELD => {
+                let code = self.func.code();
+                let pkg = u64_to_key(code.get(pc + 1)?.get_u64());
+                let callee = *self.pkg_funcs.get(&(pkg, inst.imm()))?;
+                if code.get(pc + 2)?.op() != Opcode::PRE_CALL {
+                    return None;
+                }
+                // PRE_CALL pushes the zeros of the results
+                for r in self.objs.functions[callee].ret_zeros.iter() {
+                    let typ = r.get_type();
+                    let v = zero(b, cl_type(typ)?);
+                    self.push(b, ops, typ, v);
+                }
+                ops.calls.push(callee);
+            }
+            Opcode::CALL => {
+                if t != ValueType::Zero || inst.t1() == ValueType::FlagA {
+                    return None;
+                }
+                let callee = ops.calls.pop()?;
+                self.call(b, c, ops, callee)?;
+            }
+            Opcode::RETURN => {
+                if t != ValueType::Zero {
+                    return None;
+                }
+                for i in 0..self.func.ret_count() {
+                    let v = b.use_var(Variable::new(i));
+                    b.ins()
+                        .store(MemFlags::trusted(), v, self.frame, (i * 8) as i32);
+                }
+                let status = b.ins().iconst(types::I32, OK);
+                b.ins().return_(&[status]);
+                return Some(true);
+            }
+            Opcode::JUMP => {
+                let target = Stack::offset(pc + 1, inst.imm());
+                merge(entries, target, ops.clone(), target <= pc)?;
+                b.ins().jump(blocks[&target], &[]);
+                return Some(true);
+            }
+            Opcode::JUMP_IF | Opcode::JUMP_IF_NOT => {
+                let (tc, cond) = self.pop(b, ops)?;
+                if tc != ValueType::Bool {
+                    return None;
+                }
+                let target = Stack::offset(pc + 1, inst.imm());
+                merge(entries, target, ops.clone(), target <= pc)?;
+                let next = b.create_block();
+                if inst.op() == Opcode::JUMP_IF {
+                    b.ins().brif(cond, blocks[&target], &[], next, &[]);
+                } else {
+                    b.ins().brif(cond, next, &[], blocks[&target], &[]);
+                }
+                b.switch_to_block(next);
+            }
+            Opcode::ASSERT => {
+                let (tc, cond) = self.pop(b, ops)?;
+                if tc != ValueType::Bool {
+                    return None;
+                }
+                let next = b.create_block();
+                b.ins().brif(cond, next, &[], self.bail, &[]);
+                b.switch_to_block(next);
+            }
+            _ => return None,
+        }
+        Some(false)
+    }
+
+    fn call(
+        &mut self,
+        b: &mut FunctionBuilder,
+        c: &mut Compiler,
+        ops: &mut Operands,
+        callee: FunctionKey,
+    ) -> Option<()> {
+        let func = &self.objs.functions[callee];
+        let sig = self.objs.metas[func.meta.as_non_ptr()].as_signature();
+        let rets: Vec<ValueType> = func.ret_zeros.iter().map(|x| x.get_type()).collect();
+        if sig.params_type.len() != func.param_count() {
+            return None;
+        }
+        let size = (rets.len() + func.param_count()) * 8;
+        let slot = b.create_sized_stack_slot(StackSlotData::new(
+            StackSlotKind::ExplicitSlot,
+            size as u32,
+            3,
+        ));
+        for (i, pt) in sig.params_type.iter().enumerate().rev() {
+            let (typ, v) = self.pop(b, ops)?;
+            if !same(typ, *pt) {
+                return None;
+            }
+            b.ins().stack_store(v, slot, ((rets.len() + i) * 8) as i32);
+        }
+        for _ in 0..rets.len() {
+            self.pop(b, ops)?;
+        }
+        let id = c.func_id(callee)?;
+        let fref = c.module.declare_func_in_func(id, b.func);
+        let ptr = c.module.target_config().pointer_type();
+        let addr = b.ins().stack_addr(ptr, slot, 0);
+        let depth = b.ins().iadd_imm(self.depth, 1);
+        let call = b.ins().call(fref, &[addr, depth]);
+        let status = b.inst_results(call)[0];
+        let next = b.create_block();
+        b.ins().brif(status, self.bail, &[], next, &[]);
+        b.switch_to_block(next);
+        for (i, typ) in rets.into_iter().enumerate() {
+            let v = b.ins().stack_load(cl_type(typ)?, slot, (i * 8) as i32);
+            self.push(b, ops, typ, v);
+        }
+        self.callees.push(callee);
+        Some(())
+    }
+
+    fn binary(
+        &mut self,
+        b: &mut FunctionBuilder,
+        op: Opcode,
+        t: ValueType,
+        x: Value,
+        y: Value,
+    ) -> Option<Value> {
+        let v = if is_float(t) {
+            match op {
+                Opcode::ADD => b.ins().fadd(x, y),
+                Opcode::SUB => b.ins().fsub(x, y),
+                Opcode::MUL => b.ins().fmul(x, y),
+                Opcode::QUO => b.ins().fdiv(x, y),
+                _ => return None,
+            }
+        } else if is_int(t) {
+            match op {
+                Opcode::ADD => b.ins().iadd(x, y),
+                Opcode::SUB => b.ins().isub(x, y),
+                Opcode::MUL => b.ins().imul(x, y),
+                Opcode::QUO | Opcode::REM => {
+                    // the interpreter panics dividing by zero, -1 is left to
+                    // it as well so that the overflows are handled the same
+                    let ty = cl_type(t)?;
+                    let zero = iconst(b, ty, 0);
+                    let bad = if is_signed(t) {
+                        let minus_one = iconst(b, ty, -1);
+                        let is_zero = b.ins().icmp(IntCC::Equal, y, zero);
+                        let is_minus_one = b.ins().icmp(IntCC::Equal, y, minus_one);
+                        b.ins().bor(is_zero, is_minus_one)
+                    } else {
+                        b.ins().icmp(IntCC::Equal, y, zero)
+                    };
+                    let next = b.create_block();
+                    b.ins().brif(bad, self.bail, &[], next, &[]);
+                    b.switch_to_block(next);
+                    match (op, is_signed(t)) {
+                        (Opcode::QUO, true) => b.ins().sdiv(x, y),
+                        (Opcode::QUO, false) => b.ins().udiv(x, y),
+                        (_, true) => b.ins().srem(x, y),
+                        (_, false) => b.ins().urem(x, y),
+                    }
+                }
+                Opcode::AND => b.ins().band(x, y),
+                Opcode::OR => b.ins().bor(x, y),
+                Opcode::XOR => b.ins().bxor(x, y),
+                Opcode::AND_NOT => b.ins().band_not(x, y),
+                _ => return None,
+            }
+        } else {
+            return None;
+        };
+        Some(v)
+    }
+
+    /// shift shifts x by the uint32 y, shifting all the bits out gives zero
+    fn shift(
+        &mut self,
+        b: &mut FunctionBuilder,
+        op: Opcode,
+        t: ValueType,
+        x: Value,
+        y: Value,
+    ) -> Option<Value> {
+        if !is_int(t) {
+            return None;
+        }
+        let ty = cl_type(t)?;
+        let shifted = match (op, is_signed(t)) {
+            (Opcode::SHL, _) => b.ins().ishl(x, y),
+            (Opcode::SHR, true) => b.ins().sshr(x, y),
+            (Opcode::SHR, false) => b.ins().ushr(x, y),
+            _ => return None,
+        };
+        let bits = iconst(b, types::I32, ty.bits() as i64);
+        let out = b.ins().icmp(IntCC::UnsignedGreaterThanOrEqual, y, bits);
+        let zero = iconst(b, ty, 0);
+        Some(b.ins().select(out, zero, shifted))
+    }
+
+    fn compare(
+        &mut self,
+        b: &mut FunctionBuilder,
+        op: Opcode,
+        t: ValueType,
+        x: Value,
+        y: Value,
+    ) -> Option<Value> {
+        if is_float(t) {
+            return float_compare(b, op, x, y);
+        }
+        let signed = is_signed(t);
+        let cc = match op {
+            Opcode::EQL => IntCC::Equal,
+            Opcode::NEQ => IntCC::NotEqual,
+            _ if t == ValueType::Bool => return None,
+            Opcode::LSS if signed => IntCC::SignedLessThan,
+            Opcode::LSS => IntCC::UnsignedLessThan,
+            Opcode::GTR if signed => IntCC::SignedGreaterThan,
+            Opcode::GTR => IntCC::UnsignedGreaterThan,
+            Opcode::LEQ if signed => IntCC::SignedLessThanOrEqual,
+            Opcode::LEQ => IntCC::UnsignedLessThanOrEqual,
+            Opcode::GEQ if signed => IntCC::SignedGreaterThanOrEqual,
+            Opcode::GEQ => IntCC::UnsignedGreaterThanOrEqual,
+            _ => return None,
+        };
+        Some(b.ins().icmp(cc, x, y))
+    }
+
+    fn local(&self, index: OpIndex) -> Option<usize> {
+        let i = usize::try_from(index).ok()?;
+        if i < self.locals.len() {
+            Some(i)
+        } else {
+            None
+        }
+    }
+
+    fn slot(&mut self, b: &mut FunctionBuilder, depth: usize, t: ValueType) -> Variable {
+        let ty = cl_type(t).unwrap();
+        let class = [
+            types::I8,
+            types::I16,
+            types::I32,
+            types::I64,
+            types::F32,
+            types::F64,
+        ]
+        .iter()
+        .position(|x| *x == ty)
+        .unwrap();
+        let index = self.locals.len() + depth * SLOT_TYPES + class;
+        let var = Variable::new(index);
+        if self.slots.insert(index) {
+            b.declare_var(var, ty);
+        }
+        var
+    }
+
+    fn push(&mut self, b: &mut FunctionBuilder, ops: &mut Operands, t: ValueType, v: Value) {
+        let var = self.slot(b, ops.types.len(), t);
+        b.def_var(var, v);
+        ops.types.push(t);
+    }
+
+    fn pop(&mut self, b: &mut FunctionBuilder, ops: &mut Operands) -> Option<(ValueType, Value)> {
+        let t = ops.types.pop()?;
+        let var = self.slot(b, ops.types.len(), t);
+        Some((t, b.use_var(var)))
+    }
+
+    /// get reads the value at the offset from the top of the operand stack
+    fn get(
+        &mut self,
+        b: &mut FunctionBuilder,
+        ops: &Operands,
+        offset: OpIndex,
+    ) -> Option<(ValueType, Value)> {
+        let i = ops
+            .types
+            .len()
+            .checked_sub(offset.checked_neg()? as usize)?;
+        let t = ops.types[i];
+        let var = self.slot(b, i, t);
+        Some((t, b.use_var(var)))
+    }
+
+    fn set(
+        &mut self,
+        b: &mut FunctionBuilder,
+        ops: &mut Operands,
+        offset: OpIndex,
+        t: ValueType,
+        v: Value,
+    ) {
+        let i = ops.types.len() - (-offset) as usize;
+        let var = self.slot(b, i, t);
+        b.def_var(var, v);
+        ops.types[i] = t;
+    }
+}
+
+/// merge records the operands at the entry of a block, all the jumps to it
+/// have to agree on them. A block jumped back to is translated already, so
+/// it must have them.
+fn merge(
+    entries: &mut HashMap<usize, Operands>,
+    pc: usize,
+    ops: Operands,
+    backward: bool,
+) -> Option<()> {
+    match entries.get(&pc) {
+        Some(x) if *x != ops => None,
+        Some(_) => Some(()),
+        None if backward => None,
+        None => {
+            entries.insert(pc, ops);
+            Some(())
+        }
+    }
+}
+
+fn local_types(func: &FunctionVal, sig: &SigMetadata) -> Option<Vec<ValueType>> {
+    if sig.params_type.len() != func.param_count() {
+        return None;
+    }
+    let types: Vec<ValueType> = func
+        .ret_zeros
+        .iter()
+        .map(|x| x.get_type())
+        .chain(sig.params_type.iter().copied())
+        .chain(func.local_zeros.iter().map(|x| x.get_type()))
+        .collect();
+    if types.iter().all(|t| cl_type(*t).is_some()) {
+        Some(types)
+    } else {
+        None
+    }
+}
+
+fn reg_base(op: Opcode) -> Opcode {
+    match op {
+        Opcode::ADD_R => Opcode::ADD,
+        Opcode::SUB_R => Opcode::SUB,
+        Opcode::MUL_R => Opcode::MUL,
+        Opcode::QUO_R => Opcode::QUO,
+        Opcode::REM_R => Opcode::REM,
+        Opcode::AND_R => Opcode::AND,
+        Opcode::OR_R => Opcode::OR,
+        Opcode::XOR_R => Opcode::XOR,
+        Opcode::AND_NOT_R => Opcode::AND_NOT,
+        Opcode::EQL_R => Opcode::EQL,
+        Opcode::NEQ_R => Opcode::NEQ,
+        Opcode::LSS_R => Opcode::LSS,
+        Opcode::GTR_R => Opcode::GTR,
+        Opcode::LEQ_R => Opcode::LEQ,
+        Opcode::GEQ_R => Opcode::GEQ,
+        Opcode::ADD_RI => Opcode::ADD,
+        Opcode::EQL_RI => Opcode::EQL,
+        Opcode::NEQ_RI => Opcode::NEQ,
+        Opcode::LSS_RI => Opcode::LSS,
+        Opcode::GTR_RI => Opcode::GTR,
+        Opcode::LEQ_RI => Opcode::LEQ,
+        Opcode::GEQ_RI => Opcode::GEQ,
+        _ => unreachable!(),
+    }
+}
+
+/// float_compare compares floats the way OrderedFloat does, NaN equals
+/// itself and is greater than everything else
+fn float_compare(b: &mut FunctionBuilder, op: Opcode, x: Value, y: Value) -> Option<Value> {
+    let eq = {
+        let x_nan = b.ins().fcmp(FloatCC::Unordered, x, x);
+        let y_nan = b.ins().fcmp(FloatCC::Unordered, y, y);
+        let both_nan = b.ins().band(x_nan, y_nan);
+        let eq = b.ins().fcmp(FloatCC::Equal, x, y);
+        b.ins().bor(eq, both_nan)
+    };
+    let lss = |b: &mut FunctionBuilder, x: Value, y: Value| {
+        let x_nan = b.ins().fcmp(FloatCC::Unordered, x, x);
+        let y_nan = b.ins().fcmp(FloatCC::Unordered, y, y);
+        let lt = b.ins().fcmp(FloatCC::LessThan, x, y);
+        let lt = b.ins().bor(y_nan, lt);
+        b.ins().band_not(lt, x_nan)
+    };
+    let v = match op {
+        Opcode::EQL => eq,
+        Opcode::NEQ => b.ins().bxor_imm(eq, 1),
+        Opcode::LSS => lss(b, x, y),
+        Opcode::GTR => lss(b, y, x),
+        Opcode::LEQ => {
+            let lt = lss(b, x, y);
+            b.ins().bor(lt, eq)
+        }
+        Opcode::GEQ => {
+            let gt = lss(b, y, x);
+            b.ins().bor(gt, eq)
+        }
+        _ => return None,
+    };
+    Some(v)
+}
+
+/// convert converts a number the way `as` does in Rust, which is what CAST
+/// does in the interpreter
+fn convert(b: &mut FunctionBuilder, from: ValueType, to: ValueType, v: Value) -> Option<Value> {
+    let (ft, tt) = (cl_type(from)?, cl_type(to)?);
+    let v = match (is_int(from), is_int(to)) {
+        (true, true) if tt.bits() < ft.bits() => b.ins().ireduce(tt, v),
+        (true, true) if tt.bits() > ft.bits() && is_signed(from) => b.ins().sextend(tt, v),
+        (true, true) if tt.bits() > ft.bits() => b.ins().uextend(tt, v),
+        (true, true) => v,
+        (true, false) if !is_float(to) => return None,
+        (true, false) if is_signed(from) => b.ins().fcvt_from_sint(tt, v),
+        (true, false) => b.ins().fcvt_from_uint(tt, v),
+        (false, _) if !is_float(from) => return None,
+        // only the wide ones are supported by all the backends
+        (false, true) if tt.bits() < 32 => return None,
+        (false, true) if is_signed(to) => b.ins().fcvt_to_sint_sat(tt, v),
+        (false, true) => b.ins().fcvt_to_uint_sat(tt, v),
+        (false, false) if !is_float(to) => return None,
+        (false, false) if tt.bits() > ft.bits() => b.ins().fpromote(tt, v),
+        (false, false) if tt.bits() < ft.bits() => b.ins().fdemote(tt, v),
+        (false, false) => v,
+    };
+    Some(v)
+}
+
+fn iconst(b: &mut FunctionBuilder, ty: ir::Type, v: i64) -> Value {
+    let v = if ty.bits() < 64 {
+        v & ((1 << ty.bits()) - 1)
+    } else {
+        v
+    };
+    b.ins().iconst(ty, v)
+}
+
+fn zero(b: &mut FunctionBuilder, ty: ir::Type) -> Value {
+    match ty {
+        types::F32 => b.ins().f32const(0.0),
+        types::F64 => b.ins().f64const(0.0),
+        _ => b.ins().iconst(ty, 0),
+    }
+}
+
+/// same tells if values of the types have the same Cranelift type
+fn same(a: ValueType, b: ValueType) -> bool {
+    cl_type(a).is_some() && cl_type(a) == cl_type(b)
+}
+
+fn cl_type(t: ValueType) -> Option<ir::Type> {
+    match t {
+        ValueType::Bool | ValueType::Int8 | ValueType::Uint8 => Some(types::I8),
+        ValueType::Int16 | ValueType::Uint16 => Some(types::I16),
+        ValueType::Int32 | ValueType::Uint32 => Some(types::I32),
+        ValueType::Int64 | ValueType::Uint64 => Some(types::I64),
+        ValueType::Int | ValueType::Uint => ir::Type::int(usize::BITS as u16),
+        ValueType::Float32 => Some(types::F32),
+        ValueType::Float64 => Some(types::F64),
+        _ => None,
+    }
+}
+
+fn is_int(t: ValueType) -> bool {
+    ValueType::Int <= t && t <= ValueType::Uint64
+}
+
+fn is_signed(t: ValueType) -> bool {
+    ValueType::Int <= t && t <= ValueType::Int64
+}
+
+fn is_float(t: ValueType) -> bool {
+    t == ValueType::Float32 || t == ValueType::Float64
+}
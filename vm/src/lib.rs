@@ -16,6 +16,25 @@ mod stack;
 #[macro_use]
 mod vm_util;
 
+pub mod inline_cache;
+
+#[cfg(feature = "jit")]
+pub mod jit;
+
 pub mod vm;
 
 pub mod gc;
+
+pub mod serialize;
+
+pub mod disasm;
+
+pub mod debug;
+
+pub mod profile;
+
+pub mod stats;
+
+pub mod coverage;
+
+pub mod trace;
@@ -1,9 +1,11 @@
 use super::gc::GcoVec;
 use super::instruction::{OpIndex, ValueType};
 use super::objects::{FunctionKey, MetadataKey, MetadataObjs, StructObj, VMObjects};
+use super::serialize::{invalid_data, Decode, Decoder, Encode, Encoder};
 use super::value::GosValue;
 use std::cell::RefCell;
 use std::collections::HashMap;
+use std::io;
 use std::rc::Rc;
 
 #[macro_export]
@@ -785,3 +787,285 @@ impl MetadataType {
         }
     }
 }
+
+// ----------------------------------------------------------------------------
+// serialization
+
+impl Encode for Metadata {
+    fn encode(&self, e: &mut Encoder) {
+        for m in [
+            self.mbool,
+            self.mint,
+            self.mint8,
+            self.mint16,
+            self.mint32,
+            self.mint64,
+            self.muint,
+            self.muint8,
+            self.muint16,
+            self.muint32,
+            self.muint64,
+            self.mfloat32,
+            self.mfloat64,
+            self.mcomplex64,
+            self.mcomplex128,
+            self.mstr,
+            self.default_sig,
+            self.empty_iface,
+        ]
+        .iter()
+        {
+            e.write(m);
+        }
+    }
+}
+
+impl Decode for Metadata {
+    fn decode(d: &mut Decoder) -> io::Result<Self> {
+        Ok(Metadata {
+            mbool: d.read()?,
+            mint: d.read()?,
+            mint8: d.read()?,
+            mint16: d.read()?,
+            mint32: d.read()?,
+            mint64: d.read()?,
+            muint: d.read()?,
+            muint8: d.read()?,
+            muint16: d.read()?,
+            muint32: d.read()?,
+            muint64: d.read()?,
+            mfloat32: d.read()?,
+            mfloat64: d.read()?,
+            mcomplex64: d.read()?,
+            mcomplex128: d.read()?,
+            mstr: d.read()?,
+            default_sig: d.read()?,
+            empty_iface: d.read()?,
+        })
+    }
+}
+
+impl Encode for MetaCategory {
+    fn encode(&self, e: &mut Encoder) {
+        e.write(&(*self as u8));
+    }
+}
+
+impl Decode for MetaCategory {
+    fn decode(d: &mut Decoder) -> io::Result<Self> {
+        match d.read::<u8>()? {
+            0 => Ok(MetaCategory::Default),
+            1 => Ok(MetaCategory::Array),
+            2 => Ok(MetaCategory::Type),
+            3 => Ok(MetaCategory::ArrayType),
+            _ => Err(invalid_data("invalid meta category")),
+        }
+    }
+}
+
+impl Encode for GosMetadata {
+    fn encode(&self, e: &mut Encoder) {
+        let (tag, k, c) = match self {
+            GosMetadata::Untyped => return e.write(&0u8),
+            GosMetadata::NonPtr(k, c) => (1u8, k, c),
+            GosMetadata::Ptr1(k, c) => (2, k, c),
+            GosMetadata::Ptr2(k, c) => (3, k, c),
+            GosMetadata::Ptr3(k, c) => (4, k, c),
+            GosMetadata::Ptr4(k, c) => (5, k, c),
+            GosMetadata::Ptr5(k, c) => (6, k, c),
+            GosMetadata::Ptr6(k, c) => (7, k, c),
+            GosMetadata::Ptr7(k, c) => (8, k, c),
+        };
+        e.write(&tag);
+        e.write(k);
+        e.write(c);
+    }
+}
+
+impl Decode for GosMetadata {
+    fn decode(d: &mut Decoder) -> io::Result<Self> {
+        let tag: u8 = d.read()?;
+        if tag == 0 {
+            return Ok(GosMetadata::Untyped);
+        }
+        let (k, c) = (d.read()?, d.read()?);
+        match tag {
+            1 => Ok(GosMetadata::NonPtr(k, c)),
+            2 => Ok(GosMetadata::Ptr1(k, c)),
+            3 => Ok(GosMetadata::Ptr2(k, c)),
+            4 => Ok(GosMetadata::Ptr3(k, c)),
+            5 => Ok(GosMetadata::Ptr4(k, c)),
+            6 => Ok(GosMetadata::Ptr5(k, c)),
+            7 => Ok(GosMetadata::Ptr6(k, c)),
+            8 => Ok(GosMetadata::Ptr7(k, c)),
+            _ => Err(invalid_data("invalid metadata")),
+        }
+    }
+}
+
+impl Encode for Fields {
+    fn encode(&self, e: &mut Encoder) {
+        e.write(&self.fields);
+        e.write(&self.mapping);
+    }
+}
+
+impl Decode for Fields {
+    fn decode(d: &mut Decoder) -> io::Result<Self> {
+        Ok(Fields::new(d.read()?, d.read()?))
+    }
+}
+
+impl Encode for Methods {
+    fn encode(&self, e: &mut Encoder) {
+        e.write(&self.members.len());
+        for m in self.members.iter() {
+            let m = m.borrow();
+            e.write(&m.pointer_recv);
+            e.write(&m.func);
+        }
+        e.write(&self.mapping);
+    }
+}
+
+impl Decode for Methods {
+    fn decode(d: &mut Decoder) -> io::Result<Self> {
+        let count = d.read_len()?;
+        let members = (0..count)
+            .map(|_| {
+                Ok(Rc::new(RefCell::new(MethodDesc {
+                    pointer_recv: d.read()?,
+                    func: d.read()?,
+                })))
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+        Ok(Methods {
+            members: members,
+            mapping: d.read()?,
+        })
+    }
+}
+
+impl Encode for SigMetadata {
+    fn encode(&self, e: &mut Encoder) {
+        e.write(&self.recv);
+        e.write(&self.params);
+        e.write(&self.results);
+        e.write(&self.variadic);
+        e.write(&self.params_type);
+    }
+}
+
+impl Decode for SigMetadata {
+    fn decode(d: &mut Decoder) -> io::Result<Self> {
+        Ok(SigMetadata {
+            recv: d.read()?,
+            params: d.read()?,
+            results: d.read()?,
+            variadic: d.read()?,
+            params_type: d.read()?,
+        })
+    }
+}
+
+impl Encode for MetadataType {
+    fn encode(&self, e: &mut Encoder) {
+        match self {
+            Self::Bool => e.write(&0u8),
+            Self::Int => e.write(&1u8),
+            Self::Int8 => e.write(&2u8),
+            Self::Int16 => e.write(&3u8),
+            Self::Int32 => e.write(&4u8),
+            Self::Int64 => e.write(&5u8),
+            Self::Uint => e.write(&6u8),
+            Self::Uint8 => e.write(&7u8),
+            Self::Uint16 => e.write(&8u8),
+            Self::Uint32 => e.write(&9u8),
+            Self::Uint64 => e.write(&10u8),
+            Self::Float32 => e.write(&11u8),
+            Self::Float64 => e.write(&12u8),
+            Self::Complex64 => e.write(&13u8),
+            Self::Complex128 => e.write(&14u8),
+            Self::Str(zero) => {
+                e.write(&15u8);
+                e.write(zero);
+            }
+            Self::SliceOrArray(m, size) => {
+                e.write(&16u8);
+                e.write(m);
+                e.write(size);
+            }
+            Self::Struct(f, zero) => {
+                e.write(&17u8);
+                e.write(f);
+                e.write(zero);
+            }
+            Self::Signature(s) => {
+                e.write(&18u8);
+                e.write(s);
+            }
+            Self::Map(k, v) => {
+                e.write(&19u8);
+                e.write(k);
+                e.write(v);
+            }
+            Self::Interface(f) => {
+                e.write(&20u8);
+                e.write(f);
+            }
+            Self::Channel(t, m) => {
+                e.write(&21u8);
+                e.write(&match t {
+                    ChannelType::Send => 0u8,
+                    ChannelType::Recv => 1,
+                    ChannelType::SendRecv => 2,
+                });
+                e.write(m);
+            }
+            Self::Named(methods, m) => {
+                e.write(&22u8);
+                e.write(methods);
+                e.write(m);
+            }
+        }
+    }
+}
+
+impl Decode for MetadataType {
+    fn decode(d: &mut Decoder) -> io::Result<Self> {
+        Ok(match d.read::<u8>()? {
+            0 => Self::Bool,
+            1 => Self::Int,
+            2 => Self::Int8,
+            3 => Self::Int16,
+            4 => Self::Int32,
+            5 => Self::Int64,
+            6 => Self::Uint,
+            7 => Self::Uint8,
+            8 => Self::Uint16,
+            9 => Self::Uint32,
+            10 => Self::Uint64,
+            11 => Self::Float32,
+            12 => Self::Float64,
+            13 => Self::Complex64,
+            14 => Self::Complex128,
+            15 => Self::Str(d.read()?),
+            16 => Self::SliceOrArray(d.read()?, d.read()?),
+            17 => Self::Struct(d.read()?, d.read()?),
+            18 => Self::Signature(d.read()?),
+            19 => Self::Map(d.read()?, d.read()?),
+            20 => Self::Interface(d.read()?),
+            21 => {
+                let t = match d.read::<u8>()? {
+                    0 => ChannelType::Send,
+                    1 => ChannelType::Recv,
+                    2 => ChannelType::SendRecv,
+                    _ => return Err(invalid_data("invalid channel type")),
+                };
+                Self::Channel(t, d.read()?)
+            }
+            22 => Self::Named(d.read()?, d.read()?),
+            _ => return Err(invalid_data("invalid metadata type")),
+        })
+    }
+}
@@ -4,10 +4,12 @@ use super::ffi::Ffi;
 use super::gc::GcoVec;
 use super::instruction::{Instruction, OpIndex, Opcode, ValueType};
 use super::metadata::*;
+use super::serialize::{invalid_data, Decode, Decoder, Encode, Encoder};
 use super::stack::Stack;
 use super::value::{rcount_mark_and_queue, EmptyResult, GosValue, RCQueue, RCount};
 use goscript_parser::objects::{EntityKey, IdentKey};
 use slotmap::{new_key_type, DenseSlotMap};
+use std::borrow::Cow;
 use std::cell::{Cell, Ref, RefCell, RefMut};
 use std::cmp::Ordering;
 use std::collections::HashMap;
@@ -16,8 +18,10 @@ use std::convert::TryInto;
 use std::fmt::Write;
 use std::fmt::{self, Display};
 use std::hash::{Hash, Hasher};
+use std::io;
 use std::iter::FromIterator;
 use std::rc::{Rc, Weak};
+use std::str;
 
 const DEFAULT_CAPACITY: usize = 128;
 
@@ -76,13 +80,61 @@ impl VMObjects {
 // ----------------------------------------------------------------------------
 // StringObj
 
-pub type StringIter<'a> = std::str::Chars<'a>;
+/// StringIter decodes the UTF-8 of a string the way Go's range does: it yields
+/// the byte index of every rune, an invalid encoding yields U+FFFD and
+/// advances by one byte.
+pub struct StringIter<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> StringIter<'a> {
+    #[inline]
+    pub fn new(bytes: &'a [u8]) -> StringIter<'a> {
+        StringIter {
+            bytes: bytes,
+            pos: 0,
+        }
+    }
+}
 
-pub type StringEnumIter<'a> = std::iter::Enumerate<StringIter<'a>>;
+impl<'a> Iterator for StringIter<'a> {
+    type Item = (usize, char);
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.pos >= self.bytes.len() {
+            return None;
+        }
+        let (c, size) = decode_rune(&self.bytes[self.pos..]);
+        let index = self.pos;
+        self.pos += size;
+        Some((index, c))
+    }
+}
 
+/// decode_rune decodes the first rune of a non-empty byte slice and returns
+/// it with its width in bytes, (U+FFFD, 1) if the encoding is invalid.
+pub fn decode_rune(b: &[u8]) -> (char, usize) {
+    if b[0] < 0x80 {
+        return (b[0] as char, 1);
+    }
+    let head = &b[..b.len().min(4)];
+    let valid = match str::from_utf8(head) {
+        Ok(s) => s,
+        Err(e) => unsafe { str::from_utf8_unchecked(&head[..e.valid_up_to()]) },
+    };
+    valid
+        .chars()
+        .next()
+        .map_or((std::char::REPLACEMENT_CHARACTER, 1), |c| (c, c.len_utf8()))
+}
+
+/// StringObj is an immutable Go string, which is a sequence of arbitrary bytes
+/// and is not required to be valid UTF-8. Slices share the underlying data.
 #[derive(Debug)]
 pub struct StringObj {
-    data: Rc<String>,
+    data: Rc<Vec<u8>>,
     begin: usize,
     end: usize,
 }
@@ -90,22 +142,29 @@ pub struct StringObj {
 impl StringObj {
     #[inline]
     pub fn with_str(s: String) -> StringObj {
-        let len = s.len();
+        StringObj::with_bytes(s.into_bytes())
+    }
+
+    #[inline]
+    pub fn with_bytes(b: Vec<u8>) -> StringObj {
+        let len = b.len();
         StringObj {
-            data: Rc::new(s),
+            data: Rc::new(b),
             begin: 0,
             end: len,
         }
     }
 
     #[inline]
-    pub fn as_str(&self) -> &str {
+    pub fn as_bytes(&self) -> &[u8] {
         &self.data.as_ref()[self.begin..self.end]
     }
 
+    /// as_str returns the string as UTF-8, with invalid sequences replaced
+    /// by U+FFFD. It borrows if the string is valid.
     #[inline]
-    pub fn into_string(self) -> String {
-        Rc::try_unwrap(self.data).unwrap()
+    pub fn as_str(&self) -> Cow<str> {
+        String::from_utf8_lossy(self.as_bytes())
     }
 
     #[inline]
@@ -115,7 +174,7 @@ impl StringObj {
 
     #[inline]
     pub fn get_byte(&self, i: usize) -> Option<&u8> {
-        self.as_str().as_bytes().get(i)
+        self.as_bytes().get(i)
     }
 
     pub fn slice(&self, begin: isize, end: isize) -> StringObj {
@@ -124,13 +183,14 @@ impl StringObj {
         let ei = ((self_end + end) % self_end) as usize;
         StringObj {
             data: Rc::clone(&self.data),
-            begin: bi,
-            end: ei,
+            begin: self.begin + bi,
+            end: self.begin + ei,
         }
     }
 
+    #[inline]
     pub fn iter(&self) -> StringIter {
-        self.as_str().chars()
+        StringIter::new(self.as_bytes())
     }
 }
 
@@ -148,7 +208,7 @@ impl Clone for StringObj {
 impl PartialEq for StringObj {
     #[inline]
     fn eq(&self, other: &StringObj) -> bool {
-        self.as_str().eq(other.as_str())
+        self.as_bytes().eq(other.as_bytes())
     }
 }
 
@@ -164,7 +224,7 @@ impl PartialOrd for StringObj {
 impl Ord for StringObj {
     #[inline]
     fn cmp(&self, other: &Self) -> Ordering {
-        self.as_str().cmp(other.as_str())
+        self.as_bytes().cmp(other.as_bytes())
     }
 }
 
@@ -346,6 +406,19 @@ impl ArrayObj {
         }
     }
 
+    /// copy_semantic copies the elements by value, like assigning an array in Go
+    pub fn copy_semantic(&self, gcos: &GcoVec) -> ArrayObj {
+        ArrayObj {
+            meta: self.meta,
+            vec: Rc::new(RefCell::new(
+                self.borrow_data()
+                    .iter()
+                    .map(|x| RefCell::new(x.borrow().copy_semantic(gcos)))
+                    .collect(),
+            )),
+        }
+    }
+
     pub fn deep_clone(&self, gcos: &GcoVec) -> ArrayObj {
         ArrayObj {
             meta: self.meta,
@@ -721,6 +794,14 @@ pub struct StructObj {
 }
 
 impl StructObj {
+    /// copy_semantic copies the fields by value, like assigning a struct in Go
+    pub fn copy_semantic(&self, gcos: &GcoVec) -> StructObj {
+        StructObj {
+            meta: self.meta,
+            fields: Vec::from_iter(self.fields.iter().map(|x| x.copy_semantic(gcos))),
+        }
+    }
+
     pub fn deep_clone(&self, gcos: &GcoVec) -> StructObj {
         StructObj {
             meta: self.meta,
@@ -1042,11 +1123,11 @@ impl PartialEq for PointerObj {
     #[inline]
     fn eq(&self, other: &PointerObj) -> bool {
         match (self, other) {
-            (Self::UpVal(x), Self::UpVal(y)) => x == y,
-            (Self::Struct(x, _), Self::Struct(y, _)) => x == y,
-            (Self::Array(x, _), Self::Array(y, _)) => x == y,
-            (Self::Slice(x, _), Self::Slice(y, _)) => x == y,
-            (Self::Map(x, _), Self::Map(y, _)) => x == y,
+            (Self::UpVal(x), Self::UpVal(y)) => Rc::ptr_eq(&x.inner, &y.inner),
+            (Self::Struct(x, _), Self::Struct(y, _)) => Rc::ptr_eq(x, y),
+            (Self::Array(x, _), Self::Array(y, _)) => Rc::ptr_eq(x, y),
+            (Self::Slice(x, _), Self::Slice(y, _)) => Rc::ptr_eq(x, y),
+            (Self::Map(x, _), Self::Map(y, _)) => Rc::ptr_eq(x, y),
             (Self::SliceMember(x, ix), Self::SliceMember(y, iy)) => Rc::ptr_eq(x, y) && ix == iy,
             (Self::StructField(x, ix), Self::StructField(y, iy)) => Rc::ptr_eq(x, y) && ix == iy,
             (Self::PkgMember(ka, ix), Self::PkgMember(kb, iy)) => ka == kb && ix == iy,
@@ -1058,7 +1139,7 @@ impl PartialEq for PointerObj {
 impl Hash for PointerObj {
     fn hash<H: Hasher>(&self, state: &mut H) {
         match self {
-            Self::UpVal(x) => x.hash(state),
+            Self::UpVal(x) => Rc::as_ptr(&x.inner).hash(state),
             Self::Struct(s, _) => Rc::as_ptr(s).hash(state),
             Self::Array(s, _) => Rc::as_ptr(s).hash(state),
             Self::Slice(s, _) => Rc::as_ptr(s).hash(state),
@@ -1339,6 +1420,22 @@ impl PackageVal {
         index as OpIndex
     }
 
+    /// add_shared_member adds a member that lives in a cell of another
+    /// package too, the variables of a REPL session are kept this way
+    pub fn add_shared_member(&mut self, name: String, cell: Rc<RefCell<GosValue>>) -> OpIndex {
+        self.members.push(cell);
+        let index = (self.members.len() - 1) as OpIndex;
+        self.member_indices.insert(name, index);
+        index as OpIndex
+    }
+
+    /// shared_member returns the cell of a member, see add_shared_member
+    pub fn shared_member(&self, name: &str) -> Option<Rc<RefCell<GosValue>>> {
+        self.member_indices
+            .get(name)
+            .map(|i| self.members[*i as usize].clone())
+    }
+
     pub fn add_var_mapping(&mut self, name: String, fn_index: OpIndex) -> OpIndex {
         let index = *self.get_member_index(&name).unwrap();
         self.var_mapping
@@ -1357,6 +1454,14 @@ impl PackageVal {
         self.var_mapping.as_ref().unwrap().len()
     }
 
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn member_indices(&self) -> &HashMap<String, OpIndex> {
+        &self.member_indices
+    }
+
     pub fn get_member_index(&self, name: &str) -> Option<&OpIndex> {
         self.member_indices.get(name)
     }
@@ -1423,6 +1528,9 @@ pub struct FunctionVal {
     pub meta: GosMetadata,
     code: Vec<Instruction>,
     pos: Vec<Option<usize>>,
+    // the positions of the calls the instructions are inlined at, it's empty
+    // if nothing is inlined
+    inlined: Vec<Option<usize>>,
     pub consts: Vec<GosValue>,
     pub up_ptrs: Vec<ValueDesc>,
 
@@ -1434,6 +1542,8 @@ pub struct FunctionVal {
     entities: HashMap<EntityKey, EntIndex>,
     uv_entities: HashMap<EntityKey, EntIndex>,
     local_alloc: u16,
+    // names of params, results and local vars, for debugging
+    local_names: Vec<Option<String>>,
 }
 
 impl FunctionVal {
@@ -1456,6 +1566,7 @@ impl FunctionVal {
                     meta: meta,
                     code: Vec::new(),
                     pos: Vec::new(),
+                    inlined: Vec::new(),
                     consts: Vec::new(),
                     up_ptrs: Vec::new(),
                     ret_zeros: returns,
@@ -1465,6 +1576,7 @@ impl FunctionVal {
                     entities: HashMap::new(),
                     uv_entities: HashMap::new(),
                     local_alloc: 0,
+                    local_names: Vec::new(),
                 }
             }
             _ => unreachable!(),
@@ -1476,6 +1588,24 @@ impl FunctionVal {
         &self.code
     }
 
+    /// replace_code is for passes that rewrite the code after it's generated
+    pub fn replace_code(&mut self, code: Vec<Instruction>, pos: Vec<Option<usize>>) {
+        assert_eq!(code.len(), pos.len());
+        self.code = code;
+        self.pos = pos;
+    }
+
+    pub fn set_inlined_calls(&mut self, calls: Vec<Option<usize>>) {
+        assert!(calls.is_empty() || calls.len() == self.code.len());
+        self.inlined = calls;
+    }
+
+    /// returns the position of the call the instruction is inlined at
+    #[inline]
+    pub fn inlined_call(&self, pc: usize) -> Option<usize> {
+        self.inlined.get(pc).copied().flatten()
+    }
+
     #[inline]
     pub fn instruction_mut(&mut self, i: usize) -> &mut Instruction {
         self.code.get_mut(i).unwrap()
@@ -1506,6 +1636,13 @@ impl FunctionVal {
         self.local_alloc as usize - self.param_count() - self.ret_count()
     }
 
+    #[inline]
+    pub fn local_name(&self, index: OpIndex) -> Option<&str> {
+        self.local_names
+            .get(index as usize)
+            .and_then(|x| x.as_ref().map(|s| s.as_str()))
+    }
+
     #[inline]
     pub fn entity_index(&self, entity: &EntityKey) -> Option<&EntIndex> {
         self.entities.get(entity)
@@ -1609,12 +1746,13 @@ impl FunctionVal {
         })
     }
 
-    pub fn add_local(&mut self, entity: Option<EntityKey>) -> EntIndex {
+    pub fn add_local(&mut self, entity: Option<EntityKey>, name: Option<String>) -> EntIndex {
         let result = self.local_alloc as OpIndex;
         if let Some(key) = entity {
             let old = self.entities.insert(key, EntIndex::LocalVar(result));
             assert_eq!(old, None);
         };
+        self.local_names.push(name);
         self.local_alloc += 1;
         EntIndex::LocalVar(result)
     }
@@ -1655,3 +1793,324 @@ impl FunctionVal {
         et
     }
 }
+
+// ----------------------------------------------------------------------------
+// serialization
+
+impl Encode for StringObj {
+    fn encode(&self, e: &mut Encoder) {
+        e.write(self.as_bytes());
+    }
+}
+
+impl Decode for StringObj {
+    fn decode(d: &mut Decoder) -> io::Result<Self> {
+        let l = d.read_len()?;
+        Ok(StringObj::with_bytes(d.read_bytes(l)?.to_vec()))
+    }
+}
+
+impl Encode for ArrayObj {
+    fn encode(&self, e: &mut Encoder) {
+        e.write(&self.meta);
+        let vec = self.vec.borrow();
+        e.write(&vec.len());
+        for v in vec.iter() {
+            e.write(&*v.borrow());
+        }
+    }
+}
+
+impl Decode for ArrayObj {
+    fn decode(d: &mut Decoder) -> io::Result<Self> {
+        let meta = d.read()?;
+        Ok(ArrayObj::with_data(d.read()?, meta))
+    }
+}
+
+/// only the visible part of a slice is kept, the loaded one doesn't share
+/// its underlying array with anything
+impl Encode for SliceObj {
+    fn encode(&self, e: &mut Encoder) {
+        e.write(&self.meta);
+        e.write(&if self.is_nil() {
+            None
+        } else {
+            Some(self.get_vec())
+        });
+    }
+}
+
+impl Decode for SliceObj {
+    fn decode(d: &mut Decoder) -> io::Result<Self> {
+        let meta = d.read()?;
+        Ok(match d.read()? {
+            Some(vec) => SliceObj::with_data(vec, meta),
+            None => SliceObj::new_nil(meta),
+        })
+    }
+}
+
+impl Encode for MapObj {
+    fn encode(&self, e: &mut Encoder) {
+        e.write(&self.meta);
+        e.write(&*self.default_val.borrow());
+        e.write(&self.map.as_ref().map(|m| {
+            m.borrow()
+                .iter()
+                .map(|(k, v)| (k.clone(), v.borrow().clone()))
+                .collect::<Vec<_>>()
+        }));
+    }
+}
+
+impl Decode for MapObj {
+    fn decode(d: &mut Decoder) -> io::Result<Self> {
+        let (meta, default_val) = (d.read()?, d.read()?);
+        let data: Option<Vec<(GosValue, GosValue)>> = d.read()?;
+        Ok(match data {
+            Some(data) => {
+                let m = MapObj::new(meta, default_val);
+                for (k, v) in data.into_iter() {
+                    m.insert(k, v);
+                }
+                m
+            }
+            None => MapObj::new_nil(meta, default_val),
+        })
+    }
+}
+
+impl Encode for StructObj {
+    fn encode(&self, e: &mut Encoder) {
+        e.write(&self.meta);
+        e.write(&self.fields);
+    }
+}
+
+impl Decode for StructObj {
+    fn decode(d: &mut Decoder) -> io::Result<Self> {
+        Ok(StructObj {
+            meta: d.read()?,
+            fields: d.read()?,
+        })
+    }
+}
+
+/// interfaces backed by an FFI object only exist at runtime
+impl Encode for InterfaceObj {
+    fn encode(&self, e: &mut Encoder) {
+        e.write(&self.meta);
+        match self.underlying() {
+            IfaceUnderlying::None => e.write(&false),
+            IfaceUnderlying::Gos(v, methods) => {
+                e.write(&true);
+                e.write(v);
+                e.write(methods);
+            }
+            IfaceUnderlying::Ffi(_) => panic!("cannot serialize FFI interface"),
+        }
+    }
+}
+
+impl Decode for InterfaceObj {
+    fn decode(d: &mut Decoder) -> io::Result<Self> {
+        let meta = d.read()?;
+        let underlying = match d.read()? {
+            Some((v, methods)) => IfaceUnderlying::Gos(v, methods),
+            None => IfaceUnderlying::None,
+        };
+        Ok(InterfaceObj::new(meta, underlying))
+    }
+}
+
+/// the stack of a ValueDesc is only set at runtime, so it's not kept
+impl Encode for ValueDesc {
+    fn encode(&self, e: &mut Encoder) {
+        e.write(&self.func);
+        e.write(&self.index);
+        e.write(&self.typ);
+        e.write(&self.is_up_value);
+        e.write(&self.stack_base);
+    }
+}
+
+impl Decode for ValueDesc {
+    fn decode(d: &mut Decoder) -> io::Result<Self> {
+        Ok(ValueDesc {
+            func: d.read()?,
+            index: d.read()?,
+            typ: d.read()?,
+            is_up_value: d.read()?,
+            stack: Weak::new(),
+            stack_base: d.read()?,
+        })
+    }
+}
+
+impl Encode for UpValue {
+    fn encode(&self, e: &mut Encoder) {
+        let state: &UpValueState = &self.inner.borrow();
+        match state {
+            UpValueState::Open(desc) => {
+                e.write(&0u8);
+                e.write(desc);
+            }
+            UpValueState::Closed(v) => {
+                e.write(&1u8);
+                e.write(v);
+            }
+        }
+    }
+}
+
+impl Decode for UpValue {
+    fn decode(d: &mut Decoder) -> io::Result<Self> {
+        match d.read::<u8>()? {
+            0 => Ok(UpValue::new(d.read()?)),
+            1 => Ok(UpValue::new_closed(d.read()?)),
+            _ => Err(invalid_data("invalid upvalue")),
+        }
+    }
+}
+
+/// closures with a receiver or backed by FFI only exist at runtime
+impl Encode for ClosureObj {
+    fn encode(&self, e: &mut Encoder) {
+        if self.recv.is_some() || self.ffi.is_some() {
+            panic!("cannot serialize runtime closure");
+        }
+        e.write(&self.func);
+        e.write(&self.uvs);
+        e.write(&self.meta);
+    }
+}
+
+impl Decode for ClosureObj {
+    fn decode(d: &mut Decoder) -> io::Result<Self> {
+        Ok(ClosureObj {
+            func: d.read()?,
+            uvs: d.read()?,
+            recv: None,
+            ffi: None,
+            meta: d.read()?,
+        })
+    }
+}
+
+impl Encode for PackageVal {
+    fn encode(&self, e: &mut Encoder) {
+        e.write(&self.name);
+        e.write(&self.members.len());
+        for m in self.members.iter() {
+            e.write(&*m.borrow());
+        }
+        e.write(&self.member_indices);
+        e.write(&self.var_mapping);
+    }
+}
+
+impl Decode for PackageVal {
+    fn decode(d: &mut Decoder) -> io::Result<Self> {
+        let name = d.read()?;
+        let members: Vec<GosValue> = d.read()?;
+        Ok(PackageVal {
+            name: name,
+            members: members
+                .into_iter()
+                .map(|x| Rc::new(RefCell::new(x)))
+                .collect(),
+            member_indices: d.read()?,
+            var_mapping: d.read()?,
+        })
+    }
+}
+
+impl Encode for FuncFlag {
+    fn encode(&self, e: &mut Encoder) {
+        e.write(&(*self as u8));
+    }
+}
+
+impl Decode for FuncFlag {
+    fn decode(d: &mut Decoder) -> io::Result<Self> {
+        match d.read::<u8>()? {
+            0 => Ok(FuncFlag::Default),
+            1 => Ok(FuncFlag::PkgCtor),
+            2 => Ok(FuncFlag::HasDefer),
+            _ => Err(invalid_data("invalid function flag")),
+        }
+    }
+}
+
+/// the entities are only used by the code generator, so they are not kept
+impl Encode for FunctionVal {
+    fn encode(&self, e: &mut Encoder) {
+        e.write(&self.package);
+        e.write(&self.meta);
+        e.write(&self.code.len());
+        let mut iter = self.code.iter();
+        while let Some(inst) = iter.next() {
+            e.write(inst);
+            match inst.op() {
+                // followed by a package key instead of an instruction
+                Opcode::LOAD_PKG_FIELD | Opcode::STORE_PKG_FIELD | Opcode::REF_PKG_MEMBER => {
+                    let key: PackageKey = u64_to_key(iter.next().unwrap().get_u64());
+                    e.write(&key);
+                }
+                _ => {}
+            }
+        }
+        e.write(&self.pos);
+        e.write(&self.inlined);
+        e.write(&self.consts);
+        e.write(&self.up_ptrs);
+        e.write(&self.ret_zeros);
+        e.write(&self.local_zeros);
+        e.write(&self.flag);
+        e.write(&self.param_count);
+        e.write(&self.local_alloc);
+        e.write(&self.local_names);
+    }
+}
+
+impl Decode for FunctionVal {
+    fn decode(d: &mut Decoder) -> io::Result<Self> {
+        let (package, meta) = (d.read()?, d.read()?);
+        let count = d.read_len()?;
+        let mut code = Vec::with_capacity(count);
+        while code.len() < count {
+            let inst: Instruction = d.read()?;
+            code.push(inst);
+            match inst.op() {
+                Opcode::LOAD_PKG_FIELD | Opcode::STORE_PKG_FIELD | Opcode::REF_PKG_MEMBER => {
+                    let key: PackageKey = d.read()?;
+                    code.push(Instruction::from_u64(key_to_u64(key)));
+                }
+                _ => {}
+            }
+        }
+        let pos: Vec<Option<usize>> = d.read()?;
+        let inlined: Vec<Option<usize>> = d.read()?;
+        if pos.len() != code.len() || !(inlined.is_empty() || inlined.len() == code.len()) {
+            return Err(invalid_data("invalid function positions"));
+        }
+        Ok(FunctionVal {
+            package: package,
+            meta: meta,
+            code: code,
+            pos: pos,
+            inlined: inlined,
+            consts: d.read()?,
+            up_ptrs: d.read()?,
+            ret_zeros: d.read()?,
+            local_zeros: d.read()?,
+            flag: d.read()?,
+            param_count: d.read()?,
+            entities: HashMap::new(),
+            uv_entities: HashMap::new(),
+            local_alloc: d.read()?,
+            local_names: d.read()?,
+        })
+    }
+}
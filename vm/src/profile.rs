@@ -0,0 +1,327 @@
+//! Profiler for the vm. Every instruction run is counted by function and pc,
+//! and every SAMPLE_INTERVAL instructions the call stack of the running fiber
+//! is sampled, with the time since the last sample. The Profile it makes can
+//! be written in the pprof format, or as folded stacks for flamegraphs.
+
+use super::disasm::func_names;
+use super::objects::FunctionKey;
+use super::vm::ByteCode;
+use goscript_parser::FileSet;
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::time::{Duration, Instant};
+
+/// the stacks are sampled every SAMPLE_INTERVAL instructions, it's a prime so
+/// that the samples don't line up with loops
+pub const SAMPLE_INTERVAL: usize = 97;
+
+/// a call stack, the innermost (func, pc) first
+type Stack = Vec<(FunctionKey, usize)>;
+
+pub struct Profiler {
+    counts: RefCell<HashMap<(FunctionKey, usize), u64>>,
+    stacks: RefCell<HashMap<Stack, (u64, Duration)>>,
+    ticks: Cell<usize>,
+    start: Instant,
+    last: Cell<Instant>,
+}
+
+impl Profiler {
+    pub fn new() -> Profiler {
+        let now = Instant::now();
+        Profiler {
+            counts: RefCell::new(HashMap::new()),
+            stacks: RefCell::new(HashMap::new()),
+            ticks: Cell::new(0),
+            start: now,
+            last: Cell::new(now),
+        }
+    }
+
+    /// count counts the instruction at pc of func, and returns true if it's
+    /// time to take a sample
+    #[inline]
+    pub fn count(&self, func: FunctionKey, pc: usize) -> bool {
+        *self.counts.borrow_mut().entry((func, pc)).or_insert(0) += 1;
+        let ticks = self.ticks.get() + 1;
+        self.ticks.set(ticks);
+        ticks % SAMPLE_INTERVAL == 0
+    }
+
+    /// sample adds the time since the last sample to the stack
+    pub fn sample(&self, stack: Stack) {
+        let now = Instant::now();
+        let elapsed = now - self.last.replace(now);
+        let mut stacks = self.stacks.borrow_mut();
+        let entry = stacks.entry(stack).or_insert((0, Duration::default()));
+        entry.0 += SAMPLE_INTERVAL as u64;
+        entry.1 += elapsed;
+    }
+
+    /// finish looks up the names and lines of what was counted
+    pub fn finish(self, code: &ByteCode, fs: Option<&FileSet>) -> Profile {
+        let duration = self.start.elapsed();
+        let counts = self.counts.into_inner();
+        let stacks = self.stacks.into_inner();
+        let names = func_names(code);
+        let mut funcs = HashMap::new();
+        let mut lines = HashMap::new();
+        let pcs = counts
+            .keys()
+            .chain(stacks.keys().flat_map(|s| s.iter()))
+            .copied();
+        for (key, pc) in pcs {
+            let func = &code.objects.functions[key];
+            let pos = |pc: usize| Some(fs?.position(func.pos().get(pc).copied()??));
+            funcs.entry(key).or_insert_with(|| {
+                // the first line with code, as the declaration has none
+                let first = (0..func.pos().len()).find_map(pos);
+                FuncInfo {
+                    name: names.get(&key).cloned().unwrap_or("?".to_string()),
+                    file: first
+                        .as_ref()
+                        .map_or("".to_string(), |p| p.filename.to_string()),
+                    line: first.map_or(0, |p| p.line),
+                }
+            });
+            lines.insert((key, pc), pos(pc).map_or(0, |p| p.line));
+        }
+        Profile {
+            funcs: funcs,
+            lines: lines,
+            counts: counts,
+            stacks: stacks,
+            duration: duration,
+        }
+    }
+}
+
+struct FuncInfo {
+    name: String,
+    file: String,
+    line: usize,
+}
+
+pub struct Profile {
+    funcs: HashMap<FunctionKey, FuncInfo>,
+    // the line of every pc that was counted or sampled, 0 if not known
+    lines: HashMap<(FunctionKey, usize), usize>,
+    counts: HashMap<(FunctionKey, usize), u64>,
+    stacks: HashMap<Stack, (u64, Duration)>,
+    duration: Duration,
+}
+
+impl Profile {
+    /// instructions returns the number of instructions run
+    pub fn instructions(&self) -> u64 {
+        self.counts.values().sum()
+    }
+
+    /// functions returns the name, the instructions and the sampled time of
+    /// every function that ran, the most instructions first. The numbers
+    /// don't include the functions it calls.
+    pub fn functions(&self) -> Vec<(&str, u64, Duration)> {
+        let mut list: HashMap<FunctionKey, (u64, Duration)> = HashMap::new();
+        for ((func, _), n) in self.counts.iter() {
+            list.entry(*func).or_default().0 += n;
+        }
+        for (stack, (_, time)) in self.stacks.iter() {
+            list.entry(stack[0].0).or_default().1 += *time;
+        }
+        let mut list: Vec<(&str, u64, Duration)> = list
+            .into_iter()
+            .map(|(f, (n, t))| (self.funcs[&f].name.as_str(), n, t))
+            .collect();
+        list.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+        list
+    }
+
+    /// lines returns the instructions run on every line of the function
+    pub fn lines(&self, func_name: &str) -> Vec<(usize, u64)> {
+        let mut lines: HashMap<usize, u64> = HashMap::new();
+        for ((func, pc), n) in self.counts.iter() {
+            if self.funcs[func].name == func_name {
+                *lines.entry(self.lines[&(*func, *pc)]).or_default() += n;
+            }
+        }
+        let mut lines: Vec<(usize, u64)> = lines.into_iter().collect();
+        lines.sort();
+        lines
+    }
+
+    /// write_folded writes the sampled stacks in the folded format of
+    /// flamegraph.pl and inferno, like "main.main;main.fib 1234", weighted by
+    /// instructions
+    pub fn write_folded<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        let mut folded: HashMap<String, u64> = HashMap::new();
+        for (stack, (n, _)) in self.stacks.iter() {
+            let names: Vec<&str> = stack
+                .iter()
+                .rev()
+                .map(|(f, _)| self.funcs[f].name.as_str())
+                .collect();
+            *folded.entry(names.join(";")).or_default() += n;
+        }
+        let mut folded: Vec<(String, u64)> = folded.into_iter().collect();
+        folded.sort();
+        for (stack, n) in folded.iter() {
+            writeln!(w, "{} {}", stack, n)?;
+        }
+        Ok(())
+    }
+
+    /// write_pprof writes the sampled stacks in the protobuf format of pprof,
+    /// uncompressed, with the sample types instructions and time
+    pub fn write_pprof<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        let mut strings = StringTable::new();
+        let mut p = Proto::new();
+        for (typ, unit) in [("instructions", "count"), ("time", "nanoseconds")].iter() {
+            let mut vt = Proto::new();
+            vt.int(1, strings.index(typ) as u64);
+            vt.int(2, strings.index(unit) as u64);
+            p.message(1, &vt);
+        }
+
+        // the ids of pprof start from 1
+        let mut func_ids: HashMap<FunctionKey, u64> = HashMap::new();
+        let mut loc_ids: HashMap<(FunctionKey, usize), u64> = HashMap::new();
+        let mut stacks: Vec<(&Stack, &(u64, Duration))> = self.stacks.iter().collect();
+        stacks.sort();
+        for (stack, (n, time)) in stacks.into_iter() {
+            let mut ids = vec![];
+            for loc in stack.iter() {
+                let count = loc_ids.len() as u64;
+                ids.push(*loc_ids.entry(*loc).or_insert(count + 1));
+                let count = func_ids.len() as u64;
+                func_ids.entry(loc.0).or_insert(count + 1);
+            }
+            let mut sample = Proto::new();
+            sample.packed(1, &ids);
+            sample.packed(2, &[*n, time.as_nanos() as u64]);
+            p.message(2, &sample);
+        }
+
+        let mut locs: Vec<(&(FunctionKey, usize), &u64)> = loc_ids.iter().collect();
+        locs.sort_by_key(|(_, id)| **id);
+        for (loc, id) in locs.into_iter() {
+            let mut line = Proto::new();
+            line.int(1, func_ids[&loc.0]);
+            line.int(2, self.lines[loc] as u64);
+            let mut location = Proto::new();
+            location.int(1, *id);
+            // the pc, so that every location is different
+            location.int(3, loc.1 as u64);
+            location.message(4, &line);
+            p.message(4, &location);
+        }
+        let mut funcs: Vec<(&FunctionKey, &u64)> = func_ids.iter().collect();
+        funcs.sort_by_key(|(_, id)| **id);
+        for (key, id) in funcs.into_iter() {
+            let info = &self.funcs[key];
+            let mut f = Proto::new();
+            f.int(1, *id);
+            f.int(2, strings.index(&info.name) as u64);
+            f.int(3, strings.index(&info.name) as u64);
+            f.int(4, strings.index(&info.file) as u64);
+            f.int(5, info.line as u64);
+            p.message(5, &f);
+        }
+
+        p.int(10, self.duration.as_nanos() as u64);
+        let mut period = Proto::new();
+        period.int(1, strings.index("instructions") as u64);
+        period.int(2, strings.index("count") as u64);
+        p.message(11, &period);
+        p.int(12, SAMPLE_INTERVAL as u64);
+        // the strings go last, when they are all known
+        for s in strings.list.iter() {
+            p.bytes(6, s.as_bytes());
+        }
+        w.write_all(&p.buf)
+    }
+}
+
+struct StringTable {
+    list: Vec<String>,
+    map: HashMap<String, usize>,
+}
+
+impl StringTable {
+    fn new() -> StringTable {
+        // the first string of a pprof string table is always ""
+        StringTable {
+            list: vec!["".to_string()],
+            map: vec![("".to_string(), 0)].into_iter().collect(),
+        }
+    }
+
+    fn index(&mut self, s: &str) -> usize {
+        if let Some(i) = self.map.get(s) {
+            return *i;
+        }
+        self.list.push(s.to_string());
+        self.map.insert(s.to_string(), self.list.len() - 1);
+        self.list.len() - 1
+    }
+}
+
+/// Proto encodes a protobuf message, it only has what pprof needs
+struct Proto {
+    buf: Vec<u8>,
+}
+
+impl Proto {
+    fn new() -> Proto {
+        Proto { buf: vec![] }
+    }
+
+    fn varint(&mut self, mut v: u64) {
+        while v >= 0x80 {
+            self.buf.push((v as u8) | 0x80);
+            v >>= 7;
+        }
+        self.buf.push(v as u8);
+    }
+
+    fn int(&mut self, field: u64, v: u64) {
+        self.varint(field << 3);
+        self.varint(v);
+    }
+
+    fn bytes(&mut self, field: u64, b: &[u8]) {
+        self.varint(field << 3 | 2);
+        self.varint(b.len() as u64);
+        self.buf.extend_from_slice(b);
+    }
+
+    fn message(&mut self, field: u64, m: &Proto) {
+        self.bytes(field, &m.buf);
+    }
+
+    fn packed(&mut self, field: u64, vals: &[u64]) {
+        let mut p = Proto::new();
+        for v in vals.iter() {
+            p.varint(*v);
+        }
+        self.bytes(field, &p.buf);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_proto() {
+        let mut p = Proto::new();
+        p.int(1, 150);
+        assert_eq!(p.buf, vec![0x08, 0x96, 0x01]);
+        let mut p = Proto::new();
+        p.bytes(6, b"ab");
+        assert_eq!(p.buf, vec![0x32, 2, b'a', b'b']);
+        let mut p = Proto::new();
+        p.packed(1, &[3, 270]);
+        assert_eq!(p.buf, vec![0x0a, 3, 3, 0x8e, 0x02]);
+    }
+}
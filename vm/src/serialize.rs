@@ -0,0 +1,514 @@
+//! Binary serialization of ByteCode, so a program can be compiled once and
+//! later loaded without the parser or the type checker.
+//!
+//! A file starts with MAGIC and the format VERSION, anything after that is
+//! only readable by the same version. Slotmap keys are written as positions
+//! in their maps and remapped to fresh keys on loading.
+
+use super::gc::GcoVec;
+use super::metadata::{Metadata, MetadataType};
+use super::objects::{
+    FunctionKey, FunctionVal, MetadataKey, MetadataObjs, PackageKey, PackageVal, VMObjects,
+};
+use super::vm::ByteCode;
+use goscript_parser::FileSet;
+use slotmap::{DenseSlotMap, Key};
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::io::{self, Read, Write};
+use std::rc::Rc;
+
+pub const MAGIC: &[u8; 4] = b"GOSB";
+/// VERSION has to change with the opcodes too, an instruction is written as
+/// it's encoded in memory
+pub const VERSION: u32 = 6;
+
+pub trait Encode {
+    fn encode(&self, e: &mut Encoder);
+}
+
+pub trait Decode: Sized {
+    fn decode(d: &mut Decoder) -> io::Result<Self>;
+}
+
+pub fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+pub struct Encoder {
+    buf: Vec<u8>,
+    metas: HashMap<MetadataKey, u32>,
+    functions: HashMap<FunctionKey, u32>,
+    packages: HashMap<PackageKey, u32>,
+}
+
+impl Encoder {
+    fn new(objs: &VMObjects) -> Encoder {
+        fn positions<K: Key + Copy + Eq + Hash, V>(m: &DenseSlotMap<K, V>) -> HashMap<K, u32> {
+            m.keys().enumerate().map(|(i, k)| (k, i as u32)).collect()
+        }
+        Encoder {
+            buf: Vec::new(),
+            metas: positions(&objs.metas),
+            functions: positions(&objs.functions),
+            packages: positions(&objs.packages),
+        }
+    }
+
+    #[inline]
+    pub fn write<T: Encode + ?Sized>(&mut self, v: &T) {
+        v.encode(self)
+    }
+
+    #[inline]
+    pub fn write_bytes(&mut self, b: &[u8]) {
+        self.buf.extend_from_slice(b)
+    }
+}
+
+pub struct Decoder<'a> {
+    data: &'a [u8],
+    pos: usize,
+    metas: Vec<MetadataKey>,
+    functions: Vec<FunctionKey>,
+    packages: Vec<PackageKey>,
+    gcv: GcoVec,
+}
+
+impl<'a> Decoder<'a> {
+    fn new(data: &'a [u8]) -> Decoder<'a> {
+        Decoder {
+            data: data,
+            pos: 0,
+            metas: vec![],
+            functions: vec![],
+            packages: vec![],
+            gcv: GcoVec::new(),
+        }
+    }
+
+    #[inline]
+    pub fn read<T: Decode>(&mut self) -> io::Result<T> {
+        T::decode(self)
+    }
+
+    pub fn read_bytes(&mut self, n: usize) -> io::Result<&'a [u8]> {
+        if n > self.data.len() - self.pos {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "unexpected end of bytecode",
+            ));
+        }
+        let b = &self.data[self.pos..self.pos + n];
+        self.pos += n;
+        Ok(b)
+    }
+
+    /// gcv is what decoded values are registered with, the VM never sees it
+    /// so it's the same as the dummy one used by the code generator
+    #[inline]
+    pub fn gcv(&self) -> &GcoVec {
+        &self.gcv
+    }
+
+    /// read_len reads a length and makes sure it's not beyond the remaining
+    /// data, so that a corrupted length doesn't trigger a huge allocation
+    pub fn read_len(&mut self) -> io::Result<usize> {
+        let l = self.read::<u64>()?;
+        if l > (self.data.len() - self.pos) as u64 {
+            return Err(invalid_data("length out of range"));
+        }
+        Ok(l as usize)
+    }
+}
+
+/// new_keys returns the keys a fresh DenseSlotMap hands out for count
+/// insertions, which are the keys of the loaded objects
+fn new_keys<K: Key + Copy>(count: usize) -> Vec<K> {
+    let mut m: DenseSlotMap<K, ()> = DenseSlotMap::with_key();
+    (0..count).map(|_| m.insert(())).collect()
+}
+
+fn insert_all<K: Key + Copy + PartialEq, V>(
+    m: &mut DenseSlotMap<K, V>,
+    vals: Vec<V>,
+    keys: &[K],
+) -> io::Result<()> {
+    for (v, k) in vals.into_iter().zip(keys.iter()) {
+        if m.insert(v) != *k {
+            return Err(invalid_data("unexpected object key"));
+        }
+    }
+    Ok(())
+}
+
+impl ByteCode {
+    /// write_to serializes the ByteCode, with the FileSet if provided so that
+    /// runtime errors can still be reported with source positions.
+    /// It must be called before the ByteCode is run.
+    pub fn write_to<W: Write>(&self, w: &mut W, fs: Option<&FileSet>) -> io::Result<()> {
+        let objs = &self.objects;
+        let mut e = Encoder::new(objs);
+        e.write_bytes(MAGIC);
+        e.write(&VERSION);
+        e.write(&objs.metas.len());
+        e.write(&objs.functions.len());
+        e.write(&objs.packages.len());
+        for m in objs.metas.values() {
+            e.write(m);
+        }
+        e.write(&objs.metadata);
+        for f in objs.functions.values() {
+            e.write(f);
+        }
+        for p in objs.packages.values() {
+            e.write(p);
+        }
+        e.write(&self.packages);
+        e.write(&self.ifaces);
+        e.write(&self.entry);
+        e.write(&fs);
+        w.write_all(&e.buf)
+    }
+
+    pub fn read_from<R: Read>(r: &mut R) -> io::Result<(ByteCode, Option<FileSet>)> {
+        let mut data = vec![];
+        r.read_to_end(&mut data)?;
+        let mut d = Decoder::new(&data);
+        if d.read_bytes(MAGIC.len())? != MAGIC {
+            return Err(invalid_data("not goscript bytecode"));
+        }
+        let version: u32 = d.read()?;
+        if version != VERSION {
+            return Err(invalid_data(&format!(
+                "unsupported bytecode version {}, expecting {}",
+                version, VERSION
+            )));
+        }
+        let (meta_count, func_count, pkg_count) = (d.read_len()?, d.read_len()?, d.read_len()?);
+        d.metas = new_keys(meta_count);
+        d.functions = new_keys(func_count);
+        d.packages = new_keys(pkg_count);
+
+        let mut metas: MetadataObjs = DenseSlotMap::with_capacity_and_key(meta_count);
+        let vals = (0..meta_count)
+            .map(|_| d.read())
+            .collect::<io::Result<Vec<MetadataType>>>()?;
+        insert_all(&mut metas, vals, &d.metas)?;
+        let metadata: Metadata = d.read()?;
+        let mut functions = DenseSlotMap::with_capacity_and_key(func_count);
+        let vals = (0..func_count)
+            .map(|_| d.read())
+            .collect::<io::Result<Vec<FunctionVal>>>()?;
+        insert_all(&mut functions, vals, &d.functions)?;
+        let mut packages = DenseSlotMap::with_capacity_and_key(pkg_count);
+        let vals = (0..pkg_count)
+            .map(|_| d.read())
+            .collect::<io::Result<Vec<PackageVal>>>()?;
+        insert_all(&mut packages, vals, &d.packages)?;
+
+        let bc = ByteCode {
+            objects: Box::pin(VMObjects {
+                metas: metas,
+                functions: functions,
+                packages: packages,
+                metadata: metadata,
+            }),
+            packages: d.read()?,
+            ifaces: d.read()?,
+            entry: d.read()?,
+        };
+        let fs = d.read()?;
+        if d.pos != data.len() {
+            return Err(invalid_data("trailing data after bytecode"));
+        }
+        Ok((bc, fs))
+    }
+}
+
+// ----------------------------------------------------------------------------
+// keys
+
+macro_rules! impl_key {
+    ($key:ty, $field:ident) => {
+        impl Encode for $key {
+            fn encode(&self, e: &mut Encoder) {
+                let i = if self.is_null() {
+                    std::u32::MAX
+                } else {
+                    e.$field[self]
+                };
+                e.write(&i);
+            }
+        }
+
+        impl Decode for $key {
+            fn decode(d: &mut Decoder) -> io::Result<Self> {
+                let i: u32 = d.read()?;
+                if i == std::u32::MAX {
+                    Ok(null_key!())
+                } else {
+                    d.$field
+                        .get(i as usize)
+                        .copied()
+                        .ok_or_else(|| invalid_data("object key out of range"))
+                }
+            }
+        }
+    };
+}
+
+impl_key!(MetadataKey, metas);
+impl_key!(FunctionKey, functions);
+impl_key!(PackageKey, packages);
+
+// ----------------------------------------------------------------------------
+// primitives and containers
+
+macro_rules! impl_num {
+    ($($t:ty),*) => {
+        $(
+            impl Encode for $t {
+                #[inline]
+                fn encode(&self, e: &mut Encoder) {
+                    e.write_bytes(&self.to_le_bytes());
+                }
+            }
+
+            impl Decode for $t {
+                #[inline]
+                fn decode(d: &mut Decoder) -> io::Result<Self> {
+                    let mut b = [0; std::mem::size_of::<$t>()];
+                    b.copy_from_slice(d.read_bytes(std::mem::size_of::<$t>())?);
+                    Ok(<$t>::from_le_bytes(b))
+                }
+            }
+        )*
+    };
+}
+
+impl_num!(u8, u16, u32, u64, i8, i16, i32, i64, f32, f64);
+
+impl Encode for usize {
+    #[inline]
+    fn encode(&self, e: &mut Encoder) {
+        e.write(&(*self as u64));
+    }
+}
+
+impl Decode for usize {
+    #[inline]
+    fn decode(d: &mut Decoder) -> io::Result<Self> {
+        let v: u64 = d.read()?;
+        if v > std::usize::MAX as u64 {
+            return Err(invalid_data("usize out of range"));
+        }
+        Ok(v as usize)
+    }
+}
+
+impl Encode for isize {
+    #[inline]
+    fn encode(&self, e: &mut Encoder) {
+        e.write(&(*self as i64));
+    }
+}
+
+impl Decode for isize {
+    #[inline]
+    fn decode(d: &mut Decoder) -> io::Result<Self> {
+        let v: i64 = d.read()?;
+        if v > std::isize::MAX as i64 || v < std::isize::MIN as i64 {
+            return Err(invalid_data("isize out of range"));
+        }
+        Ok(v as isize)
+    }
+}
+
+impl Encode for bool {
+    #[inline]
+    fn encode(&self, e: &mut Encoder) {
+        e.write(&(*self as u8));
+    }
+}
+
+impl Decode for bool {
+    #[inline]
+    fn decode(d: &mut Decoder) -> io::Result<Self> {
+        match d.read::<u8>()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(invalid_data("invalid bool")),
+        }
+    }
+}
+
+impl Encode for [u8] {
+    fn encode(&self, e: &mut Encoder) {
+        e.write(&self.len());
+        e.write_bytes(self);
+    }
+}
+
+impl Encode for String {
+    fn encode(&self, e: &mut Encoder) {
+        e.write(self.as_bytes());
+    }
+}
+
+impl Decode for String {
+    fn decode(d: &mut Decoder) -> io::Result<Self> {
+        let l = d.read_len()?;
+        String::from_utf8(d.read_bytes(l)?.to_vec()).map_err(|_| invalid_data("invalid utf-8"))
+    }
+}
+
+impl<T: Encode> Encode for Vec<T> {
+    fn encode(&self, e: &mut Encoder) {
+        e.write(&self.len());
+        for v in self.iter() {
+            e.write(v);
+        }
+    }
+}
+
+impl<T: Decode> Decode for Vec<T> {
+    fn decode(d: &mut Decoder) -> io::Result<Self> {
+        let l = d.read_len()?;
+        (0..l).map(|_| d.read()).collect()
+    }
+}
+
+impl<T: Encode> Encode for Option<T> {
+    fn encode(&self, e: &mut Encoder) {
+        match self {
+            Some(v) => {
+                e.write(&true);
+                e.write(v);
+            }
+            None => e.write(&false),
+        }
+    }
+}
+
+impl<T: Decode> Decode for Option<T> {
+    fn decode(d: &mut Decoder) -> io::Result<Self> {
+        Ok(if d.read()? { Some(d.read()?) } else { None })
+    }
+}
+
+impl<T: Encode> Encode for Rc<T> {
+    fn encode(&self, e: &mut Encoder) {
+        e.write(self.as_ref());
+    }
+}
+
+impl<T: Decode> Decode for Rc<T> {
+    fn decode(d: &mut Decoder) -> io::Result<Self> {
+        Ok(Rc::new(d.read()?))
+    }
+}
+
+impl<T: Encode> Encode for &T {
+    fn encode(&self, e: &mut Encoder) {
+        e.write(*self);
+    }
+}
+
+impl<A: Encode, B: Encode> Encode for (A, B) {
+    fn encode(&self, e: &mut Encoder) {
+        e.write(&self.0);
+        e.write(&self.1);
+    }
+}
+
+impl<A: Decode, B: Decode> Decode for (A, B) {
+    fn decode(d: &mut Decoder) -> io::Result<Self> {
+        Ok((d.read()?, d.read()?))
+    }
+}
+
+impl<K: Encode, V: Encode> Encode for HashMap<K, V> {
+    fn encode(&self, e: &mut Encoder) {
+        e.write(&self.len());
+        for (k, v) in self.iter() {
+            e.write(k);
+            e.write(v);
+        }
+    }
+}
+
+impl<K: Decode + Eq + Hash, V: Decode> Decode for HashMap<K, V> {
+    fn decode(d: &mut Decoder) -> io::Result<Self> {
+        let l = d.read_len()?;
+        (0..l).map(|_| d.read()).collect()
+    }
+}
+
+// ----------------------------------------------------------------------------
+// FileSet
+
+impl Encode for FileSet {
+    fn encode(&self, e: &mut Encoder) {
+        e.write(&self.iter().count());
+        for f in self.iter() {
+            e.write(f.name().as_bytes());
+            e.write(&f.base());
+            e.write(&f.size());
+            e.write(&f.lines().to_vec());
+        }
+    }
+}
+
+impl Decode for FileSet {
+    fn decode(d: &mut Decoder) -> io::Result<Self> {
+        let mut fs = FileSet::new();
+        let count = d.read_len()?;
+        for _ in 0..count {
+            let (name, base, size): (String, usize, usize) = (d.read()?, d.read()?, d.read()?);
+            let lines: Vec<usize> = d.read()?;
+            if base < fs.base() {
+                return Err(invalid_data("invalid file base"));
+            }
+            let f = fs.add_file(name, Some(base), size);
+            // a new File already has the first line
+            if lines.len() > 1 && !f.set_lines(lines) {
+                return Err(invalid_data("invalid file lines"));
+            }
+        }
+        Ok(fs)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::instruction::{Instruction, Opcode, ValueType};
+
+    fn decode_inst(val: u64) -> io::Result<Instruction> {
+        let buf = val.to_le_bytes();
+        Decoder::new(&buf).read()
+    }
+
+    #[test]
+    fn test_decode_instruction() {
+        let inst = Instruction::new(
+            Opcode::ADD,
+            Some(ValueType::Int),
+            Some(ValueType::Int),
+            None,
+            None,
+        );
+        assert_eq!(decode_inst(inst.get_u64()).unwrap().op(), Opcode::ADD);
+
+        // the t1 byte is out of range
+        let bad = inst.get_u64() | 0xff << (8 + 32);
+        assert!(decode_inst(bad).is_err());
+        let bad = (Opcode::GEQ_FLOAT64 as u64 + 1) << (8 * 3 + 32);
+        assert!(decode_inst(bad).is_err());
+
+        // the register forms keep locals where t1 and t2 would be
+        let inst = Instruction::new_reg(Opcode::ADD_R, ValueType::Int, 0xff00, 0xffff, 1);
+        assert!(decode_inst(inst.get_u64()).is_ok());
+    }
+}
@@ -31,6 +31,34 @@ macro_rules! stack_cmp_op {
     }};
 }
 
+macro_rules! reg_binary_op {
+    ($stack:ident, $op:tt, $base:ident, $inst:ident) => {{
+        let (d, a, b) = $inst.reg();
+        let a = $stack.get_c(Stack::offset($base, a));
+        let b = $stack.get_c(Stack::offset($base, b));
+        let v = GosValue64::$op(a, b, $inst.t0());
+        $stack.set_reg($base, d, v);
+    }};
+}
+
+macro_rules! reg_cmp_op {
+    ($stack:ident, $op:tt, $base:ident, $inst:ident) => {{
+        let (d, a, b) = $inst.reg();
+        let a = $stack.get_c(Stack::offset($base, a));
+        let b = $stack.get_c(Stack::offset($base, b));
+        let v = GosValue64::from_bool(GosValue64::$op(a, b, $inst.t0()));
+        $stack.set_reg($base, d, v);
+    }};
+}
+
+macro_rules! reg_cmp_imm_op {
+    ($stack:ident, $op:tt, $base:ident, $inst:ident) => {{
+        let (d, a, k) = $inst.reg_imm();
+        let a = $stack.get_c(Stack::offset($base, a)).get_int();
+        $stack.set_reg($base, d, GosValue64::from_bool(a $op k as isize));
+    }};
+}
+
 macro_rules! store_to_copy_semantic {
     ($from:expr,
         $to:expr,
@@ -599,11 +627,276 @@ impl Stack {
         }
     }
 
+    // the type-specialised forms, the type is a constant here so that the match
+    // on it in GosValue64 is gone once inlined
+
+    #[inline]
+    pub fn add_int(&mut self) {
+        let t = ValueType::Int;
+        stack_binary_op!(self, binary_op_add, t)
+    }
+
+    #[inline]
+    pub fn sub_int(&mut self) {
+        let t = ValueType::Int;
+        stack_binary_op!(self, binary_op_sub, t)
+    }
+
+    #[inline]
+    pub fn mul_int(&mut self) {
+        let t = ValueType::Int;
+        stack_binary_op!(self, binary_op_mul, t)
+    }
+
+    #[inline]
+    pub fn compare_eql_int(&mut self) {
+        let t = ValueType::Int;
+        stack_cmp_op!(self, compare_eql, t)
+    }
+
+    #[inline]
+    pub fn compare_neq_int(&mut self) {
+        let t = ValueType::Int;
+        stack_cmp_op!(self, compare_neq, t)
+    }
+
+    #[inline]
+    pub fn compare_lss_int(&mut self) {
+        let t = ValueType::Int;
+        stack_cmp_op!(self, compare_lss, t)
+    }
+
+    #[inline]
+    pub fn compare_gtr_int(&mut self) {
+        let t = ValueType::Int;
+        stack_cmp_op!(self, compare_gtr, t)
+    }
+
+    #[inline]
+    pub fn compare_leq_int(&mut self) {
+        let t = ValueType::Int;
+        stack_cmp_op!(self, compare_leq, t)
+    }
+
+    #[inline]
+    pub fn compare_geq_int(&mut self) {
+        let t = ValueType::Int;
+        stack_cmp_op!(self, compare_geq, t)
+    }
+
+    #[inline]
+    pub fn add_imm_int(&mut self, imm: i32) {
+        let v = self.get_c_mut(self.len() - 1);
+        *v = GosValue64::from_int(v.get_int().wrapping_add(imm as isize));
+    }
+
+    #[inline]
+    pub fn add_float64(&mut self) {
+        let t = ValueType::Float64;
+        stack_binary_op!(self, binary_op_add, t)
+    }
+
+    #[inline]
+    pub fn sub_float64(&mut self) {
+        let t = ValueType::Float64;
+        stack_binary_op!(self, binary_op_sub, t)
+    }
+
+    #[inline]
+    pub fn mul_float64(&mut self) {
+        let t = ValueType::Float64;
+        stack_binary_op!(self, binary_op_mul, t)
+    }
+
+    #[inline]
+    pub fn quo_float64(&mut self) {
+        let t = ValueType::Float64;
+        stack_binary_op!(self, binary_op_quo, t)
+    }
+
+    #[inline]
+    pub fn compare_eql_float64(&mut self) {
+        let t = ValueType::Float64;
+        stack_cmp_op!(self, compare_eql, t)
+    }
+
+    #[inline]
+    pub fn compare_neq_float64(&mut self) {
+        let t = ValueType::Float64;
+        stack_cmp_op!(self, compare_neq, t)
+    }
+
+    #[inline]
+    pub fn compare_lss_float64(&mut self) {
+        let t = ValueType::Float64;
+        stack_cmp_op!(self, compare_lss, t)
+    }
+
+    #[inline]
+    pub fn compare_gtr_float64(&mut self) {
+        let t = ValueType::Float64;
+        stack_cmp_op!(self, compare_gtr, t)
+    }
+
+    #[inline]
+    pub fn compare_leq_float64(&mut self) {
+        let t = ValueType::Float64;
+        stack_cmp_op!(self, compare_leq, t)
+    }
+
+    #[inline]
+    pub fn compare_geq_float64(&mut self) {
+        let t = ValueType::Float64;
+        stack_cmp_op!(self, compare_geq, t)
+    }
+
+    // the register form, only for copyable values
+
+    #[inline]
+    fn set_reg(&mut self, base: usize, d: OpIndex, v: GosValue64) {
+        if d == Instruction::REG_STACK {
+            *self.get_c_mut(self.cursor) = v;
+            self.cursor += 1;
+            assert!(self.cursor <= self.max); //todo: expand
+        } else {
+            *self.get_c_mut(Stack::offset(base, d)) = v;
+        }
+    }
+
+    #[inline]
+    pub fn add_r(&mut self, base: usize, inst: Instruction) {
+        reg_binary_op!(self, binary_op_add, base, inst)
+    }
+
+    #[inline]
+    pub fn sub_r(&mut self, base: usize, inst: Instruction) {
+        reg_binary_op!(self, binary_op_sub, base, inst)
+    }
+
+    #[inline]
+    pub fn mul_r(&mut self, base: usize, inst: Instruction) {
+        reg_binary_op!(self, binary_op_mul, base, inst)
+    }
+
+    #[inline]
+    pub fn quo_r(&mut self, base: usize, inst: Instruction) {
+        reg_binary_op!(self, binary_op_quo, base, inst)
+    }
+
+    #[inline]
+    pub fn rem_r(&mut self, base: usize, inst: Instruction) {
+        reg_binary_op!(self, binary_op_rem, base, inst)
+    }
+
+    #[inline]
+    pub fn and_r(&mut self, base: usize, inst: Instruction) {
+        reg_binary_op!(self, binary_op_and, base, inst)
+    }
+
+    #[inline]
+    pub fn or_r(&mut self, base: usize, inst: Instruction) {
+        reg_binary_op!(self, binary_op_or, base, inst)
+    }
+
+    #[inline]
+    pub fn xor_r(&mut self, base: usize, inst: Instruction) {
+        reg_binary_op!(self, binary_op_xor, base, inst)
+    }
+
+    #[inline]
+    pub fn and_not_r(&mut self, base: usize, inst: Instruction) {
+        reg_binary_op!(self, binary_op_and_not, base, inst)
+    }
+
+    #[inline]
+    pub fn compare_eql_r(&mut self, base: usize, inst: Instruction) {
+        reg_cmp_op!(self, compare_eql, base, inst)
+    }
+
+    #[inline]
+    pub fn compare_neq_r(&mut self, base: usize, inst: Instruction) {
+        reg_cmp_op!(self, compare_neq, base, inst)
+    }
+
+    #[inline]
+    pub fn compare_lss_r(&mut self, base: usize, inst: Instruction) {
+        reg_cmp_op!(self, compare_lss, base, inst)
+    }
+
+    #[inline]
+    pub fn compare_gtr_r(&mut self, base: usize, inst: Instruction) {
+        reg_cmp_op!(self, compare_gtr, base, inst)
+    }
+
+    #[inline]
+    pub fn compare_leq_r(&mut self, base: usize, inst: Instruction) {
+        reg_cmp_op!(self, compare_leq, base, inst)
+    }
+
+    #[inline]
+    pub fn compare_geq_r(&mut self, base: usize, inst: Instruction) {
+        reg_cmp_op!(self, compare_geq, base, inst)
+    }
+
+    #[inline]
+    pub fn add_ri(&mut self, base: usize, inst: Instruction) {
+        let (d, a, k) = inst.reg_imm();
+        let a = self.get_c(Stack::offset(base, a)).get_int();
+        self.set_reg(base, d, GosValue64::from_int(a.wrapping_add(k as isize)));
+    }
+
+    #[inline]
+    pub fn compare_eql_ri(&mut self, base: usize, inst: Instruction) {
+        reg_cmp_imm_op!(self, ==, base, inst)
+    }
+
+    #[inline]
+    pub fn compare_neq_ri(&mut self, base: usize, inst: Instruction) {
+        reg_cmp_imm_op!(self, !=, base, inst)
+    }
+
+    #[inline]
+    pub fn compare_lss_ri(&mut self, base: usize, inst: Instruction) {
+        reg_cmp_imm_op!(self, <, base, inst)
+    }
+
+    #[inline]
+    pub fn compare_gtr_ri(&mut self, base: usize, inst: Instruction) {
+        reg_cmp_imm_op!(self, >, base, inst)
+    }
+
+    #[inline]
+    pub fn compare_leq_ri(&mut self, base: usize, inst: Instruction) {
+        reg_cmp_imm_op!(self, <=, base, inst)
+    }
+
+    #[inline]
+    pub fn compare_geq_ri(&mut self, base: usize, inst: Instruction) {
+        reg_cmp_imm_op!(self, >=, base, inst)
+    }
+
+    /// reg_index returns the container and the index of LOAD_INDEX_R
+    #[inline]
+    pub fn reg_index(&self, base: usize, inst: Instruction) -> (&GosValue, usize) {
+        let (_, a, b) = inst.reg();
+        let index = self.get_c(Stack::offset(base, b)).get_int() as usize;
+        (self.get_rc(Stack::offset(base, a)), index)
+    }
+
     #[inline]
     fn get_c(&self, i: usize) -> &GosValue64 {
         unsafe { self.c.get_unchecked(i) }
     }
 
+    /// c_ptr is the pointer to the copyable value at the index, native code
+    /// reads and writes the frames of its calls through it
+    #[cfg(feature = "jit")]
+    #[inline]
+    pub fn c_ptr(&mut self, i: usize) -> *mut GosValue64 {
+        assert!(i <= self.max);
+        unsafe { self.c.as_mut_ptr().add(i) }
+    }
+
     #[inline]
     pub fn get_c_mut(&mut self, i: usize) -> &mut GosValue64 {
         unsafe { self.c.get_unchecked_mut(i) }
@@ -641,7 +934,7 @@ impl Stack {
 pub struct RangeStack {
     maps: Vec<GosHashMapIter<'static>>,
     slices: Vec<SliceEnumIter<'static>>,
-    strings: Vec<StringEnumIter<'static>>,
+    strings: Vec<StringIter<'static>>,
 }
 
 impl RangeStack {
@@ -666,7 +959,7 @@ impl RangeStack {
                 self.slices.push(iter);
             }
             GosValue::Str(s) => {
-                let iter = unsafe { mem::transmute(s.iter().enumerate()) };
+                let iter = unsafe { mem::transmute(s.iter()) };
                 self.strings.push(iter);
             }
             _ => unreachable!(),
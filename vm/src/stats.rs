@@ -0,0 +1,95 @@
+//! Opcode statistics, to find the superinstructions and the type-specialised
+//! opcodes worth adding. Every instruction run is counted by its opcode, by
+//! its opcode and type, and with the instruction before it. Only the
+//! instructions next to each other in the same function make a pair, as
+//! those are the ones that could be fused.
+
+use super::instruction::{Instruction, Opcode, ValueType};
+use super::objects::FunctionKey;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::Hash;
+
+/// the report shows the top REPORT_LINES of every table
+const REPORT_LINES: usize = 30;
+
+#[derive(Default)]
+pub struct OpStats {
+    ops: RefCell<HashMap<Opcode, u64>>,
+    types: RefCell<HashMap<(Opcode, ValueType), u64>>,
+    pairs: RefCell<HashMap<(Opcode, Opcode), u64>>,
+    // the last instruction run by every fiber
+    last: RefCell<HashMap<usize, (FunctionKey, usize, Opcode)>>,
+}
+
+impl OpStats {
+    pub fn new() -> OpStats {
+        OpStats::default()
+    }
+
+    /// count counts the instruction at pc of func, run by the fiber
+    pub fn count(&self, fiber: usize, func: FunctionKey, pc: usize, inst: Instruction) {
+        let op = inst.op();
+        *self.ops.borrow_mut().entry(op).or_insert(0) += 1;
+        *self.types.borrow_mut().entry((op, inst.t0())).or_insert(0) += 1;
+        let last = self.last.borrow_mut().insert(fiber, (func, pc, op));
+        if let Some((lfunc, lpc, lop)) = last {
+            if lfunc == func && lpc + 1 == pc {
+                *self.pairs.borrow_mut().entry((lop, op)).or_insert(0) += 1;
+            }
+        }
+    }
+
+    pub fn instructions(&self) -> u64 {
+        self.ops.borrow().values().sum()
+    }
+
+    /// ops returns the count of every opcode, the most run first
+    pub fn ops(&self) -> Vec<(Opcode, u64)> {
+        sorted(&self.ops.borrow())
+    }
+
+    pub fn types(&self) -> Vec<((Opcode, ValueType), u64)> {
+        sorted(&self.types.borrow())
+    }
+
+    pub fn pairs(&self) -> Vec<((Opcode, Opcode), u64)> {
+        sorted(&self.pairs.borrow())
+    }
+}
+
+fn sorted<K: Copy + Eq + Hash + fmt::Debug>(map: &HashMap<K, u64>) -> Vec<(K, u64)> {
+    let mut list: Vec<(K, u64)> = map.iter().map(|(k, n)| (*k, *n)).collect();
+    // the names break ties, so that the order is stable
+    list.sort_by_cached_key(|(k, n)| (std::cmp::Reverse(*n), format!("{:?}", k)));
+    list
+}
+
+impl fmt::Display for OpStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let total = self.instructions();
+        let percent = |n: u64| n as f64 * 100.0 / total.max(1) as f64;
+        writeln!(f, "{} instructions", total)?;
+        writeln!(f, "\nopcodes:")?;
+        for (op, n) in self.ops().into_iter().take(REPORT_LINES) {
+            writeln!(f, "{:>12} {:>6.2}%  {}", n, percent(n), op.text())?;
+        }
+        writeln!(f, "\nopcodes by type:")?;
+        for ((op, t), n) in self.types().into_iter().take(REPORT_LINES) {
+            writeln!(f, "{:>12} {:>6.2}%  {} {:?}", n, percent(n), op.text(), t)?;
+        }
+        writeln!(f, "\nopcode pairs:")?;
+        for ((a, b), n) in self.pairs().into_iter().take(REPORT_LINES) {
+            writeln!(
+                f,
+                "{:>12} {:>6.2}%  {} {}",
+                n,
+                percent(n),
+                a.text(),
+                b.text()
+            )?;
+        }
+        Ok(())
+    }
+}
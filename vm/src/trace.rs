@@ -0,0 +1,236 @@
+//! Go style goroutine traces. A panic that reaches the top of a fiber prints
+//! the trace of it, and a TraceHandle asks a running vm for the traces of all
+//! its goroutines from any thread, like a watchdog does when it fires.
+
+use super::disasm::func_names;
+use super::objects::FunctionKey;
+use super::value::GosValue;
+use super::vm::ByteCode;
+use goscript_parser::FileSet;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::Duration;
+
+/// a frame shows MAX_ARGS arguments at most, like Go does
+const MAX_ARGS: usize = 10;
+/// longer arguments are cut at ARG_LEN chars
+const ARG_LEN: usize = 16;
+
+/// StackFrame is a frame as the vm captures it, the arguments are the
+/// receiver and the parameters
+#[derive(Debug)]
+pub struct StackFrame {
+    pub func: FunctionKey,
+    pub pc: usize,
+    pub args: Vec<GosValue>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Frame {
+    pub func: String,
+    /// the summaries of the arguments, None for an inlined call
+    pub args: Option<Vec<String>>,
+    /// "file:line", None without debug info
+    pub position: Option<String>,
+    pub pc: usize,
+}
+
+/// Goroutine is the trace of a goroutine, the innermost frame first
+#[derive(Clone, Debug)]
+pub struct Goroutine {
+    pub id: usize,
+    /// "running", "runnable", "chan receive", "chan send" or "select"
+    pub state: String,
+    pub frames: Vec<Frame>,
+}
+
+impl fmt::Display for Goroutine {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "goroutine {} [{}]:", self.id, self.state)?;
+        for frame in self.frames.iter() {
+            match &frame.args {
+                Some(args) if args.len() > MAX_ARGS => {
+                    writeln!(f, "{}({}, ...)", frame.func, args[..MAX_ARGS].join(", "))?
+                }
+                Some(args) => writeln!(f, "{}({})", frame.func, args.join(", "))?,
+                None => writeln!(f, "{}(...)", frame.func)?,
+            }
+            match &frame.position {
+                Some(p) => writeln!(f, "\t{} +{:#x}", p, frame.pc)?,
+                None => writeln!(f, "\t<no debug info available>")?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Tracer turns the captured frames into traces
+pub struct Tracer<'a> {
+    code: &'a ByteCode,
+    fs: Option<&'a FileSet>,
+    names: HashMap<FunctionKey, String>,
+    // the source range of the code of every function, not counting what's
+    // inlined in it, to tell which function an inlined instruction is from
+    ranges: Vec<(usize, usize, FunctionKey)>,
+}
+
+impl<'a> Tracer<'a> {
+    pub fn new(code: &'a ByteCode, fs: Option<&'a FileSet>) -> Tracer<'a> {
+        let mut ranges = vec![];
+        for (key, func) in code.objects.functions.iter() {
+            let own = (0..func.pos().len())
+                .filter(|pc| func.inlined_call(*pc).is_none())
+                .filter_map(|pc| func.pos()[pc]);
+            let range = own.fold(None, |r: Option<(usize, usize)>, p| {
+                Some(r.map_or((p, p), |(b, e)| (b.min(p), e.max(p))))
+            });
+            if let Some((begin, end)) = range {
+                ranges.push((begin, end, key));
+            }
+        }
+        Tracer {
+            code: code,
+            fs: fs,
+            names: func_names(code),
+            ranges: ranges,
+        }
+    }
+
+    pub fn goroutine(&self, id: usize, state: &str, frames: &[StackFrame]) -> Goroutine {
+        let mut list = vec![];
+        for sf in frames.iter() {
+            let func = &self.code.objects.functions[sf.func];
+            let pos = func.pos().get(sf.pc).copied().flatten();
+            // an inlined call shows as the frame it would have had
+            if let Some(call) = func.inlined_call(sf.pc) {
+                let callee = pos.and_then(|p| self.func_at(p));
+                list.push(Frame {
+                    func: callee.map_or("?".to_string(), |k| self.name(k)),
+                    args: None,
+                    position: pos.and_then(|p| self.position(p)),
+                    pc: sf.pc,
+                });
+                list.push(self.frame(sf, Some(call)));
+            } else {
+                list.push(self.frame(sf, pos));
+            }
+        }
+        Goroutine {
+            id: id,
+            state: state.to_string(),
+            frames: list,
+        }
+    }
+
+    fn frame(&self, sf: &StackFrame, pos: Option<usize>) -> Frame {
+        Frame {
+            func: self.name(sf.func),
+            args: Some(sf.args.iter().map(summary).collect()),
+            position: pos.and_then(|p| self.position(p)),
+            pc: sf.pc,
+        }
+    }
+
+    fn name(&self, key: FunctionKey) -> String {
+        self.names.get(&key).cloned().unwrap_or("?".to_string())
+    }
+
+    fn position(&self, pos: usize) -> Option<String> {
+        let p = self.fs?.position(pos);
+        Some(format!("{}:{}", p.filename, p.line))
+    }
+
+    /// func_at returns the innermost function with its code around pos
+    fn func_at(&self, pos: usize) -> Option<FunctionKey> {
+        self.ranges
+            .iter()
+            .filter(|(b, e, _)| *b <= pos && pos <= *e)
+            .min_by_key(|(b, e, _)| e - b)
+            .map(|(_, _, k)| *k)
+    }
+}
+
+/// summary shows the value of an argument in short, the containers only
+/// with their lengths
+fn summary(val: &GosValue) -> String {
+    let s = match val {
+        GosValue::Nil(_) => "nil".to_string(),
+        GosValue::Str(s) => format!("{:?}", s.as_str()),
+        GosValue::Array(a) => format!("[{}]{{...}}", a.0.len()),
+        GosValue::Slice(s) => format!("[]{{len {}}}", s.0.len()),
+        GosValue::Map(m) => format!("map{{len {}}}", m.0.len()),
+        GosValue::Struct(_) => "{...}".to_string(),
+        GosValue::Pointer(_) => "<pointer>".to_string(),
+        GosValue::Interface(i) => match i.borrow().underlying_value() {
+            Some(v) => return summary(v),
+            None => "nil".to_string(),
+        },
+        GosValue::Named(n) => return summary(&n.0),
+        _ => val.to_string(),
+    };
+    if s.chars().count() > ARG_LEN {
+        s.chars().take(ARG_LEN).collect::<String>() + "..."
+    } else {
+        s
+    }
+}
+
+#[derive(Default)]
+struct Shared {
+    requested: AtomicBool,
+    traces: Mutex<Option<Vec<Goroutine>>>,
+    answered: Condvar,
+}
+
+/// TraceHandle asks a running vm for the traces of all its goroutines, it
+/// can be cloned and sent to other threads. The vm answers between two runs
+/// of its fibers, so it can't while it's stuck in an ffi call. While a vm
+/// has a handle, its fibers keep their frames up to date at every yield and
+/// channel operation, which costs a little.
+#[derive(Clone, Default)]
+pub struct TraceHandle {
+    shared: Arc<Shared>,
+}
+
+impl TraceHandle {
+    pub fn new() -> TraceHandle {
+        TraceHandle::default()
+    }
+
+    /// request asks for the traces, without waiting for them
+    pub fn request(&self) {
+        *self.shared.traces.lock().unwrap() = None;
+        self.shared.requested.store(true, Ordering::SeqCst);
+    }
+
+    /// take returns the traces if the vm has answered the last request
+    pub fn take(&self) -> Option<Vec<Goroutine>> {
+        self.shared.traces.lock().unwrap().take()
+    }
+
+    /// goroutines requests the traces and waits up to timeout for them,
+    /// the goroutines are sorted by id
+    pub fn goroutines(&self, timeout: Duration) -> Option<Vec<Goroutine>> {
+        self.request();
+        let traces = self.shared.traces.lock().unwrap();
+        let (mut traces, _) = self
+            .shared
+            .answered
+            .wait_timeout_while(traces, timeout, |t| t.is_none())
+            .unwrap();
+        traces.take()
+    }
+
+    pub fn requested(&self) -> bool {
+        self.shared.requested.load(Ordering::Relaxed)
+    }
+
+    /// answer is for the vm, to hand over the traces requested
+    pub fn answer(&self, traces: Vec<Goroutine>) {
+        self.shared.requested.store(false, Ordering::SeqCst);
+        *self.shared.traces.lock().unwrap() = Some(traces);
+        self.shared.answered.notify_all();
+    }
+}
@@ -3,6 +3,7 @@ use super::gc::{GcWeak, GcoVec};
 use super::instruction::{Opcode, ValueType};
 use super::metadata::*;
 pub use super::objects::*;
+use super::serialize::{invalid_data, Decode, Decoder, Encode, Encoder};
 use super::stack::Stack;
 use ordered_float;
 use std::cell::{Cell, RefCell};
@@ -10,6 +11,7 @@ use std::cmp::Ordering;
 use std::collections::VecDeque;
 use std::fmt::{self, Display};
 use std::hash::{Hash, Hasher};
+use std::io;
 use std::num::Wrapping;
 use std::rc::Rc;
 use std::result;
@@ -275,6 +277,11 @@ impl GosValue {
         GosValue::Str(Rc::new(StringObj::with_str(s)))
     }
 
+    #[inline]
+    pub fn new_str_bytes(b: Vec<u8>) -> GosValue {
+        GosValue::Str(Rc::new(StringObj::with_bytes(b)))
+    }
+
     #[inline]
     pub fn new_pointer(v: PointerObj) -> GosValue {
         GosValue::Pointer(Box::new(v))
@@ -565,6 +572,83 @@ impl GosValue {
         }
     }
 
+    /// uncomparable_kind returns the kind of the first slice, map or func value
+    /// found in self, such values make hashing self a runtime error
+    fn uncomparable_kind(&self) -> Option<&'static str> {
+        match self {
+            GosValue::Slice(_) => Some("slice"),
+            GosValue::Map(_) => Some("map"),
+            GosValue::Closure(_) => Some("func"),
+            GosValue::Named(n) => n.0.uncomparable_kind(),
+            GosValue::Interface(iface) => iface
+                .borrow()
+                .underlying_value()
+                .and_then(|v| v.uncomparable_kind()),
+            GosValue::Struct(s) => {
+                s.0.borrow()
+                    .fields
+                    .iter()
+                    .find_map(|x| x.uncomparable_kind())
+            }
+            GosValue::Array(a) => {
+                a.0.borrow_data()
+                    .iter()
+                    .find_map(|x| x.borrow().uncomparable_kind())
+            }
+            _ => None,
+        }
+    }
+
+    /// check_hashable returns an error if self can not be used as a map key,
+    /// which happens when an interface in it holds a slice, map or func
+    pub fn check_hashable(&self) -> EmptyResult {
+        match self.uncomparable_kind() {
+            Some(k) => Err(format!("hash of unhashable type {}", k)),
+            None => Ok(()),
+        }
+    }
+
+    /// check_comparable returns an error if comparing self with b compares two
+    /// values of the same uncomparable dynamic type
+    pub fn check_comparable(&self, b: &GosValue) -> EmptyResult {
+        let err = |k| Err(format!("comparing uncomparable type {}", k));
+        match (self, b) {
+            (Self::Slice(_), Self::Slice(_)) => err("slice"),
+            (Self::Map(_), Self::Map(_)) => err("map"),
+            (Self::Closure(_), Self::Closure(_)) => err("func"),
+            (Self::Named(x), Self::Named(y)) => x.0.check_comparable(&y.0),
+            (Self::Interface(x), Self::Interface(y)) => {
+                let (bx, by) = (x.borrow(), y.borrow());
+                match (bx.underlying_value(), by.underlying_value()) {
+                    (Some(vx), Some(vy)) => vx.check_comparable(vy),
+                    _ => Ok(()),
+                }
+            }
+            (Self::Interface(iface), val) | (val, Self::Interface(iface)) => {
+                match iface.borrow().underlying_value() {
+                    Some(v) => v.check_comparable(val),
+                    None => Ok(()),
+                }
+            }
+            (Self::Struct(x), Self::Struct(y)) => {
+                let (bx, by) = (x.0.borrow(), y.0.borrow());
+                bx.fields
+                    .iter()
+                    .zip(by.fields.iter())
+                    .map(|(a, b)| a.check_comparable(b))
+                    .collect()
+            }
+            (Self::Array(x), Self::Array(y)) => {
+                let (bx, by) = (x.0.borrow_data(), y.0.borrow_data());
+                bx.iter()
+                    .zip(by.iter())
+                    .map(|(a, b)| a.borrow().check_comparable(&b.borrow()))
+                    .collect()
+            }
+            _ => Ok(()),
+        }
+    }
+
     #[inline]
     pub fn get_type(&self) -> ValueType {
         match self {
@@ -692,8 +776,13 @@ impl GosValue {
                 gcos.add_weak(GcWeak::Map(Rc::downgrade(&rc)));
                 GosValue::Map(rc)
             }
+            GosValue::Array(a) => {
+                let rc = Rc::new((a.0.copy_semantic(gcos), Cell::new(0)));
+                gcos.add_weak(GcWeak::Array(Rc::downgrade(&rc)));
+                GosValue::Array(rc)
+            }
             GosValue::Struct(s) => {
-                let rc = Rc::new((RefCell::clone(&s.0), Cell::new(0)));
+                let rc = Rc::new((RefCell::new(s.0.borrow().copy_semantic(gcos)), Cell::new(0)));
                 gcos.add_weak(GcWeak::Struct(Rc::downgrade(&rc)));
                 GosValue::Struct(rc)
             }
@@ -733,9 +822,9 @@ impl GosValue {
 
     #[inline]
     pub fn add_str(a: &GosValue, b: &GosValue) -> GosValue {
-        let mut s = a.as_str().as_str().to_string();
-        s.push_str(b.as_str().as_str());
-        GosValue::new_str(s)
+        let mut s = a.as_str().as_bytes().to_vec();
+        s.extend_from_slice(b.as_str().as_bytes());
+        GosValue::new_str_bytes(s)
     }
 
     /// for gc
@@ -860,7 +949,7 @@ impl Hash for GosValue {
             GosValue::Uint64(i) => i.hash(state),
             GosValue::Float32(f) => f.to_bits().hash(state),
             GosValue::Float64(f) => f.to_bits().hash(state),
-            GosValue::Str(s) => s.as_str().hash(state),
+            GosValue::Str(s) => s.as_bytes().hash(state),
             GosValue::Array(a) => a.0.hash(state),
             GosValue::Complex64(i, r) => {
                 i.hash(state);
@@ -930,7 +1019,7 @@ impl Display for GosValue {
             GosValue::Float64(fl) => write!(f, "{}", fl),
             GosValue::Complex64(r, i) => write!(f, "({}, {})", r, i),
             GosValue::Complex128(b) => write!(f, "({}, {})", b.0, b.1),
-            GosValue::Str(s) => f.write_str(s.as_ref().as_str()),
+            GosValue::Str(s) => f.write_str(&s.as_ref().as_str()),
             GosValue::Array(a) => write!(f, "{}", a.0),
             GosValue::Pointer(p) => p.fmt(f),
             GosValue::Closure(_) => f.write_str("<closure>"),
@@ -947,6 +1036,113 @@ impl Display for GosValue {
     }
 }
 
+// ----------------------------------------------------------------------------
+// serialization
+
+/// pointers and channels only exist at runtime, so they can't be serialized
+impl Encode for GosValue {
+    fn encode(&self, e: &mut Encoder) {
+        e.write(&self.get_type());
+        match self {
+            GosValue::Nil(m) => e.write(m),
+            GosValue::Bool(b) => e.write(b),
+            GosValue::Int(i) => e.write(i),
+            GosValue::Int8(i) => e.write(i),
+            GosValue::Int16(i) => e.write(i),
+            GosValue::Int32(i) => e.write(i),
+            GosValue::Int64(i) => e.write(i),
+            GosValue::Uint(i) => e.write(i),
+            GosValue::Uint8(i) => e.write(i),
+            GosValue::Uint16(i) => e.write(i),
+            GosValue::Uint32(i) => e.write(i),
+            GosValue::Uint64(i) => e.write(i),
+            GosValue::Float32(f) => e.write(&f.into_inner()),
+            GosValue::Float64(f) => e.write(&f.into_inner()),
+            GosValue::Complex64(r, i) => {
+                e.write(&r.into_inner());
+                e.write(&i.into_inner());
+            }
+            GosValue::Complex128(b) => {
+                e.write(&b.0.into_inner());
+                e.write(&b.1.into_inner());
+            }
+            GosValue::Str(s) => e.write(s.as_ref()),
+            GosValue::Array(a) => e.write(&a.0),
+            GosValue::Closure(c) => e.write(&*c.0.borrow()),
+            GosValue::Slice(s) => e.write(&s.0),
+            GosValue::Map(m) => e.write(&m.0),
+            GosValue::Interface(i) => e.write(&*i.borrow()),
+            GosValue::Struct(s) => e.write(&*s.0.borrow()),
+            GosValue::Function(k) => e.write(k),
+            GosValue::Package(k) => e.write(k),
+            GosValue::Metadata(m) => e.write(m),
+            GosValue::Named(v) => {
+                e.write(&v.0);
+                e.write(&v.1);
+            }
+            GosValue::Pointer(_) | GosValue::Channel(_) => {
+                panic!("cannot serialize runtime value {:?}", self.get_type())
+            }
+        }
+    }
+}
+
+impl Decode for GosValue {
+    fn decode(d: &mut Decoder) -> io::Result<Self> {
+        let read_f32 = |d: &mut Decoder| d.read::<f32>().map(|x| x.into());
+        let read_f64 = |d: &mut Decoder| d.read::<f64>().map(|x| x.into());
+        let val = match d.read::<ValueType>()? {
+            ValueType::Nil => GosValue::Nil(d.read()?),
+            ValueType::Bool => GosValue::Bool(d.read()?),
+            ValueType::Int => GosValue::Int(d.read()?),
+            ValueType::Int8 => GosValue::Int8(d.read()?),
+            ValueType::Int16 => GosValue::Int16(d.read()?),
+            ValueType::Int32 => GosValue::Int32(d.read()?),
+            ValueType::Int64 => GosValue::Int64(d.read()?),
+            ValueType::Uint => GosValue::Uint(d.read()?),
+            ValueType::Uint8 => GosValue::Uint8(d.read()?),
+            ValueType::Uint16 => GosValue::Uint16(d.read()?),
+            ValueType::Uint32 => GosValue::Uint32(d.read()?),
+            ValueType::Uint64 => GosValue::Uint64(d.read()?),
+            ValueType::Float32 => GosValue::Float32(read_f32(d)?),
+            ValueType::Float64 => GosValue::Float64(read_f64(d)?),
+            ValueType::Complex64 => GosValue::Complex64(read_f32(d)?, read_f32(d)?),
+            ValueType::Complex128 => GosValue::Complex128(Box::new((read_f64(d)?, read_f64(d)?))),
+            ValueType::Str => GosValue::Str(Rc::new(d.read()?)),
+            ValueType::Array => {
+                let v = GosValue::Array(Rc::new((d.read()?, Cell::new(0))));
+                d.gcv().add(&v);
+                v
+            }
+            // same as GosValue::new_closure, static closures are not tracked
+            ValueType::Closure => {
+                GosValue::Closure(Rc::new((RefCell::new(d.read()?), Cell::new(0))))
+            }
+            ValueType::Slice => {
+                let v = GosValue::Slice(Rc::new((d.read()?, Cell::new(0))));
+                d.gcv().add(&v);
+                v
+            }
+            ValueType::Map => {
+                let v = GosValue::Map(Rc::new((d.read()?, Cell::new(0))));
+                d.gcv().add(&v);
+                v
+            }
+            ValueType::Interface => GosValue::Interface(Rc::new(RefCell::new(d.read()?))),
+            ValueType::Struct => {
+                let obj: StructObj = d.read()?;
+                GosValue::new_struct(obj, d.gcv())
+            }
+            ValueType::Function => GosValue::Function(d.read()?),
+            ValueType::Package => GosValue::Package(d.read()?),
+            ValueType::Metadata => GosValue::Metadata(d.read()?),
+            ValueType::Named => GosValue::Named(Box::new((d.read()?, d.read()?))),
+            _ => return Err(invalid_data("invalid value")),
+        };
+        Ok(val)
+    }
+}
+
 // ----------------------------------------------------------------------------
 // GosValue64
 // nil is only allowed on the stack as a rhs value
@@ -1,23 +1,31 @@
 #![allow(dead_code)]
 use super::channel;
+use super::coverage::{Coverage, CoverageCounter};
+use super::debug::{Action, DebugHandler, Debugger, Frame};
 use super::ffi::FfiFactory;
 use super::gc::{gc, GcoVec};
+use super::inline_cache::InlineCaches;
 use super::instruction::*;
+#[cfg(feature = "jit")]
+use super::jit::Jit;
 use super::metadata::*;
 use super::objects::{u64_to_key, ClosureObj, GosHashMap};
+use super::profile::{Profile, Profiler};
 use super::stack::{RangeStack, Stack};
+use super::stats::OpStats;
+use super::trace::{StackFrame, TraceHandle, Tracer};
 use super::value::*;
 use super::vm_util;
 use goscript_parser::FileSet;
-use smol::future;
+use smol::future::{self, FutureExt};
 use smol::LocalExecutor;
 use std::cell::{Cell, RefCell};
 use std::collections::HashMap;
 use std::convert::TryInto;
+use std::panic::AssertUnwindSafe;
 use std::pin::Pin;
 use std::ptr;
 use std::rc::Rc;
-use std::str;
 
 #[derive(Debug)]
 pub struct ByteCode {
@@ -133,41 +141,79 @@ enum Result {
 #[derive(Debug)]
 struct PanicData {
     msg: GosValue,
-    call_stack: Vec<(FunctionKey, usize)>,
+    // the pc of the instruction that panicked
+    pc: usize,
+    // the frames when it panicked, captured when the panicking frame returns
+    frames: Option<Vec<StackFrame>>,
 }
 
 impl PanicData {
-    fn new(m: GosValue) -> PanicData {
+    fn new(m: GosValue, pc: usize) -> PanicData {
         PanicData {
             msg: m,
-            call_stack: vec![],
+            pc: pc,
+            frames: None,
         }
     }
 }
 
+/// the frames of the fibers that are not running, and what they wait for
+type Parked = Rc<RefCell<HashMap<usize, (&'static str, Vec<StackFrame>)>>>;
+
 #[derive(Clone)]
 struct Context<'a> {
     exec: Rc<LocalExecutor<'a>>,
     code: &'a ByteCode,
+    ics: &'a InlineCaches,
+    #[cfg(feature = "jit")]
+    jit: &'a Jit,
     gcv: &'a GcoVec,
     ffi_factory: &'a FfiFactory,
     fs: Option<&'a FileSet>,
+    debugger: Option<&'a Debugger<'a>>,
+    profiler: Option<&'a Profiler>,
+    stats: Option<&'a OpStats>,
+    coverage: Option<&'a CoverageCounter>,
+    // only kept when the vm has a TraceHandle
+    parked: Option<Parked>,
+    // the id of the last fiber spawned
+    fiber_id: Rc<Cell<usize>>,
+    // set when a fiber ends with a panic that isn't recovered, which ends
+    // the program like in Go
+    panicked: Rc<Cell<bool>>,
 }
 
 impl<'a> Context<'a> {
     fn new(
         exec: Rc<LocalExecutor<'a>>,
         code: &'a ByteCode,
+        ics: &'a InlineCaches,
+        #[cfg(feature = "jit")] jit: &'a Jit,
         gcv: &'a GcoVec,
         ffi_factory: &'a FfiFactory,
         fs: Option<&'a FileSet>,
+        debugger: Option<&'a Debugger<'a>>,
+        profiler: Option<&'a Profiler>,
+        stats: Option<&'a OpStats>,
+        coverage: Option<&'a CoverageCounter>,
+        parked: Option<Parked>,
     ) -> Context<'a> {
         Context {
             exec: exec,
             code: code,
+            ics: ics,
+            #[cfg(feature = "jit")]
+            jit: jit,
             gcv: gcv,
             ffi_factory: ffi_factory,
             fs: fs,
+            debugger: debugger,
+            profiler: profiler,
+            stats: stats,
+            coverage: coverage,
+            parked: parked,
+            fiber_id: Rc::new(Cell::new(0)),
+            panicked: Rc::new(Cell::new(false)),
         }
     }
 
@@ -176,19 +222,34 @@ impl<'a> Context<'a> {
         CallFrame::with_closure(cls.as_closure().clone(), 0)
     }
 
-    fn spawn_fiber(&self, stack: Stack, first_frame: CallFrame) {
-        let mut f = Fiber::new(self.clone(), stack, first_frame);
+    /// spawn_fiber returns the id of the new fiber, the ids start from 1
+    fn spawn_fiber(&self, stack: Stack, first_frame: CallFrame) -> usize {
+        let id = self.fiber_id.get() + 1;
+        self.fiber_id.set(id);
+        let mut f = Fiber::new(id, self.clone(), stack, first_frame);
+        park(self, id, "runnable", &f.frames, &f.stack.borrow(), 0);
+        let panicked = self.panicked.clone();
         self.exec
             .spawn(async move {
                 // let parent fiber go first
                 future::yield_now().await;
-                f.main_loop().await;
+                // a bug of the vm panics in rust, it ends the program too
+                // instead of being lost in the executor
+                if AssertUnwindSafe(f.main_loop())
+                    .catch_unwind()
+                    .await
+                    .is_err()
+                {
+                    panicked.set(true);
+                }
             })
             .detach();
+        id
     }
 }
 
 pub struct Fiber<'a> {
+    id: usize,
     stack: Rc<RefCell<Stack>>,
     rstack: RangeStack,
     frames: Vec<CallFrame>,
@@ -197,8 +258,9 @@ pub struct Fiber<'a> {
 }
 
 impl<'a> Fiber<'a> {
-    fn new(c: Context<'a>, stack: Stack, first_frame: CallFrame) -> Fiber<'a> {
+    fn new(id: usize, c: Context<'a>, stack: Stack, first_frame: CallFrame) -> Fiber<'a> {
         Fiber {
+            id: id,
             stack: Rc::new(RefCell::new(stack)),
             rstack: RangeStack::new(),
             frames: vec![first_frame],
@@ -214,6 +276,7 @@ impl<'a> Fiber<'a> {
         let metadata: &Metadata = &objs.metadata;
         let pkgs = &ctx.code.packages;
         let ifaces = &ctx.code.ifaces;
+        let ics = ctx.ics;
         let frame = self.frames.last_mut().unwrap();
         let mut func = &objs.functions[frame.func()];
 
@@ -227,18 +290,26 @@ impl<'a> Fiber<'a> {
         let mut stack_base = frame.stack_base;
         let mut frame_height = self.frames.len();
 
-        let mut total_inst = 0;
-        //let mut stats: HashMap<Opcode, usize> = HashMap::new();
+        // checked once, as the hooks are not for the fast path
+        let hooked = ctx.debugger.is_some()
+            || ctx.profiler.is_some()
+            || ctx.stats.is_some()
+            || ctx.coverage.is_some();
         loop {
             let mut frame = self.frames.last_mut().unwrap();
             let mut result: Result = Result::Continue;
             let mut panic: Option<PanicData> = None;
             let yield_unit = 1024;
             for _ in 0..yield_unit {
+                if hooked {
+                    if run_hooks(ctx, self.id, &self.frames, stack, objs) {
+                        result = Result::End;
+                        break;
+                    }
+                    frame = self.frames.last_mut().unwrap();
+                }
                 let inst = code[frame.pc];
                 let inst_op = inst.op();
-                total_inst += 1;
-                //stats.entry(*inst).and_modify(|e| *e += 1).or_insert(1);
                 frame.pc += 1;
                 //dbg!(inst_op);
                 match inst_op {
@@ -261,6 +332,13 @@ impl<'a> Fiber<'a> {
                         let index = Stack::offset(stack_base, inst.imm());
                         stack.push_from_index(index, inst.t0()); // (index![stack, index]);
                     }
+                    Opcode::LOAD_LOCAL_ADD_IMM => {
+                        let (imm, index) = inst.imm824();
+                        let t = inst.t0();
+                        stack.push_from_index(Stack::offset(stack_base, index), t);
+                        stack.push_int32_as(imm, t);
+                        stack.add(t);
+                    }
                     Opcode::STORE_LOCAL => {
                         let (rhs_index, index) = inst.imm824();
                         let s_index = Stack::offset(stack_base, index);
@@ -297,6 +375,8 @@ impl<'a> Fiber<'a> {
                                     go_panic_str!(panic, &objs.metadata, e, frame, code);
                                 }
                             }
+                        } else if let Err(e) = ind.check_hashable() {
+                            go_panic_str!(panic, &objs.metadata, e, frame, code);
                         } else {
                             vm_util::push_index_comma_ok(stack, val, &ind);
                         }
@@ -324,7 +404,11 @@ impl<'a> Fiber<'a> {
                         let s_index = Stack::offset(stack.len(), index);
                         let key = stack.get_with_type(s_index + 1, inst.t2());
                         let target = &stack.get_with_type(s_index, inst.t1());
-                        vm_util::store_index(stack, target, &key, rhs_index, inst.t0(), gcv);
+                        if let Err(e) =
+                            vm_util::store_index(stack, target, &key, rhs_index, inst.t0(), gcv)
+                        {
+                            go_panic_str!(panic, metadata, e, frame, code);
+                        }
                     }
                     Opcode::STORE_INDEX_IMM => {
                         // the only place we can store the immediate index is t2
@@ -554,22 +638,29 @@ impl<'a> Fiber<'a> {
                         let rhs_s_index = Stack::offset(stack.len(), target);
                         match inst.t0() {
                             ValueType::Interface => {
-                                let iface = ifaces[mapping as usize].clone();
-                                let under = stack.get_with_type(rhs_s_index, inst.t1());
-                                let val = match &objs.metas[iface.0.as_non_ptr()] {
-                                    MetadataType::Named(_, md) => GosValue::Named(Box::new((
-                                        GosValue::new_iface(
-                                            *md,
-                                            IfaceUnderlying::Gos(under, iface.1),
-                                        ),
-                                        iface.0,
-                                    ))),
-                                    MetadataType::Interface(_) => GosValue::new_iface(
-                                        iface.0,
-                                        IfaceUnderlying::Gos(under, iface.1),
+                                let iface = &ifaces[mapping as usize];
+                                let under = match inst.t1() {
+                                    ValueType::Interface => {
+                                        let src = match stack.get_rc(rhs_s_index) {
+                                            GosValue::Named(n) => n.0.as_interface().clone(),
+                                            GosValue::Interface(i) => i.clone(),
+                                            _ => unreachable!(),
+                                        };
+                                        let under = vm_util::iface_to_iface(
+                                            &iface.0,
+                                            &src.borrow(),
+                                            &ics.ifaces[mapping as usize],
+                                            objs,
+                                            stack,
+                                        );
+                                        under
+                                    }
+                                    _ => IfaceUnderlying::Gos(
+                                        stack.get_with_type(rhs_s_index, inst.t1()),
+                                        iface.1.clone(),
                                     ),
-                                    _ => unreachable!(),
                                 };
+                                let val = vm_util::to_iface(iface.0, under, &objs.metas);
                                 stack.set(rhs_s_index, val);
                             }
                             ValueType::Str => {
@@ -584,41 +675,42 @@ impl<'a> Fiber<'a> {
                                                 .map(|x| {
                                                     vm_util::char_from_i32(*(x.borrow().as_int32()))
                                                 })
+                                                .collect::<String>()
+                                                .into_bytes(),
+                                            ValueType::Uint8 => slice
+                                                .0
+                                                .borrow_data()
+                                                .iter()
+                                                .map(|x| *(x.borrow().as_uint8()))
                                                 .collect(),
-                                            ValueType::Uint8 => {
-                                                let buf: Vec<u8> = slice
-                                                    .0
-                                                    .borrow_data()
-                                                    .iter()
-                                                    .map(|x| *(x.borrow().as_uint8()))
-                                                    .collect();
-                                                // todo: error handling
-                                                str::from_utf8(&buf).unwrap().to_string()
-                                            }
                                             _ => unreachable!(),
                                         }
                                     }
                                     _ => {
                                         let target = stack.get_c_mut(rhs_s_index);
                                         target.to_uint32(inst.t1());
-                                        vm_util::char_from_u32(target.get_uint32()).to_string()
+                                        vm_util::char_from_u32(target.get_uint32())
+                                            .to_string()
+                                            .into_bytes()
                                     }
                                 };
-                                stack.set(rhs_s_index, GosValue::new_str(result));
+                                stack.set(rhs_s_index, GosValue::new_str_bytes(result));
                             }
                             ValueType::Slice => {
                                 let from = stack.get_rc(rhs_s_index).as_str();
                                 let result = match inst.t2() {
                                     ValueType::Int32 => (
                                         objs.metadata.mint32,
-                                        from.as_str()
-                                            .chars()
-                                            .map(|x| GosValue::Int32(x as i32))
+                                        from.iter()
+                                            .map(|(_, x)| GosValue::Int32(x as i32))
                                             .collect(),
                                     ),
                                     ValueType::Uint8 => (
                                         objs.metadata.muint8,
-                                        from.as_str().bytes().map(|x| GosValue::Uint8(x)).collect(),
+                                        from.as_bytes()
+                                            .iter()
+                                            .map(|x| GosValue::Uint8(*x))
+                                            .collect(),
                                     ),
                                     _ => unreachable!(),
                                 };
@@ -665,15 +757,118 @@ impl<'a> Fiber<'a> {
                     Opcode::UNARY_SUB => stack.unary_negate(inst.t0()),
                     Opcode::UNARY_XOR => stack.unary_xor(inst.t0()),
                     Opcode::NOT => stack.logical_not(inst.t0()),
-                    Opcode::EQL => stack.compare_eql(inst.t0()),
+                    Opcode::EQL => match vm_util::check_comparable(stack, inst.t0()) {
+                        Ok(()) => stack.compare_eql(inst.t0()),
+                        Err(e) => {
+                            go_panic_str!(panic, metadata, e, frame, code);
+                        }
+                    },
                     Opcode::LSS => stack.compare_lss(inst.t0()),
                     Opcode::GTR => stack.compare_gtr(inst.t0()),
-                    Opcode::NEQ => stack.compare_neq(inst.t0()),
+                    Opcode::NEQ => match vm_util::check_comparable(stack, inst.t0()) {
+                        Ok(()) => stack.compare_neq(inst.t0()),
+                        Err(e) => {
+                            go_panic_str!(panic, metadata, e, frame, code);
+                        }
+                    },
                     Opcode::LEQ => stack.compare_leq(inst.t0()),
                     Opcode::GEQ => stack.compare_geq(inst.t0()),
+                    Opcode::ADD_R => stack.add_r(stack_base, inst),
+                    Opcode::SUB_R => stack.sub_r(stack_base, inst),
+                    Opcode::MUL_R => stack.mul_r(stack_base, inst),
+                    Opcode::QUO_R => stack.quo_r(stack_base, inst),
+                    Opcode::REM_R => stack.rem_r(stack_base, inst),
+                    Opcode::AND_R => stack.and_r(stack_base, inst),
+                    Opcode::OR_R => stack.or_r(stack_base, inst),
+                    Opcode::XOR_R => stack.xor_r(stack_base, inst),
+                    Opcode::AND_NOT_R => stack.and_not_r(stack_base, inst),
+                    Opcode::EQL_R => stack.compare_eql_r(stack_base, inst),
+                    Opcode::NEQ_R => stack.compare_neq_r(stack_base, inst),
+                    Opcode::LSS_R => stack.compare_lss_r(stack_base, inst),
+                    Opcode::GTR_R => stack.compare_gtr_r(stack_base, inst),
+                    Opcode::LEQ_R => stack.compare_leq_r(stack_base, inst),
+                    Opcode::GEQ_R => stack.compare_geq_r(stack_base, inst),
+                    Opcode::LOAD_STRUCT_FIELD_R => {
+                        let (d, a, ind) = inst.reg();
+                        let a = Stack::offset(stack_base, a);
+                        let val = match stack.get_rc(a) {
+                            GosValue::Named(n) => {
+                                n.0.as_struct().0.borrow().fields[ind as usize].clone()
+                            }
+                            GosValue::Struct(sval) => sval.0.borrow().fields[ind as usize].clone(),
+                            // a value method called through an interface
+                            // holding a pointer gets the pointer
+                            GosValue::Pointer(_) => {
+                                let target = stack.get_rc(a).clone();
+                                let target = deref_value!(target, self, stack, self.frames, objs);
+                                frame = self.frames.last_mut().unwrap();
+                                match &target {
+                                    GosValue::Named(n) => {
+                                        n.0.as_struct().0.borrow().fields[ind as usize].clone()
+                                    }
+                                    GosValue::Struct(sval) => {
+                                        sval.0.borrow().fields[ind as usize].clone()
+                                    }
+                                    _ => unreachable!(),
+                                }
+                            }
+                            _ => unreachable!(),
+                        };
+                        if d == Instruction::REG_STACK {
+                            stack.push(val);
+                        } else {
+                            stack.set(Stack::offset(stack_base, d), val.copy_semantic(gcv));
+                        }
+                    }
+                    Opcode::LOAD_INDEX_R => {
+                        let (container, index) = stack.reg_index(stack_base, inst);
+                        match vm_util::load_index_int(container, index) {
+                            Ok(v) => {
+                                let (d, _, _) = inst.reg();
+                                if d == Instruction::REG_STACK {
+                                    stack.push(v);
+                                } else {
+                                    stack.set(Stack::offset(stack_base, d), v.copy_semantic(gcv));
+                                }
+                            }
+                            Err(e) => {
+                                go_panic_str!(panic, metadata, e, frame, code);
+                            }
+                        }
+                    }
+                    Opcode::ADD_RI => stack.add_ri(stack_base, inst),
+                    Opcode::EQL_RI => stack.compare_eql_ri(stack_base, inst),
+                    Opcode::NEQ_RI => stack.compare_neq_ri(stack_base, inst),
+                    Opcode::LSS_RI => stack.compare_lss_ri(stack_base, inst),
+                    Opcode::GTR_RI => stack.compare_gtr_ri(stack_base, inst),
+                    Opcode::LEQ_RI => stack.compare_leq_ri(stack_base, inst),
+                    Opcode::GEQ_RI => stack.compare_geq_ri(stack_base, inst),
+                    Opcode::ADD_INT => stack.add_int(),
+                    Opcode::SUB_INT => stack.sub_int(),
+                    Opcode::MUL_INT => stack.mul_int(),
+                    Opcode::EQL_INT => stack.compare_eql_int(),
+                    Opcode::NEQ_INT => stack.compare_neq_int(),
+                    Opcode::LSS_INT => stack.compare_lss_int(),
+                    Opcode::GTR_INT => stack.compare_gtr_int(),
+                    Opcode::LEQ_INT => stack.compare_leq_int(),
+                    Opcode::GEQ_INT => stack.compare_geq_int(),
+                    Opcode::ADD_IMM_INT => stack.add_imm_int(inst.imm()),
+                    Opcode::ADD_FLOAT64 => stack.add_float64(),
+                    Opcode::SUB_FLOAT64 => stack.sub_float64(),
+                    Opcode::MUL_FLOAT64 => stack.mul_float64(),
+                    Opcode::QUO_FLOAT64 => stack.quo_float64(),
+                    Opcode::EQL_FLOAT64 => stack.compare_eql_float64(),
+                    Opcode::NEQ_FLOAT64 => stack.compare_neq_float64(),
+                    Opcode::LSS_FLOAT64 => stack.compare_lss_float64(),
+                    Opcode::GTR_FLOAT64 => stack.compare_gtr_float64(),
+                    Opcode::LEQ_FLOAT64 => stack.compare_leq_float64(),
+                    Opcode::GEQ_FLOAT64 => stack.compare_geq_float64(),
                     Opcode::SEND => {
                         let val = stack.pop_with_type(inst.t0());
                         let chan = stack.pop_rc();
+                        let pc = frame.pc - 1;
+                        park(ctx, self.id, "chan send", &self.frames, stack, pc);
+                        frame = self.frames.last_mut().unwrap();
                         drop(stack_mut_ref);
                         let re = chan.as_channel().send(&val).await;
                         restore_stack_ref!(self, stack, stack_mut_ref);
@@ -684,6 +879,9 @@ impl<'a> Fiber<'a> {
                     Opcode::RECV => {
                         let chan_val = stack.pop_rc();
                         let chan = chan_val.as_channel();
+                        let pc = frame.pc - 1;
+                        park(ctx, self.id, "chan receive", &self.frames, stack, pc);
+                        frame = self.frames.last_mut().unwrap();
                         drop(stack_mut_ref);
                         let val = chan.recv().await;
                         restore_stack_ref!(self, stack, stack_mut_ref);
@@ -723,7 +921,11 @@ impl<'a> Fiber<'a> {
                         )));
                     }
                     Opcode::REF_STRUCT_FIELD => {
-                        let struct_ = stack.pop_with_type(inst.t0());
+                        let mut struct_ = stack.pop_with_type(inst.t0());
+                        if let GosValue::Pointer(_) = &struct_ {
+                            struct_ = deref_value!(struct_, self, stack, self.frames, objs);
+                            frame = self.frames.last_mut().unwrap();
+                        }
                         let struct_ = match &struct_ {
                             GosValue::Named(n) => n.0.clone(),
                             GosValue::Struct(_) => struct_,
@@ -786,9 +988,34 @@ impl<'a> Fiber<'a> {
                                 nframe.stack_base + sig.params.len() + sig.results.len() - 1;
                             stack.pack_variadic(index, meta, vt, gcv);
                         }
+                        // arguments are passed by value
+                        let sig = &objs.metas[cls.meta.as_non_ptr()].as_signature();
+                        let params_base = nframe.stack_base + sig.results.len();
+                        for (i, t) in sig.params_type.iter().enumerate() {
+                            match t {
+                                ValueType::Array | ValueType::Struct | ValueType::Named => {
+                                    let v = stack.get_rc(params_base + i).copy_semantic(gcv);
+                                    stack.set(params_base + i, v);
+                                }
+                                _ => {}
+                            }
+                        }
                         match cls.func {
                             Some(key) => {
                                 let nfunc = &objs.functions[key];
+                                #[cfg(feature = "jit")]
+                                {
+                                    let plain = call_style == ValueType::Zero
+                                        && !pack
+                                        && cls.recv.is_none()
+                                        && cls.uvs.is_none()
+                                        && !hooked;
+                                    let base = nframe.stack_base;
+                                    if plain && ctx.jit.call(key, objs, stack, base) {
+                                        stack.truncate(base + nfunc.ret_count());
+                                        continue;
+                                    }
+                                }
                                 if let Some(uvs) = &cls.uvs {
                                     let mut ptrs: Vec<UpValue> =
                                         Vec::with_capacity(nfunc.up_ptrs.len());
@@ -857,6 +1084,14 @@ impl<'a> Fiber<'a> {
                         }
                     }
                     Opcode::RETURN => {
+                        // the frames of a panic are captured before any of
+                        // them returns
+                        if let Some(p) = &mut panic {
+                            if p.frames.is_none() {
+                                p.frames = Some(stack_frames(&self.frames, stack, objs, p.pc));
+                                frame = self.frames.last_mut().unwrap();
+                            }
+                        }
                         //dbg!(stack.len());
                         //for s in stack.iter() {
                         //    dbg!(GosValueDebug::new(&s, &objs));
@@ -868,14 +1103,19 @@ impl<'a> Fiber<'a> {
                             }
                             // init_package func
                             ValueType::FlagA => {
-                                let index = inst.imm() as usize;
-                                let pkey = pkgs[index];
-                                let pkg = &objs.packages[pkey];
-                                let count = pkg.var_count();
-                                // remove garbage first
-                                debug_assert!(stack.len() == stack_base + count);
-                                // the var values left on the stack are for pkg members
-                                stack.init_pkg_vars(pkg, count);
+                                if panic.is_some() {
+                                    // it panicked before the vars were all set
+                                    stack.truncate(stack_base);
+                                } else {
+                                    let index = inst.imm() as usize;
+                                    let pkey = pkgs[index];
+                                    let pkg = &objs.packages[pkey];
+                                    let count = pkg.var_count();
+                                    // remove garbage first
+                                    debug_assert!(stack.len() == stack_base + count);
+                                    // the var values left on the stack are for pkg members
+                                    stack.init_pkg_vars(pkg, count);
+                                }
                             }
                             // func with deferred calls
                             ValueType::FlagB => {
@@ -912,15 +1152,6 @@ impl<'a> Fiber<'a> {
                         self.frames.pop();
                         frame_height -= 1;
                         if self.frames.is_empty() {
-                            dbg!(total_inst);
-                            /* dbg!
-                            let mut s = stats
-                                .iter()
-                                .map(|(&k, &v)| (k, v))
-                                .collect::<Vec<(Opcode, usize)>>();
-                            s.sort_by(|a, b| b.1.cmp(&a.1));
-                            dbg!(s);
-                            */
                             result = Result::End;
                             break;
                         }
@@ -931,14 +1162,19 @@ impl<'a> Fiber<'a> {
                         consts = &func.consts;
                         code = func.code();
 
-                        if let Some(p) = &mut panic {
-                            p.call_stack.push((frame.func(), frame.pc - 1));
+                        if panic.is_some() {
                             frame.pc = code.len() - 1;
                         }
                     }
 
                     Opcode::JUMP => {
                         frame.pc = Stack::offset(frame.pc, inst.imm());
+                        #[cfg(feature = "jit")]
+                        {
+                            if inst.imm() < 0 {
+                                ctx.jit.on_loop(frame.func());
+                            }
+                        }
                     }
                     Opcode::JUMP_IF => {
                         if stack.pop_bool() {
@@ -990,6 +1226,8 @@ impl<'a> Fiber<'a> {
                             .collect();
                         let selector = channel::Selector::new(comms, default_offset);
 
+                        park(ctx, self.id, "select", &self.frames, stack, begin);
+                        frame = self.frames.last_mut().unwrap();
                         drop(stack_mut_ref);
                         let re = selector.select().await;
                         restore_stack_ref!(self, stack, stack_mut_ref);
@@ -1067,12 +1305,32 @@ impl<'a> Fiber<'a> {
                             IfaceUnderlying::Gos(v, _) => v.copy_semantic(gcv),
                             _ => GosValue::new_nil(),
                         };
-                        stack.push(GosValue::Metadata(val.get_meta(objs, stack)));
+                        let meta = val.get_meta(objs, stack);
+                        stack.push(GosValue::Metadata(meta));
                         if inst.t2_as_index() > 0 {
                             let index = inst.imm();
                             let s_index = Stack::offset(stack_base, index);
                             stack.set(s_index, val);
                         }
+                        // go straight to the case matching the type
+                        if let Some(cache) = ics.type_cache(&inst) {
+                            let target = cache.get(&meta).or_else(|| {
+                                let target = vm_util::type_switch_target(
+                                    &meta,
+                                    code,
+                                    consts,
+                                    frame.pc,
+                                    &objs.metas,
+                                );
+                                if let Some(t) = target {
+                                    cache.insert(meta, t);
+                                }
+                                target
+                            });
+                            if let Some(t) = target {
+                                frame.pc = t;
+                            }
+                        }
                     }
                     Opcode::IMPORT => {
                         let pkey = pkgs[inst.imm() as usize];
@@ -1107,6 +1365,9 @@ impl<'a> Fiber<'a> {
                             GosValue::Function(fkey) => {
                                 // NEW a closure
                                 let mut val = ClosureObj::new_gos(*fkey, &objs.functions, None);
+                                // FlagA: the closure never outlives the frames, so
+                                // the frames don't need to close its upvalues
+                                let confined = inst.t1() == ValueType::FlagA;
                                 if let Some(uvs) = &mut val.uvs {
                                     drop(frame);
                                     for (_, uv) in uvs.iter_mut() {
@@ -1119,7 +1380,9 @@ impl<'a> Fiber<'a> {
                                                     let upframe = &mut self.frames[index];
                                                     d.stack = Rc::downgrade(&self.stack);
                                                     d.stack_base = upframe.stack_base as OpIndex;
-                                                    upframe.add_referred_by(d.index, d.typ, uv);
+                                                    if !confined {
+                                                        upframe.add_referred_by(d.index, d.typ, uv);
+                                                    }
                                                     // if not found, the upvalue is already closed, nothing to be done
                                                     break;
                                                 }
@@ -1143,7 +1406,8 @@ impl<'a> Fiber<'a> {
                                         let mut cur_index = -1;
                                         for _ in 0..count {
                                             let i = stack.pop_int();
-                                            let elem = stack.pop_with_type(elem_type);
+                                            let elem =
+                                                stack.pop_with_type(elem_type).copy_semantic(gcv);
                                             if i < 0 {
                                                 cur_index += 1;
                                             } else {
@@ -1178,8 +1442,8 @@ impl<'a> Fiber<'a> {
                                         let tk = km.get_value_type(&objs.metas);
                                         let tv = vm.get_value_type(&objs.metas);
                                         for _ in 0..count {
-                                            let k = stack.pop_with_type(tk);
-                                            let v = stack.pop_with_type(tv);
+                                            let k = stack.pop_with_type(tk).copy_semantic(gcv);
+                                            let v = stack.pop_with_type(tv).copy_semantic(gcv);
                                             map.0.insert(k, v);
                                         }
                                         gosv
@@ -1190,7 +1454,8 @@ impl<'a> Fiber<'a> {
                                         for _ in 0..count {
                                             let index = stack.pop_uint();
                                             let tv = f.fields[index].get_value_type(&objs.metas);
-                                            sref.fields[index] = stack.pop_with_type(tv);
+                                            sref.fields[index] =
+                                                stack.pop_with_type(tv).copy_semantic(gcv);
                                         }
                                         drop(sref);
                                         struct_val
@@ -1319,7 +1584,7 @@ impl<'a> Fiber<'a> {
                             .as_signature()
                             .params_type[2..];
                         let params = stack.pop_with_type_n(ptypes);
-                        let v = match self.context.ffi_factory.create_by_name(name_str, params) {
+                        let v = match self.context.ffi_factory.create_by_name(&name_str, params) {
                             Ok(v) => {
                                 let meta = itype.as_meta().get_underlying(&objs.metas).clone();
                                 let info = objs.metas[meta.as_non_ptr()]
@@ -1347,30 +1612,17 @@ impl<'a> Fiber<'a> {
             match result {
                 Result::End => {
                     if let Some(p) = panic {
-                        println!("panic: {}", p.msg);
-                        if let Some(files) = self.context.fs {
-                            for (fkey, pc) in p.call_stack.iter() {
-                                let func = &objs.functions[*fkey];
-                                if let Some(p) = func.pos()[*pc] {
-                                    println!("{}", files.position(p));
-                                } else {
-                                    println!("<no debug info available>");
-                                }
-                            }
-                        }
-
-                        // a hack to make the test case fail
-                        if let GosValue::Str(s) =
-                            p.msg.as_interface().borrow().underlying_value().unwrap()
-                        {
-                            if s.as_str().starts_with("Opcode::ASSERT") {
-                                panic!("ASSERT");
-                            }
-                        }
+                        let tracer = Tracer::new(ctx.code, ctx.fs);
+                        let frames = p.frames.unwrap_or(vec![]);
+                        let trace = tracer.goroutine(self.id, "running", &frames);
+                        println!("panic: {}\n\n{}", p.msg, trace);
+                        ctx.panicked.set(true);
                     }
                     break;
                 }
                 Result::Continue => {
+                    let pc = self.frames.last().unwrap().pc;
+                    park(ctx, self.id, "runnable", &self.frames, stack, pc);
                     drop(stack_mut_ref);
                     future::yield_now().await;
                     restore_stack_ref!(self, stack, stack_mut_ref);
@@ -1378,41 +1630,313 @@ impl<'a> Fiber<'a> {
             };
         } //loop
 
+        if let Some(dbg) = ctx.debugger {
+            dbg.fiber_done(self.id);
+        }
+        if let Some(parked) = &ctx.parked {
+            parked.borrow_mut().remove(&self.id);
+        }
         stack.clear_rc_garbage();
         gc(gcv);
     }
 }
 
+/// run_hooks runs the stats, the coverage, the profiler and the debugger before
+/// an instruction, returns true if the fiber has to end
+#[inline(never)]
+fn run_hooks(
+    ctx: &Context,
+    fiber: usize,
+    frames: &Vec<CallFrame>,
+    stack: &Stack,
+    objs: &VMObjects,
+) -> bool {
+    let frame = frames.last().unwrap();
+    if let Some(stats) = ctx.stats {
+        let func = frame.func();
+        let inst = objs.functions[func].code()[frame.pc];
+        stats.count(fiber, func, frame.pc, inst);
+    }
+    if let Some(cov) = ctx.coverage {
+        let func = frame.func();
+        let op = objs.functions[func].code()[frame.pc].op();
+        cov.count(fiber, func, frame.pc, op);
+    }
+    if let Some(prof) = ctx.profiler {
+        if prof.count(frame.func(), frame.pc) {
+            prof.sample(call_stack(frames));
+        }
+    }
+    if let Some(dbg) = ctx.debugger {
+        let mut action = dbg.check(fiber, frames.len(), frame.func(), frame.pc);
+        if let Action::Stop(reason) = action {
+            action = dbg.stop(fiber, reason, debug_frames(frames, stack, objs));
+        }
+        if let Action::Quit = action {
+            return true;
+        }
+    }
+    false
+}
+
+/// call_stack returns the function and pc of every frame, the innermost
+/// first, the pcs of the frames below the top are of their calls
+fn call_stack(frames: &Vec<CallFrame>) -> Vec<(FunctionKey, usize)> {
+    let top = frames.len() - 1;
+    frames
+        .iter()
+        .enumerate()
+        .rev()
+        .map(|(i, f)| (f.func(), if i == top { f.pc } else { f.pc - 1 }))
+        .collect()
+}
+
+/// stack_frames captures the frames of a fiber with their arguments, the
+/// innermost first, top_pc is the pc of the innermost
+fn stack_frames(
+    frames: &Vec<CallFrame>,
+    stack: &Stack,
+    objs: &VMObjects,
+    top_pc: usize,
+) -> Vec<StackFrame> {
+    let top = frames.len() - 1;
+    frames
+        .iter()
+        .enumerate()
+        .rev()
+        .map(|(i, f)| {
+            let key = f.func();
+            let func = &objs.functions[key];
+            let sig = objs.metas[func.meta.as_non_ptr()].as_signature();
+            let begin = f.stack_base + func.ret_zeros.len();
+            let args = sig
+                .recv
+                .iter()
+                .map(|x| x.get_value_type(&objs.metas))
+                .chain(sig.params_type.iter().copied())
+                .enumerate()
+                .map(|(j, t)| stack.get_with_type(begin + j, t))
+                .collect();
+            StackFrame {
+                func: key,
+                pc: if i == top { top_pc } else { f.pc - 1 },
+                args: args,
+            }
+        })
+        .collect()
+}
+
+/// park keeps the frames of a fiber that is about to wait, for the traces
+/// of a TraceHandle, top_pc is the pc of the innermost frame
+fn park(
+    ctx: &Context,
+    fiber: usize,
+    state: &'static str,
+    frames: &Vec<CallFrame>,
+    stack: &Stack,
+    top_pc: usize,
+) {
+    if let Some(parked) = &ctx.parked {
+        let frames = stack_frames(frames, stack, &ctx.code.objects, top_pc);
+        parked.borrow_mut().insert(fiber, (state, frames));
+    }
+}
+
+/// debug_frames copies the frames of a fiber for the debugger, the innermost
+/// first
+fn debug_frames(frames: &Vec<CallFrame>, stack: &Stack, objs: &VMObjects) -> Vec<Frame> {
+    let top = frames.len() - 1;
+    frames
+        .iter()
+        .enumerate()
+        .rev()
+        .map(|(i, f)| {
+            let key = f.func();
+            let func = &objs.functions[key];
+            let sig = objs.metas[func.meta.as_non_ptr()].as_signature();
+            let types = func
+                .ret_zeros
+                .iter()
+                .map(|x| x.get_type())
+                .chain(sig.recv.iter().map(|x| x.get_value_type(&objs.metas)))
+                .chain(sig.params_type.iter().copied())
+                .chain(func.local_zeros.iter().map(|x| x.get_type()));
+            let locals = types
+                .enumerate()
+                .map(|(j, t)| stack.get_with_type(f.stack_base + j, t))
+                .collect();
+            let upvalues = f.var_ptrs.as_ref().map_or(vec![], |ptrs| {
+                ptrs.iter()
+                    .take(func.up_ptrs.len())
+                    .map(|uv| match &*uv.inner.borrow() {
+                        UpValueState::Open(desc) => {
+                            let index = desc.stack_base as usize + desc.index as usize;
+                            let uv_stack = desc.stack.upgrade().unwrap();
+                            if ptr::eq(uv_stack.as_ptr(), stack) {
+                                stack.get_with_type(index, desc.typ)
+                            } else {
+                                uv_stack.borrow().get_with_type(index, desc.typ)
+                            }
+                        }
+                        UpValueState::Closed(val) => val.clone(),
+                    })
+                    .collect()
+            });
+            Frame {
+                func: key,
+                // the frames below the top are past their calls
+                pc: if i == top { f.pc } else { f.pc - 1 },
+                locals: locals,
+                upvalues: upvalues,
+            }
+        })
+        .collect()
+}
+
 pub struct GosVM<'a> {
     code: ByteCode,
+    ics: InlineCaches,
+    #[cfg(feature = "jit")]
+    jit: Jit,
     gcv: GcoVec,
     ffi: &'a FfiFactory,
     fs: Option<&'a FileSet>,
+    traces: Option<TraceHandle>,
 }
 
 impl<'a> GosVM<'a> {
-    pub fn new(bc: ByteCode, ffi: &'a FfiFactory, fs: Option<&'a FileSet>) -> GosVM<'a> {
+    pub fn new(mut bc: ByteCode, ffi: &'a FfiFactory, fs: Option<&'a FileSet>) -> GosVM<'a> {
+        let ics = InlineCaches::new(&mut bc);
+        #[cfg(feature = "jit")]
+        let jit = Jit::new(&bc);
         GosVM {
             code: bc,
+            ics: ics,
+            #[cfg(feature = "jit")]
+            jit: jit,
             gcv: GcoVec::new(),
             ffi: ffi,
             fs: fs,
+            traces: None,
+        }
+    }
+
+    /// set_trace_handle lets the handle ask for the traces of the goroutines
+    /// while the vm runs
+    pub fn set_trace_handle(&mut self, handle: TraceHandle) {
+        self.traces = Some(handle);
+    }
+
+    /// run runs the program, returns false if it ended with a panic that
+    /// wasn't recovered
+    pub fn run(&self) -> bool {
+        self.run_with(None, None, None, None)
+    }
+
+    /// into_code gives the bytecode back after a run, with what the run
+    /// added to its objects
+    pub fn into_code(self) -> ByteCode {
+        self.code
+    }
+
+    /// jit_compiled returns the names of the functions the JIT has compiled
+    /// to native code in the runs so far, the ones that bailed out later too
+    #[cfg(feature = "jit")]
+    pub fn jit_compiled(&self) -> Vec<String> {
+        let names = super::disasm::func_names(&self.code);
+        self.jit
+            .compiled()
+            .iter()
+            .map(|f| names.get(f).cloned().unwrap_or_else(|| format!("{:?}", f)))
+            .collect()
+    }
+
+    /// opcode_stats runs the program counting the opcodes
+    pub fn opcode_stats(&self) -> OpStats {
+        let stats = OpStats::new();
+        self.run_with(None, None, Some(&stats), None);
+        stats
+    }
+
+    /// profile runs the program with the profiler on
+    pub fn profile(&self) -> Profile {
+        let prof = Profiler::new();
+        self.run_with(None, Some(&prof), None, None);
+        prof.finish(&self.code, self.fs)
+    }
+
+    /// coverage runs the program recording the lines and branches that ran,
+    /// it needs the positions in the FileSet
+    pub fn coverage(&self) -> Option<Coverage> {
+        let fs = self.fs?;
+        let cov = CoverageCounter::new();
+        self.run_with(None, None, None, Some(&cov));
+        Some(cov.finish(&self.code, fs))
+    }
+
+    /// debug runs the program with a debugger, the handler decides where to
+    /// stop and what to do then
+    pub fn debug(&self, handler: Box<dyn DebugHandler>) {
+        let dbg = Debugger::new(&self.code, self.fs, handler);
+        // the entry fiber is always the first one
+        if dbg.start(1) {
+            self.run_with(Some(&dbg), None, None, None);
         }
+        dbg.exit();
     }
 
-    pub fn run(&self) {
+    fn run_with(
+        &self,
+        debugger: Option<&Debugger>,
+        profiler: Option<&Profiler>,
+        stats: Option<&OpStats>,
+        coverage: Option<&CoverageCounter>,
+    ) -> bool {
         let exec = Rc::new(LocalExecutor::new());
-        let ctx = Context::new(exec.clone(), &self.code, &self.gcv, self.ffi, self.fs);
+        let ctx = Context::new(
+            exec.clone(),
+            &self.code,
+            &self.ics,
+            #[cfg(feature = "jit")]
+            &self.jit,
+            &self.gcv,
+            self.ffi,
+            self.fs,
+            debugger,
+            profiler,
+            stats,
+            coverage,
+            self.traces
+                .as_ref()
+                .map(|_| Rc::new(RefCell::new(HashMap::new()))),
+        );
         let entry = ctx.new_entry_frame(self.code.entry);
         ctx.spawn_fiber(Stack::new(), entry);
 
         future::block_on(async {
             loop {
-                if !exec.try_tick() {
+                if !exec.try_tick()
+                    || ctx.panicked.get()
+                    || debugger.map_or(false, |d| d.quitting())
+                {
                     break;
                 }
+                // no fiber is running between two ticks, they are all parked
+                if let (Some(handle), Some(parked)) = (&self.traces, &ctx.parked) {
+                    if handle.requested() {
+                        let tracer = Tracer::new(&self.code, self.fs);
+                        let mut list: Vec<_> = parked
+                            .borrow()
+                            .iter()
+                            .map(|(id, (state, frames))| tracer.goroutine(*id, state, frames))
+                            .collect();
+                        list.sort_by_key(|g| g.id);
+                        handle.answer(list);
+                    }
+                }
             }
         });
+        !ctx.panicked.get()
     }
 }
 
@@ -1,10 +1,13 @@
 //#![allow(dead_code)]
 //use super::opcode::OpIndex;
 use super::gc::GcoVec;
+use super::inline_cache::InlineCache;
 use super::instruction::*;
-use super::objects::MetadataObjs;
+use super::metadata::{Fields, GosMetadata, MetadataType};
+use super::objects::{FunctionKey, IfaceUnderlying, InterfaceObj, MetadataObjs};
 use super::stack::Stack;
 use super::value::{EmptyResult, GosValue, GosValue64, RtValueResult, VMObjects};
+use std::rc::Rc;
 
 // restore stack_ref after drop to allow code in block call yield
 macro_rules! restore_stack_ref {
@@ -16,9 +19,7 @@ macro_rules! restore_stack_ref {
 
 macro_rules! go_panic {
     ($panic:ident, $msg:expr, $frame:ident, $code:ident) => {
-        let mut data = PanicData::new($msg);
-        data.call_stack.push(($frame.func(), $frame.pc - 1));
-        $panic = Some(data);
+        $panic = Some(PanicData::new($msg, $frame.pc - 1));
         $frame.pc = $code.len() - 1;
     };
 }
@@ -27,9 +28,7 @@ macro_rules! go_panic_str {
     ($panic:ident, $mdata:expr, $msg:expr, $frame:ident, $code:ident) => {
         let str_val = GosValue::new_str($msg);
         let iface = GosValue::new_empty_iface($mdata, str_val);
-        let mut data = PanicData::new(iface);
-        data.call_stack.push(($frame.func(), $frame.pc - 1));
-        $panic = Some(data);
+        $panic = Some(PanicData::new(iface, $frame.pc - 1));
         $frame.pc = $code.len() - 1;
     };
 }
@@ -163,17 +162,20 @@ macro_rules! unwrap_recv_val {
 
 #[inline]
 pub fn char_from_u32(u: u32) -> char {
-    unsafe { char::from_u32_unchecked(u) }
+    std::char::from_u32(u).unwrap_or(std::char::REPLACEMENT_CHARACTER)
 }
 
 #[inline]
 pub fn char_from_i32(i: i32) -> char {
-    unsafe { char::from_u32_unchecked(i as u32) }
+    char_from_u32(i as u32)
 }
 
 pub fn load_index(val: &GosValue, ind: &GosValue) -> RtValueResult {
     match val {
-        GosValue::Map(map) => Ok(map.0.get(&ind).clone()),
+        GosValue::Map(map) => {
+            ind.check_hashable()?;
+            Ok(map.0.get(&ind).clone())
+        }
         GosValue::Named(n) => load_index(&n.0, ind),
         _ => {
             let (mut ind64, t) = GosValue64::from_v128(ind);
@@ -247,7 +249,7 @@ pub fn store_index(
     r_index: OpIndex,
     t: ValueType,
     gcos: &GcoVec,
-) {
+) -> EmptyResult {
     match target {
         GosValue::Array(arr) => {
             let target_cell = &arr.0.borrow_data()[*key.as_int() as usize];
@@ -258,13 +260,32 @@ pub fn store_index(
             stack.store_val(&mut target_cell.borrow_mut(), r_index, t, gcos);
         }
         GosValue::Map(map) => {
-            map.0.touch_key(&key);
+            if map.0.is_nil() {
+                return Err("assignment to entry in nil map".to_string());
+            }
+            key.check_hashable()?;
+            // the map keeps its own copy of the key
+            map.0.touch_key(&key.copy_semantic(gcos));
             let borrowed = map.0.borrow_data();
             let target_cell = borrowed.get(&key).unwrap();
             stack.store_val(&mut target_cell.borrow_mut(), r_index, t, gcos);
         }
         _ => unreachable!(),
     }
+    Ok(())
+}
+
+#[inline]
+pub fn check_comparable(stack: &Stack, t: ValueType) -> EmptyResult {
+    match t {
+        ValueType::Interface | ValueType::Struct | ValueType::Array | ValueType::Named => {
+            let len = stack.len();
+            stack
+                .get_rc(len - 2)
+                .check_comparable(stack.get_rc(len - 1))
+        }
+        _ => Ok(()),
+    }
 }
 
 #[inline]
@@ -330,7 +351,7 @@ pub fn store_field(
                     stack.store_val(target, r_index, t, gcos);
                 }
                 GosValue::Str(sval) => {
-                    let i = s.0.borrow().meta.field_index(sval.as_str(), metas);
+                    let i = s.0.borrow().meta.field_index(&sval.as_str(), metas);
                     let target = &mut s.0.borrow_mut().fields[i as usize];
                     stack.store_val(target, r_index, t, gcos);
                 }
@@ -343,10 +364,137 @@ pub fn store_field(
 
 #[inline]
 pub fn push_index_comma_ok(stack: &mut Stack, map: &GosValue, index: &GosValue) {
-    let (v, b) = match map.as_map().0.try_get(index) {
+    let mobj = &map.as_map().0;
+    let (v, b) = match mobj.try_get(index) {
         Some(v) => (v, true),
-        None => (GosValue::new_nil(), false),
+        None => (mobj.get(index), false),
     };
     stack.push(v);
     stack.push_bool(b);
 }
+
+/// to_iface wraps `under` in an interface of type `iface`, which may be a
+/// named interface
+pub fn to_iface(iface: GosMetadata, under: IfaceUnderlying, metas: &MetadataObjs) -> GosValue {
+    match &metas[iface.as_non_ptr()] {
+        MetadataType::Named(_, md) => {
+            GosValue::Named(Box::new((GosValue::new_iface(*md, under), iface)))
+        }
+        MetadataType::Interface(_) => GosValue::new_iface(iface, under),
+        _ => unreachable!(),
+    }
+}
+
+fn iface_fields<'a>(iface: &GosMetadata, metas: &'a MetadataObjs) -> &'a Fields {
+    match &metas[iface.as_non_ptr()] {
+        MetadataType::Named(_, md) => match &metas[md.as_non_ptr()] {
+            MetadataType::Interface(f) => f,
+            _ => unreachable!(),
+        },
+        MetadataType::Interface(f) => f,
+        _ => unreachable!(),
+    }
+}
+
+/// iface_methods looks up the methods of the type `meta` that implement the
+/// interface `iface`, in the order of the methods of the interface.
+/// Returns None if `meta` is not a named type
+pub fn iface_methods(
+    iface: &GosMetadata,
+    meta: &GosMetadata,
+    metas: &MetadataObjs,
+) -> Option<Rc<Vec<FunctionKey>>> {
+    let named = match meta {
+        GosMetadata::NonPtr(k, _) => *k,
+        GosMetadata::Ptr1(k, _) => *k,
+        _ => return None,
+    };
+    match &metas[named] {
+        MetadataType::Named(methods, _) => Some(Rc::new(
+            iface_fields(iface, metas)
+                .iface_named_mapping(methods)
+                .iter()
+                .map(|x| x.borrow().func.unwrap())
+                .collect(),
+        )),
+        _ => None,
+    }
+}
+
+/// remap_iface_methods reorders the methods `funcs` of the interface `from`
+/// to be the methods of the interface `iface`, by their names
+pub fn remap_iface_methods(
+    iface: &GosMetadata,
+    from: &GosMetadata,
+    funcs: &Vec<FunctionKey>,
+    metas: &MetadataObjs,
+) -> Rc<Vec<FunctionKey>> {
+    let from_fields = iface_fields(from, metas);
+    let mut result: Vec<(OpIndex, FunctionKey)> = iface_fields(iface, metas)
+        .mapping
+        .iter()
+        .map(|(n, i)| (*i, funcs[from_fields.mapping[n] as usize]))
+        .collect();
+    result.sort_by_key(|x| x.0);
+    Rc::new(result.into_iter().map(|x| x.1).collect())
+}
+
+/// iface_to_iface converts the underlying of the interface `src` for the
+/// interface `iface`, the methods of the value depend on its dynamic type
+pub fn iface_to_iface(
+    iface: &GosMetadata,
+    src: &InterfaceObj,
+    cache: &InlineCache<Rc<Vec<FunctionKey>>>,
+    objs: &VMObjects,
+    stack: &Stack,
+) -> IfaceUnderlying {
+    match src.underlying() {
+        IfaceUnderlying::Gos(v, funcs) => {
+            let meta = v.get_meta(objs, stack);
+            let funcs = match cache.get(&meta) {
+                Some(f) => Some(f),
+                None => match iface_methods(iface, &meta, &objs.metas) {
+                    Some(f) => {
+                        cache.insert(meta, f.clone());
+                        Some(f)
+                    }
+                    // the value doesn't know its named type, e.g. a named int,
+                    // it can only be remapped by the names of the methods
+                    None => funcs
+                        .as_ref()
+                        .map(|f| remap_iface_methods(iface, &src.meta, f, &objs.metas)),
+                },
+            };
+            IfaceUnderlying::Gos(v.clone(), funcs)
+        }
+        under => under.clone(),
+    }
+}
+
+/// type_switch_target returns the index of the instruction a type switch
+/// goes to for the type `meta`, the cases of the switch start at `pc`.
+/// If no case matches it's the instruction following the cases.
+/// Returns None if the cases are not all types
+pub fn type_switch_target(
+    meta: &GosMetadata,
+    code: &[Instruction],
+    consts: &[GosValue],
+    pc: usize,
+    metas: &MetadataObjs,
+) -> Option<usize> {
+    let mut i = pc;
+    while i + 1 < code.len() && code[i + 1].op() == Opcode::SWITCH {
+        if code[i].op() != Opcode::PUSH_CONST || code[i + 1].t0() != ValueType::Metadata {
+            return None;
+        }
+        let case = match &consts[code[i].imm() as usize] {
+            GosValue::Metadata(m) => m,
+            _ => return None,
+        };
+        if meta.semantic_eq(case, metas) {
+            return Some(Stack::offset(i + 2, code[i + 1].imm()));
+        }
+        i += 2;
+    }
+    Some(i)
+}